@@ -0,0 +1,31 @@
+// Generates the server's protobuf types from proto/game.proto, and the
+// FlatBuffers code both the client and the server use from proto/state.fbs.
+// protoc comes from protoc-bin-vendored and the FlatBuffers compiler is
+// planus, so building doesn't need either installed. The wasm client doesn't
+// speak protobuf and skips that part.
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-changed=proto/state.fbs");
+    let declarations = planus_translation::translate_files(&["proto/state.fbs"]).expect("failed to compile proto/state.fbs");
+    let mut code = planus_codegen::generate_rust(&declarations, false).expect("failed to generate code for proto/state.fbs");
+    // planus leaves the file identifier out, so it's carried over from the schema
+    let schema = std::fs::read_to_string("proto/state.fbs").unwrap();
+    let identifier = schema
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("file_identifier \"")?.strip_suffix("\";"))
+        .expect("proto/state.fbs has no file_identifier");
+    code.push_str(&format!("\npub const STATE_SNAPSHOT_IDENTIFIER: [u8; 4] = *b\"{identifier}\";\n"));
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    std::fs::write(out_dir.join("state.rs"), code).expect("failed to write the FlatBuffers code");
+
+    #[cfg(feature = "server")]
+    {
+        println!("cargo:rerun-if-changed=proto/game.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform");
+        prost_build::Config::new()
+            .protoc_executable(protoc)
+            .compile_protos(&["proto/game.proto"], &["proto"])
+            .expect("failed to compile proto/game.proto");
+    }
+}
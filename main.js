@@ -1,9 +1,39 @@
-import init, { connect_to_game, move_player, send_chat_message, change_nickname } from './pkg/rust_wasm_hello.js';
+import init, { connect_to_game, move_player, send_chat_message, change_nickname, list_rooms, join_room, set_chat_collapsed, on_unread_change, unread_count, enable_notifications, notifications_enabled, default_nickname, copy_invite_link, use_minimap_worker, set_zoom, zoom_level, change_sprite, load_sprite_sheet, on_error, key_action, is_spectating } from './pkg/rust_wasm_hello.js';
 
 let wasmModule = null;
 let isConnected = false;
 let playerPosition = { x: 200, y: 150 };
-let keys = {};
+// Actions whose keys are held down, see set_key_binding
+let held = {};
+// Set to e.g. { url: '/avatars.png', frameWidth: 32, frameHeight: 32, walkFrames: 4 }
+// to draw avatars from a sprite sheet: one avatar per row, the idle frame
+// first and then the walk cycle
+const SPRITE_SHEET = null;
+// A game server other than the page's own, e.g. 'wss://game.example.com/ws'
+const SERVER_URL = null;
+
+// Signed-in browsers carry a session cookie that the game connection picks up
+// on its own; otherwise show a sign-in link for each configured OAuth provider
+async function setupLoginLinks() {
+    try {
+        const container = document.getElementById('login-links');
+        const session = await fetch('/api/session');
+        if (session.ok) {
+            const { nickname } = await session.json();
+            container.textContent = `Signed in as ${nickname}`;
+            return;
+        }
+        const providers = await (await fetch('/auth/providers')).json();
+        for (const provider of providers) {
+            const link = document.createElement('a');
+            link.href = `/auth/${provider}/login`;
+            link.textContent = `Sign in with ${provider === 'github' ? 'GitHub' : 'Google'}`;
+            container.appendChild(link);
+        }
+    } catch (error) {
+        console.warn('Could not load login providers:', error);
+    }
+}
 
 // Initialize WASM
 async function initWasm() {
@@ -14,6 +44,12 @@ async function initWasm() {
         document.getElementById('connect-btn').disabled = false;
         setupKeyboardInput();
         startGameLoop();
+        on_unread_change(updateChatToggle);
+        on_error(showError);
+        restorePreferences();
+        setupMinimapWorker();
+        setupSpriteSheet();
+        setupLoginLinks();
     } catch (error) {
         console.error('❌ WASM failed:', error);
         document.getElementById('wasm-status').innerHTML = '❌ WASM failed to load';
@@ -21,7 +57,7 @@ async function initWasm() {
 }
 
 // Connect to game server
-window.connectToGame = function() {
+window.connectToGame = async function() {
     if (isConnected) return;
     
     const statusEl = document.getElementById('connection-status');
@@ -34,35 +70,58 @@ window.connectToGame = function() {
         connectBtn.disabled = true;
         statusEl.innerHTML = '🔄 Connecting to WebSocket server...';
         
-        connect_to_game(nickname);
-        
-        // Give it a moment to connect
-        setTimeout(() => {
-            isConnected = true;
-            statusEl.innerHTML = '✅ Connected! Use WASD or arrow keys to move around.';
-            connectBtn.innerHTML = '✅ Connected';
-            connectBtn.style.background = '#4caf50';
-            console.log('✅ Connected to WebSocket game server');
-        }, 500);
+        // Resolves once the server has welcomed us
+        await connect_to_game(nickname, null, SERVER_URL);
         
+        isConnected = true;
+        statusEl.innerHTML = is_spectating()
+            ? '👀 Playing in another tab, watching from here.'
+            : '✅ Connected! Use WASD or arrow keys to move around.';
+        connectBtn.innerHTML = '✅ Connected';
+        connectBtn.style.background = '#4caf50';
+        console.log('✅ Connected to WebSocket game server');
     } catch (error) {
         console.error('❌ Connection failed:', error);
-        statusEl.innerHTML = `❌ Connection failed: ${error.message}`;
+        statusEl.textContent = `❌ Connection failed: ${error.message}`;
         connectBtn.disabled = false;
         connectBtn.style.background = '#ff6b6b';
     }
 };
 
-// Setup keyboard input
+// Problems reported by the client. After a lost connection the player can
+// connect again.
+function showError({ kind, message, recoverable }) {
+    const statusEl = document.getElementById('connection-status');
+    statusEl.textContent = `⚠️ ${message}`;
+    if (!recoverable && kind === 'connection' && isConnected) {
+        isConnected = false;
+        const connectBtn = document.getElementById('connect-btn');
+        connectBtn.disabled = false;
+        connectBtn.innerHTML = '🔗 Reconnect';
+        connectBtn.style.background = '#ff6b6b';
+    }
+}
+
+// Setup keyboard input. Keys go through the bindings, so set_key_binding
+// remaps them; typing into a field is left alone.
 function setupKeyboardInput() {
     document.addEventListener('keydown', (e) => {
-        keys[e.code] = true;
+        if (e.target.closest('input, textarea')) return;
+        const action = key_action(e.code);
+        if (!action) return;
         e.preventDefault();
+        if (action === 'focus_chat') {
+            document.getElementById('chat-input').focus();
+        } else if (action === 'emote') {
+            if (isConnected && !e.repeat) send_chat_message('👋');
+        } else {
+            held[action] = true;
+        }
     });
     
     document.addEventListener('keyup', (e) => {
-        keys[e.code] = false;
-        e.preventDefault();
+        const action = key_action(e.code);
+        if (action) held[action] = false;
     });
 }
 
@@ -82,26 +141,26 @@ function handleMovement() {
     let moved = false;
     const speed = 3;
     
-    // WASD or Arrow Keys
-    if (keys['KeyW'] || keys['ArrowUp']) {
+    // WASD or arrow keys unless remapped
+    if (held.move_up) {
         if (playerPosition.y > 10) {
             playerPosition.y -= speed;
             moved = true;
         }
     }
-    if (keys['KeyS'] || keys['ArrowDown']) {
+    if (held.move_down) {
         if (playerPosition.y < 360) {
             playerPosition.y += speed;
             moved = true;
         }
     }
-    if (keys['KeyA'] || keys['ArrowLeft']) {
+    if (held.move_left) {
         if (playerPosition.x > 10) {
             playerPosition.x -= speed;
             moved = true;
         }
     }
-    if (keys['KeyD'] || keys['ArrowRight']) {
+    if (held.move_right) {
         if (playerPosition.x < 770) {
             playerPosition.x += speed;
             moved = true;
@@ -139,6 +198,65 @@ window.sendChat = function(event) {
     }
 };
 
+// Collapse or expand the chat; the toggle shows how many messages came in
+// while it was collapsed or the tab was hidden
+window.toggleChat = function() {
+    const panel = document.getElementById('chat-panel');
+    const collapsed = panel.classList.toggle('collapsed');
+    set_chat_collapsed(collapsed);
+    updateChatToggle(unread_count());
+};
+
+// Draw the minimap in a worker when the browser can hand a canvas over to one;
+// otherwise the client keeps drawing it on the page
+function setupMinimapWorker() {
+    const canvas = document.getElementById('minimap');
+    if (!canvas.transferControlToOffscreen || !window.Worker) return;
+    const worker = new Worker(new URL('./minimap-worker.js', import.meta.url), { type: 'module' });
+    worker.onerror = (error) => console.error('Minimap worker failed:', error);
+    const offscreen = canvas.transferControlToOffscreen();
+    worker.postMessage({ canvas: offscreen }, [offscreen]);
+    use_minimap_worker(worker);
+}
+
+// Avatars stay circles unless a sheet is configured and loads
+async function setupSpriteSheet() {
+    if (!SPRITE_SHEET) return;
+    const { url, frameWidth, frameHeight, walkFrames } = SPRITE_SHEET;
+    try {
+        await load_sprite_sheet(url, frameWidth, frameHeight, walkFrames);
+        document.getElementById('sprite-picker').hidden = false;
+    } catch (error) {
+        console.warn('Could not load the sprite sheet:', error);
+    }
+}
+
+window.changeSprite = function(value) {
+    change_sprite(Number(value));
+};
+
+// Zoom buttons; the wheel and pinching over the game area zoom too
+window.zoomBy = function(factor) {
+    set_zoom(factor ? zoom_level() * factor : 1);
+};
+
+// Pre-fill the form from the link or what was remembered from the last visit
+function restorePreferences() {
+    document.getElementById('nickname-input').value = default_nickname() ?? '';
+    document.getElementById('notifications-toggle').checked = notifications_enabled();
+}
+
+// Opt in to desktop notifications; the click lets the browser ask for permission
+window.enableNotifications = function(enabled) {
+    enable_notifications(enabled);
+};
+
+function updateChatToggle(unread) {
+    const collapsed = document.getElementById('chat-panel').classList.contains('collapsed');
+    const label = collapsed ? 'Show' : 'Hide';
+    document.getElementById('chat-toggle').textContent = unread > 0 ? `${label} (${unread})` : label;
+}
+
 // Change nickname
 window.changeNickname = function() {
     if (!isConnected) {
@@ -156,6 +274,58 @@ window.changeNickname = function() {
     }
 };
 
+// Refresh the room browser
+window.refreshRooms = function() {
+    if (!isConnected) {
+        alert('Please connect to the game first!');
+        return;
+    }
+    list_rooms();
+};
+
+// Put a link to the current room on the clipboard
+window.copyInviteLink = async function() {
+    const button = document.getElementById('invite-btn');
+    try {
+        await copy_invite_link();
+        button.textContent = '✅ Link copied';
+    } catch (error) {
+        console.error('Could not copy the invite link:', error);
+        button.textContent = '❌ Could not copy';
+    }
+    setTimeout(() => { button.textContent = '📋 Copy invite link'; }, 2000);
+};
+
+// Join a room from the browser, or create one by name
+window.joinRoom = function(room, locked) {
+    if (!isConnected) {
+        alert('Please connect to the game first!');
+        return;
+    }
+    const typed = room === undefined;
+    if (typed) {
+        room = prompt('Room name (a new name creates the room):')?.trim();
+    }
+    if (!room) return;
+    let password = null;
+    if (locked || typed) {
+        password = prompt(locked ? `Password for ${room}:` : 'Password (leave empty for none):');
+        if (password === null) return;
+    }
+    try {
+        join_room(room, password || null);
+    } catch (error) {
+        console.error('Join room error:', error);
+    }
+};
+
+document.getElementById('room-list').addEventListener('click', (e) => {
+    const item = e.target.closest('li[data-room]');
+    if (item) {
+        joinRoom(item.dataset.room, item.dataset.locked === 'true');
+    }
+});
+
 // Disable connect button until WASM loads
 document.getElementById('connect-btn').disabled = true;
 
@@ -0,0 +1,34 @@
+-- Registered players (accounts). Guests are never stored.
+CREATE TABLE players (
+    username      TEXT PRIMARY KEY,
+    password_hash TEXT NOT NULL,
+    nickname      TEXT NOT NULL,
+    color         TEXT NOT NULL,
+    created_at    BIGINT NOT NULL
+);
+
+CREATE TABLE scores (
+    username   TEXT PRIMARY KEY REFERENCES players (username) ON DELETE CASCADE,
+    score      BIGINT NOT NULL DEFAULT 0,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE chat_messages (
+    id        BIGSERIAL PRIMARY KEY,
+    player_id TEXT NOT NULL,
+    nickname  TEXT NOT NULL,
+    message   TEXT NOT NULL,
+    sent_at   BIGINT NOT NULL
+);
+
+CREATE INDEX chat_messages_sent_at ON chat_messages (sent_at);
+
+-- A ban matches on account, IP address, or both; NULL expires_at is permanent
+CREATE TABLE bans (
+    id         BIGSERIAL PRIMARY KEY,
+    username   TEXT,
+    ip         TEXT,
+    reason     TEXT NOT NULL,
+    expires_at BIGINT,
+    created_at BIGINT NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Where a player was when they last disconnected, for resuming on reconnect
+ALTER TABLE players
+    ADD COLUMN last_x           REAL,
+    ADD COLUMN last_y           REAL,
+    ADD COLUMN last_position_at BIGINT;
@@ -0,0 +1,8 @@
+-- Lifetime per-player statistics, flushed periodically while playing
+CREATE TABLE player_stats (
+    username      TEXT PRIMARY KEY REFERENCES players (username) ON DELETE CASCADE,
+    distance      DOUBLE PRECISION NOT NULL DEFAULT 0,
+    messages_sent BIGINT NOT NULL DEFAULT 0,
+    session_secs  BIGINT NOT NULL DEFAULT 0,
+    joins         BIGINT NOT NULL DEFAULT 0
+);
@@ -0,0 +1,6 @@
+CREATE TABLE achievements (
+    username    TEXT NOT NULL REFERENCES players (username) ON DELETE CASCADE,
+    achievement TEXT NOT NULL,
+    unlocked_at BIGINT NOT NULL,
+    PRIMARY KEY (username, achievement)
+);
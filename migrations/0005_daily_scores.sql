@@ -0,0 +1,4 @@
+-- Points earned on the current UTC day, for the daily leaderboard
+ALTER TABLE scores
+    ADD COLUMN daily_day   BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN daily_score BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Staff role granted through the admin API: player, moderator or admin
+ALTER TABLE players ADD COLUMN role TEXT NOT NULL DEFAULT 'player';
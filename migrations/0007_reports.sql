@@ -0,0 +1,14 @@
+-- Player reports for moderators to review
+CREATE TABLE reports (
+    id                BIGINT PRIMARY KEY,
+    reporter_id       TEXT NOT NULL,
+    reporter_nickname TEXT NOT NULL,
+    reporter_account  TEXT,
+    target_id         TEXT NOT NULL,
+    target_nickname   TEXT NOT NULL,
+    target_account    TEXT,
+    reason            TEXT NOT NULL,
+    -- The target's recent chat as a JSON array of chat records
+    recent_chat       TEXT NOT NULL,
+    created_at        BIGINT NOT NULL
+);
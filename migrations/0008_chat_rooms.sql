@@ -0,0 +1,6 @@
+-- The room each message was sent in, for the chat archive API; earlier
+-- messages were all in the lobby
+ALTER TABLE chat_messages ADD COLUMN room TEXT NOT NULL DEFAULT 'lobby';
+
+CREATE INDEX chat_messages_room ON chat_messages (room, id);
+CREATE INDEX chat_messages_player ON chat_messages (player_id, id);
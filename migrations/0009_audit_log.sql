@@ -0,0 +1,11 @@
+-- Moderation actions by staff, newest last
+CREATE TABLE audit_log (
+    id         BIGINT PRIMARY KEY,
+    action     TEXT NOT NULL,
+    actor      TEXT NOT NULL,
+    actor_role TEXT NOT NULL,
+    target     TEXT NOT NULL,
+    reason     TEXT,
+    detail     TEXT,
+    created_at BIGINT NOT NULL
+);
@@ -0,0 +1,9 @@
+// Draws the minimap off the main thread. The page sends the canvas first,
+// then a frame of JSON per animation frame, which the WASM module draws.
+import init, { start_minimap_worker } from './pkg/rust_wasm_hello.js';
+
+self.onmessage = async (event) => {
+    if (!event.data?.canvas) return;
+    await init();
+    start_minimap_worker(event.data.canvas);
+};
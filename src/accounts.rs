@@ -0,0 +1,343 @@
+use crate::achievements::{UnlockedAchievement, ACHIEVEMENTS};
+use crate::leaderboard::{self, DailyScore, LeaderboardEntry, Period};
+use crate::stats::PlayerStats;
+use crate::storage::StorageHandle;
+use crate::tier::Role;
+use anyhow::{bail, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::{engine::general_purpose, Engine as _};
+use dashmap::DashMap;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Session tokens stay valid for this long after login
+const SESSION_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+// A registered player. Usernames are unique case-insensitively; the nickname
+// and color are what other players see and persist across sessions.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub username: String,
+    pub password_hash: String,
+    pub nickname: String,
+    pub color: String,
+    pub score: u64,
+    pub created_at: u64,
+    // Where the player was when they last disconnected
+    #[serde(default)]
+    pub last_position: Option<SavedPosition>,
+    // Lifetime totals, flushed from the live stats tracker
+    #[serde(default)]
+    pub stats: PlayerStats,
+    #[serde(default)]
+    pub achievements: Vec<UnlockedAchievement>,
+    #[serde(default)]
+    pub daily_score: DailyScore,
+    // Granted through the admin API; ADMINS and MODERATORS can raise it further
+    #[serde(default)]
+    pub role: Role,
+}
+
+// An account's score and stats as kept in a snapshot, so --restore and
+// handoffs bring them back
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScoreState {
+    pub username: String,
+    pub score: u64,
+    pub daily_score: DailyScore,
+    pub stats: PlayerStats,
+    pub achievements: Vec<UnlockedAchievement>,
+}
+
+impl Account {
+    fn add_score(&mut self, points: u64) {
+        if points == 0 {
+            return;
+        }
+        let today = leaderboard::today();
+        if self.daily_score.day != today {
+            self.daily_score = DailyScore { day: today, score: 0 };
+        }
+        self.score += points;
+        self.daily_score.score += points;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct SavedPosition {
+    pub x: f32,
+    pub y: f32,
+    pub saved_at: u64,
+}
+
+impl Account {
+    // The saved position if it's recent enough to resume from
+    pub fn resume_position(&self, window_secs: u64) -> Option<(f32, f32)> {
+        self.last_position
+            .filter(|p| now_secs().saturating_sub(p.saved_at) < window_secs)
+            .map(|p| (p.x, p.y))
+    }
+}
+
+struct Session {
+    username: String,
+    expires_at: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn account_key(username: &str) -> String {
+    username.to_lowercase()
+}
+
+// Accounts are kept in memory and written behind to the storage backend on
+// every change. Sessions are in memory only, so a restart logs everyone out.
+pub struct AccountStore {
+    accounts: DashMap<String, Account>,
+    sessions: DashMap<String, Session>,
+    storage: StorageHandle,
+}
+
+impl AccountStore {
+    // `accounts` are the ones loaded from storage at startup
+    pub fn new(accounts: Vec<Account>, storage: StorageHandle) -> Self {
+        Self {
+            accounts: accounts.into_iter().map(|a| (account_key(&a.username), a)).collect(),
+            sessions: DashMap::new(),
+            storage,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    // `nickname` has been checked by the caller, see nicknames.rs
+    pub async fn register(&self, username: &str, password: &str, nickname: String, color: String) -> Result<Account> {
+        let key = account_key(username);
+        if self.accounts.contains_key(&key) {
+            bail!("username already taken");
+        }
+        let password = password.to_string();
+        let password_hash = tokio::task::spawn_blocking(move || hash_password(&password)).await??;
+        let account = Account {
+            username: username.to_string(),
+            password_hash,
+            nickname,
+            color,
+            score: 0,
+            created_at: now_secs(),
+            last_position: None,
+            stats: PlayerStats::default(),
+            achievements: Vec::new(),
+            daily_score: DailyScore::default(),
+            role: Role::Player,
+        };
+        // Re-check now that hashing is done, in case of a concurrent registration
+        match self.accounts.entry(key) {
+            dashmap::mapref::entry::Entry::Occupied(_) => bail!("username already taken"),
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(account.clone());
+            }
+        }
+        self.storage.save_account(account.clone());
+        Ok(account)
+    }
+
+    // Account for an external (OAuth) identity, created on first login. These
+    // have no password, so they can't be used with /api/login.
+    pub fn find_or_create_external(&self, username: &str, nickname: String, color: String) -> Account {
+        let account = match self.accounts.entry(account_key(username)) {
+            dashmap::mapref::entry::Entry::Occupied(entry) => return entry.get().clone(),
+            dashmap::mapref::entry::Entry::Vacant(entry) => entry
+                .insert(Account {
+                    username: username.to_string(),
+                    password_hash: String::new(),
+                    nickname,
+                    color,
+                    score: 0,
+                    created_at: now_secs(),
+                    last_position: None,
+                    stats: PlayerStats::default(),
+                    achievements: Vec::new(),
+                    daily_score: DailyScore::default(),
+                    role: Role::Player,
+                })
+                .clone(),
+        };
+        self.storage.save_account(account.clone());
+        account
+    }
+
+    // Returns the account if the password matches
+    pub async fn verify(&self, username: &str, password: &str) -> Option<Account> {
+        let account = self.accounts.get(&account_key(username))?.clone();
+        let password = password.to_string();
+        let hash = account.password_hash.clone();
+        let valid = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+            .await
+            .unwrap_or(false);
+        valid.then_some(account)
+    }
+
+    pub fn create_session(&self, username: &str) -> String {
+        let mut bytes = [0u8; 32];
+        thread_rng().fill_bytes(&mut bytes);
+        let token = general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        self.sessions.insert(
+            token.clone(),
+            Session {
+                username: username.to_string(),
+                expires_at: now_secs() + SESSION_TTL_SECS,
+            },
+        );
+        token
+    }
+
+    pub fn end_session(&self, token: &str) {
+        self.sessions.remove(token);
+    }
+
+    // The account a session token belongs to, if the session is still valid
+    pub fn session_account(&self, token: &str) -> Option<Account> {
+        let session = self.sessions.get(token)?;
+        if session.expires_at <= now_secs() {
+            drop(session);
+            self.sessions.remove(token);
+            return None;
+        }
+        self.accounts.get(&account_key(&session.username)).map(|a| a.clone())
+    }
+
+    // Username of the account currently using `nickname`, ignoring case
+    pub fn nickname_owner(&self, nickname: &str) -> Option<String> {
+        self.accounts
+            .iter()
+            .find(|account| account.nickname.eq_ignore_ascii_case(nickname))
+            .map(|account| account.username.clone())
+    }
+
+    pub fn set_nickname(&self, username: &str, nickname: &str) {
+        if let Some(mut account) = self.accounts.get_mut(&account_key(username)) {
+            account.nickname = nickname.to_string();
+            self.storage.save_account(account.clone());
+        }
+    }
+
+    pub fn save_position(&self, username: &str, x: f32, y: f32) {
+        if let Some(mut account) = self.accounts.get_mut(&account_key(username)) {
+            account.last_position = Some(SavedPosition { x, y, saved_at: now_secs() });
+            self.storage.save_account(account.clone());
+        }
+    }
+
+    pub fn add_stats(&self, username: &str, stats: &PlayerStats) {
+        if let Some(mut account) = self.accounts.get_mut(&account_key(username)) {
+            let points = leaderboard::distance_points(account.stats.distance, account.stats.distance + stats.distance);
+            account.stats.add(stats);
+            account.add_score(points);
+            self.storage.save_account(account.clone());
+        }
+    }
+
+    pub fn unlock_achievement(&self, username: &str, id: &str) {
+        if let Some(mut account) = self.accounts.get_mut(&account_key(username)) {
+            if account.achievements.iter().any(|a| a.id == id) {
+                return;
+            }
+            account.achievements.push(UnlockedAchievement {
+                id: id.to_string(),
+                unlocked_at: now_secs(),
+            });
+            let points = ACHIEVEMENTS.iter().find(|a| a.id == id).map_or(0, |a| a.points);
+            account.add_score(points);
+            self.storage.save_account(account.clone());
+        }
+    }
+
+    pub fn leaderboard(&self, period: Period) -> Vec<LeaderboardEntry> {
+        let today = leaderboard::today();
+        let scores = self
+            .accounts
+            .iter()
+            .filter_map(|account| match period {
+                Period::AllTime => Some((account.nickname.clone(), account.score)),
+                Period::Daily => (account.daily_score.day == today).then(|| (account.nickname.clone(), account.daily_score.score)),
+            })
+            .collect();
+        leaderboard::rank(scores)
+    }
+
+    // Looks up an account by username, for public profile data like stats
+    pub fn get(&self, username: &str) -> Option<Account> {
+        self.accounts.get(&account_key(username)).map(|a| a.clone())
+    }
+
+    // Returns false when there's no such account
+    pub fn set_role(&self, username: &str, role: Role) -> bool {
+        let Some(mut account) = self.accounts.get_mut(&account_key(username)) else {
+            return false;
+        };
+        account.role = role;
+        self.storage.save_account(account.clone());
+        true
+    }
+
+    pub fn scores(&self) -> Vec<ScoreState> {
+        self.accounts
+            .iter()
+            .map(|account| ScoreState {
+                username: account.username.clone(),
+                score: account.score,
+                daily_score: account.daily_score,
+                stats: account.stats,
+                achievements: account.achievements.clone(),
+            })
+            .collect()
+    }
+
+    // Puts back scores from a snapshot, for accounts whose stored score is
+    // behind it, e.g. when storage didn't keep up before a restart. Accounts
+    // that are gone stay gone.
+    pub fn restore_scores(&self, scores: Vec<ScoreState>) {
+        for state in scores {
+            let Some(mut account) = self.accounts.get_mut(&account_key(&state.username)) else {
+                continue;
+            };
+            if account.score >= state.score {
+                continue;
+            }
+            account.score = state.score;
+            account.daily_score = state.daily_score;
+            account.stats = state.stats;
+            account.achievements = state.achievements;
+            self.storage.save_account(account.clone());
+        }
+    }
+
+    pub fn set_color(&self, username: &str, color: &str) {
+        if let Some(mut account) = self.accounts.get_mut(&account_key(username)) {
+            account.color = color.to_string();
+            self.storage.save_account(account.clone());
+        }
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    thread_rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).map_err(|e| anyhow::anyhow!("{e}"))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
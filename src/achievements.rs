@@ -0,0 +1,90 @@
+use crate::stats::PlayerStats;
+use serde::{Deserialize, Serialize};
+
+// What has to be reached to unlock an achievement. Registered players are
+// measured by their lifetime stats, guests by their current session.
+pub enum Rule {
+    Distance(f64),
+    MessagesSent(u64),
+    SessionSecs(u64),
+    Joins(u64),
+}
+
+impl Rule {
+    pub fn is_met(&self, stats: &PlayerStats) -> bool {
+        match *self {
+            Rule::Distance(px) => stats.distance >= px,
+            Rule::MessagesSent(n) => stats.messages_sent >= n,
+            Rule::SessionSecs(secs) => stats.session_secs >= secs,
+            Rule::Joins(n) => stats.joins >= n,
+        }
+    }
+}
+
+pub struct Achievement {
+    // Stable identifier stored with the account; never rename
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    // Added to the player's score when unlocked
+    pub points: u64,
+    pub rule: Rule,
+}
+
+pub const ACHIEVEMENTS: &[Achievement] = &[
+    Achievement {
+        id: "first_steps",
+        name: "First Steps",
+        description: "Travel 1,000px",
+        points: 10,
+        rule: Rule::Distance(1_000.0),
+    },
+    Achievement {
+        id: "wanderer",
+        name: "Wanderer",
+        description: "Travel 10,000px",
+        points: 50,
+        rule: Rule::Distance(10_000.0),
+    },
+    Achievement {
+        id: "marathon",
+        name: "Marathon",
+        description: "Travel 100,000px",
+        points: 200,
+        rule: Rule::Distance(100_000.0),
+    },
+    Achievement {
+        id: "hello_world",
+        name: "Hello, World",
+        description: "Send your first chat message",
+        points: 10,
+        rule: Rule::MessagesSent(1),
+    },
+    Achievement {
+        id: "chatterbox",
+        name: "Chatterbox",
+        description: "Send 100 chat messages",
+        points: 50,
+        rule: Rule::MessagesSent(100),
+    },
+    Achievement {
+        id: "regular",
+        name: "Regular",
+        description: "Join 10 times",
+        points: 50,
+        rule: Rule::Joins(10),
+    },
+    Achievement {
+        id: "dedicated",
+        name: "Dedicated",
+        description: "Play for an hour",
+        points: 100,
+        rule: Rule::SessionSecs(60 * 60),
+    },
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnlockedAchievement {
+    pub id: String,
+    pub unlocked_at: u64,
+}
@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Clients that have acked nothing for this many messages are considered stalled
+pub const MAX_UNACKED: u64 = 2000;
+
+// Per-connection sequence numbering for outgoing messages, shared between the
+// outgoing task (which assigns sequence numbers) and the incoming task (which
+// records the client's acks). Seq 0 is never sent, so an acked value of 0
+// means the client doesn't ack at all and is never treated as stalled.
+#[derive(Default)]
+pub struct AckTracker {
+    sent: AtomicU64,
+    acked: AtomicU64,
+}
+
+impl AckTracker {
+    pub fn next_seq(&self) -> u64 {
+        self.sent.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn ack(&self, seq: u64) {
+        let sent = self.sent.load(Ordering::Relaxed);
+        self.acked.fetch_max(seq.min(sent), Ordering::Relaxed);
+    }
+
+    pub fn unacked(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed) - self.acked.load(Ordering::Relaxed)
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        self.acked.load(Ordering::Relaxed) > 0 && self.unacked() > MAX_UNACKED
+    }
+}
@@ -0,0 +1,377 @@
+use crate::audit::{AuditAction, AuditQuery};
+use crate::commands::{format_duration, MAX_DURATION};
+use crate::http::{error_response, json_response, read_json};
+use crate::moderation::Moderator;
+use crate::session;
+use crate::tier::Role;
+use crate::tls::ClientCertificate;
+use crate::GameServer;
+use base64::{engine::general_purpose, Engine as _};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+
+#[derive(Deserialize)]
+struct BanRequest {
+    username: Option<String>,
+    ip: Option<IpAddr>,
+    reason: String,
+    // Omitted for a permanent ban
+    duration_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct KickRequest {
+    player_id: String,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MuteRequest {
+    player_id: String,
+    duration_secs: u64,
+}
+
+#[derive(Deserialize)]
+struct AnnounceRequest {
+    message: String,
+}
+
+// `interval_secs` 0 turns slow mode off
+#[derive(Deserialize)]
+struct SlowModeRequest {
+    room: String,
+    interval_secs: u64,
+}
+
+#[derive(Deserialize)]
+struct WorldRequest {
+    room: String,
+    world: String,
+}
+
+#[derive(Deserialize)]
+struct RoleRequest {
+    role: Role,
+}
+
+// Entries per page of GET /admin/audit
+const DEFAULT_AUDIT_PAGE: usize = 50;
+const MAX_AUDIT_PAGE: usize = 200;
+
+// The built-in dashboard served at /admin
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+// Who's calling: their role, and the name shown in moderation notices
+pub struct Caller {
+    pub role: Role,
+    pub name: String,
+    // Authenticated by credentials the browser attaches by itself (the session
+    // cookie or Basic auth), which cross-site requests could ride along on
+    pub ambient: bool,
+}
+
+// `Authorization: Bearer <ADMIN_TOKEN>` is an admin, as is Basic auth with
+// ADMIN_TOKEN as the password (so a browser can open the dashboard);
+// otherwise it's the account behind a session token, as the bearer token or
+// the session cookie. ADMIN_TOKEN access is disabled when no token is configured.
+// On the admin listener, the client certificate's holder is an admin.
+pub fn caller(req: &Request<Incoming>, server: &GameServer) -> Option<Caller> {
+    if let Some(cert) = req.extensions().get::<ClientCertificate>() {
+        return Some(Caller { role: Role::Admin, name: cert.name.clone(), ambient: false });
+    }
+    let authorization = req.headers().get("authorization").and_then(|h| h.to_str().ok());
+    if let Some(token) = authorization.and_then(|h| h.strip_prefix("Bearer ")) {
+        return token_caller(token, server);
+    }
+    let basic_password = authorization
+        .and_then(|h| h.strip_prefix("Basic "))
+        .and_then(|credentials| general_purpose::STANDARD.decode(credentials).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| Some(credentials.split_once(':')?.1.to_string()));
+    if basic_password.is_some_and(|password| is_admin_token(&password, server)) {
+        return Some(Caller { role: Role::Admin, name: "Admin".to_string(), ambient: true });
+    }
+    let token = session::session_token(&server.config(), req)?;
+    let account = server.accounts.session_account(&token)?;
+    Some(Caller {
+        role: Role::of(&server.config(), &account),
+        name: account.nickname,
+        ambient: true,
+    })
+}
+
+// A caller presenting ADMIN_TOKEN or a session token explicitly, as a bearer
+// token or the admin WebSocket's `token` parameter
+pub fn token_caller(token: &str, server: &GameServer) -> Option<Caller> {
+    if is_admin_token(token, server) {
+        return Some(Caller { role: Role::Admin, name: "Admin".to_string(), ambient: false });
+    }
+    let account = server.accounts.session_account(token)?;
+    Some(Caller {
+        role: Role::of(&server.config(), &account),
+        name: account.nickname,
+        ambient: false,
+    })
+}
+
+// Whether `secret` is ADMIN_TOKEN, compared in constant time so response
+// timing doesn't leak how much of a guess was right
+fn is_admin_token(secret: &str, server: &GameServer) -> bool {
+    server.config().admin_token.as_deref().is_some_and(|token| token.as_bytes().ct_eq(secret.as_bytes()).into())
+}
+
+// Moderators may kick, mute, review reports and watch the dashboard;
+// everything else is for admins
+fn required_role(path: &str) -> Role {
+    match path {
+        "/admin" | "/admin/overview" | "/admin/kick" | "/admin/mute" | "/admin/slowmode" => Role::Moderator,
+        path if path == "/admin/reports" || path.starts_with("/admin/reports/") => Role::Moderator,
+        _ => Role::Admin,
+    }
+}
+
+impl GameServer {
+    // Everything the dashboard shows. Message counts are running totals; the
+    // dashboard turns them into rates.
+    pub fn overview(&self) -> serde_json::Value {
+        let players: Vec<serde_json::Value> = self
+            .players
+            .iter()
+            .map(|player| {
+                let connection = self.connections.get(&player.id);
+                serde_json::json!({
+                    "player_id": player.id,
+                    "nickname": player.nickname,
+                    "room": player.room,
+                    "role": connection.as_ref().map(|c| c.role),
+                    "account": connection.as_ref().and_then(|c| c.account.clone()),
+                    "ip": connection.as_ref().map(|c| c.addr.ip()),
+                    "peer": connection.as_ref().map(|c| c.addr.peer),
+                    "broadcast_lags": connection.as_ref().map(|c| c.queue.lags()),
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "players": players,
+            "rooms": self.room_list(true),
+            "messages_received": self.metrics.messages_received.load(Ordering::Relaxed),
+            "chat_messages": self.metrics.chat_messages.load(Ordering::Relaxed),
+        })
+    }
+}
+
+pub async fn handle_admin(req: Request<Incoming>, server: GameServer) -> Response<Full<Bytes>> {
+    let Some(Caller { role, name, ambient }) = caller(&req, &server) else {
+        let mut response = error_response(StatusCode::UNAUTHORIZED, "admin token or staff session required");
+        if req.uri().path() == "/admin" {
+            // Lets a browser sign in to the dashboard with ADMIN_TOKEN
+            response
+                .headers_mut()
+                .insert("www-authenticate", "Basic realm=\"admin\"".parse().unwrap());
+        }
+        return response;
+    };
+    let required = required_role(req.uri().path());
+    if role < required {
+        return error_response(StatusCode::FORBIDDEN, &format!("requires the {} role", required.name()));
+    }
+    // Cross-site forms can post with ambient credentials but can't send JSON
+    // without a CORS preflight, which fails here
+    let json = req
+        .headers()
+        .get("content-type")
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.starts_with("application/json"));
+    if ambient && req.method() == Method::POST && !json {
+        return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "requests must be application/json");
+    }
+
+    let by = Moderator { role, name: &name };
+
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/admin") => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(DASHBOARD_HTML)))
+            .unwrap(),
+        (&Method::GET, "/admin/overview") => json_response(StatusCode::OK, server.overview().to_string()),
+        (&Method::GET, "/admin/snapshot") => {
+            let snapshot = server.snapshot();
+            json_response(StatusCode::OK, serde_json::to_string(&snapshot).unwrap())
+        }
+        (&Method::GET, "/admin/metrics") => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(Full::new(Bytes::from(server.metrics.render())))
+            .unwrap(),
+        (&Method::GET, "/admin/bans") => json_response(StatusCode::OK, serde_json::to_string(&server.bans.list()).unwrap()),
+        (&Method::POST, "/admin/bans") => {
+            let body: BanRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            if body.username.is_none() && body.ip.is_none() {
+                return error_response(StatusCode::BAD_REQUEST, "a ban needs a username, an ip, or both");
+            }
+            if body.duration_secs.is_some_and(|secs| secs > MAX_DURATION.as_secs()) {
+                let message = format!("a ban can last at most {}", format_duration(MAX_DURATION));
+                return error_response(StatusCode::BAD_REQUEST, &message);
+            }
+            let ban = server.bans.add(body.username, body.ip, body.reason, body.duration_secs.map(Duration::from_secs));
+            server.enforce_ban(&ban);
+            let target = match (&ban.username, ban.ip) {
+                (Some(username), Some(ip)) => format!("{username} ({ip})"),
+                (Some(username), None) => username.clone(),
+                (None, Some(ip)) => ip.to_string(),
+                (None, None) => unreachable!(),
+            };
+            let length = match body.duration_secs {
+                Some(secs) => format!("for {}", format_duration(Duration::from_secs(secs))),
+                None => "permanently".to_string(),
+            };
+            server.audit.record(AuditAction::Ban, &by, target, Some(ban.reason.clone()), Some(format!("ban #{} {length}", ban.id)));
+            json_response(StatusCode::CREATED, serde_json::to_string(&ban).unwrap())
+        }
+        (&Method::DELETE, path) if path.starts_with("/admin/bans/") => {
+            match path["/admin/bans/".len()..].parse().ok().filter(|&id| server.bans.remove(id)) {
+                Some(id) => {
+                    server.audit.record(AuditAction::Unban, &by, format!("ban #{id}"), None, None);
+                    json_response(StatusCode::OK, serde_json::json!({ "removed": true }).to_string())
+                }
+                None => error_response(StatusCode::NOT_FOUND, "ban not found"),
+            }
+        }
+        (&Method::POST, "/admin/kick") => {
+            let body: KickRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            match server.kick(&body.player_id, &by, body.reason.as_deref()) {
+                Ok(()) => json_response(StatusCode::OK, serde_json::json!({ "kicked": body.player_id }).to_string()),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            }
+        }
+        (&Method::POST, "/admin/mute") => {
+            let body: MuteRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            match server.mute(&body.player_id, &by, Duration::from_secs(body.duration_secs)) {
+                Ok(()) => json_response(StatusCode::OK, serde_json::json!({ "muted": body.player_id }).to_string()),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            }
+        }
+        (&Method::POST, "/admin/slowmode") => {
+            let body: SlowModeRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            let interval = (body.interval_secs > 0).then(|| Duration::from_secs(body.interval_secs));
+            match server.set_slow_mode(&body.room, interval, &by) {
+                Ok(()) => json_response(StatusCode::OK, serde_json::json!({ "room": body.room, "interval_secs": body.interval_secs }).to_string()),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            }
+        }
+        // Moderation actions, newest first. Pages with `before`, the
+        // `next_before` of the previous page.
+        (&Method::GET, "/admin/audit") => {
+            let mut query = AuditQuery { limit: DEFAULT_AUDIT_PAGE, ..AuditQuery::default() };
+            for (key, value) in url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()) {
+                match &*key {
+                    "actor" => query.actor = Some(value.into_owned()),
+                    "action" => match AuditAction::from_name(&value) {
+                        Some(action) => query.action = Some(action),
+                        None => return error_response(StatusCode::BAD_REQUEST, &format!("unknown action {value}")),
+                    },
+                    "before" => match value.parse() {
+                        Ok(before) => query.before = Some(before),
+                        Err(_) => return error_response(StatusCode::BAD_REQUEST, "before must be an entry id"),
+                    },
+                    "limit" => match value.parse::<usize>() {
+                        Ok(limit) if (1..=MAX_AUDIT_PAGE).contains(&limit) => query.limit = limit,
+                        _ => return error_response(StatusCode::BAD_REQUEST, &format!("limit must be 1-{MAX_AUDIT_PAGE}")),
+                    },
+                    _ => {}
+                }
+            }
+            let entries = server.audit.list(&query);
+            let next_before = (entries.len() == query.limit).then(|| entries.last().map(|entry| entry.id)).flatten();
+            let body = serde_json::json!({ "entries": entries, "next_before": next_before });
+            json_response(StatusCode::OK, body.to_string())
+        }
+        (&Method::GET, "/admin/reports") => json_response(StatusCode::OK, serde_json::to_string(&server.reports.list()).unwrap()),
+        (&Method::GET, path) if path.starts_with("/admin/reports/") => {
+            match path["/admin/reports/".len()..].parse().ok().and_then(|id| server.reports.get(id)) {
+                Some(report) => json_response(StatusCode::OK, serde_json::to_string(&report).unwrap()),
+                None => error_response(StatusCode::NOT_FOUND, "report not found"),
+            }
+        }
+        (&Method::POST, "/admin/announce") => {
+            let body: AnnounceRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            let _ = server.announce(body.message);
+            json_response(StatusCode::OK, serde_json::json!({ "sent": true }).to_string())
+        }
+        (&Method::GET, "/admin/drain") => json_response(StatusCode::OK, server.drain_status().to_string()),
+        (&Method::POST, "/admin/drain") => {
+            server.start_drain();
+            json_response(StatusCode::OK, server.drain_status().to_string())
+        }
+        (&Method::DELETE, "/admin/drain") => {
+            server.stop_drain();
+            json_response(StatusCode::OK, server.drain_status().to_string())
+        }
+        (&Method::GET, "/admin/worlds") => json_response(StatusCode::OK, serde_json::to_string(&server.rooms.world_names()).unwrap()),
+        (&Method::POST, "/admin/world") => {
+            let body: WorldRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            match server.switch_world(&body.room, &body.world) {
+                Ok(()) => json_response(StatusCode::OK, serde_json::json!({ "room": body.room, "world": body.world }).to_string()),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            }
+        }
+        (&Method::POST, "/admin/reload") => match server.reload_config() {
+            Ok(()) => {
+                let config = server.config();
+                let body = serde_json::json!({
+                    "guest_chat_per_minute": config.guest_chat_per_minute,
+                    "registered_chat_per_minute": config.registered_chat_per_minute,
+                    "joins_per_minute_per_ip": config.joins_per_minute_per_ip,
+                    "joins_per_minute": config.joins_per_minute,
+                    "motd": config.motd,
+                    "word_filter": config.word_filter,
+                    "expand_emoji": config.expand_emoji,
+                    "npc_count": config.npc_count,
+                });
+                json_response(StatusCode::OK, body.to_string())
+            }
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("{e:#}")),
+        },
+        (&Method::PUT, path) if path.strip_prefix("/admin/accounts/").is_some_and(|p| p.ends_with("/role")) => {
+            let username = path["/admin/accounts/".len()..path.len() - "/role".len()].to_string();
+            let body: RoleRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            if !server.accounts.set_role(&username, body.role) {
+                return error_response(StatusCode::NOT_FOUND, "account not found");
+            }
+            server.audit.record(AuditAction::SetRole, &by, username.clone(), None, Some(body.role.name().to_string()));
+            let body = serde_json::json!({ "username": username, "role": body.role });
+            json_response(StatusCode::OK, body.to_string())
+        }
+        #[cfg(feature = "pprof")]
+        (&Method::GET, "/debug/pprof/profile") => crate::profiling::profile(&req).await,
+        _ => error_response(StatusCode::NOT_FOUND, "unknown admin endpoint"),
+    }
+}
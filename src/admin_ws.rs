@@ -0,0 +1,259 @@
+use crate::admin::{self, Caller};
+use crate::http::error_response;
+use crate::moderation::Moderator;
+use crate::remote::RemoteAddr;
+use crate::tier::Role;
+use crate::{calculate_websocket_accept, GameServer, PlayerId};
+use anyhow::{bail, Result};
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::tungstenite::Utf8Bytes;
+use tracing::{error, info, info_span, Instrument};
+
+// Events an admin connection may fall behind by before it starts missing some
+const EVENT_BUFFER: usize = 1024;
+// How often admin connections get a Metrics event
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+// What happens on the server, as streamed to /ws/admin. Movement isn't
+// included; the Overview command and GET /admin/snapshot have positions.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub enum AdminEvent {
+    PlayerJoined { player_id: PlayerId, nickname: String, room: String },
+    PlayerLeft { player_id: PlayerId, nickname: String, room: String },
+    RoomChanged { player_id: PlayerId, from: String, to: String },
+    Chat { player_id: PlayerId, nickname: String, room: String, message: String },
+    // Kicks, mutes and bans, worded as the notice players see
+    Moderation { message: String },
+    Report { report_id: u64, reporter: String, target: String, reason: String },
+    Announcement { message: String },
+    RoomCreated { room: String, private: bool, locked: bool },
+    RoomDestroyed { room: String },
+    // Drain mode started with this many connections open, see drain.rs
+    Draining { connections: usize },
+    // The last connection closed while draining; safe to restart
+    Drained,
+    // Load shedding started after a tick of `tick_ms` with `backlog` messages
+    // waiting in a broadcast channel, see overload.rs
+    Overloaded { tick_ms: u64, backlog: usize },
+    Recovered,
+    Metrics {
+        players: usize,
+        connections: usize,
+        counters: BTreeMap<&'static str, u64>,
+    },
+}
+
+// Fans events out to every admin connection. Each event is serialized once,
+// and not at all while nobody's listening.
+pub struct AdminEvents {
+    tx: broadcast::Sender<Utf8Bytes>,
+}
+
+impl Default for AdminEvents {
+    fn default() -> Self {
+        Self { tx: broadcast::channel(EVENT_BUFFER).0 }
+    }
+}
+
+impl AdminEvents {
+    pub fn emit(&self, event: AdminEvent) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.tx.send(serde_json::to_string(&event).unwrap().into());
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Utf8Bytes> {
+        self.tx.subscribe()
+    }
+}
+
+// Commands sent over /ws/admin, e.g. `{"type": "Kick", "player_id": "..."}`.
+// They take an optional `id`, which is echoed in the reply.
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum AdminCommand {
+    Kick {
+        player_id: String,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    Mute { player_id: String, duration_secs: u64 },
+    Ban {
+        player_id: String,
+        // Omitted for a permanent ban
+        #[serde(default)]
+        duration_secs: Option<u64>,
+        reason: String,
+    },
+    Announce { message: String },
+    // `interval_secs` 0 turns slow mode off
+    SlowMode { room: String, interval_secs: u64 },
+    // Answered with the same data as GET /admin/overview
+    Overview,
+}
+
+#[derive(Deserialize)]
+struct AdminRequest {
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    #[serde(flatten)]
+    command: AdminCommand,
+}
+
+impl AdminCommand {
+    // The same roles as the chat commands
+    fn required_role(&self) -> Role {
+        match self {
+            AdminCommand::Kick { .. }
+            | AdminCommand::Mute { .. }
+            | AdminCommand::Ban { .. }
+            | AdminCommand::SlowMode { .. }
+            | AdminCommand::Overview => Role::Moderator,
+            AdminCommand::Announce { .. } => Role::Admin,
+        }
+    }
+}
+
+impl GameServer {
+    fn metrics_event(&self) -> AdminEvent {
+        AdminEvent::Metrics {
+            players: self.players.len(),
+            connections: self.connections.len(),
+            counters: self.metrics.counters(),
+        }
+    }
+
+    fn admin_command(&self, caller: &Caller, command: AdminCommand) -> Result<Option<serde_json::Value>> {
+        let required = command.required_role();
+        if caller.role < required {
+            bail!("requires the {} role", required.name());
+        }
+        let by = Moderator { role: caller.role, name: &caller.name };
+        match command {
+            AdminCommand::Kick { player_id, reason } => self.kick(&player_id, &by, reason.as_deref())?,
+            AdminCommand::Mute { player_id, duration_secs } => {
+                self.mute(&player_id, &by, Duration::from_secs(duration_secs))?
+            }
+            AdminCommand::Ban { player_id, duration_secs, reason } => {
+                self.ban(&player_id, &by, duration_secs.map(Duration::from_secs), reason)?
+            }
+            AdminCommand::Announce { message } => self.announce(message)?,
+            AdminCommand::SlowMode { room, interval_secs } => {
+                self.set_slow_mode(&room, (interval_secs > 0).then(|| Duration::from_secs(interval_secs)), &by)?
+            }
+            AdminCommand::Overview => return Ok(Some(self.overview())),
+        }
+        Ok(None)
+    }
+
+    // The reply to one command: `Ok`, `Overview` or `Error`
+    fn admin_reply(&self, caller: &Caller, text: &str) -> String {
+        let request: AdminRequest = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return serde_json::json!({ "type": "Error", "message": e.to_string() }).to_string(),
+        };
+        let mut reply = match self.admin_command(caller, request.command) {
+            Ok(Some(mut overview)) => {
+                overview["type"] = "Overview".into();
+                overview
+            }
+            Ok(None) => serde_json::json!({ "type": "Ok" }),
+            Err(e) => serde_json::json!({ "type": "Error", "message": e.to_string() }),
+        };
+        if let Some(id) = request.id {
+            reply["id"] = id;
+        }
+        reply.to_string()
+    }
+}
+
+// Answers an upgrade request for /ws/admin. Credentials must be explicit, a
+// bearer token or `?token=`, since browsers attach cookies to cross-site
+// WebSocket connections.
+pub fn upgrade(mut req: Request<Incoming>, server: GameServer, addr: RemoteAddr) -> Response<Full<Bytes>> {
+    let query_token = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .find_map(|(key, value)| (key == "token").then(|| value.into_owned()));
+    let caller = match query_token {
+        Some(token) => admin::token_caller(&token, &server),
+        None => admin::caller(&req, &server).filter(|caller| !caller.ambient),
+    };
+    let Some(caller) = caller else {
+        return error_response(StatusCode::UNAUTHORIZED, "admin token or staff session token required");
+    };
+    if caller.role < Role::Moderator {
+        return error_response(StatusCode::FORBIDDEN, "requires the moderator role");
+    }
+
+    let accept_key = calculate_websocket_accept(
+        req.headers().get("sec-websocket-key").and_then(|h| h.to_str().ok()).unwrap_or(""),
+    );
+    let on_upgrade = hyper::upgrade::on(&mut req);
+    let span = info_span!("admin_connection", %addr, admin = %caller.name);
+    tokio::spawn(
+        async move {
+            match on_upgrade.await {
+                Ok(upgraded) => {
+                    if let Err(e) = run(upgraded, server, caller).await {
+                        error!("Admin WebSocket error: {}", e);
+                    }
+                }
+                Err(e) => error!("Admin WebSocket upgrade failed: {}", e),
+            }
+        }
+        .instrument(span),
+    );
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("upgrade", "websocket")
+        .header("connection", "upgrade")
+        .header("sec-websocket-accept", accept_key)
+        .body(Full::new(Bytes::new()))
+        .unwrap()
+}
+
+async fn run(upgraded: hyper::upgrade::Upgraded, server: GameServer, caller: Caller) -> Result<()> {
+    let ws_stream = tokio_tungstenite::WebSocketStream::from_raw_socket(
+        TokioIo::new(upgraded),
+        tokio_tungstenite::tungstenite::protocol::Role::Server,
+        None,
+    )
+    .await;
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    let mut events = server.events.subscribe();
+    let mut metrics = tokio::time::interval(METRICS_INTERVAL);
+    info!(role = caller.role.name(), "Admin WebSocket opened");
+
+    loop {
+        let outgoing: Utf8Bytes = tokio::select! {
+            msg = ws_receiver.next() => match msg {
+                Some(Ok(Message::Text(text))) => server.admin_reply(&caller, &text).into(),
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+            },
+            event = events.recv() => match event {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => serde_json::json!({ "type": "Lagged", "missed": missed }).to_string().into(),
+                Err(RecvError::Closed) => break,
+            },
+            _ = metrics.tick() => serde_json::to_string(&server.metrics_event()).unwrap().into(),
+        };
+        ws_sender.send(Message::Text(outgoing)).await?;
+    }
+
+    info!("Admin WebSocket closed");
+    Ok(())
+}
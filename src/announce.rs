@@ -0,0 +1,70 @@
+// Screen reader announcements. Events that sighted players notice on the
+// game area (someone joining or leaving, a mention, an error) are written to
+// visually hidden aria-live regions in index.html, and the players are also
+// listed as focusable items next to the game area.
+use crate::i18n;
+use crate::world::World;
+use std::cell::RefCell;
+
+thread_local! {
+    // The player list markup last written, so it's only replaced when it
+    // changes and keyboard focus stays put while players move
+    static PLAYER_LIST: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// Read out once the screen reader has finished what it's saying
+pub fn polite(text: &str) {
+    announce("announcer-polite", text);
+}
+
+// Read out right away, for errors
+pub fn assertive(text: &str) {
+    announce("announcer-assertive", text);
+}
+
+// Replaces the region's text; a repeat of the same text is still announced
+// since the old node is removed first
+fn announce(region_id: &str, text: &str) {
+    let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+        return;
+    };
+    let (Some(region), Ok(line)) = (document.get_element_by_id(region_id), document.create_element("p")) else {
+        return;
+    };
+    line.set_text_content(Some(text));
+    region.replace_children_with_node_1(&line);
+}
+
+// The players in the room, sorted by nickname, into #player-list
+pub fn render_player_list(world: &World) {
+    let mut players: Vec<_> = world.profiles.iter().collect();
+    players.sort_by_key(|(_, profile)| profile.nickname.to_lowercase());
+    let mut html = String::new();
+    for (entity, profile) in players {
+        let mut label = profile.nickname.clone();
+        if world.me == Some(*entity) {
+            label = i18n::text("players.me", &[("nickname", &label)]);
+        }
+        if profile.idle {
+            label = i18n::text("players.idle", &[("nickname", &label)]);
+        }
+        html.push_str(&format!(r#"<li tabindex="0">{}</li>"#, crate::systems::escape_html(&label)));
+    }
+    let changed = PLAYER_LIST.with(|last| {
+        let mut last = last.borrow_mut();
+        if *last == html {
+            return false;
+        }
+        *last = html.clone();
+        true
+    });
+    if !changed {
+        return;
+    }
+    if let Some(list) = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id("player-list"))
+    {
+        list.set_inner_html(&html);
+    }
+}
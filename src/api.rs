@@ -0,0 +1,279 @@
+use crate::admin;
+use crate::http::{error_response, json_response, read_json};
+use crate::integrations;
+use crate::invites::{self, Invite};
+use crate::leaderboard::Period;
+use crate::nicknames;
+use crate::rooms;
+use crate::session;
+use crate::storage::ChatQuery;
+use crate::tier::Role;
+use crate::{GameServer, PLAYER_COLORS};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use rand::prelude::*;
+use serde::Deserialize;
+use std::time::Duration;
+
+// Messages per page of GET /api/chat
+const DEFAULT_CHAT_PAGE: usize = 50;
+const MAX_CHAT_PAGE: usize = 200;
+
+#[derive(Deserialize)]
+struct RegisterRequest {
+    username: String,
+    password: String,
+    nickname: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct InviteRequest {
+    // Omitted to open a new private room
+    room: Option<String>,
+    nickname: Option<String>,
+    ttl_secs: Option<u64>,
+    // An existing invite to `room`, proving the caller was let in
+    invite: Option<String>,
+}
+
+// Either endpoint takes an optional `room`: announcements go everywhere
+// without one, chat to the lobby
+#[derive(Deserialize)]
+struct PostRequest {
+    message: String,
+    room: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+fn valid_username(username: &str) -> bool {
+    (3..=32).contains(&username.len())
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Public account endpoints. Register and login return
+// `{ "token", "username", "nickname" }` and also set the signed session cookie,
+// so a browser's game connection joins as that account; other clients put the
+// token in `Join` instead.
+pub async fn handle_api(req: Request<Incoming>, server: GameServer) -> Response<Full<Bytes>> {
+    match (req.method(), req.uri().path()) {
+        (&Method::POST, "/api/register") => {
+            let body: RegisterRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            if !valid_username(&body.username) {
+                return error_response(StatusCode::BAD_REQUEST, "username must be 3-32 letters, digits, '_' or '-'");
+            }
+            if body.password.len() < 8 {
+                return error_response(StatusCode::BAD_REQUEST, "password must be at least 8 characters");
+            }
+            // The same rules as ChangeNick, since accounts skip them on Join
+            let nickname = body.nickname.map_or_else(|| body.username.clone(), |n| n.trim().to_string());
+            if !nicknames::is_valid(&nickname) {
+                return error_response(StatusCode::BAD_REQUEST, &format!("nickname must be 1 to {} characters", nicknames::MAX_LEN));
+            }
+            if let Some(reason) = server.nickname_reserved(&nickname, Some(&body.username), Role::Player) {
+                return error_response(StatusCode::CONFLICT, &reason);
+            }
+            let color = PLAYER_COLORS[thread_rng().gen_range(0..PLAYER_COLORS.len())].to_string();
+            match server.accounts.register(&body.username, &body.password, nickname, color).await {
+                Ok(account) => {
+                    let token = server.accounts.create_session(&account.username);
+                    session_response(&server, StatusCode::CREATED, token, &account.username, &account.nickname)
+                }
+                Err(e) => error_response(StatusCode::CONFLICT, &e.to_string()),
+            }
+        }
+        (&Method::POST, "/api/login") => {
+            let body: LoginRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            match server.accounts.verify(&body.username, &body.password).await {
+                Some(account) => {
+                    let token = server.accounts.create_session(&account.username);
+                    session_response(&server, StatusCode::OK, token, &account.username, &account.nickname)
+                }
+                None => error_response(StatusCode::UNAUTHORIZED, "invalid username or password"),
+            }
+        }
+        // Who the session cookie belongs to
+        (&Method::GET, "/api/session") => {
+            let account = session::session_token(&server.config(), &req).and_then(|t| server.accounts.session_account(&t));
+            match account {
+                Some(account) => {
+                    let body = serde_json::json!({ "username": account.username, "nickname": account.nickname });
+                    json_response(StatusCode::OK, body.to_string())
+                }
+                None => error_response(StatusCode::UNAUTHORIZED, "not signed in"),
+            }
+        }
+        (&Method::POST, "/api/logout") => {
+            if let Some(token) = session::session_token(&server.config(), &req) {
+                server.accounts.end_session(&token);
+            }
+            let mut response = json_response(StatusCode::OK, "{}".to_string());
+            response
+                .headers_mut()
+                .insert("set-cookie", session::clear_session_cookie(&server.config()).parse().unwrap());
+            response
+        }
+        // A signed link into a room. Anyone can open a new private room; inviting
+        // into an existing one takes an invite to it or a staff session.
+        (&Method::POST, "/api/invites") => {
+            let staff = admin::caller(&req, &server).is_some_and(|caller| caller.role >= Role::Moderator);
+            let body: InviteRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            let room = match body.room {
+                None => uuid::Uuid::new_v4().simple().to_string()[..12].to_string(),
+                Some(room) if !rooms::valid_room_id(&room) => {
+                    return error_response(StatusCode::BAD_REQUEST, "room must be 1-32 letters, digits, '_' or '-'");
+                }
+                Some(room) => {
+                    let member = body
+                        .invite
+                        .and_then(|token| Invite::verify(&server.config(), &token).ok())
+                        .is_some_and(|invite| invite.room == room);
+                    if !member && !staff {
+                        return error_response(StatusCode::FORBIDDEN, "an invite to this room is required");
+                    }
+                    room
+                }
+            };
+            let nickname = body.nickname.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+            let ttl = body.ttl_secs.map_or(invites::DEFAULT_TTL, Duration::from_secs).min(invites::MAX_TTL);
+            let invite = Invite::new(room, nickname, ttl);
+            let body = serde_json::json!({
+                "room": invite.room,
+                "url": invite.url(&server.config()),
+                "token": invite.sign(&server.config()),
+                "expires_at": invite.expires_at,
+            });
+            json_response(StatusCode::CREATED, body.to_string())
+        }
+        // Messages from integrations, see integrations.rs
+        (&Method::POST, "/api/announce") | (&Method::POST, "/api/chat") => {
+            let Some(sender) = integrations::integration_caller(&req, &server) else {
+                return error_response(StatusCode::UNAUTHORIZED, "API key or staff token required");
+            };
+            let chat = req.uri().path() == "/api/chat";
+            let body: PostRequest = match read_json(req).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            let result = if chat {
+                let room = body.room.unwrap_or_else(|| rooms::default_room().to_string());
+                server.post_chat(&sender, &room, body.message)
+            } else {
+                server.announce_from(&sender, body.room.as_deref(), body.message)
+            };
+            match result {
+                Ok(()) => json_response(StatusCode::OK, serde_json::json!({ "sent": true }).to_string()),
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            }
+        }
+        // Stored chat, newest first, for moderation tools and archives. Pages
+        // with `before`, the `next_before` of the previous page.
+        (&Method::GET, "/api/chat") => {
+            if integrations::integration_caller(&req, &server).is_none() {
+                return error_response(StatusCode::UNAUTHORIZED, "API key or staff token required");
+            }
+            let mut query = ChatQuery { limit: DEFAULT_CHAT_PAGE, ..ChatQuery::default() };
+            for (key, value) in url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()) {
+                match &*key {
+                    "room" => query.room = Some(value.into_owned()),
+                    "player" => query.player_id = Some(value.into_owned()),
+                    "before" => match value.parse() {
+                        Ok(before) => query.before = Some(before),
+                        Err(_) => return error_response(StatusCode::BAD_REQUEST, "before must be a message id"),
+                    },
+                    "limit" => match value.parse::<usize>() {
+                        Ok(limit) if (1..=MAX_CHAT_PAGE).contains(&limit) => query.limit = limit,
+                        _ => return error_response(StatusCode::BAD_REQUEST, &format!("limit must be 1-{MAX_CHAT_PAGE}")),
+                    },
+                    _ => {}
+                }
+            }
+            match server.storage.query_chat(&query).await {
+                Ok(Some(messages)) => {
+                    let next_before = (messages.len() == query.limit).then(|| messages.last().map(|chat| chat.id)).flatten();
+                    let body = serde_json::json!({ "messages": messages, "next_before": next_before });
+                    json_response(StatusCode::OK, body.to_string())
+                }
+                Ok(None) => error_response(StatusCode::NOT_IMPLEMENTED, "chat history is only kept by the postgres and redb storage backends"),
+                Err(e) => {
+                    tracing::warn!("Chat query failed: {:#}", e);
+                    error_response(StatusCode::INTERNAL_SERVER_ERROR, "chat history is unavailable")
+                }
+            }
+        }
+        // Public rooms with their player counts, for a server browser
+        (&Method::GET, "/api/rooms") => json_response(StatusCode::OK, serde_json::to_string(&server.room_list(false)).unwrap()),
+        // Top registered players; `?period=daily` counts today (UTC) only
+        (&Method::GET, "/api/leaderboard") => {
+            let period = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                .find(|(key, _)| key == "period")
+                .map(|(_, value)| value.into_owned());
+            let period = match period.as_deref() {
+                None => Period::AllTime,
+                Some(value) => match Period::from_query(value) {
+                    Some(period) => period,
+                    None => return error_response(StatusCode::BAD_REQUEST, "period must be daily or alltime"),
+                },
+            };
+            json_response(StatusCode::OK, serde_json::to_string(&server.accounts.leaderboard(period)).unwrap())
+        }
+        // Stats of a connected player by id, or an account's lifetime stats by username
+        (&Method::GET, path) if path.strip_prefix("/api/players/").is_some_and(|p| p.ends_with("/stats")) => {
+            let id = &path["/api/players/".len()..path.len() - "/stats".len()];
+            if let Some(snapshot) = server.stats_snapshot(&id.into()) {
+                return json_response(StatusCode::OK, serde_json::to_string(&snapshot).unwrap());
+            }
+            match server.accounts.get(id) {
+                Some(account) => {
+                    let body = serde_json::json!({ "username": account.username, "lifetime": account.stats });
+                    json_response(StatusCode::OK, body.to_string())
+                }
+                None => error_response(StatusCode::NOT_FOUND, "player not found"),
+            }
+        }
+        // Presence across every instance sharing REDIS_URL
+        (&Method::GET, path) if path == "/api/presence" || path.starts_with("/api/presence/") => {
+            let Some(presence) = &server.presence else {
+                return error_response(StatusCode::SERVICE_UNAVAILABLE, "presence is not enabled");
+            };
+            let result = match path.strip_prefix("/api/presence/") {
+                None => presence.list_online().await.map(|records| serde_json::to_string(&records).unwrap()),
+                Some(player_id) => match presence.get(player_id).await {
+                    Ok(Some(record)) => Ok(serde_json::to_string(&record).unwrap()),
+                    Ok(None) => return error_response(StatusCode::NOT_FOUND, "player not found"),
+                    Err(e) => Err(e),
+                },
+            };
+            match result {
+                Ok(body) => json_response(StatusCode::OK, body),
+                Err(e) => {
+                    tracing::warn!("Presence query failed: {:#}", e);
+                    error_response(StatusCode::BAD_GATEWAY, "presence is unavailable")
+                }
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "unknown API endpoint"),
+    }
+}
+
+fn session_response(server: &GameServer, status: StatusCode, token: String, username: &str, nickname: &str) -> Response<Full<Bytes>> {
+    let cookie = session::session_cookie(&server.config(), &token);
+    let body = serde_json::json!({ "token": token, "username": username, "nickname": nickname });
+    let mut response = json_response(status, body.to_string());
+    response.headers_mut().insert("set-cookie", cookie.parse().unwrap());
+    response
+}
@@ -0,0 +1,54 @@
+// The frontend's static files. Built with the embed-assets feature, dist/ is
+// compiled into the binary so the server ships as a single file, and setting
+// STATIC_PATH serves from disk instead, for development. Without the feature
+// they're always read from STATIC_PATH, by default ./dist.
+use crate::config::Config;
+use bytes::Bytes;
+use mime_guess::mime;
+
+const DEFAULT_STATIC_PATH: &str = "dist";
+
+// Content-Type for a file, by its extension. Text gets `charset=utf-8`,
+// which is what the build writes, and files without an extension are taken
+// for HTML.
+pub fn content_type(path: &str) -> String {
+    if std::path::Path::new(path).extension().is_none() {
+        return "text/html; charset=utf-8".to_string();
+    }
+    // mime_guess takes source maps for text/plain
+    let mime = if path.ends_with(".map") {
+        mime::APPLICATION_JSON
+    } else {
+        mime_guess::from_path(path).first_or_octet_stream()
+    };
+    let textual = mime.type_() == mime::TEXT
+        || mime.subtype() == mime::JAVASCRIPT
+        || mime.subtype() == mime::JSON
+        || mime.suffix() == Some(mime::JSON)
+        || mime.suffix() == Some(mime::XML);
+    if textual {
+        format!("{mime}; charset=utf-8")
+    } else {
+        mime.to_string()
+    }
+}
+
+// Debug builds read the folder at runtime; release builds carry it
+#[cfg(feature = "embed-assets")]
+#[derive(rust_embed::Embed)]
+#[folder = "dist/"]
+#[allow_missing = true]
+struct Embedded;
+
+// The file at `path` under the site root, if there is one
+pub async fn read(config: &Config, path: &str) -> Option<Bytes> {
+    #[cfg(feature = "embed-assets")]
+    if config.static_path.is_none() {
+        return Embedded::get(path.trim_start_matches('/')).map(|file| match file.data {
+            std::borrow::Cow::Borrowed(data) => Bytes::from_static(data),
+            std::borrow::Cow::Owned(data) => Bytes::from(data),
+        });
+    }
+    let root = config.static_path.as_deref().unwrap_or(DEFAULT_STATIC_PATH);
+    tokio::fs::read(format!("{root}{path}")).await.ok().map(Bytes::from)
+}
@@ -0,0 +1,117 @@
+use crate::moderation::Moderator;
+use crate::storage::StorageHandle;
+use crate::tier::Role;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Kick,
+    Mute,
+    Ban,
+    Unban,
+    SlowMode,
+    SetRole,
+}
+
+impl AuditAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            AuditAction::Kick => "kick",
+            AuditAction::Mute => "mute",
+            AuditAction::Ban => "ban",
+            AuditAction::Unban => "unban",
+            AuditAction::SlowMode => "slow_mode",
+            AuditAction::SetRole => "set_role",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "kick" => Some(AuditAction::Kick),
+            "mute" => Some(AuditAction::Mute),
+            "ban" => Some(AuditAction::Ban),
+            "unban" => Some(AuditAction::Unban),
+            "slow_mode" => Some(AuditAction::SlowMode),
+            "set_role" => Some(AuditAction::SetRole),
+            _ => None,
+        }
+    }
+}
+
+// One staff action. `target` is whoever or whatever it was done to, in the
+// words of the action: a nickname, a ban id, a room or an account.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub id: u64,
+    pub action: AuditAction,
+    pub actor: String,
+    pub actor_role: Role,
+    pub target: String,
+    pub reason: Option<String>,
+    // What else there is to know, e.g. a mute's duration
+    pub detail: Option<String>,
+    pub created_at: u64,
+}
+
+// Which entries GET /admin/audit wants: the newest `limit` with an id below
+// `before`, by one actor or of one action when given
+#[derive(Default)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<AuditAction>,
+    pub before: Option<u64>,
+    pub limit: usize,
+}
+
+// Every moderation action, written behind to storage like reports
+pub struct AuditLog {
+    entries: Mutex<Vec<AuditEntry>>,
+    next_id: AtomicU64,
+    storage: StorageHandle,
+}
+
+impl AuditLog {
+    pub fn new(mut entries: Vec<AuditEntry>, storage: StorageHandle) -> Self {
+        entries.sort_by_key(|e| e.id);
+        let next_id = entries.last().map_or(0, |e| e.id) + 1;
+        Self {
+            entries: Mutex::new(entries),
+            next_id: AtomicU64::new(next_id),
+            storage,
+        }
+    }
+
+    pub fn record(&self, action: AuditAction, by: &Moderator, target: String, reason: Option<String>, detail: Option<String>) {
+        let entry = AuditEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            action,
+            actor: by.name.to_string(),
+            actor_role: by.role,
+            target,
+            reason,
+            detail,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        };
+        self.storage.save_audit(entry.clone());
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    // Newest first
+    pub fn list(&self, query: &AuditQuery) -> Vec<AuditEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|e| query.before.is_none_or(|before| e.id < before))
+            .filter(|e| query.actor.as_ref().is_none_or(|actor| e.actor.eq_ignore_ascii_case(actor)))
+            .filter(|e| query.action.is_none_or(|action| e.action == action))
+            .take(query.limit)
+            .cloned()
+            .collect()
+    }
+}
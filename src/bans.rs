@@ -0,0 +1,120 @@
+use crate::storage::StorageHandle;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// WebSocket close code sent to banned connections (4000-4999 is reserved for
+// applications)
+pub const BANNED_CLOSE_CODE: u16 = 4003;
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// A ban matches an account, an IP address, or both; either matching is enough
+// to be rejected
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Ban {
+    pub id: u64,
+    pub username: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub reason: String,
+    // None means permanent
+    pub expires_at: Option<u64>,
+    pub created_at: u64,
+}
+
+impl Ban {
+    fn is_active(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|at| at > now)
+    }
+
+    pub fn matches(&self, ip: Option<IpAddr>, username: Option<&str>) -> bool {
+        let ip_match = ip.is_some() && self.ip == ip;
+        let account_match = match (&self.username, username) {
+            (Some(banned), Some(username)) => banned.eq_ignore_ascii_case(username),
+            _ => false,
+        };
+        ip_match || account_match
+    }
+
+    // Shown to the banned player
+    pub fn message(&self) -> String {
+        match self.expires_at {
+            Some(at) => {
+                let minutes = at.saturating_sub(now_secs()).div_ceil(60);
+                format!("You are banned for {} more minute(s): {}", minutes, self.reason)
+            }
+            None => format!("You are banned: {}", self.reason),
+        }
+    }
+}
+
+// Active bans, checked on the WebSocket upgrade (by IP) and on Join (by IP
+// and account). Changes are written behind to storage like accounts.
+pub struct BanStore {
+    bans: DashMap<u64, Ban>,
+    next_id: AtomicU64,
+    storage: StorageHandle,
+}
+
+impl BanStore {
+    pub fn new(bans: Vec<Ban>, storage: StorageHandle) -> Self {
+        let next_id = bans.iter().map(|b| b.id).max().unwrap_or(0) + 1;
+        Self {
+            bans: bans.into_iter().map(|b| (b.id, b)).collect(),
+            next_id: AtomicU64::new(next_id),
+            storage,
+        }
+    }
+
+    pub fn add(&self, username: Option<String>, ip: Option<IpAddr>, reason: String, duration: Option<Duration>) -> Ban {
+        let now = now_secs();
+        let ban = Ban {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            username,
+            ip,
+            reason,
+            expires_at: duration.map(|d| now.saturating_add(d.as_secs())),
+            created_at: now,
+        };
+        self.bans.insert(ban.id, ban.clone());
+        self.storage.save_ban(ban.clone());
+        ban
+    }
+
+    pub fn remove(&self, id: u64) -> bool {
+        let removed = self.bans.remove(&id).is_some();
+        if removed {
+            self.storage.remove_ban(id);
+        }
+        removed
+    }
+
+    // Active bans, oldest first. Expired ones are dropped here.
+    pub fn list(&self) -> Vec<Ban> {
+        self.remove_expired();
+        let mut bans: Vec<Ban> = self.bans.iter().map(|b| b.value().clone()).collect();
+        bans.sort_by_key(|b| b.id);
+        bans
+    }
+
+    // The active ban covering this IP or account, if any
+    pub fn find(&self, ip: Option<IpAddr>, username: Option<&str>) -> Option<Ban> {
+        let now = now_secs();
+        self.bans
+            .iter()
+            .find(|b| b.is_active(now) && b.matches(ip, username))
+            .map(|b| b.value().clone())
+    }
+
+    fn remove_expired(&self) {
+        let now = now_secs();
+        let expired: Vec<u64> = self.bans.iter().filter(|b| !b.is_active(now)).map(|b| b.id).collect();
+        for id in expired {
+            self.remove(id);
+        }
+    }
+}
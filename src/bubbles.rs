@@ -0,0 +1,22 @@
+// The latest chat message of each player, shown as a speech bubble above
+// their avatar until it expires
+pub const DEFAULT_DURATION_MS: f64 = 5000.0;
+// Longer messages are cut short in the bubble; the chat log has them in full
+const MAX_BUBBLE_CHARS: usize = 80;
+
+pub struct Bubble {
+    pub message: String,
+    // js_sys::Date::now() milliseconds
+    pub expires_at: f64,
+}
+
+impl Bubble {
+    pub fn new(message: &str, expires_at: f64) -> Self {
+        let mut chars = message.chars();
+        let mut shown: String = chars.by_ref().take(MAX_BUBBLE_CHARS).collect();
+        if chars.next().is_some() {
+            shown.push('…');
+        }
+        Self { message: shown, expires_at }
+    }
+}
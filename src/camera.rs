@@ -0,0 +1,148 @@
+// Zoom for the game area. At zoom 1 the whole world fills it; zoomed in, the
+// camera follows the local player and keeps its view inside the world. The
+// view is applied as a CSS transform on #players-container, so avatars, name
+// tags and bubbles all scale together. The game area itself is sized to the
+// world the server describes.
+use crate::world::{Bounds, Position};
+use std::cell::Cell;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{TouchEvent, TouchList, WheelEvent};
+
+pub const MIN_ZOOM: f64 = 1.0;
+pub const MAX_ZOOM: f64 = 4.0;
+// Zoom factor per pixel of wheel scroll
+const WHEEL_SENSITIVITY: f64 = 0.002;
+
+thread_local! {
+    // Distance between the two fingers of a pinch in progress
+    static PINCH: Cell<Option<f64>> = const { Cell::new(None) };
+}
+
+pub struct Camera {
+    bounds: Bounds,
+    zoom: f64,
+    // Top-left corner of the view, in world coordinates
+    x: f64,
+    y: f64,
+    // The transform last put on the page, to skip unchanged frames
+    applied: Option<(f64, f64, f64)>,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self { bounds: Bounds::default(), zoom: MIN_ZOOM, x: 0.0, y: 0.0, applied: None }
+    }
+}
+
+impl Camera {
+    pub fn bounds(&self) -> Bounds {
+        self.bounds
+    }
+
+    // Resizes #game-area to `bounds`, one pixel per world unit
+    pub fn set_bounds(&mut self, bounds: Bounds) {
+        self.bounds = bounds;
+        let Some(area) = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id("game-area"))
+        else {
+            return;
+        };
+        let _ = area.set_attribute("style", &format!("width: {}px; height: {}px;", bounds.width, bounds.height));
+    }
+
+    pub fn zoom(&self) -> f64 {
+        self.zoom
+    }
+
+    pub fn set_zoom(&mut self, zoom: f64) {
+        if zoom.is_finite() {
+            self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        }
+    }
+
+    // The part of the world in view as (x, y, width, height)
+    pub fn view(&self) -> (f64, f64, f64, f64) {
+        let (width, height) = (f64::from(self.bounds.width) / self.zoom, f64::from(self.bounds.height) / self.zoom);
+        (self.x, self.y, width, height)
+    }
+
+    // Centres the view on `target` as far as the world's edges allow
+    pub fn follow(&mut self, target: Option<Position>) {
+        let (_, _, width, height) = self.view();
+        let (x, y) = match target {
+            Some(target) => (f64::from(target.x) - width / 2.0, f64::from(target.y) - height / 2.0),
+            None => (self.x, self.y),
+        };
+        self.x = x.clamp(0.0, f64::from(self.bounds.width) - width);
+        self.y = y.clamp(0.0, f64::from(self.bounds.height) - height);
+    }
+
+    // Updates the container's transform if the view moved since last time
+    pub fn apply(&mut self) {
+        let transform = (self.zoom, self.x, self.y);
+        if self.applied == Some(transform) {
+            return;
+        }
+        let Some(container) = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id("players-container"))
+        else {
+            return;
+        };
+        let style = format!(
+            "transform-origin: 0 0; transform: scale({}) translate({}px, {}px);",
+            self.zoom, -self.x, -self.y
+        );
+        if container.set_attribute("style", &style).is_ok() {
+            self.applied = Some(transform);
+        }
+    }
+}
+
+// Calls `on_zoom` with a factor to multiply the zoom by for every wheel
+// scroll or pinch over the game area
+pub fn on_gesture(on_zoom: impl Fn(f64) + 'static) {
+    let Some(area) = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id("game-area"))
+    else {
+        return;
+    };
+    let on_zoom = Rc::new(on_zoom);
+
+    let zoom = Rc::clone(&on_zoom);
+    let on_wheel = Closure::<dyn FnMut(WheelEvent)>::new(move |e: WheelEvent| {
+        e.prevent_default();
+        zoom((-e.delta_y() * WHEEL_SENSITIVITY).exp());
+    });
+    let _ = area.add_event_listener_with_callback("wheel", on_wheel.as_ref().unchecked_ref());
+    on_wheel.forget();
+
+    let on_touch = Closure::<dyn FnMut(TouchEvent)>::new(move |e: TouchEvent| {
+        let Some(distance) = pinch_distance(&e.touches()) else {
+            PINCH.with(|pinch| pinch.set(None));
+            return;
+        };
+        e.prevent_default();
+        if let Some(previous) = PINCH.with(|pinch| pinch.replace(Some(distance))) {
+            if previous > 0.0 {
+                on_zoom(distance / previous);
+            }
+        }
+    });
+    for event in ["touchstart", "touchmove", "touchend", "touchcancel"] {
+        let _ = area.add_event_listener_with_callback(event, on_touch.as_ref().unchecked_ref());
+    }
+    on_touch.forget();
+}
+
+fn pinch_distance(touches: &TouchList) -> Option<f64> {
+    if touches.length() != 2 {
+        return None;
+    }
+    let (a, b) = (touches.get(0)?, touches.get(1)?);
+    Some(f64::from(a.client_x() - b.client_x()).hypot(f64::from(a.client_y() - b.client_y())))
+}
@@ -0,0 +1,25 @@
+// Custom messages from the server, for apps that define their own on top of
+// the game. The callback registered with on_custom gets each one as
+// `(channel, payload, from)`; `from` is the sending player's ID, or null.
+use std::cell::RefCell;
+use wasm_bindgen::JsValue;
+
+thread_local! {
+    static ON_CUSTOM: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+pub fn set_callback(callback: Option<js_sys::Function>) {
+    ON_CUSTOM.with(|on_custom| *on_custom.borrow_mut() = callback);
+}
+
+pub fn received(channel: &str, payload: &serde_json::Value, from: Option<&str>) {
+    // Cloned out first, so the callback may register another one
+    let Some(callback) = ON_CUSTOM.with(|on_custom| on_custom.borrow().clone()) else {
+        return;
+    };
+    let Ok(payload) = js_sys::JSON::parse(&payload.to_string()) else {
+        return;
+    };
+    let from = from.map_or(JsValue::NULL, JsValue::from_str);
+    let _ = callback.call3(&JsValue::NULL, &JsValue::from_str(channel), &payload, &from);
+}
@@ -0,0 +1,156 @@
+// The chat panel. Only the newest MAX_LINES messages are kept, and only the
+// lines scrolled into view (plus a few either side) are in the DOM, so long
+// sessions don't slow the page down. Lines have a fixed height (.chat-line in
+// index.html) so positions can be computed without measuring.
+use crate::i18n;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{Document, Element};
+
+pub const MAX_LINES: usize = 500;
+const LINE_HEIGHT: f64 = 20.0;
+// Lines rendered beyond each edge of the visible area
+const OVERSCAN: usize = 10;
+
+thread_local! {
+    static CHAT_LOG: RefCell<ChatLog> = RefCell::new(ChatLog::default());
+}
+
+struct ChatLine {
+    time: String,
+    // None for messages from the server itself
+    nickname: Option<String>,
+    text: String,
+}
+
+#[derive(Default)]
+struct ChatLog {
+    lines: VecDeque<ChatLine>,
+    // The scroll listener is added with the first line
+    listening: bool,
+}
+
+fn chat_container() -> Option<(Document, Element)> {
+    let document = web_sys::window()?.document()?;
+    let container = document.get_element_by_id("chat-messages")?;
+    Some((document, container))
+}
+
+fn time_string(timestamp: u64) -> String {
+    i18n::time(timestamp as f64 * 1000.0)
+}
+
+pub fn add_chat(nickname: &str, message: &str, timestamp: u64) {
+    push(ChatLine {
+        time: time_string(timestamp),
+        nickname: Some(nickname.to_string()),
+        text: message.to_string(),
+    });
+}
+
+pub fn add_system(message: &str, timestamp: u64) {
+    push(ChatLine {
+        time: time_string(timestamp),
+        nickname: None,
+        text: message.to_string(),
+    });
+}
+
+fn push(line: ChatLine) {
+    let Some((_, container)) = chat_container() else {
+        return;
+    };
+    let at_bottom = container.scroll_top() as f64 + container.client_height() as f64
+        >= container.scroll_height() as f64 - LINE_HEIGHT;
+    CHAT_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        if !log.listening {
+            listen_for_scroll(&container);
+            log.listening = true;
+        }
+        log.lines.push_back(line);
+        if log.lines.len() > MAX_LINES {
+            log.lines.pop_front();
+            // Keep what the reader is looking at in place
+            if !at_bottom {
+                container.set_scroll_top(container.scroll_top() - LINE_HEIGHT as i32);
+            }
+        }
+        log.render();
+    });
+    // Follow new messages unless the reader scrolled up
+    if at_bottom {
+        container.set_scroll_top(container.scroll_height());
+        render();
+    }
+}
+
+fn render() {
+    CHAT_LOG.with(|log| log.borrow().render());
+}
+
+// For when the panel is shown again: hidden elements lose their scroll position
+pub fn scroll_to_bottom() {
+    if let Some((_, container)) = chat_container() {
+        container.set_scroll_top(container.scroll_height());
+        render();
+    }
+}
+
+fn listen_for_scroll(container: &Element) {
+    let on_scroll = Closure::<dyn FnMut()>::new(render);
+    let _ = container.add_event_listener_with_callback("scroll", on_scroll.as_ref().unchecked_ref());
+    on_scroll.forget();
+}
+
+impl ChatLog {
+    fn render(&self) {
+        let Some((document, container)) = chat_container() else {
+            return;
+        };
+        let first_visible = (container.scroll_top() as f64 / LINE_HEIGHT) as usize;
+        let visible = (container.client_height() as f64 / LINE_HEIGHT).ceil() as usize;
+        let start = first_visible.saturating_sub(OVERSCAN).min(self.lines.len());
+        let end = (first_visible + visible + OVERSCAN).min(self.lines.len());
+
+        // A spacer as tall as every line together keeps the scrollbar right
+        let Ok(spacer) = document.create_element("div") else {
+            return;
+        };
+        let _ = spacer.set_attribute(
+            "style",
+            &format!("position: relative; height: {}px;", self.lines.len() as f64 * LINE_HEIGHT),
+        );
+        for (index, line) in self.lines.iter().enumerate().take(end).skip(start) {
+            if let Some(element) = line.element(&document) {
+                let _ = element.set_attribute("style", &format!("top: {}px;", index as f64 * LINE_HEIGHT));
+                let _ = spacer.append_child(&element);
+            }
+        }
+        container.replace_children_with_node_1(&spacer);
+    }
+}
+
+impl ChatLine {
+    // Built from text nodes, since nicknames and messages are typed by players
+    fn element(&self, document: &Document) -> Option<Element> {
+        let element = document.create_element("div").ok()?;
+        match &self.nickname {
+            Some(nickname) => {
+                element.set_class_name("chat-line");
+                let sender = document.create_element("strong").ok()?;
+                sender.set_text_content(Some(&format!("[{}] {}:", self.time, nickname)));
+                element.append_child(&sender).ok()?;
+                element.append_with_str_1(&format!(" {}", self.text)).ok()?;
+            }
+            None => {
+                element.set_class_name("chat-line system-message");
+                element.set_text_content(Some(&format!("[{}] {}", self.time, self.text)));
+            }
+        }
+        element.set_attribute("title", &element.text_content().unwrap_or_default()).ok()?;
+        Some(element)
+    }
+}
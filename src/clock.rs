@@ -0,0 +1,45 @@
+// Estimates the offset between the browser clock and the server clock from
+// TimeSyncRequest/TimeSyncResponse round trips (NTP-style). Samples with the
+// smallest round trip are the least skewed by queueing, so the estimate comes
+// from the best of the most recent samples.
+const MAX_SAMPLES: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    offset: f64,
+    round_trip: f64,
+}
+
+#[derive(Default, Debug)]
+pub struct ClockSync {
+    samples: Vec<Sample>,
+}
+
+impl ClockSync {
+    // All times are Unix epoch milliseconds: t0 client send, t1 server receive,
+    // t2 server send, t3 client receive
+    pub fn add_sample(&mut self, t0: f64, t1: f64, t2: f64, t3: f64) {
+        let round_trip = (t3 - t0) - (t2 - t1);
+        let offset = ((t1 - t0) + (t2 - t3)) / 2.0;
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.remove(0);
+        }
+        self.samples.push(Sample { offset, round_trip: round_trip.max(0.0) });
+    }
+
+    fn best(&self) -> Option<Sample> {
+        self.samples
+            .iter()
+            .copied()
+            .min_by(|a, b| a.round_trip.total_cmp(&b.round_trip))
+    }
+
+    // Milliseconds to add to the local clock to get server time
+    pub fn offset_ms(&self) -> f64 {
+        self.best().map_or(0.0, |s| s.offset)
+    }
+
+    pub fn round_trip_ms(&self) -> Option<f64> {
+        self.best().map(|s| s.round_trip)
+    }
+}
@@ -0,0 +1,358 @@
+use crate::frame::OutboundFrame;
+use crate::protobuf::game;
+use crate::state_writer::encode_state_snapshot;
+use crate::{ClientMessage, ServerMessage};
+use anyhow::{bail, Result};
+use prost::Message as _;
+use bytes::Bytes;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{Data, OpCode};
+use tokio_tungstenite::tungstenite::protocol::frame::Frame;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+// Set in a flatbuffers-zstd frame's flags byte when the rest is compressed
+const FLAG_ZSTD: u8 = 0x01;
+// Snapshots smaller than this aren't worth compressing
+const ZSTD_MIN_BYTES: usize = 1024;
+// Fast enough to run on every snapshot; each is compressed once, for all
+// the connections that share it
+const ZSTD_LEVEL: i32 = 3;
+
+// Wire encoding for a connection, picked from the client's
+// Sec-WebSocket-Protocol list during the upgrade. JSON stays the default so
+// existing clients that don't request a subprotocol keep working.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    Protobuf,
+    // JSON, except position updates which go out as FlatBuffers state
+    // deltas. With `zstd`, a flags byte ahead of each binary frame says
+    // whether the rest is zstd-compressed, which it is from ZSTD_MIN_BYTES
+    // on. With `quantized`, positions are u16s across the world's bounds.
+    FlatBuffers { zstd: bool, quantized: bool },
+    // Binary frames carrying the same serde messages as JSON
+    Cbor,
+}
+
+impl Codec {
+    const SUPPORTED: [Codec; 7] = [
+        Codec::Json,
+        Codec::Protobuf,
+        Codec::FlatBuffers { zstd: false, quantized: false },
+        Codec::FlatBuffers { zstd: true, quantized: false },
+        Codec::FlatBuffers { zstd: false, quantized: true },
+        Codec::FlatBuffers { zstd: true, quantized: true },
+        Codec::Cbor,
+    ];
+
+    pub fn subprotocol(self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::Protobuf => "protobuf",
+            Codec::FlatBuffers { zstd: false, quantized: false } => "flatbuffers",
+            Codec::FlatBuffers { zstd: true, quantized: false } => "flatbuffers-zstd",
+            Codec::FlatBuffers { zstd: false, quantized: true } => "flatbuffers-q16",
+            Codec::FlatBuffers { zstd: true, quantized: true } => "flatbuffers-zstd-q16",
+            Codec::Cbor => "cbor",
+        }
+    }
+
+    // Returns the first codec in the client's preference order that we support
+    pub fn negotiate(requested: &str) -> Option<Codec> {
+        requested
+            .split(',')
+            .map(str::trim)
+            .find_map(|name| Self::SUPPORTED.into_iter().find(|c| c.subprotocol() == name))
+    }
+
+    // Number of distinct shared bodies an OutboundFrame can cache
+    pub const BODY_KINDS: usize = 7;
+
+    // FlatBuffers connections share the JSON body for everything but
+    // snapshots, which have a FlatBuffers body with float or quantized
+    // positions, compressed or not
+    pub fn body_kind(self, message: &ServerMessage) -> usize {
+        match (self, message) {
+            (Codec::FlatBuffers { zstd, quantized }, ServerMessage::Snapshot { .. }) => {
+                3 + 2 * usize::from(zstd) + usize::from(quantized)
+            }
+            (Codec::Json | Codec::FlatBuffers { .. }, _) => 0,
+            (Codec::Protobuf, _) => 1,
+            (Codec::Cbor, _) => 2,
+        }
+    }
+
+    // The message serialized without a `seq`, shared across connections.
+    // `bounds` is the world a Snapshot's positions are in.
+    pub fn encode_body(self, message: &ServerMessage, bounds: Option<(f32, f32)>) -> Bytes {
+        match (self, message) {
+            (Codec::FlatBuffers { zstd, quantized }, ServerMessage::Snapshot { players }) => {
+                let states = players.iter().map(|p| (&*p.player_id, p.x, p.y, p.vx, p.vy));
+                let snapshot = encode_state_snapshot(states, bounds.filter(|_| quantized));
+                if zstd { with_flags(&snapshot).into() } else { snapshot.into() }
+            }
+            (Codec::Protobuf, _) => game::ServerMessage::from(message).encode_to_vec().into(),
+            (Codec::Cbor, _) => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(message, &mut bytes).unwrap();
+                bytes.into()
+            }
+            (Codec::Json | Codec::FlatBuffers { .. }, _) => serde_json::to_vec(message).unwrap().into(),
+        }
+    }
+
+    // Builds the WebSocket frames for one connection. The shared body goes out
+    // untouched as a continuation fragment after a small per-connection
+    // fragment carrying `seq`, so the body is never copied per subscriber.
+    pub fn encode(self, seq: u64, frame: &OutboundFrame) -> Vec<Message> {
+        let body = frame.body(self);
+        let (opcode, head, tail) = match (self, &frame.message) {
+            // seq leads as 8 little-endian bytes, outside the FlatBuffers table
+            // and the compression
+            (Codec::FlatBuffers { .. }, ServerMessage::Snapshot { .. }) => (Data::Binary, seq.to_le_bytes().to_vec(), body),
+            (Codec::Protobuf, _) => {
+                // Protobuf fields may appear in any order, so seq can just lead
+                let mut head = Vec::with_capacity(11);
+                prost::encoding::uint64::encode(game::SEQ_TAG, &seq, &mut head);
+                (Data::Binary, head, body)
+            }
+            (Codec::Cbor, _) => {
+                let (len, header_len) = cbor_map_len(&body);
+                let mut head = Vec::with_capacity(16);
+                match len {
+                    Some(len) => cbor_header(5, len + 1, &mut head),
+                    None => head.push(0xbf),
+                }
+                cbor_header(3, 3, &mut head);
+                head.extend_from_slice(b"seq");
+                cbor_header(0, seq, &mut head);
+                (Data::Binary, head, body.slice(header_len..))
+            }
+            (Codec::Json | Codec::FlatBuffers { .. }, _) => {
+                // Every message is a JSON object, so splice seq in after the `{`
+                let head = format!("{{\"seq\":{seq},").into_bytes();
+                (Data::Text, head, body.slice(1..))
+            }
+        };
+        vec![
+            Message::Frame(Frame::message(head, OpCode::Data(opcode), false)),
+            Message::Frame(Frame::message(tail, OpCode::Data(Data::Continue), true)),
+        ]
+    }
+
+    // Text frames are always JSON so a binary client can still send the odd
+    // hand-written message while debugging
+    pub fn decode(self, frame: &Message) -> Result<ClientMessage> {
+        match (self, frame) {
+            (_, Message::Text(text)) => Ok(serde_json::from_str(text.as_str())?),
+            (Codec::Protobuf, Message::Binary(bytes)) => {
+                game::ClientMessage::decode(bytes.as_ref())?.try_into()
+            }
+            (Codec::Cbor, Message::Binary(bytes)) => Ok(ciborium::from_reader(bytes.as_ref())?),
+            (_, Message::Binary(_)) => bail!("binary frame on a text-only connection"),
+            _ => bail!("unexpected frame type"),
+        }
+    }
+}
+
+// Prefixes a flatbuffers-zstd snapshot with its flags, compressing the
+// snapshot if that's worth it
+fn with_flags(buffer: &[u8]) -> Vec<u8> {
+    let compressed = (buffer.len() >= ZSTD_MIN_BYTES)
+        .then(|| zstd::bulk::compress(buffer, ZSTD_LEVEL).ok())
+        .flatten();
+    let (flags, payload) = match &compressed {
+        Some(compressed) => (FLAG_ZSTD, compressed.as_slice()),
+        None => (0, buffer),
+    };
+    let mut framed = Vec::with_capacity(payload.len() + 1);
+    framed.push(flags);
+    framed.extend_from_slice(payload);
+    framed
+}
+
+// Writes a CBOR item header (major type plus length or value)
+fn cbor_header(major: u8, value: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xff => out.extend([major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(value.to_be_bytes());
+        }
+    }
+}
+
+// Entry count (None when indefinite) and header size of the top-level map
+fn cbor_map_len(body: &[u8]) -> (Option<u64>, usize) {
+    let info = body[0] & 0x1f;
+    let be = |n: usize| body[1..=n].iter().fold(0u64, |acc, b| acc << 8 | u64::from(*b));
+    match info {
+        0..=23 => (Some(u64::from(info)), 1),
+        24 => (Some(be(1)), 2),
+        25 => (Some(be(2)), 3),
+        26 => (Some(be(4)), 5),
+        27 => (Some(be(8)), 9),
+        _ => (None, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_fb::StateSnapshotRef;
+    use crate::PlayerPosition;
+    use planus::ReadAsRoot;
+    use std::sync::Arc;
+
+    const SEQS: [u64; 8] = [0, 7, 23, 24, 255, 256, 65_536, u64::MAX];
+
+    fn chat() -> Arc<OutboundFrame> {
+        OutboundFrame::new(ServerMessage::ChatMessage {
+            player_id: "p1".into(),
+            nickname: "alice".into(),
+            message: "hi \"there\" {}".into(),
+            timestamp: 1234,
+        })
+    }
+
+    fn assert_chat(message: ServerMessage) {
+        let ServerMessage::ChatMessage { player_id, nickname, message, timestamp } = message else {
+            panic!("expected a ChatMessage, got {message:?}");
+        };
+        assert_eq!((&*player_id, &*nickname, &*message, timestamp), ("p1", "alice", "hi \"there\" {}", 1234));
+    }
+
+    // The opcode and reassembled payload of an encoded message, checking it's
+    // a head fragment followed by the final continuation
+    fn encoded(codec: Codec, seq: u64, frame: &OutboundFrame) -> (OpCode, Vec<u8>) {
+        let messages = codec.encode(seq, frame);
+        let [Message::Frame(head), Message::Frame(tail)] = messages.as_slice() else {
+            panic!("expected two fragments, got {messages:?}");
+        };
+        assert!(!head.header().is_final);
+        assert!(tail.header().is_final);
+        assert_eq!(tail.header().opcode, OpCode::Data(Data::Continue));
+        (head.header().opcode, [head.payload(), tail.payload()].concat())
+    }
+
+    #[test]
+    fn negotiates_the_first_supported_subprotocol() {
+        assert_eq!(Codec::negotiate("protobuf"), Some(Codec::Protobuf));
+        assert_eq!(Codec::negotiate("msgpack, cbor, json"), Some(Codec::Cbor));
+        assert_eq!(Codec::negotiate("flatbuffers-zstd-q16"), Some(Codec::FlatBuffers { zstd: true, quantized: true }));
+        assert_eq!(Codec::negotiate("msgpack"), None);
+        for codec in Codec::SUPPORTED {
+            assert_eq!(Codec::negotiate(codec.subprotocol()), Some(codec));
+        }
+    }
+
+    #[test]
+    fn json_round_trips_with_seq() {
+        let frame = chat();
+        for codec in [Codec::Json, Codec::FlatBuffers { zstd: true, quantized: false }] {
+            for seq in SEQS {
+                let (opcode, payload) = encoded(codec, seq, &frame);
+                assert_eq!(opcode, OpCode::Data(Data::Text));
+                let value: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+                assert_eq!(value["seq"], seq);
+                assert_eq!(value["type"], "ChatMessage");
+                assert_chat(serde_json::from_slice(&payload).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn cbor_round_trips_with_seq() {
+        let frame = chat();
+        let body: ciborium::Value = ciborium::from_reader(frame.body(Codec::Cbor).as_ref()).unwrap();
+        let entries = body.as_map().unwrap().len();
+        for seq in SEQS {
+            let (opcode, payload) = encoded(Codec::Cbor, seq, &frame);
+            assert_eq!(opcode, OpCode::Data(Data::Binary));
+            let value: ciborium::Value = ciborium::from_reader(payload.as_slice()).unwrap();
+            let map = value.as_map().unwrap();
+            assert_eq!(map.len(), entries + 1);
+            let (_, found) = map.iter().find(|(key, _)| key.as_text() == Some("seq")).unwrap();
+            assert_eq!(found.as_integer(), Some(seq.into()));
+            assert_chat(ciborium::from_reader(payload.as_slice()).unwrap());
+        }
+    }
+
+    #[test]
+    fn protobuf_round_trips_with_seq() {
+        let frame = chat();
+        let expected = game::ServerMessage::from(&frame.message).message;
+        for seq in SEQS {
+            let (opcode, payload) = encoded(Codec::Protobuf, seq, &frame);
+            assert_eq!(opcode, OpCode::Data(Data::Binary));
+            let decoded = game::ServerMessage::decode(payload.as_slice()).unwrap();
+            assert_eq!(decoded.seq, seq);
+            assert_eq!(decoded.message, expected);
+        }
+    }
+
+    #[test]
+    fn flatbuffers_snapshots_lead_with_seq() {
+        let players = vec![PlayerPosition { player_id: "p1".into(), x: 12.5, y: 300.0, vx: 1.0, vy: -2.0 }];
+        let frame = OutboundFrame::snapshot(players, Some((800.0, 600.0)));
+        for codec in [Codec::FlatBuffers { zstd: false, quantized: false }, Codec::FlatBuffers { zstd: true, quantized: false }] {
+            let (opcode, payload) = encoded(codec, 42, &frame);
+            assert_eq!(opcode, OpCode::Data(Data::Binary));
+            let (seq, mut body) = payload.split_at(8);
+            assert_eq!(u64::from_le_bytes(seq.try_into().unwrap()), 42);
+            if codec == (Codec::FlatBuffers { zstd: true, quantized: false }) {
+                // Too small to be worth compressing
+                assert_eq!(body[0], 0);
+                body = &body[1..];
+            }
+            assert_eq!(&body[4..8], b"GSNP");
+            let snapshot = StateSnapshotRef::read_as_root(body).unwrap();
+            let state = snapshot.players().unwrap().unwrap().get(0).unwrap().unwrap();
+            assert_eq!(state.id().unwrap(), "p1");
+            assert_eq!((state.x().unwrap(), state.y().unwrap()), (12.5, 300.0));
+            assert_eq!((state.vx().unwrap(), state.vy().unwrap()), (1.0, -2.0));
+        }
+    }
+
+    #[test]
+    fn compresses_large_flatbuffers_snapshots() {
+        let players = (0..100)
+            .map(|i| PlayerPosition { player_id: format!("player-{i}").into(), x: i as f32, y: 0.0, vx: 0.0, vy: 0.0 })
+            .collect();
+        let frame = OutboundFrame::snapshot(players, None);
+        let body = frame.body(Codec::FlatBuffers { zstd: true, quantized: false });
+        assert_eq!(body[0], FLAG_ZSTD);
+        let snapshot = zstd::bulk::decompress(&body[1..], 1 << 20).unwrap();
+        assert_eq!(snapshot, frame.body(Codec::FlatBuffers { zstd: false, quantized: false }));
+    }
+
+    #[test]
+    fn decodes_text_frames_as_json() {
+        for codec in Codec::SUPPORTED {
+            let message = codec.decode(&Message::Text(r#"{"type":"Ack","seq":3}"#.into())).unwrap();
+            assert!(matches!(message, ClientMessage::Ack { seq: 3 }));
+        }
+        assert!(Codec::Json.decode(&Message::Binary(Bytes::from_static(b"{}"))).is_err());
+    }
+
+    #[test]
+    fn decodes_binary_client_messages() {
+        let ack = game::ClientMessage { message: Some(game::client_message::Message::Ack(game::Ack { seq: 9 })) };
+        let message = Codec::Protobuf.decode(&Message::Binary(ack.encode_to_vec().into())).unwrap();
+        assert!(matches!(message, ClientMessage::Ack { seq: 9 }));
+
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&serde_json::json!({ "type": "Ack", "seq": 9 }), &mut cbor).unwrap();
+        let message = Codec::Cbor.decode(&Message::Binary(cbor.into())).unwrap();
+        assert!(matches!(message, ClientMessage::Ack { seq: 9 }));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::moderation::Moderator;
+use crate::tier::Role;
+use crate::GameServer;
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+// Chat messages starting with '/' are commands for the server and are never
+// broadcast as chat
+pub enum Command {
+    Kick { nickname: String, reason: Option<String> },
+    Mute { nickname: String, duration: Duration },
+    // `duration` None is permanent
+    Ban { nickname: String, duration: Option<Duration>, reason: String },
+    Announce { message: String },
+    // `interval` None turns slow mode off; `room` defaults to the sender's
+    SlowMode { room: Option<String>, interval: Option<Duration> },
+}
+
+impl Command {
+    // None when the message isn't a command; errors are usage help for the sender
+    pub fn parse(message: &str) -> Option<Result<Self>> {
+        let rest = message.strip_prefix('/')?;
+        let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let args = args.trim();
+        let (target, extra) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let extra = extra.trim();
+        Some(match name {
+            "kick" if !target.is_empty() => Ok(Command::Kick {
+                nickname: target.to_string(),
+                reason: (!extra.is_empty()).then(|| extra.to_string()),
+            }),
+            "kick" => Err(anyhow::anyhow!("Usage: /kick <nick> [reason]")),
+            "mute" => match parse_duration(extra) {
+                Some(duration) if !target.is_empty() => Ok(Command::Mute {
+                    nickname: target.to_string(),
+                    duration,
+                }),
+                _ => Err(anyhow::anyhow!("Usage: /mute <nick> <duration>, e.g. /mute bob 10m")),
+            },
+            "ban" => {
+                let (duration, reason) = extra.split_once(char::is_whitespace).unwrap_or((extra, ""));
+                let duration = match duration {
+                    "forever" => Some(None),
+                    duration => parse_duration(duration).map(Some),
+                };
+                match duration {
+                    Some(duration) if !target.is_empty() => Ok(Command::Ban {
+                        nickname: target.to_string(),
+                        duration,
+                        reason: match reason.trim() {
+                            "" => "No reason given".to_string(),
+                            reason => reason.to_string(),
+                        },
+                    }),
+                    _ => Err(anyhow::anyhow!("Usage: /ban <nick> <duration|forever> [reason], e.g. /ban bob 1d spamming")),
+                }
+            }
+            "announce" if !args.is_empty() => Ok(Command::Announce { message: args.to_string() }),
+            "announce" => Err(anyhow::anyhow!("Usage: /announce <message>")),
+            "slow" => {
+                let interval = match target {
+                    "off" => Some(None),
+                    interval => parse_duration(interval).map(Some),
+                };
+                match interval {
+                    Some(interval) => Ok(Command::SlowMode {
+                        room: (!extra.is_empty()).then(|| extra.to_string()),
+                        interval,
+                    }),
+                    None => Err(anyhow::anyhow!("Usage: /slow <duration|off> [room], e.g. /slow 30s")),
+                }
+            }
+            _ => Err(anyhow::anyhow!("Unknown command /{name}")),
+        })
+    }
+
+    pub fn required_role(&self) -> Role {
+        match self {
+            Command::Kick { .. } | Command::Mute { .. } | Command::Ban { .. } | Command::SlowMode { .. } => Role::Moderator,
+            Command::Announce { .. } => Role::Admin,
+        }
+    }
+
+    // Fills in `room` for commands that act on the sender's room by default
+    pub fn in_room(self, room: &str) -> Self {
+        match self {
+            Command::SlowMode { room: None, interval } => Command::SlowMode { room: Some(room.to_string()), interval },
+            command => command,
+        }
+    }
+
+    // Every command's effect is broadcast, so the sender only hears back
+    // about failures
+    pub fn run(self, server: &GameServer, by: &Moderator) -> Result<()> {
+        if by.role < self.required_role() {
+            bail!("You need the {} role to do that", self.required_role().name());
+        }
+        match self {
+            Command::Kick { nickname, reason } => server.kick(&server.find_player(&nickname)?, by, reason.as_deref()),
+            Command::Mute { nickname, duration } => server.mute(&server.find_player(&nickname)?, by, duration),
+            Command::Ban { nickname, duration, reason } => {
+                server.ban(&server.find_player(&nickname)?, by, duration, reason)
+            }
+            Command::Announce { message } => server.announce(message),
+            Command::SlowMode { room, interval } => {
+                server.set_slow_mode(room.as_deref().unwrap_or(crate::rooms::DEFAULT_ROOM), interval, by)
+            }
+        }
+    }
+}
+
+// Longest a mute or ban can last short of a permanent ban
+pub const MAX_DURATION: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+// `30s`, `10m`, `2h` or `1d`; a bare number is minutes. Anything longer than
+// MAX_DURATION is rejected.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, unit_secs) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1),
+        (i, 'm') => (&value[..i], 60),
+        (i, 'h') => (&value[..i], 60 * 60),
+        (i, 'd') => (&value[..i], 24 * 60 * 60),
+        _ => (value, 60),
+    };
+    let number: u64 = number.parse().ok().filter(|n| *n > 0)?;
+    Some(Duration::from_secs(number.checked_mul(unit_secs)?)).filter(|duration| *duration <= MAX_DURATION)
+}
+
+// Inverse of parse_duration, in the largest unit that divides it exactly
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    [(24 * 60 * 60, 'd'), (60 * 60, 'h'), (60, 'm')]
+        .into_iter()
+        .find(|(unit, _)| secs >= *unit && secs.is_multiple_of(*unit))
+        .map_or_else(|| format!("{secs}s"), |(unit, suffix)| format!("{}{suffix}", secs / unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(5 * 60)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_duration("7d"), Some(Duration::from_secs(7 * 24 * 60 * 60)));
+        // Minutes without a unit
+        assert_eq!(parse_duration("15"), Some(Duration::from_secs(15 * 60)));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for value in ["", "0", "0m", "-5m", "m", "5w", "1.5h", " 5m", "abc", "18446744073709551615d"] {
+            assert_eq!(parse_duration(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn caps_durations_at_max_duration() {
+        let days = MAX_DURATION.as_secs() / (24 * 60 * 60);
+        assert_eq!(parse_duration(&format!("{days}d")), Some(MAX_DURATION));
+        assert_eq!(parse_duration(&format!("{}d", days + 1)), None);
+        assert_eq!(parse_duration(&format!("{}s", MAX_DURATION.as_secs() + 1)), None);
+    }
+
+    #[test]
+    fn formats_durations_in_the_largest_exact_unit() {
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+        assert_eq!(format_duration(Duration::from_secs(120)), "2m");
+        assert_eq!(format_duration(Duration::from_secs(90 * 60)), "90m");
+        assert_eq!(format_duration(Duration::from_secs(3 * 60 * 60)), "3h");
+        assert_eq!(format_duration(Duration::from_secs(2 * 24 * 60 * 60)), "2d");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+    }
+
+    #[test]
+    fn format_duration_round_trips() {
+        for value in ["1s", "59s", "1m", "61m", "1h", "25h", "1d", "3650d"] {
+            let duration = parse_duration(value).unwrap();
+            assert_eq!(format_duration(duration), value);
+            assert_eq!(parse_duration(&format_duration(duration)), Some(duration));
+        }
+    }
+}
@@ -0,0 +1,726 @@
+use anyhow::{Context, Result};
+use rand::RngCore;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    // One JSON object per line, for Loki/ELK ingestion
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    Daily,
+    // Rotate once the current file exceeds this many bytes
+    Size(u64),
+}
+
+// Where accounts (and, for the database backends, chat) are persisted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    // The ACCOUNTS_PATH JSON file, or nothing when it's empty
+    Json,
+    // DATABASE_URL, with the `persistence` feature
+    Postgres,
+    // An embedded redb file at STORAGE_PATH, with the `embedded` feature
+    Redb,
+}
+
+// What happens when an account joins while it's already playing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateSessions {
+    // Both play, as two players
+    Allow,
+    // The new connection gets an Error
+    Reject,
+    // The earlier connection is closed with SessionTakenOver
+    TakeOver,
+}
+
+// Width and height of a world
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+// Headers sent with the frontend's files; each is left out when None
+#[derive(Clone, Debug, PartialEq)]
+pub struct SecurityHeaders {
+    pub content_security_policy: Option<String>,
+    pub content_type_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub strict_transport_security: Option<String>,
+}
+
+// Which other sites' pages may call us from the browser, see cors.rs
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorsPolicy {
+    // None allows any origin; an empty set, only our own pages
+    pub origins: Option<HashSet<String>>,
+    pub methods: String,
+    // Whether cookies are sent along; never with any origin allowed
+    pub credentials: bool,
+    pub max_age_secs: u64,
+}
+
+// Lets the page load its scripts (including the htmx bundle from unpkg and
+// the inline onclick handlers), compile the wasm module and connect to game
+// servers on other hosts, and nothing else
+const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+    script-src 'self' 'unsafe-inline' 'wasm-unsafe-eval' https://unpkg.com; \
+    style-src 'self' 'unsafe-inline'; img-src 'self' data: blob:; \
+    connect-src 'self' ws: wss:; worker-src 'self' blob:; \
+    object-src 'none'; base-uri 'self'; frame-ancestors 'none'";
+
+// Reverse proxies whose forwarding headers are believed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TrustedProxies {
+    // Connect directly; forwarding headers are ignored
+    #[default]
+    None,
+    // Every peer, for hosts like Railway where all traffic comes through a
+    // proxy whose addresses aren't fixed
+    All,
+    Only(HashSet<IpAddr>),
+}
+
+impl TrustedProxies {
+    pub fn trusts(&self, ip: IpAddr) -> bool {
+        match self {
+            TrustedProxies::None => false,
+            TrustedProxies::All => true,
+            TrustedProxies::Only(proxies) => proxies.contains(&ip),
+        }
+    }
+}
+
+// Settings from CONFIG_FILE, `KEY=value` lines like a .env file, which take
+// precedence over the environment. The file is read again on reload.
+struct Vars(HashMap<String, String>);
+
+impl Vars {
+    fn read() -> Result<Self> {
+        let Some(path) = std::env::var("CONFIG_FILE").ok().filter(|p| !p.is_empty()) else {
+            return Ok(Self(HashMap::new()));
+        };
+        let vars = dotenvy::from_path_iter(&path)
+            .and_then(|vars| vars.collect())
+            .with_context(|| format!("failed to read CONFIG_FILE {path}"))?;
+        Ok(Self(vars))
+    }
+
+    fn var(&self, name: &str) -> Result<String, std::env::VarError> {
+        match self.0.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => std::env::var(name),
+        }
+    }
+}
+
+// Credentials of an OAuth app registered with GitHub or Google
+#[derive(Clone, Debug)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+// A header's value from its env var: the default when unset, and None (so
+// it isn't sent) when set to empty
+fn header_var(vars: &Vars, name: &str, default: Option<&str>) -> Option<String> {
+    match vars.var(name) {
+        Ok(value) => Some(value.trim().to_string()).filter(|v| !v.is_empty()),
+        Err(_) => default.map(str::to_string),
+    }
+}
+
+fn oauth_client(vars: &Vars, prefix: &str) -> Option<OAuthClient> {
+    let client_id = vars.var(&format!("{prefix}_CLIENT_ID")).ok().filter(|v| !v.is_empty())?;
+    let client_secret = vars.var(&format!("{prefix}_CLIENT_SECRET")).ok().filter(|v| !v.is_empty())?;
+    Some(OAuthClient { client_id, client_secret })
+}
+
+// Comma-separated account usernames, e.g. `alice,github:1234`
+fn username_list(vars: &Vars, var: &str) -> HashSet<String> {
+    vars.var(var)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+// Server configuration, read at startup from env vars, CONFIG_FILE and CLI
+// args. See Config::reload for what can change while running.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub port: u16,
+    // Addresses to accept connections on; by default every IPv4 address on
+    // PORT. Ignored when systemd passes the sockets in.
+    pub listen: Vec<SocketAddr>,
+    // PEM certificate chain and private key; with both set connections are
+    // TLS, see tls.rs
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    // A separate listener for the admin routes that requires a client
+    // certificate issued by this CA; with it, the main port doesn't serve
+    // them at all
+    pub admin_port: Option<u16>,
+    pub admin_client_ca: Option<PathBuf>,
+    // Plain HTTP on 127.0.0.1 only, for `--prestop` to start a drain through,
+    // see lifecycle.rs
+    pub lifecycle_port: u16,
+    // Where the frontend is served from; unset serves ./dist, or the copy
+    // built into the binary with the embed-assets feature
+    pub static_path: Option<String>,
+    pub security_headers: SecurityHeaders,
+    pub cors: CorsPolicy,
+    // Other sites whose pages may open WebSockets to us; None allows any.
+    // See origin.rs.
+    pub allowed_origins: Option<HashSet<String>>,
+    pub admin_token: Option<String>,
+    // Name of the integration behind each API key, see integrations.rs
+    pub api_keys: HashMap<String, String>,
+    pub restore_path: Option<PathBuf>,
+    // Run as a Kubernetes preStop hook instead of serving, see lifecycle.rs
+    pub prestop: bool,
+    // Snapshot written on drain and restored on the next start, see handoff.rs
+    pub handoff_path: Option<PathBuf>,
+    // Where registered accounts are stored; empty ACCOUNTS_PATH keeps them in memory
+    pub accounts_path: Option<PathBuf>,
+    pub storage_backend: StorageBackend,
+    // Postgres connection string for the postgres backend
+    pub database_url: Option<String>,
+    // Database file for the redb backend
+    pub storage_path: PathBuf,
+    // Redis for cross-instance presence; disabled when unset
+    pub redis_url: Option<String>,
+    // Externally visible base URL, used to build OAuth redirect URIs
+    pub public_url: String,
+    // Account usernames (e.g. `alice` or `github:1234`) with the moderator role
+    pub moderators: HashSet<String>,
+    // Account usernames with the admin role
+    pub admins: HashSet<String>,
+    pub duplicate_sessions: DuplicateSessions,
+    // Whose X-Forwarded-For/Forwarded headers give the client's address
+    pub trusted_proxies: TrustedProxies,
+    // Key for signing session cookies; random per process unless SESSION_SECRET is set
+    pub session_secret: Vec<u8>,
+    // Registered players who rejoin within this many seconds resume where they
+    // left off; later they respawn at a random spot
+    pub resume_window_secs: u64,
+    // Rooms other than the lobby are destroyed after being empty this long
+    pub room_idle: Duration,
+    // Size of the default world, used without a WORLDS_FILE
+    pub world: WorldSize,
+    // JSON list of worlds, see maps.rs
+    pub worlds_file: Option<PathBuf>,
+    // Lua script defining event hooks, see scripting.rs
+    pub script_path: Option<PathBuf>,
+    // Directory of WebAssembly plugins, see plugins.rs
+    pub plugins_dir: Option<PathBuf>,
+    // Fuel each plugin call may burn before it's stopped
+    pub plugin_fuel: u64,
+    // Server-controlled players put in the lobby at startup
+    pub npc_count: usize,
+    // Players without a connection are removed once unseen this long
+    pub stale_player_secs: u64,
+    // Player limit of rooms other than the lobby
+    pub room_capacity: usize,
+    // Chat messages a minute allowed to guests and registered players; staff
+    // are unlimited
+    pub guest_chat_per_minute: u32,
+    pub registered_chat_per_minute: u32,
+    // Join attempts allowed per minute from one address and server-wide,
+    // 0 for no limit; see join_limit.rs
+    pub joins_per_minute_per_ip: u32,
+    pub joins_per_minute: u32,
+    // Load shedding, see overload.rs
+    pub overload_protection: bool,
+    // Shown to every player when they join
+    pub motd: Option<String>,
+    // Lowercase nicknames set aside for the account username given, or for
+    // staff when None
+    pub reserved_nicknames: HashMap<String, Option<String>>,
+    // Lowercase words masked in chat
+    pub word_filter: HashSet<String>,
+    // Expand `:smile:` shortcodes in chat before it's sent out, for clients
+    // that don't themselves
+    pub expand_emoji: bool,
+    // Channels clients may send Custom messages on; None allows any
+    pub custom_channels: Option<HashSet<String>>,
+    // Largest Custom payload accepted, as JSON
+    pub custom_max_bytes: usize,
+    pub github_oauth: Option<OAuthClient>,
+    pub google_oauth: Option<OAuthClient>,
+    pub snapshot_rate_hz: f64,
+    pub interest_near_radius: f32,
+    pub far_update_hz: f64,
+    // Chunks of a chunked world sent around the player, in each direction
+    pub chunk_radius: i32,
+    pub send_queue_capacity: usize,
+    // Messages each broadcast channel (server-wide, and one per room) holds
+    // for connections that haven't read them yet
+    pub broadcast_capacity: usize,
+    pub send_queue_drop_limit: u64,
+    // Retry-After sent to clients turned away or told to move while draining
+    pub drain_retry_after_secs: u64,
+    // How long a shutdown waits for connections to close before closing them
+    pub shutdown_grace_secs: u64,
+    pub log_format: LogFormat,
+    // File logging is enabled by setting a directory
+    pub log_dir: Option<PathBuf>,
+    pub log_rotation: LogRotation,
+    pub log_max_files: usize,
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        Ok(Self::from_vars(Vars::read()?))
+    }
+
+    // This config with the settings that can change at runtime read again:
+    // the chat and join rate limits, MOTD, word filter, emoji expansion and reserved
+    // nicknames. The rest takes a restart.
+    pub fn reload(&self) -> Result<Self> {
+        let fresh = Self::from_vars(Vars::read()?);
+        Ok(Self {
+            guest_chat_per_minute: fresh.guest_chat_per_minute,
+            registered_chat_per_minute: fresh.registered_chat_per_minute,
+            joins_per_minute_per_ip: fresh.joins_per_minute_per_ip,
+            joins_per_minute: fresh.joins_per_minute,
+            motd: fresh.motd,
+            word_filter: fresh.word_filter,
+            expand_emoji: fresh.expand_emoji,
+            custom_channels: fresh.custom_channels,
+            custom_max_bytes: fresh.custom_max_bytes,
+            reserved_nicknames: fresh.reserved_nicknames,
+            npc_count: fresh.npc_count,
+            ..self.clone()
+        })
+    }
+
+    fn from_vars(vars: Vars) -> Self {
+        let port = vars.var("PORT")
+            .unwrap_or_else(|_| "8080".to_string())
+            .parse::<u16>()
+            .unwrap_or(8080);
+
+        // e.g. `[::]:8080,0.0.0.0:8080`
+        let listen: Vec<SocketAddr> = vars.var("LISTEN")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|addr| addr.trim().parse().ok())
+            .collect();
+        let listen = if listen.is_empty() { vec![SocketAddr::from(([0, 0, 0, 0], port))] } else { listen };
+
+        let static_path = vars.var("STATIC_PATH").ok().filter(|p| !p.is_empty());
+
+        // An empty token would make the admin API trivially accessible
+        let admin_token = vars.var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+
+        // `name=key` pairs; entries without a key are ignored for the same reason
+        let api_keys = vars.var("API_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (name, key) = entry.split_once('=')?;
+                let (name, key) = (name.trim(), key.trim());
+                (!name.is_empty() && !key.is_empty()).then(|| (key.to_string(), name.to_string()))
+            })
+            .collect();
+
+        let snapshot_rate_hz = vars.var("SNAPSHOT_RATE_HZ")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|hz| *hz > 0.0 && hz.is_finite())
+            .unwrap_or(20.0);
+
+        let resume_window_secs = vars.var("RESUME_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(600);
+
+        let room_idle = vars.var("ROOM_IDLE_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map_or(Duration::from_secs(300), Duration::from_secs);
+
+        let dimension = |name: &str| {
+            vars.var(name)
+                .ok()
+                .and_then(|v| v.parse::<f32>().ok())
+                .filter(|size| *size > 0.0 && size.is_finite())
+        };
+        let world = WorldSize {
+            width: dimension("WORLD_WIDTH").unwrap_or(800.0),
+            height: dimension("WORLD_HEIGHT").unwrap_or(400.0),
+        };
+
+        let worlds_file = vars.var("WORLDS_FILE").ok().filter(|f| !f.is_empty()).map(PathBuf::from);
+
+        let script_path = vars.var("SCRIPT_PATH").ok().filter(|p| !p.is_empty()).map(PathBuf::from);
+
+        let tls_cert = vars.var("TLS_CERT").ok().filter(|p| !p.is_empty()).map(PathBuf::from);
+        let tls_key = vars.var("TLS_KEY").ok().filter(|p| !p.is_empty()).map(PathBuf::from);
+        let admin_port = vars.var("ADMIN_PORT").ok().and_then(|p| p.parse::<u16>().ok());
+        let admin_client_ca = vars.var("ADMIN_CLIENT_CA").ok().filter(|p| !p.is_empty()).map(PathBuf::from);
+        let lifecycle_port = vars.var("LIFECYCLE_PORT").ok().and_then(|p| p.parse::<u16>().ok()).unwrap_or(8081);
+
+        // HSTS only makes sense once browsers reach us over HTTPS, so it's
+        // only on by default when we terminate TLS ourselves
+        let security_headers = SecurityHeaders {
+            content_security_policy: header_var(&vars, "CONTENT_SECURITY_POLICY", Some(DEFAULT_CONTENT_SECURITY_POLICY)),
+            content_type_options: header_var(&vars, "X_CONTENT_TYPE_OPTIONS", Some("nosniff")),
+            referrer_policy: header_var(&vars, "REFERRER_POLICY", Some("strict-origin-when-cross-origin")),
+            strict_transport_security: header_var(
+                &vars,
+                "STRICT_TRANSPORT_SECURITY",
+                tls_cert.as_ref().and(tls_key.as_ref()).map(|_| "max-age=31536000"),
+            ),
+        };
+
+        // Origins are compared as browsers send them, e.g. `https://example.com`
+        let cors = CorsPolicy {
+            origins: match vars.var("CORS_ORIGINS").as_deref().map(str::trim) {
+                Ok("*") => None,
+                Ok(list) => Some(
+                    list.split(',')
+                        .map(|o| o.trim().trim_end_matches('/').to_string())
+                        .filter(|o| !o.is_empty())
+                        .collect(),
+                ),
+                Err(_) => Some(HashSet::new()),
+            },
+            methods: vars.var("CORS_METHODS")
+                .ok()
+                .filter(|m| !m.trim().is_empty())
+                .unwrap_or_else(|| "GET, POST, PUT, DELETE".to_string()),
+            credentials: vars.var("CORS_CREDENTIALS").is_ok_and(|v| v == "true" || v == "1"),
+            max_age_secs: vars.var("CORS_MAX_AGE")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(600),
+        };
+
+        let allowed_origins = match vars.var("ALLOWED_ORIGINS").as_deref().map(str::trim) {
+            Ok("*") => None,
+            list => Some(
+                list.unwrap_or_default()
+                    .split(',')
+                    .map(|o| o.trim().trim_end_matches('/').to_string())
+                    .filter(|o| !o.is_empty())
+                    .collect(),
+            ),
+        };
+
+        let handoff_path = vars.var("HANDOFF_PATH").ok().filter(|p| !p.is_empty()).map(PathBuf::from);
+
+        let plugins_dir = vars.var("PLUGINS_DIR").ok().filter(|d| !d.is_empty()).map(PathBuf::from);
+
+        let plugin_fuel = vars.var("PLUGIN_FUEL")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|&fuel| fuel > 0)
+            .unwrap_or(1_000_000);
+
+        let npc_count = vars.var("NPC_COUNT")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+
+        let stale_player_secs = vars.var("STALE_PLAYER_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(120);
+
+        let room_capacity = vars.var("ROOM_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(50);
+
+        let guest_chat_per_minute = vars.var("GUEST_CHAT_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(10);
+
+        let registered_chat_per_minute = vars.var("REGISTERED_CHAT_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(30);
+
+        let joins_per_minute_per_ip = vars.var("JOINS_PER_MINUTE_PER_IP")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(10);
+        let joins_per_minute = vars.var("JOINS_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(600);
+
+        let overload_protection = !vars.var("OVERLOAD_PROTECTION").is_ok_and(|v| v == "false" || v == "0");
+
+        let motd = vars.var("MOTD").ok().filter(|m| !m.trim().is_empty());
+
+        let word_filter = vars.var("WORD_FILTER")
+            .unwrap_or_default()
+            .split(',')
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        let custom_channels = vars.var("CUSTOM_CHANNELS")
+            .ok()
+            .map(|list| list.split(',').map(|channel| channel.trim().to_string()).filter(|c| !c.is_empty()).collect())
+            .filter(|channels: &HashSet<String>| !channels.is_empty());
+
+        let custom_max_bytes = vars.var("CUSTOM_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(4096);
+
+        // `nickname` for staff or `nickname=username` for one account
+        let reserved_nicknames = vars.var("RESERVED_NICKNAMES")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (nickname, owner) = match entry.split_once('=') {
+                    Some((nickname, owner)) => (nickname.trim(), Some(owner.trim().to_string())),
+                    None => (entry.trim(), None),
+                };
+                (!nickname.is_empty()).then(|| (nickname.to_lowercase(), owner.filter(|o| !o.is_empty())))
+            })
+            .collect();
+
+        let expand_emoji = vars.var("EXPAND_EMOJI").is_ok_and(|v| v == "true" || v == "1");
+
+        // Players farther away than this get position updates at far_update_hz
+        let interest_near_radius = vars.var("INTEREST_NEAR_RADIUS")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .unwrap_or(300.0);
+
+        let far_update_hz = vars.var("FAR_UPDATE_HZ")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|hz| *hz > 0.0 && hz.is_finite())
+            .unwrap_or(2.0);
+
+        let chunk_radius = vars.var("CHUNK_RADIUS")
+            .ok()
+            .and_then(|v| v.parse::<i32>().ok())
+            .filter(|radius| *radius >= 0)
+            .unwrap_or(1);
+
+        // Per-connection outgoing buffer; see queue::SendQueue for the policy
+        let send_queue_capacity = vars.var("SEND_QUEUE_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(256);
+
+        let broadcast_capacity = vars.var("BROADCAST_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(1000);
+
+        let send_queue_drop_limit = vars.var("SEND_QUEUE_DROP_LIMIT")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(100);
+
+        let drain_retry_after_secs = vars.var("DRAIN_RETRY_AFTER")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(5);
+
+        let shutdown_grace_secs = vars.var("SHUTDOWN_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(25);
+
+        let log_format = match vars.var("LOG_FORMAT").as_deref() {
+            Ok("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        };
+
+        let log_dir = vars.var("LOG_DIR").ok().filter(|d| !d.is_empty()).map(PathBuf::from);
+
+        let log_max_size_mb = vars.var("LOG_MAX_SIZE_MB")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|mb| *mb > 0)
+            .unwrap_or(100);
+
+        let log_rotation = match vars.var("LOG_ROTATION").as_deref() {
+            Ok("never") => LogRotation::Never,
+            Ok("hourly") => LogRotation::Hourly,
+            Ok("size") => LogRotation::Size(log_max_size_mb * 1024 * 1024),
+            _ => LogRotation::Daily,
+        };
+
+        let log_max_files = vars.var("LOG_MAX_FILES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(7);
+
+        let accounts_path = match vars.var("ACCOUNTS_PATH") {
+            Ok(path) if path.is_empty() => None,
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from("accounts.json")),
+        };
+
+        let database_url = vars.var("DATABASE_URL").ok().filter(|u| !u.is_empty());
+
+        // Defaults to Postgres when a database is configured
+        let storage_backend = match vars.var("STORAGE_BACKEND").as_deref() {
+            Ok("json") => StorageBackend::Json,
+            Ok("postgres") => StorageBackend::Postgres,
+            Ok("redb") => StorageBackend::Redb,
+            _ if database_url.is_some() => StorageBackend::Postgres,
+            _ => StorageBackend::Json,
+        };
+
+        let storage_path = vars.var("STORAGE_PATH")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("game.redb"));
+
+        let redis_url = vars.var("REDIS_URL").ok().filter(|u| !u.is_empty());
+
+        let public_url = vars.var("PUBLIC_URL")
+            .ok()
+            .filter(|u| !u.is_empty())
+            .map(|u| u.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| format!("http://localhost:{port}"));
+
+        let moderators = username_list(&vars, "MODERATORS");
+        let admins = username_list(&vars, "ADMINS");
+
+        // `*` or comma-separated addresses; unparseable ones are skipped
+        let trusted_proxies = match vars.var("TRUSTED_PROXIES").as_deref().map(str::trim) {
+            Ok("*") => TrustedProxies::All,
+            Ok(list) if !list.is_empty() => {
+                TrustedProxies::Only(list.split(',').filter_map(|ip| ip.trim().parse().ok()).collect())
+            }
+            _ => TrustedProxies::None,
+        };
+
+        let duplicate_sessions = match vars.var("DUPLICATE_SESSIONS").as_deref() {
+            Ok("reject") => DuplicateSessions::Reject,
+            Ok("takeover") => DuplicateSessions::TakeOver,
+            _ => DuplicateSessions::Allow,
+        };
+
+        let session_secret = vars.var("SESSION_SECRET")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(String::into_bytes)
+            .unwrap_or_else(|| {
+                let mut secret = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                secret
+            });
+
+        let mut restore_path = None;
+        let mut prestop = false;
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "--prestop" {
+                prestop = true;
+            } else if arg == "--restore" {
+                restore_path = args.next().map(PathBuf::from);
+            } else if let Some(path) = arg.strip_prefix("--restore=") {
+                restore_path = Some(PathBuf::from(path));
+            }
+        }
+
+        Self {
+            port,
+            listen,
+            tls_cert,
+            tls_key,
+            admin_port,
+            admin_client_ca,
+            lifecycle_port,
+            static_path,
+            security_headers,
+            cors,
+            allowed_origins,
+            admin_token,
+            api_keys,
+            restore_path,
+            prestop,
+            handoff_path,
+            accounts_path,
+            storage_backend,
+            database_url,
+            storage_path,
+            redis_url,
+            public_url,
+            moderators,
+            admins,
+            duplicate_sessions,
+            trusted_proxies,
+            session_secret,
+            resume_window_secs,
+            room_idle,
+            world,
+            worlds_file,
+            script_path,
+            plugins_dir,
+            plugin_fuel,
+            npc_count,
+            stale_player_secs,
+            room_capacity,
+            guest_chat_per_minute,
+            registered_chat_per_minute,
+            joins_per_minute_per_ip,
+            joins_per_minute,
+            overload_protection,
+            motd,
+            reserved_nicknames,
+            word_filter,
+            custom_channels,
+            custom_max_bytes,
+            expand_emoji,
+            github_oauth: oauth_client(&vars, "GITHUB"),
+            google_oauth: oauth_client(&vars, "GOOGLE"),
+            snapshot_rate_hz,
+            interest_near_radius,
+            far_update_hz,
+            chunk_radius,
+            send_queue_capacity,
+            broadcast_capacity,
+            send_queue_drop_limit,
+            drain_retry_after_secs,
+            shutdown_grace_secs,
+            log_format,
+            log_dir,
+            log_rotation,
+            log_max_files,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    // The defaults, with a fixed SESSION_SECRET of `secret`
+    pub fn for_tests(secret: &str) -> Self {
+        Self {
+            session_secret: secret.as_bytes().to_vec(),
+            ..Self::from_vars(Vars(HashMap::new()))
+        }
+    }
+}
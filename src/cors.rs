@@ -0,0 +1,56 @@
+// Cross-origin requests from browsers. Pages on the origins in CORS_ORIGINS
+// may call any route and read the response, preflights (OPTIONS with
+// Access-Control-Request-Method) included; everyone else gets no CORS
+// headers, so the browser keeps the response from them. WebSockets aren't
+// covered: browsers don't apply CORS to them.
+use crate::config::CorsPolicy;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Method, Request, Response, StatusCode};
+
+pub fn is_preflight<B>(req: &Request<B>) -> bool {
+    req.method() == Method::OPTIONS && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+// What Access-Control-Allow-Origin should say to `origin`, if anything
+fn allowed_origin(policy: &CorsPolicy, origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+    let origin = origin?;
+    match &policy.origins {
+        None => Some(HeaderValue::from_static("*")),
+        Some(origins) => origins.contains(origin.to_str().ok()?).then(|| origin.clone()),
+    }
+}
+
+// Adds the CORS headers for a request from `origin` to its response
+pub fn apply(policy: &CorsPolicy, origin: Option<&HeaderValue>, headers: &mut HeaderMap) {
+    // Caches must not hand one origin's answer to another
+    if policy.origins.is_some() {
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+    let Some(allowed) = allowed_origin(policy, origin) else {
+        return;
+    };
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed);
+    if policy.credentials && policy.origins.is_some() {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+    }
+}
+
+pub fn preflight<B>(policy: &CorsPolicy, req: &Request<B>) -> Response<Full<Bytes>> {
+    let mut response = Response::builder().status(StatusCode::NO_CONTENT);
+    let headers = response.headers_mut().unwrap();
+    let origin = req.headers().get(header::ORIGIN);
+    apply(policy, origin, headers);
+    if allowed_origin(policy, origin).is_some() {
+        if let Ok(methods) = HeaderValue::from_str(&policy.methods) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        // Whatever headers the page asks to send, e.g. Authorization
+        if let Some(requested) = req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+        }
+        headers.insert(header::ACCESS_CONTROL_MAX_AGE, policy.max_age_secs.into());
+    }
+    response.body(Full::new(Bytes::new())).unwrap()
+}
@@ -0,0 +1,37 @@
+// Custom messages: app-defined JSON the server passes along without looking
+// inside. A client's Custom goes to everyone in its room, itself included,
+// or to one player in it, as a Custom naming the sender. CUSTOM_CHANNELS
+// limits the channels clients may use.
+use crate::frame::OutboundFrame;
+use crate::{GameServer, ServerMessage};
+use anyhow::{bail, Result};
+
+impl GameServer {
+    pub fn send_custom(&self, player_id: &str, channel: String, payload: serde_json::Value, to: Option<&str>) -> Result<()> {
+        let config = self.config();
+        if let Some(channels) = &config.custom_channels {
+            if !channels.contains(&channel) {
+                bail!("Unknown channel {}", channel);
+            }
+        }
+        if serde_json::to_vec(&payload)?.len() > config.custom_max_bytes {
+            bail!("Custom payload over {} bytes", config.custom_max_bytes);
+        }
+        let Some(room) = self.players.get(player_id).map(|player| player.room.clone()) else {
+            return Ok(());
+        };
+        let message = ServerMessage::Custom { channel, payload, from: Some(player_id.into()) };
+        match to {
+            Some(target) => {
+                let in_room = self.players.get(target).is_some_and(|player| player.room == room);
+                let connection = self.connections.get(target).filter(|_| in_room);
+                let Some(connection) = connection else {
+                    bail!("No such player");
+                };
+                let _ = connection.queue.push(OutboundFrame::new(message));
+                Ok(())
+            }
+            None => self.broadcast_to_room(&room, message),
+        }
+    }
+}
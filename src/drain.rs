@@ -0,0 +1,90 @@
+// Drain mode, for deploys: once an admin starts it, the game state is handed
+// off (see handoff.rs), new /ws upgrades are turned away with 503 and a
+// Retry-After, everyone connected is sent Draining so their client can move
+// to another instance, and the admin event stream hears Drained when the last
+// connection closes. Deploy scripts start it with POST /admin/drain and poll
+// GET /admin/drain until `drained` before restarting.
+use crate::admin_ws::AdminEvent;
+use crate::{GameServer, ServerMessage};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tracing::{error, info};
+
+#[derive(Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    // Open /ws connections, joined or not
+    open: AtomicUsize,
+}
+
+// Held for as long as a /ws connection is open
+pub struct OpenConnection {
+    server: GameServer,
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        let open = self.server.drain.open.fetch_sub(1, Ordering::SeqCst) - 1;
+        if open == 0 && self.server.is_draining() {
+            self.server.drained();
+        }
+    }
+}
+
+impl GameServer {
+    pub fn is_draining(&self) -> bool {
+        self.drain.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.drain.open.load(Ordering::SeqCst)
+    }
+
+    pub fn open_connection(&self) -> OpenConnection {
+        self.drain.open.fetch_add(1, Ordering::SeqCst);
+        OpenConnection { server: self.clone() }
+    }
+
+    // Stops taking new connections and tells everyone connected to move.
+    // Starting it again while draining does nothing.
+    pub fn start_drain(&self) {
+        if self.drain.draining.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Err(e) = self.write_handoff() {
+            error!("Failed to write handoff: {:#}", e);
+        }
+        let connections = self.open_connections();
+        info!(connections, "Draining");
+        self.events.emit(AdminEvent::Draining { connections });
+        let _ = self.broadcast_message(ServerMessage::Draining {
+            retry_after_secs: self.config().drain_retry_after_secs,
+        });
+        if connections == 0 {
+            self.drained();
+        }
+    }
+
+    // Takes new connections again; clients already told to move aren't
+    // told otherwise
+    pub fn stop_drain(&self) {
+        if self.drain.draining.swap(false, Ordering::SeqCst) {
+            info!("Stopped draining");
+        }
+    }
+
+    fn drained(&self) {
+        info!("Drained: no connections left");
+        self.events.emit(AdminEvent::Drained);
+    }
+
+    // What GET /admin/drain reports
+    pub fn drain_status(&self) -> serde_json::Value {
+        let connections = self.open_connections();
+        let draining = self.is_draining();
+        serde_json::json!({
+            "draining": draining,
+            "connections": connections,
+            "drained": draining && connections == 0,
+        })
+    }
+}
@@ -0,0 +1,201 @@
+use crate::accounts::Account;
+use crate::audit::AuditEntry;
+use crate::bans::Ban;
+use crate::reports::Report;
+use crate::storage::{ArchivedChat, ChatQuery, ChatRecord, Storage};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition};
+use std::path::Path;
+use std::sync::Arc;
+
+// Accounts keyed by lowercase username, values are JSON
+const ACCOUNTS: TableDefinition<&str, &[u8]> = TableDefinition::new("accounts");
+// Chat history in send order, values are JSON
+const CHAT: TableDefinition<u64, &[u8]> = TableDefinition::new("chat");
+// Bans keyed by id, values are JSON
+const BANS: TableDefinition<u64, &[u8]> = TableDefinition::new("bans");
+// Player reports keyed by id, values are JSON
+const REPORTS: TableDefinition<u64, &[u8]> = TableDefinition::new("reports");
+// Audit log entries keyed by id, values are JSON
+const AUDIT: TableDefinition<u64, &[u8]> = TableDefinition::new("audit");
+
+// Accounts, chat history, bans, reports and the audit log in a single redb
+// file, for self-hosting without a database server. redb is synchronous, so
+// every call runs on the blocking pool.
+pub struct RedbStorage {
+    db: Arc<Database>,
+}
+
+impl RedbStorage {
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = Database::create(path).with_context(|| format!("failed to open {}", path.display()))?;
+        // Create the tables up front so reads on a fresh file don't fail
+        let txn = db.begin_write()?;
+        txn.open_table(ACCOUNTS)?;
+        txn.open_table(CHAT)?;
+        txn.open_table(BANS)?;
+        txn.open_table(REPORTS)?;
+        txn.open_table(AUDIT)?;
+        txn.commit()?;
+        Ok(Self { db: Arc::new(db) })
+    }
+
+    async fn blocking<T: Send + 'static>(&self, f: impl FnOnce(&Database) -> Result<T> + Send + 'static) -> Result<T> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || f(&db)).await?
+    }
+}
+
+#[async_trait]
+impl Storage for RedbStorage {
+    async fn load_accounts(&self) -> Result<Vec<Account>> {
+        self.blocking(|db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(ACCOUNTS)?;
+            let mut accounts = Vec::new();
+            for entry in table.iter()? {
+                let (_, value) = entry?;
+                accounts.push(serde_json::from_slice(value.value())?);
+            }
+            Ok(accounts)
+        })
+        .await
+    }
+
+    async fn save_account(&self, account: &Account) -> Result<()> {
+        let key = account.username.to_lowercase();
+        let value = serde_json::to_vec(account)?;
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            txn.open_table(ACCOUNTS)?.insert(key.as_str(), value.as_slice())?;
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn append_chat(&self, chat: &ChatRecord) -> Result<()> {
+        let value = serde_json::to_vec(chat)?;
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            {
+                let mut table = txn.open_table(CHAT)?;
+                let next = table.last()?.map_or(0, |(id, _)| id.value() + 1);
+                table.insert(next, value.as_slice())?;
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn query_chat(&self, query: &ChatQuery) -> Result<Option<Vec<ArchivedChat>>> {
+        let query = query.clone();
+        self.blocking(move |db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(CHAT)?;
+            let mut chat = Vec::new();
+            for entry in table.range(..query.before.unwrap_or(u64::MAX))?.rev() {
+                let (id, value) = entry?;
+                let record: ChatRecord = serde_json::from_slice(value.value())?;
+                if query.matches(id.value(), &record) {
+                    chat.push(ArchivedChat { id: id.value(), record });
+                    if chat.len() == query.limit {
+                        break;
+                    }
+                }
+            }
+            Ok(Some(chat))
+        })
+        .await
+    }
+
+    async fn load_bans(&self) -> Result<Vec<Ban>> {
+        self.blocking(|db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(BANS)?;
+            let mut bans = Vec::new();
+            for entry in table.iter()? {
+                let (_, value) = entry?;
+                bans.push(serde_json::from_slice(value.value())?);
+            }
+            Ok(bans)
+        })
+        .await
+    }
+
+    async fn save_ban(&self, ban: &Ban) -> Result<()> {
+        let id = ban.id;
+        let value = serde_json::to_vec(ban)?;
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            txn.open_table(BANS)?.insert(id, value.as_slice())?;
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_ban(&self, id: u64) -> Result<()> {
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            txn.open_table(BANS)?.remove(id)?;
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load_reports(&self) -> Result<Vec<Report>> {
+        self.blocking(|db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(REPORTS)?;
+            let mut reports = Vec::new();
+            for entry in table.iter()? {
+                let (_, value) = entry?;
+                reports.push(serde_json::from_slice(value.value())?);
+            }
+            Ok(reports)
+        })
+        .await
+    }
+
+    async fn save_report(&self, report: &Report) -> Result<()> {
+        let id = report.id;
+        let value = serde_json::to_vec(report)?;
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            txn.open_table(REPORTS)?.insert(id, value.as_slice())?;
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load_audit(&self) -> Result<Vec<AuditEntry>> {
+        self.blocking(|db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(AUDIT)?;
+            let mut audit = Vec::new();
+            for entry in table.iter()? {
+                let (_, value) = entry?;
+                audit.push(serde_json::from_slice(value.value())?);
+            }
+            Ok(audit)
+        })
+        .await
+    }
+
+    async fn save_audit(&self, entry: &AuditEntry) -> Result<()> {
+        let id = entry.id;
+        let value = serde_json::to_vec(entry)?;
+        self.blocking(move |db| {
+            let txn = db.begin_write()?;
+            txn.open_table(AUDIT)?.insert(id, value.as_slice())?;
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
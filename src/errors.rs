@@ -0,0 +1,61 @@
+// Problems the page may want to show the player, passed to the callback
+// registered with on_error as `{kind, message, recoverable}`, and announced
+// to screen readers. They are still logged to the console where they happen.
+use crate::announce;
+use std::cell::RefCell;
+use wasm_bindgen::JsValue;
+
+thread_local! {
+    static ON_ERROR: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Copy)]
+pub enum ErrorKind {
+    // A server message the client couldn't read
+    Parse,
+    // A message that couldn't be sent
+    Send,
+    // An Error message from the server
+    Server,
+    // The connection failed or closed
+    Connection,
+}
+
+impl ErrorKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Parse => "parse",
+            ErrorKind::Send => "send",
+            ErrorKind::Server => "server",
+            ErrorKind::Connection => "connection",
+        }
+    }
+}
+
+pub fn set_callback(callback: Option<js_sys::Function>) {
+    ON_ERROR.with(|on_error| *on_error.borrow_mut() = callback);
+}
+
+// `recoverable` tells the page whether the game carries on (true) or the
+// player needs to reconnect (false)
+pub fn report(kind: ErrorKind, message: &str, recoverable: bool) {
+    report_with_code(kind, message, None, recoverable);
+}
+
+// With the server's machine-readable `code` for the failure, passed on as a
+// `code` property
+pub fn report_with_code(kind: ErrorKind, message: &str, code: Option<&str>, recoverable: bool) {
+    announce::assertive(message);
+    // Cloned out first, so the callback may register another one
+    let Some(callback) = ON_ERROR.with(|on_error| on_error.borrow().clone()) else {
+        return;
+    };
+    let error = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&error, &JsValue::from_str("kind"), &JsValue::from_str(kind.as_str()));
+    let _ = js_sys::Reflect::set(&error, &JsValue::from_str("message"), &JsValue::from_str(message));
+    let _ = js_sys::Reflect::set(&error, &JsValue::from_str("recoverable"), &JsValue::from_bool(recoverable));
+    if let Some(code) = code {
+        let _ = js_sys::Reflect::set(&error, &JsValue::from_str("code"), &JsValue::from_str(code));
+    }
+    let _ = callback.call1(&JsValue::NULL, &error);
+}
@@ -0,0 +1,51 @@
+use crate::codec::Codec;
+use crate::maps::WorldMap;
+use crate::{PlayerPosition, ServerMessage};
+use bytes::Bytes;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+// A server message on its way out to one or more connections. Each wire
+// encoding of the message is serialized at most once, by whichever connection
+// needs it first, and the resulting buffer is shared by every other
+// connection using the same encoding. The per-connection `seq` is not part of
+// the shared body; the codec prepends it when the frame is written.
+pub struct OutboundFrame {
+    pub message: ServerMessage,
+    // For the delivery latency metric
+    pub created: Instant,
+    // For a Snapshot, the (width, height) of the world its positions are in,
+    // which quantized encodings scale them against
+    pub bounds: Option<(f32, f32)>,
+    bodies: [OnceLock<Bytes>; Codec::BODY_KINDS],
+}
+
+impl OutboundFrame {
+    pub fn new(message: ServerMessage) -> Arc<Self> {
+        Arc::new(Self {
+            message,
+            created: Instant::now(),
+            bounds: None,
+            bodies: Default::default(),
+        })
+    }
+
+    pub fn snapshot(players: Vec<PlayerPosition>, bounds: Option<(f32, f32)>) -> Arc<Self> {
+        Arc::new(Self {
+            message: ServerMessage::Snapshot { players },
+            created: Instant::now(),
+            bounds,
+            bodies: Default::default(),
+        })
+    }
+
+    pub fn world_snapshot(players: Vec<PlayerPosition>, world: &WorldMap) -> Arc<Self> {
+        Self::snapshot(players, Some((world.width, world.height)))
+    }
+
+    pub fn body(&self, codec: Codec) -> Bytes {
+        self.bodies[codec.body_kind(&self.message)]
+            .get_or_init(|| codec.encode_body(&self.message, self.bounds))
+            .clone()
+    }
+}
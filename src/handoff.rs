@@ -0,0 +1,65 @@
+// Handing the world over to the next process on a restart, for deploys
+// without a database. With HANDOFF_PATH set, starting a drain writes a
+// snapshot there, and the next process to start restores it and deletes the
+// file. Registered players who log in again take their place back; until then
+// nobody sees them, and the ones who don't come back are swept. Guests have
+// nothing to prove who they were, so they start afresh.
+use crate::snapshot::{self, GameSnapshot};
+use crate::rooms::DEFAULT_ROOM;
+use crate::{GameServer, Player};
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::info;
+
+impl GameServer {
+    pub fn write_handoff(&self) -> Result<()> {
+        let Some(path) = self.config().handoff_path.clone() else {
+            return Ok(());
+        };
+        let snapshot = self.snapshot();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string(&snapshot)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("failed to write {}", path.display()))?;
+        info!(players = snapshot.players.len(), rooms = snapshot.rooms.len(), path = %path.display(), "Wrote handoff");
+        Ok(())
+    }
+
+    // Puts a player joining as `username` where the account's restored
+    // player was, if that room is still there and has space for them. The
+    // restored player is only forgotten once the join goes through, see
+    // forget_restored.
+    pub fn reclaim_restored(&self, username: &str, player: &mut Player) {
+        let Some(restored) = self.restored.get(&username.to_lowercase()).map(|p| p.clone()) else {
+            return;
+        };
+        if &*restored.room != DEFAULT_ROOM {
+            let Some(room) = self.rooms.get_claimed(&restored.room) else {
+                return;
+            };
+            if self.check_capacity(&room).is_err() {
+                return;
+            }
+        }
+        info!(player_id = %restored.id, account = %username, "Reclaimed restored player");
+        player.x = restored.x;
+        player.y = restored.y;
+        player.room = restored.room;
+    }
+
+    // The account has joined again, so its restored player is done with
+    pub fn forget_restored(&self, username: &str) {
+        self.restored.remove(&username.to_lowercase());
+    }
+}
+
+// The snapshot a previous process handed off, if there is one. It's deleted
+// once read so it's never restored twice.
+pub fn take(path: &Path) -> Result<Option<GameSnapshot>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let snapshot = snapshot::load_snapshot(path)?;
+    std::fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))?;
+    Ok(Some(snapshot))
+}
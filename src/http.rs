@@ -0,0 +1,48 @@
+use crate::config::SecurityHeaders;
+use anyhow::{Context, Result};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::http::response;
+use hyper::{Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+
+// Largest request body accepted by the JSON endpoints
+const MAX_BODY_BYTES: usize = 16 * 1024;
+
+pub fn json_response(status: StatusCode, body: String) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
+
+// Adds the configured security headers, see SecurityHeaders
+pub fn with_security_headers(mut response: response::Builder, headers: &SecurityHeaders) -> response::Builder {
+    let SecurityHeaders { content_security_policy, content_type_options, referrer_policy, strict_transport_security } = headers;
+    for (name, value) in [
+        ("content-security-policy", content_security_policy),
+        ("x-content-type-options", content_type_options),
+        ("referrer-policy", referrer_policy),
+        ("strict-transport-security", strict_transport_security),
+    ] {
+        if let Some(value) = value {
+            response = response.header(name, value);
+        }
+    }
+    response
+}
+
+pub fn error_response(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    json_response(status, serde_json::json!({ "error": message }).to_string())
+}
+
+pub async fn read_json<T: DeserializeOwned>(req: Request<Incoming>) -> Result<T> {
+    let body = Limited::new(req.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+        .map_err(|e| anyhow::anyhow!("{e}"))
+        .context("failed to read request body")?
+        .to_bytes();
+    Ok(serde_json::from_slice(&body)?)
+}
@@ -0,0 +1,118 @@
+// Locale and wording for the text the client puts on the page itself. Times
+// are formatted for the locale given to set_locale, or else the browser's
+// language. Strings come from a table of English defaults that set_strings
+// can override key by key; `{name}` placeholders are filled in by `text`.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wasm_bindgen::{JsCast, JsValue};
+
+const FALLBACK_LOCALE: &str = "en-US";
+
+const STRINGS: &[(&str, &str)] = &[
+    ("achievement.mine", "🏆 Achievement unlocked: {name} - {description}"),
+    ("achievement.other", "🏆 {nickname} unlocked {name}"),
+    ("leaderboard.entry", "{nickname} - {score}"),
+    ("room.entry", "{name}{lock} - {players} players, {mode}"),
+    ("notification.mention", "{nickname} mentioned you"),
+    ("announce.joined", "{nickname} joined"),
+    ("announce.left", "{nickname} left"),
+    ("announce.mention", "{nickname} mentioned you: {message}"),
+    ("players.me", "{nickname} (you)"),
+    ("players.idle", "{nickname}, away"),
+    ("slowmode.on", "Slow mode is on: one message every {secs}s"),
+    ("slowmode.changed", "{by} turned on slow mode: one message every {secs}s"),
+    ("slowmode.off", "{by} turned off slow mode"),
+    ("drain.notice", "The server is restarting; reconnect in {secs}s"),
+];
+
+thread_local! {
+    static LOCALE: RefCell<Option<String>> = const { RefCell::new(None) };
+    static OVERRIDES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+// The locale set explicitly, else navigator.language
+pub fn locale() -> String {
+    LOCALE
+        .with(|locale| locale.borrow().clone())
+        .or_else(|| web_sys::window()?.navigator().language().and_then(|language| canonical(&language)))
+        .unwrap_or_else(|| FALLBACK_LOCALE.to_string())
+}
+
+// Returns false, leaving the locale as it was, for an invalid language tag
+pub fn set_locale(locale: Option<&str>) -> bool {
+    let locale = match locale {
+        Some(tag) => match canonical(tag) {
+            Some(locale) => Some(locale),
+            None => return false,
+        },
+        None => None,
+    };
+    LOCALE.with(|current| *current.borrow_mut() = locale);
+    true
+}
+
+// `tag` as Intl spells it, or None if it isn't a valid language tag. Called
+// through Reflect since Intl throws on invalid tags.
+fn canonical(tag: &str) -> Option<String> {
+    let intl = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("Intl")).ok()?;
+    let get = js_sys::Reflect::get(&intl, &JsValue::from_str("getCanonicalLocales"))
+        .ok()?
+        .dyn_into::<js_sys::Function>()
+        .ok()?;
+    let locales = js_sys::Reflect::apply(&get, &intl, &js_sys::Array::of1(&JsValue::from_str(tag))).ok()?;
+    js_sys::Array::from(&locales).get(0).as_string()
+}
+
+// The time of day of `ms` since the Unix epoch
+pub fn time(ms: f64) -> String {
+    let date = js_sys::Date::new(&JsValue::from_f64(ms));
+    date.to_locale_time_string(&locale()).as_string().unwrap_or_default()
+}
+
+// Replaces the wording of the given keys; keys left out keep their current
+// text, and an empty string restores the default
+pub fn set_strings(strings: HashMap<String, String>) {
+    OVERRIDES.with(|overrides| {
+        let mut overrides = overrides.borrow_mut();
+        for (key, value) in strings {
+            if value.is_empty() {
+                overrides.remove(&key);
+            } else {
+                overrides.insert(key, value);
+            }
+        }
+    });
+}
+
+// The wording for `key` with each `{name}` replaced by its value in `args`
+pub fn text(key: &str, args: &[(&str, &str)]) -> String {
+    let template = OVERRIDES.with(|overrides| overrides.borrow().get(key).cloned()).unwrap_or_else(|| {
+        STRINGS
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map_or(key, |(_, default)| default)
+            .to_string()
+    });
+    // One pass, so braces in the values (nicknames, say) are left alone
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let value = placeholder
+            .find('}')
+            .and_then(|end| Some((end, args.iter().find(|(name, _)| *name == &placeholder[1..end])?.1)));
+        match value {
+            Some((end, value)) => {
+                text.push_str(value);
+                rest = &placeholder[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
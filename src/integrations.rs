@@ -0,0 +1,87 @@
+// Messages pushed in over HTTP by things that aren't players: deploy
+// notifications, stream overlays, chat bridges. They authenticate with a
+// bearer token, one of API_KEYS or a staff token as for /admin, and post
+// under the key's name or the staff member's nickname.
+use crate::admin;
+use crate::moderation::system_message;
+use crate::storage::ChatRecord;
+use crate::tier::Role;
+use crate::{emoji, logging, wordfilter, GameServer, ServerMessage};
+use anyhow::{bail, Result};
+use hyper::body::Incoming;
+use hyper::Request;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+// Who's posting, from the request's bearer token. Cookies don't count, so a
+// page on another site can't post as a signed-in moderator.
+pub fn integration_caller(req: &Request<Incoming>, server: &GameServer) -> Option<String> {
+    let token = req
+        .headers()
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))?;
+    if let Some(name) = server.config().api_keys.get(token) {
+        return Some(name.clone());
+    }
+    admin::token_caller(token, server)
+        .filter(|caller| caller.role >= Role::Moderator)
+        .map(|caller| caller.name)
+}
+
+impl GameServer {
+    // An announcement to one room, or everyone when `room` is None
+    pub fn announce_from(&self, sender: &str, room: Option<&str>, message: String) -> Result<()> {
+        if message.trim().is_empty() {
+            bail!("message is empty");
+        }
+        match room {
+            None => {
+                info!(%sender, "Posting announcement");
+                self.announce(message)
+            }
+            Some(room) => {
+                if self.rooms.get(room).is_none() {
+                    bail!("No room named {}", room);
+                }
+                info!(%sender, %room, %message, "Announcement");
+                self.broadcast_to_room(room, system_message(format!("📢 {message}")))
+            }
+        }
+    }
+
+    // A chat message in `room` from `sender`, filtered like players' chat and
+    // kept in the chat history. It has no player behind it, so the sender's ID
+    // is `integration:<sender>`.
+    pub fn post_chat(&self, sender: &str, room: &str, message: String) -> Result<()> {
+        if message.trim().is_empty() {
+            bail!("message is empty");
+        }
+        if self.rooms.get(room).is_none() {
+            bail!("No room named {}", room);
+        }
+        let config = self.config();
+        let mut message = wordfilter::censor(&message, &config.word_filter);
+        if config.expand_emoji {
+            message = emoji::expand(&message);
+        }
+        let player_id = format!("integration:{sender}");
+        info!(target: logging::CHAT_TARGET, %player_id, nickname = %sender, "{}", message);
+        self.metrics.chat_messages.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        self.storage.append_chat(ChatRecord {
+            player_id: player_id.clone(),
+            nickname: sender.to_string(),
+            message: message.clone(),
+            sent_at: timestamp,
+            room: room.to_string(),
+        });
+        let chat = ServerMessage::ChatMessage {
+            player_id: player_id.into(),
+            nickname: sender.to_string(),
+            message,
+            timestamp,
+        };
+        self.broadcast_to_room(room, chat)
+    }
+}
@@ -0,0 +1,144 @@
+use crate::frame::OutboundFrame;
+use crate::maps::{ChunkId, WorldMap};
+use crate::{PlayerId, PlayerPosition, ServerMessage};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Per-subscriber interest management for position updates. Players within
+// `near_radius` of the viewer are forwarded at the full tick rate; farther
+// players are held back and forwarded at most once per `far_interval`, always
+// with their latest position.
+//
+// In a chunked world it also decides which chunks the subscriber has: those
+// within `chunk_radius` chunks of its player, loaded and unloaded as it moves.
+pub struct InterestFilter {
+    near_radius: f32,
+    far_interval: Duration,
+    last_sent: HashMap<PlayerId, Instant>,
+    pending: HashMap<PlayerId, PlayerPosition>,
+    chunk_radius: i32,
+    // The world the loaded chunks belong to, and the chunks
+    chunk_world: Option<Arc<WorldMap>>,
+    loaded_chunks: HashSet<ChunkId>,
+    // The bounds of the last snapshot, for the held-back updates flushed
+    // without one
+    bounds: Option<(f32, f32)>,
+}
+
+impl InterestFilter {
+    pub fn new(near_radius: f32, far_update_hz: f64, chunk_radius: i32) -> Self {
+        Self {
+            near_radius,
+            far_interval: Duration::from_secs_f64(1.0 / far_update_hz),
+            last_sent: HashMap::new(),
+            pending: HashMap::new(),
+            chunk_radius,
+            chunk_world: None,
+            loaded_chunks: HashSet::new(),
+            bounds: None,
+        }
+    }
+
+    pub fn far_interval(&self) -> Duration {
+        self.far_interval
+    }
+
+    // Filters a broadcast for this subscriber. `viewer` is the subscriber's own
+    // player (id and position) once it has joined; spectators see everything.
+    // Unfiltered frames are passed through so their encoded body stays shared.
+    pub fn apply(&mut self, viewer: Option<(&str, f32, f32)>, frame: Arc<OutboundFrame>) -> Option<Arc<OutboundFrame>> {
+        match &frame.message {
+            ServerMessage::Snapshot { players } => {
+                let Some((viewer_id, vx, vy)) = viewer else {
+                    return Some(frame);
+                };
+                self.bounds = frame.bounds;
+                let now = Instant::now();
+                let mut visible = Vec::with_capacity(players.len());
+                let mut held_back = false;
+                for position in players {
+                    let near = &*position.player_id == viewer_id
+                        || (position.x - vx).hypot(position.y - vy) <= self.near_radius;
+                    if near || self.is_due(&position.player_id, now) {
+                        self.pending.remove(&position.player_id);
+                        self.last_sent.insert(position.player_id.clone(), now);
+                        visible.push(position.clone());
+                    } else {
+                        self.pending.insert(position.player_id.clone(), position.clone());
+                        held_back = true;
+                    }
+                }
+                let due = self.take_due(now);
+                if !held_back && due.is_empty() {
+                    return Some(frame);
+                }
+                visible.extend(due);
+                (!visible.is_empty()).then(|| OutboundFrame::snapshot(visible, frame.bounds))
+            }
+            ServerMessage::PlayerLeft { player_id } => {
+                self.last_sent.remove(player_id);
+                self.pending.remove(player_id);
+                Some(frame)
+            }
+            // The client drops every chunk for the new world
+            ServerMessage::WorldChanged { .. } => {
+                self.chunk_world = None;
+                self.loaded_chunks.clear();
+                Some(frame)
+            }
+            _ => Some(frame),
+        }
+    }
+
+    // Held-back far updates whose interval has elapsed, for subscribers that
+    // receive no further snapshots to piggyback on
+    pub fn flush(&mut self) -> Option<Arc<OutboundFrame>> {
+        let players = self.take_due(Instant::now());
+        (!players.is_empty()).then(|| OutboundFrame::snapshot(players, self.bounds))
+    }
+
+    // ChunkUnloaded and ChunkLoaded for the chunks that went out of or came
+    // into range of a player at (x, y) in `world` since the last call
+    pub fn stream_chunks(&mut self, world: &Arc<WorldMap>, x: f32, y: f32) -> Vec<Arc<OutboundFrame>> {
+        if !self.chunk_world.as_ref().is_some_and(|loaded| Arc::ptr_eq(loaded, world)) {
+            self.chunk_world = Some(Arc::clone(world));
+            self.loaded_chunks.clear();
+        }
+        let wanted = world.chunks_around(x, y, self.chunk_radius);
+        if wanted == self.loaded_chunks {
+            return Vec::new();
+        }
+        let unloaded = self
+            .loaded_chunks
+            .difference(&wanted)
+            .map(|&(column, row)| OutboundFrame::new(ServerMessage::ChunkUnloaded { column, row }));
+        let loaded = wanted.difference(&self.loaded_chunks).map(|&(column, row)| {
+            OutboundFrame::new(ServerMessage::ChunkLoaded { column, row, obstacles: world.chunk((column, row)) })
+        });
+        let frames = unloaded.chain(loaded).collect();
+        self.loaded_chunks = wanted;
+        frames
+    }
+
+    fn is_due(&self, player_id: &str, now: Instant) -> bool {
+        self.last_sent
+            .get(player_id)
+            .is_none_or(|sent| now.duration_since(*sent) >= self.far_interval)
+    }
+
+    fn take_due(&mut self, now: Instant) -> Vec<PlayerPosition> {
+        let due: Vec<PlayerId> = self
+            .pending
+            .keys()
+            .filter(|id| self.is_due(id, now))
+            .cloned()
+            .collect();
+        due.into_iter()
+            .filter_map(|id| {
+                self.last_sent.insert(id.clone(), now);
+                self.pending.remove(&id)
+            })
+            .collect()
+    }
+}
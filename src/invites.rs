@@ -0,0 +1,114 @@
+use crate::config::Config;
+use crate::session;
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose, Engine as _};
+use hmac::Mac;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const MAX_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// An invitation into a room, optionally for a specific nickname. Invites are
+// stateless: the token is the invite itself plus an HMAC under SESSION_SECRET,
+// so it can't be forged or edited, and it stops working once it expires.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Invite {
+    pub room: String,
+    // Guests joining with the invite play under this nickname
+    pub nickname: Option<String>,
+    pub expires_at: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+impl Invite {
+    pub fn new(room: String, nickname: Option<String>, ttl: Duration) -> Self {
+        Self {
+            room,
+            nickname,
+            expires_at: now_secs() + ttl.as_secs(),
+        }
+    }
+
+    // `<payload>.<tag>`, both base64url
+    pub fn sign(&self, config: &Config) -> String {
+        let payload = general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap());
+        let tag = session::signature(&config.session_secret, &payload).finalize().into_bytes();
+        format!("{payload}.{}", general_purpose::URL_SAFE_NO_PAD.encode(tag))
+    }
+
+    pub fn verify(config: &Config, token: &str) -> Result<Self> {
+        let invite = token.rsplit_once('.').and_then(|(payload, tag)| {
+            let tag = general_purpose::URL_SAFE_NO_PAD.decode(tag).ok()?;
+            session::signature(&config.session_secret, payload).verify_slice(&tag).ok()?;
+            serde_json::from_slice::<Invite>(&general_purpose::URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+        });
+        let Some(invite) = invite else {
+            bail!("Invalid invite link");
+        };
+        if invite.expires_at <= now_secs() {
+            bail!("This invite link has expired");
+        }
+        Ok(invite)
+    }
+
+    // The game page with the invite token attached
+    pub fn url(&self, config: &Config) -> String {
+        format!("{}/?invite={}", config.public_url, self.sign(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_its_own_signature() {
+        let config = Config::for_tests("secret");
+        let token = Invite::new("den".into(), Some("alice".into()), DEFAULT_TTL).sign(&config);
+        let invite = Invite::verify(&config, &token).unwrap();
+        assert_eq!(invite.room, "den");
+        assert_eq!(invite.nickname.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn rejects_a_tampered_invite() {
+        let config = Config::for_tests("secret");
+        let token = Invite::new("den".into(), None, DEFAULT_TTL).sign(&config);
+        let (_, tag) = token.rsplit_once('.').unwrap();
+
+        // Someone else's room under this invite's tag
+        let other = Invite::new("vault".into(), None, DEFAULT_TTL).sign(&config);
+        let (payload, _) = other.rsplit_once('.').unwrap();
+        let err = Invite::verify(&config, &format!("{payload}.{tag}")).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid invite link");
+
+        // A hand-edited payload keeping the original tag
+        let forged = general_purpose::URL_SAFE_NO_PAD.encode(br#"{"room":"vault","nickname":null,"expires_at":18446744073709551615}"#);
+        assert!(Invite::verify(&config, &format!("{forged}.{tag}")).is_err());
+
+        for token in ["", ".", "garbage", &token.replace('.', "")] {
+            assert!(Invite::verify(&config, token).is_err(), "{token:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_an_invite_signed_with_another_secret() {
+        let token = Invite::new("den".into(), None, DEFAULT_TTL).sign(&Config::for_tests("other"));
+        assert!(Invite::verify(&Config::for_tests("secret"), &token).is_err());
+    }
+
+    #[test]
+    fn rejects_an_expired_invite() {
+        let config = Config::for_tests("secret");
+        let expired = Invite { room: "den".into(), nickname: None, expires_at: now_secs() - 1 };
+        let err = Invite::verify(&config, &expired.sign(&config)).unwrap_err();
+        assert_eq!(err.to_string(), "This invite link has expired");
+
+        let instant = Invite::new("den".into(), None, Duration::ZERO);
+        assert!(Invite::verify(&config, &instant.sign(&config)).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+// Limits on Join attempts, so a script can't churn through players and flood
+// every room with PlayerJoined. Each address gets JOINS_PER_MINUTE_PER_IP, and
+// going over it locks the address out for a penalty that doubles each time it
+// happens again within STRIKE_MEMORY. JOINS_PER_MINUTE caps the server as a
+// whole, without penalties, for floods spread over many addresses.
+use crate::GameServer;
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// WebSocket close code for connections whose Join was turned away
+pub const JOIN_LIMITED_CLOSE_CODE: u16 = 4029;
+const WINDOW: Duration = Duration::from_secs(60);
+// The first lockout, and the longest
+const BASE_PENALTY: Duration = Duration::from_secs(30);
+const MAX_PENALTY: Duration = Duration::from_secs(60 * 60);
+// How long an address's past lockouts count against it
+const STRIKE_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Default)]
+struct AddressJoins {
+    attempts: VecDeque<Instant>,
+    strikes: u32,
+    last_strike: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Default)]
+pub struct JoinLimits {
+    by_address: DashMap<IpAddr, AddressJoins>,
+    all: Mutex<VecDeque<Instant>>,
+}
+
+// Drops attempts older than a minute and says whether another fits under `limit`
+fn has_room(attempts: &mut VecDeque<Instant>, now: Instant, limit: u32) -> bool {
+    while attempts.front().is_some_and(|t| now.duration_since(*t) >= WINDOW) {
+        attempts.pop_front();
+    }
+    attempts.len() < limit as usize
+}
+
+impl GameServer {
+    // Counts a Join from `ip`, or says how long it must wait
+    pub fn try_join(&self, ip: IpAddr) -> Result<(), Duration> {
+        let config = self.config();
+        let result = self.join_limits.try_join(ip, config.joins_per_minute_per_ip, config.joins_per_minute);
+        if let Err(wait) = result {
+            self.metrics.joins_limited.fetch_add(1, Ordering::Relaxed);
+            info!(wait_secs = wait.as_secs(), "Turned away a Join over the rate limit");
+        }
+        result
+    }
+}
+
+impl JoinLimits {
+    // Records a Join from `ip` if the limits allow it; otherwise how long to
+    // wait before trying again. A limit of 0 is no limit.
+    pub fn try_join(&self, ip: IpAddr, per_ip: u32, overall: u32) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut address = self.by_address.entry(ip).or_default();
+        if let Some(until) = address.locked_until.filter(|until| *until > now) {
+            return Err(until - now);
+        }
+        if address.last_strike.is_some_and(|t| now.duration_since(t) >= STRIKE_MEMORY) {
+            address.strikes = 0;
+        }
+        if per_ip > 0 && !has_room(&mut address.attempts, now, per_ip) {
+            let penalty = BASE_PENALTY.saturating_mul(1 << address.strikes.min(16)).min(MAX_PENALTY);
+            address.strikes += 1;
+            address.last_strike = Some(now);
+            address.locked_until = Some(now + penalty);
+            address.attempts.clear();
+            return Err(penalty);
+        }
+        let mut all = self.all.lock().unwrap();
+        if overall > 0 && !has_room(&mut all, now, overall) {
+            return Err(all.front().map_or(WINDOW, |oldest| WINDOW - now.duration_since(*oldest)));
+        }
+        all.push_back(now);
+        address.attempts.push_back(now);
+        Ok(())
+    }
+
+    // Forgets addresses with nothing left to hold against them
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.by_address.retain(|_, address| {
+            address.attempts.back().is_some_and(|t| now.duration_since(*t) < WINDOW)
+                || address.last_strike.is_some_and(|t| now.duration_since(t) < STRIKE_MEMORY)
+        });
+    }
+}
@@ -0,0 +1,90 @@
+// Which keys do what, by KeyboardEvent.code ("KeyW", "ArrowUp", "Enter").
+// The page looks up each key press with `action`; set_binding remaps an
+// action, and the whole map is remembered in localStorage.
+use crate::prefs::{self, Pref};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+pub const ACTIONS: &[&str] = &["move_up", "move_down", "move_left", "move_right", "focus_chat", "emote"];
+
+const DEFAULTS: &[(&str, &[&str])] = &[
+    ("move_up", &["KeyW", "ArrowUp"]),
+    ("move_down", &["KeyS", "ArrowDown"]),
+    ("move_left", &["KeyA", "ArrowLeft"]),
+    ("move_right", &["KeyD", "ArrowRight"]),
+    ("focus_chat", &["Enter"]),
+    ("emote", &["KeyE"]),
+];
+
+// Action name to the keys bound to it
+pub type Bindings = BTreeMap<String, Vec<String>>;
+
+thread_local! {
+    static BINDINGS: RefCell<Option<Bindings>> = const { RefCell::new(None) };
+}
+
+fn defaults() -> Bindings {
+    DEFAULTS
+        .iter()
+        .map(|(action, keys)| (action.to_string(), keys.iter().map(|key| key.to_string()).collect()))
+        .collect()
+}
+
+// The saved bindings over the defaults, so actions added since still get keys
+fn load() -> Bindings {
+    let mut bindings = defaults();
+    let saved = prefs::load(Pref::KeyBindings).and_then(|json| serde_json::from_str::<Bindings>(&json).ok());
+    for (action, keys) in saved.into_iter().flatten() {
+        if ACTIONS.contains(&action.as_str()) {
+            bindings.insert(action, keys);
+        }
+    }
+    bindings
+}
+
+fn with_bindings<T>(f: impl FnOnce(&mut Bindings) -> T) -> T {
+    BINDINGS.with(|bindings| f(bindings.borrow_mut().get_or_insert_with(load)))
+}
+
+fn save(bindings: &Bindings) {
+    if let Ok(json) = serde_json::to_string(bindings) {
+        prefs::save(Pref::KeyBindings, &json);
+    }
+}
+
+pub fn bindings() -> Bindings {
+    with_bindings(|bindings| bindings.clone())
+}
+
+// The action `key` is bound to, if any
+pub fn action(key: &str) -> Option<String> {
+    with_bindings(|bindings| {
+        bindings
+            .iter()
+            .find(|(_, keys)| keys.iter().any(|bound| bound == key))
+            .map(|(action, _)| action.clone())
+    })
+}
+
+// Binds `key` to `action` alone, taking it away from any other action.
+// Errors for an action that doesn't exist.
+pub fn set_binding(action: &str, key: &str) -> Result<(), String> {
+    if !ACTIONS.contains(&action) {
+        return Err(format!("unknown action {}, expected one of {}", action, ACTIONS.join(", ")));
+    }
+    with_bindings(|bindings| {
+        for keys in bindings.values_mut() {
+            keys.retain(|bound| bound != key);
+        }
+        bindings.insert(action.to_string(), vec![key.to_string()]);
+        save(bindings);
+    });
+    Ok(())
+}
+
+pub fn reset() {
+    with_bindings(|bindings| {
+        *bindings = defaults();
+        save(bindings);
+    });
+}
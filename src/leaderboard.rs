@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Registered players earn a point per this many pixels travelled, plus the
+// points of every achievement they unlock. Guests aren't ranked.
+pub const PX_PER_POINT: f64 = 100.0;
+pub const LEADERBOARD_SIZE: usize = 10;
+// Changes are broadcast at most this often
+pub const BROADCAST_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    // Since midnight UTC
+    Daily,
+    AllTime,
+}
+
+impl Period {
+    pub fn from_query(value: &str) -> Option<Self> {
+        match value {
+            "daily" => Some(Period::Daily),
+            "alltime" => Some(Period::AllTime),
+            _ => None,
+        }
+    }
+}
+
+// Points earned on one UTC day
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct DailyScore {
+    // Days since the Unix epoch
+    pub day: u64,
+    pub score: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LeaderboardEntry {
+    pub rank: u32,
+    pub nickname: String,
+    pub score: u64,
+}
+
+pub fn today() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() / (24 * 60 * 60)
+}
+
+// Points for travelling from `before` to `after` total pixels, so fractions
+// of a point carry over between flushes
+pub fn distance_points(before: f64, after: f64) -> u64 {
+    ((after / PX_PER_POINT).floor() - (before / PX_PER_POINT).floor()).max(0.0) as u64
+}
+
+// Ranks `scores` from highest to lowest, ties broken by nickname
+pub fn rank(mut scores: Vec<(String, u64)>) -> Vec<LeaderboardEntry> {
+    scores.retain(|(_, score)| *score > 0);
+    scores.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    scores
+        .into_iter()
+        .take(LEADERBOARD_SIZE)
+        .enumerate()
+        .map(|(i, (nickname, score))| LeaderboardEntry {
+            rank: i as u32 + 1,
+            nickname,
+            score,
+        })
+        .collect()
+}
@@ -1,10 +1,40 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use web_sys::*;
 use wasm_bindgen::closure::Closure;
 
+mod announce;
+mod bubbles;
+mod camera;
+mod channels;
+mod chatlog;
+mod clock;
+mod emoji;
+mod errors;
+mod i18n;
+mod keys;
+mod links;
+mod minimap;
+mod notify;
+mod prefs;
+mod sprites;
+mod state_fb;
+mod state_reader;
+mod systems;
+mod tabs;
+mod unread;
+mod visibility;
+mod world;
+
+use clock::ClockSync;
+use prefs::Pref;
+use state_reader::{dequantize, root_as_state_snapshot, state_snapshot_buffer_has_identifier};
+use errors::ErrorKind;
+use world::{Map, Obstacle, Position, Profile, World};
+
 // Import console functions
 #[wasm_bindgen]
 extern "C" {
@@ -30,16 +60,91 @@ struct Player {
     y: f32,
     color: String,
     last_seen: u64,
+    #[serde(default)]
+    idle: bool,
+    #[serde(default)]
+    sprite: u32,
+}
+
+// What the server lets this player do; only what the client acts on
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Capabilities {
+    #[serde(default)]
+    choose_color: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PlayerStats {
+    distance: f64,
+    messages_sent: u64,
+    session_secs: u64,
+    joins: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct LeaderboardEntry {
+    rank: u32,
+    nickname: String,
+    score: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RoomInfo {
+    name: String,
+    players: usize,
+    capacity: Option<usize>,
+    mode: String,
+    locked: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PlayerPosition {
+    player_id: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    vx: f32,
+    #[serde(default)]
+    vy: f32,
 }
 
 // Client -> Server messages
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 enum ClientMessage {
-    Join { nickname: Option<String> },
+    Join {
+        nickname: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        invite: Option<String>,
+    },
     Move { x: f32, y: f32 },
     Chat { message: String },
     ChangeNick { nickname: String },
+    ChangeColor { color: String },
+    Ack { seq: u64 },
+    Resync,
+    TimeSyncRequest { client_time: f64 },
+    RequestStats {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        player_id: Option<String>,
+    },
+    Report { target_id: String, reason: String },
+    JoinRoom {
+        room: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+    },
+    ListRooms,
+    SetIdle { idle: bool },
+    ChangeSprite { sprite: u32 },
+    Custom {
+        channel: String,
+        payload: serde_json::Value,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+    },
 }
 
 // Server -> Client messages
@@ -48,83 +153,268 @@ enum ClientMessage {
 enum ServerMessage {
     Welcome { 
         your_id: String, 
-        players: Vec<Player> 
+        players: Vec<Player>,
+        #[serde(default)]
+        capabilities: Capabilities,
+        #[serde(default)]
+        room: String,
+        #[serde(default)]
+        world: Map,
+        #[serde(default)]
+        slow_mode_secs: u64,
     },
     PlayerJoined { player: Player },
     PlayerLeft { player_id: String },
-    PlayerMoved { 
-        player_id: String, 
-        x: f32, 
-        y: f32 
-    },
+    Snapshot { players: Vec<PlayerPosition> },
     ChatMessage { 
         player_id: String, 
         nickname: String, 
         message: String, 
         timestamp: u64 
     },
-    Error { message: String },
+    Error {
+        message: String,
+        #[serde(default)]
+        code: Option<String>,
+    },
+    TimeSyncResponse {
+        client_time: f64,
+        server_receive_time: f64,
+        server_send_time: f64,
+    },
+    StatsSnapshot {
+        player_id: String,
+        session: PlayerStats,
+        lifetime: Option<PlayerStats>,
+    },
+    AchievementUnlocked {
+        player_id: String,
+        nickname: String,
+        achievement: String,
+        name: String,
+        description: String,
+    },
+    Leaderboard {
+        daily: Vec<LeaderboardEntry>,
+        alltime: Vec<LeaderboardEntry>,
+    },
+    SystemMessage { message: String, timestamp: u64 },
+    RoomList { rooms: Vec<RoomInfo> },
+    PlayerIdle { player_id: String, idle: bool },
+    AppearanceChanged { player_id: String, color: String, avatar: u32 },
+    NickChanged { player_id: String, nickname: String },
+    SessionTakenOver,
+    WorldChanged { world: Map },
+    ChunkLoaded { column: i32, row: i32, obstacles: Vec<Obstacle> },
+    ChunkUnloaded { column: i32, row: i32 },
+    SlowModeChanged { interval_secs: u64, by: String },
+    Custom {
+        channel: String,
+        payload: serde_json::Value,
+        #[serde(default)]
+        from: Option<String>,
+    },
+    Draining { retry_after_secs: u64 },
+}
+
+// Every server message carries a per-connection sequence number
+#[derive(Deserialize, Debug)]
+struct Sequenced {
+    #[serde(default)]
+    seq: u64,
+    #[serde(flatten)]
+    message: ServerMessage,
 }
 
-static mut GAME_CLIENT: Option<GameClient> = None;
+// Acks are cumulative, so one every few messages is enough
+const ACK_INTERVAL: u64 = 32;
+// The room everyone joins first
+pub(crate) const DEFAULT_ROOM: &str = "lobby";
+// Moves go out at most this many times a second, the latest one winning
+const DEFAULT_MOVE_RATE_HZ: f64 = 30.0;
+// and at most once a second while the tab is hidden
+const HIDDEN_MOVE_INTERVAL_MS: f64 = 1000.0;
+// Moves closer than this to the last one sent aren't worth sending
+const MOVE_EPSILON: f32 = 0.5;
+// A connect that hasn't been welcomed by then is given up on
+const CONNECT_TIMEOUT_MS: i32 = 10_000;
+// Asked for in this order; servers without quantized positions or zstd fall
+// back to the next
+const SUBPROTOCOLS: [&str; 3] = ["flatbuffers-zstd-q16", "flatbuffers-zstd", "flatbuffers"];
+// Subprotocols whose binary frames start with a flags byte
+const ZSTD_SUBPROTOCOL_PREFIX: &str = "flatbuffers-zstd";
+// Set in a flatbuffers-zstd frame's flags byte when the rest is compressed
+const FLAG_ZSTD: u8 = 0x01;
+
+// The promise handed out by connect_to_game: resolved with the player id by
+// the first Welcome, or rejected if the join fails, the socket closes or
+// CONNECT_TIMEOUT_MS passes first. Whichever comes first wins.
+#[derive(Clone, Default)]
+struct PendingConnect(Rc<RefCell<Option<(js_sys::Function, js_sys::Function)>>>);
+
+impl PendingConnect {
+    fn promise(&self) -> js_sys::Promise {
+        js_sys::Promise::new(&mut |resolve, reject| {
+            *self.0.borrow_mut() = Some((resolve, reject));
+        })
+    }
+
+    fn is_pending(&self) -> bool {
+        self.0.borrow().is_some()
+    }
+
+    // A spectator has no player id, so it's resolved with null once connected
+    fn resolve_spectating(&self) {
+        if let Some((resolve, _)) = self.0.borrow_mut().take() {
+            let _ = resolve.call1(&JsValue::NULL, &JsValue::NULL);
+        }
+    }
+
+    fn resolve(&self, player_id: &str) {
+        if let Some((resolve, _)) = self.0.borrow_mut().take() {
+            let _ = resolve.call1(&JsValue::NULL, &JsValue::from_str(player_id));
+        }
+    }
+
+    fn reject(&self, reason: &str) {
+        if let Some((_, reject)) = self.0.borrow_mut().take() {
+            let _ = reject.call1(&JsValue::NULL, &js_sys::Error::new(reason));
+        }
+    }
+}
+
+thread_local! {
+    static GAME_CLIENT: RefCell<Option<GameClient>> = const { RefCell::new(None) };
+}
 
 struct GameClient {
     websocket: Option<WebSocket>,
-    players: Arc<Mutex<HashMap<String, Player>>>,
-    my_player_id: Option<String>,
-    _on_message_closure: Option<Closure<dyn FnMut(MessageEvent)>>,
-    _on_close_closure: Option<Closure<dyn FnMut(CloseEvent)>>,
-    _on_error_closure: Option<Closure<dyn FnMut(Event)>>,
+    world: Arc<Mutex<World>>,
+    clock: Arc<Mutex<ClockSync>>,
+    // The room a JoinRoom was sent for, until its Welcome arrives
+    joining_room: Arc<Mutex<Option<String>>>,
+    // The room of the latest Welcome
+    room: Arc<Mutex<String>>,
+    // The WebSocket URL of the last connect
+    server_url: String,
+    rendering: bool,
+    // Minimum time between Moves, see set_move_rate_hz
+    move_interval_ms: Cell<f64>,
+    // When the last Move went out and where to, and the latest one held back
+    // since, with whether a timer is set to send it
+    last_move_ms: Cell<f64>,
+    last_sent_move: Cell<Option<(f32, f32)>>,
+    pending_move: Cell<Option<(f32, f32)>>,
+    move_flush_scheduled: Cell<bool>,
+    // The promise of the connect in progress, rejected if it's torn down first
+    pending: PendingConnect,
+    handlers: Option<SocketHandlers>,
+    // Watching without joining, because another tab is playing
+    spectating: bool,
+}
+
+// The callbacks set on the current WebSocket. They're dropped when the socket
+// is torn down, after being unset so none of them runs once freed.
+struct SocketHandlers {
+    _on_open: Closure<dyn FnMut(Event)>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+    _on_error: Closure<dyn FnMut(Event)>,
 }
 
 impl GameClient {
     fn new() -> Self {
         Self {
             websocket: None,
-            players: Arc::new(Mutex::new(HashMap::new())),
-            my_player_id: None,
-            _on_message_closure: None,
-            _on_close_closure: None,
-            _on_error_closure: None,
+            world: Arc::new(Mutex::new(World::default())),
+            clock: Arc::new(Mutex::new(ClockSync::default())),
+            joining_room: Arc::new(Mutex::new(None)),
+            room: Arc::new(Mutex::new(DEFAULT_ROOM.to_string())),
+            server_url: String::new(),
+            rendering: false,
+            move_interval_ms: Cell::new(1000.0 / DEFAULT_MOVE_RATE_HZ),
+            last_move_ms: Cell::new(0.0),
+            last_sent_move: Cell::new(None),
+            pending_move: Cell::new(None),
+            move_flush_scheduled: Cell::new(false),
+            pending: PendingConnect::default(),
+            handlers: None,
+            spectating: false,
+        }
+    }
+
+    // Closes the socket, if any, and forgets everything learned over it, so
+    // the next connect starts from scratch. Settings made from the page
+    // (move rate, smoothing, zoom, the sprite sheet) are kept.
+    fn disconnect(&mut self) {
+        if let Some(ws) = self.websocket.take() {
+            ws.set_onopen(None);
+            ws.set_onmessage(None);
+            ws.set_onclose(None);
+            ws.set_onerror(None);
+            let _ = ws.close();
         }
+        self.handlers = None;
+        self.pending.reject("Disconnected");
+        if !self.spectating {
+            tabs::release();
+        }
+        self.spectating = false;
+        if let Ok(mut world) = self.world.lock() {
+            world.clear();
+        }
+        if let Ok(mut clock) = self.clock.lock() {
+            *clock = ClockSync::default();
+        }
+        if let Ok(mut joining) = self.joining_room.lock() {
+            *joining = None;
+        }
+        if let Ok(mut room) = self.room.lock() {
+            *room = DEFAULT_ROOM.to_string();
+        }
+        self.server_url.clear();
+        self.last_move_ms.set(0.0);
+        self.last_sent_move.set(None);
+        self.pending_move.set(None);
     }
 
-    fn connect(&mut self, nickname: Option<String>) -> Result<(), JsValue> {
+    // Replaces any earlier connection. A spectating connection never sends
+    // its Join.
+    fn connect(&mut self, options: ConnectOptions, pending: PendingConnect, spectate: bool) -> Result<(), JsValue> {
+        self.disconnect();
         console_log!("Connecting to WebSocket server...");
-        
-        // Create WebSocket connection - connect to /ws endpoint on same port
-        let ws_url = if let Some(window) = web_sys::window() {
-            let location = window.location();
-            if let (Ok(hostname), Ok(protocol)) = (location.hostname(), location.protocol()) {
-                if hostname == "localhost" || hostname == "127.0.0.1" {
-                    "ws://127.0.0.1:8080/ws".to_string()
-                } else {
-                    // For production, use same domain with /ws endpoint
-                    let ws_protocol = if protocol == "https:" { "wss" } else { "ws" };
-                    let port = if let Ok(port_str) = location.port() {
-                        if !port_str.is_empty() {
-                            format!(":{}", port_str)
-                        } else {
-                            "".to_string()
-                        }
-                    } else {
-                        "".to_string()
-                    };
-                    format!("{}://{}{}/ws", ws_protocol, hostname, port)
-                }
-            } else {
-                "ws://127.0.0.1:8080/ws".to_string()
-            }
-        } else {
-            "ws://127.0.0.1:8080/ws".to_string()
-        };
-        
+        let ConnectOptions { server_url, nickname, token, .. } = options;
+        let ws_url = websocket_url(server_url.as_deref())?;
+        self.server_url = ws_url.clone();
+        self.pending = pending.clone();
+
         console_log!("Connecting to WebSocket: {}", ws_url);
-        let ws = WebSocket::new(&ws_url)?;
+        // Position updates arrive as FlatBuffers frames, everything else as JSON
+        let protocols: js_sys::Array = SUBPROTOCOLS.iter().map(|&protocol| JsValue::from(protocol)).collect();
+        let ws = WebSocket::new_with_str_sequence(&ws_url, &protocols)?;
         ws.set_binary_type(BinaryType::Arraybuffer);
 
-        let players_clone = Arc::clone(&self.players);
-        let mut my_id = None;
+        let timed_out = pending.clone();
+        let on_timeout = Closure::once_into_js(move || {
+            // Only a problem if the Welcome hasn't settled the promise yet
+            if timed_out.is_pending() {
+                timed_out.reject("Timed out waiting for the server");
+                errors::report(ErrorKind::Connection, "Timed out waiting for the server", false);
+            }
+        });
+        if let Some(window) = web_sys::window() {
+            window.set_timeout_with_callback_and_timeout_and_arguments_0(on_timeout.unchecked_ref(), CONNECT_TIMEOUT_MS)?;
+        }
+        let welcomed = pending.clone();
+        let closed = pending;
+
+        let world_clone = Arc::clone(&self.world);
+        let clock_clone = Arc::clone(&self.clock);
+        let joining_clone = Arc::clone(&self.joining_room);
+        let room_clone = Arc::clone(&self.room);
+        let ws_for_acks = ws.clone();
+        let mut last_seq = 0;
+        let mut restored = false;
         
         // Handle incoming messages
         let on_message = Closure::wrap(Box::new(move |e: MessageEvent| {
@@ -132,51 +422,188 @@ impl GameClient {
                 let message_str = String::from(text);
                 console_log!("Received: {}", message_str);
                 
-                if let Ok(server_msg) = serde_json::from_str::<ServerMessage>(&message_str) {
-                    if let Ok(mut players) = players_clone.lock() {
+                if let Ok(Sequenced { seq, message: server_msg }) = serde_json::from_str::<Sequenced>(&message_str) {
+                    let is_welcome = matches!(server_msg, ServerMessage::Welcome { .. });
+                    track_sequence(&ws_for_acks, &mut last_seq, seq, is_welcome);
+                    if let Ok(mut world) = world_clone.lock() {
                         match server_msg {
-                            ServerMessage::Welcome { your_id, players: player_list } => {
+                            ServerMessage::Welcome { your_id, players: player_list, capabilities, room, world: map, slow_mode_secs } => {
                                 console_log!("Welcome! Your ID: {}", your_id);
-                                my_id = Some(your_id);
-                                players.clear();
+                                welcomed.resolve(&your_id);
+                                if let Ok(mut current) = room_clone.lock() {
+                                    *current = room;
+                                }
+                                world.clear();
+                                world.set_map(map);
                                 for player in player_list {
-                                    players.insert(player.id.clone(), player);
+                                    world.spawn_player(player);
+                                }
+                                world.me = world.entity(&your_id);
+                                if slow_mode_secs > 0 {
+                                    let secs = slow_mode_secs.to_string();
+                                    chatlog::add_system(&i18n::text("slowmode.on", &[("secs", secs.as_str())]), now_secs());
+                                }
+                                send_time_sync_request(&ws_for_acks);
+                                if let Some(room) = joining_clone.lock().ok().and_then(|mut joining| joining.take()) {
+                                    prefs::save(Pref::Room, &room);
+                                    links::show_room(&room);
+                                }
+                                // Put back what was saved last visit, once per connection
+                                if !restored {
+                                    restored = true;
+                                    if let Some(room) = restore_prefs(&ws_for_acks, world.my_profile(), &capabilities) {
+                                        if let Ok(mut joining) = joining_clone.lock() {
+                                            *joining = Some(room);
+                                        }
+                                    }
                                 }
-                                update_ui();
                             }
                             ServerMessage::PlayerJoined { player } => {
                                 console_log!("Player joined: {}", player.nickname);
-                                players.insert(player.id.clone(), player);
-                                update_ui();
+                                announce::polite(&i18n::text("announce.joined", &[("nickname", &player.nickname)]));
+                                world.spawn_player(player);
                             }
                             ServerMessage::PlayerLeft { player_id } => {
                                 console_log!("Player left: {}", player_id);
-                                players.remove(&player_id);
-                                update_ui();
+                                if let Some(profile) = world.profile(&player_id) {
+                                    announce::polite(&i18n::text("announce.left", &[("nickname", &profile.nickname)]));
+                                }
+                                world.despawn_player(&player_id);
                             }
-                            ServerMessage::PlayerMoved { player_id, x, y } => {
-                                if let Some(player) = players.get_mut(&player_id) {
-                                    player.x = x;
-                                    player.y = y;
+                            ServerMessage::Snapshot { players: positions } => {
+                                let now = js_sys::Date::now();
+                                for position in positions {
+                                    world.move_player(
+                                        &position.player_id,
+                                        Position { x: position.x, y: position.y },
+                                        (position.vx, position.vy),
+                                        now,
+                                    );
                                 }
-                                update_ui();
                             }
                             ServerMessage::ChatMessage { player_id, nickname, message, timestamp } => {
-                                add_chat_message(&nickname, &message, timestamp);
+                                let message = emoji::expand(&message);
+                                chatlog::add_chat(&nickname, &message, timestamp);
+                                if let Some(me) = world.my_profile().filter(|me| me.id != player_id) {
+                                    unread::message_received();
+                                    if notify::mentions(&message, &me.nickname) {
+                                        let args = [("nickname", nickname.as_str()), ("message", message.as_str())];
+                                        announce::polite(&i18n::text("announce.mention", &args));
+                                        notify::mention(&nickname, &message);
+                                    }
+                                }
+                                world.say(&player_id, &message, js_sys::Date::now());
                             }
-                            ServerMessage::Error { message } => {
+                            ServerMessage::Error { message, code } => {
                                 console_error!("Server error: {}", message);
+                                errors::report_with_code(ErrorKind::Server, &message, code.as_deref(), true);
+                                // Before the Welcome, that's the Join being turned down
+                                welcomed.reject(&message);
+                                // Most likely the JoinRoom failed, e.g. a wrong password
+                                if let Ok(mut joining) = joining_clone.lock() {
+                                    *joining = None;
+                                }
+                            }
+                            ServerMessage::TimeSyncResponse { client_time, server_receive_time, server_send_time } => {
+                                if let Ok(mut clock) = clock_clone.lock() {
+                                    clock.add_sample(client_time, server_receive_time, server_send_time, js_sys::Date::now());
+                                }
+                            }
+                            ServerMessage::StatsSnapshot { player_id, session, lifetime } => {
+                                console_log!("Stats for {}: session {:?}, lifetime {:?}", player_id, session, lifetime);
+                            }
+                            ServerMessage::AchievementUnlocked { player_id, nickname, name, description, .. } => {
+                                if world.my_profile().is_some_and(|me| me.id == player_id) {
+                                    let args = [("name", name.as_str()), ("description", description.as_str())];
+                                    show_toast(&i18n::text("achievement.mine", &args));
+                                } else {
+                                    let args = [("nickname", nickname.as_str()), ("name", name.as_str())];
+                                    show_toast(&i18n::text("achievement.other", &args));
+                                }
+                            }
+                            ServerMessage::Leaderboard { daily, alltime } => {
+                                render_leaderboard("leaderboard-daily", &daily);
+                                render_leaderboard("leaderboard-alltime", &alltime);
+                            }
+                            ServerMessage::SystemMessage { message, timestamp } => {
+                                chatlog::add_system(&message, timestamp);
+                            }
+                            ServerMessage::RoomList { rooms } => render_room_list(&rooms),
+                            ServerMessage::PlayerIdle { player_id, idle } => {
+                                if let Some(profile) = world.profile_mut(&player_id) {
+                                    profile.idle = idle;
+                                }
+                            }
+                            ServerMessage::AppearanceChanged { player_id, color, avatar } => {
+                                if let Some(profile) = world.profile_mut(&player_id) {
+                                    profile.color = color;
+                                    profile.sprite = avatar;
+                                }
+                            }
+                            ServerMessage::WorldChanged { world: map } => {
+                                console_log!("World changed: {}", map.name);
+                                world.set_map(map);
+                            }
+                            ServerMessage::ChunkLoaded { column, row, obstacles } => {
+                                world.chunks.insert((column, row), obstacles);
+                                world.dirty = true;
+                            }
+                            ServerMessage::ChunkUnloaded { column, row } => {
+                                world.chunks.remove(&(column, row));
+                                world.dirty = true;
+                            }
+                            ServerMessage::SessionTakenOver => {
+                                errors::report(ErrorKind::Connection, "Signed in from another window", false);
+                            }
+                            ServerMessage::NickChanged { player_id, nickname } => {
+                                if let Some(profile) = world.profile_mut(&player_id) {
+                                    profile.nickname = nickname;
+                                }
+                            }
+                            ServerMessage::SlowModeChanged { interval_secs, by } => {
+                                let secs = interval_secs.to_string();
+                                let text = match interval_secs {
+                                    0 => i18n::text("slowmode.off", &[("by", by.as_str())]),
+                                    _ => i18n::text("slowmode.changed", &[("by", by.as_str()), ("secs", secs.as_str())]),
+                                };
+                                chatlog::add_system(&text, now_secs());
+                            }
+                            ServerMessage::Custom { channel, payload, from } => {
+                                channels::received(&channel, &payload, from.as_deref());
+                            }
+                            ServerMessage::Draining { retry_after_secs } => {
+                                let secs = retry_after_secs.to_string();
+                                errors::report(ErrorKind::Connection, &i18n::text("drain.notice", &[("secs", secs.as_str())]), false);
                             }
                         }
                     }
                 } else {
                     console_error!("Failed to parse server message: {}", message_str);
+                    errors::report(ErrorKind::Parse, "Failed to parse a server message", true);
+                }
+            } else if let Ok(buffer) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
+                let frame = js_sys::Uint8Array::new(&buffer).to_vec();
+                let zstd = ws_for_acks.protocol().starts_with(ZSTD_SUBPROTOCOL_PREFIX);
+                if let Ok(mut world) = world_clone.lock() {
+                    match read_state_frame(&mut world, &frame, zstd, js_sys::Date::now()) {
+                        Ok(seq) => track_sequence(&ws_for_acks, &mut last_seq, seq, false),
+                        Err(err) => {
+                            console_error!("Failed to read state snapshot: {}", err);
+                            errors::report(ErrorKind::Parse, &format!("Failed to read a state snapshot: {}", err), true);
+                        }
+                    }
                 }
             }
         }) as Box<dyn FnMut(MessageEvent)>);
 
         let on_close = Closure::wrap(Box::new(move |e: CloseEvent| {
             console_log!("WebSocket closed: code={}, reason={}", e.code(), e.reason());
+            if !spectate {
+                tabs::release();
+            }
+            let reason = format!("Connection closed ({})", e.code());
+            closed.reject(&reason);
+            errors::report(ErrorKind::Connection, &reason, false);
         }) as Box<dyn FnMut(CloseEvent)>);
 
         let on_error = Closure::wrap(Box::new(move |e: Event| {
@@ -188,24 +615,42 @@ impl GameClient {
         ws.set_onerror(Some(on_error.as_ref().unchecked_ref()));
 
         // Send join message when connection opens
-        let join_msg = ClientMessage::Join { nickname };
+        let join_msg = ClientMessage::Join { nickname, token, invite: links::param("invite") };
         let join_json = serde_json::to_string(&join_msg).unwrap();
         
         let ws_clone = ws.clone();
+        let opened = self.pending.clone();
         let on_open = Closure::wrap(Box::new(move |_: Event| {
             console_log!("WebSocket connected!");
+            if spectate {
+                console_log!("Another tab is playing, spectating");
+                opened.resolve_spectating();
+                return;
+            }
             if let Err(e) = ws_clone.send_with_str(&join_json) {
                 console_error!("Failed to send join message: {:?}", e);
+                errors::report(ErrorKind::Send, "Failed to send the join message", false);
             }
         }) as Box<dyn FnMut(Event)>);
         
         ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
-        on_open.forget(); // Let the closure live
 
+        if !self.rendering {
+            start_render_loop(Arc::clone(&self.world));
+            self.rendering = true;
+        }
+
+        if !spectate {
+            tabs::claim();
+        }
+        self.spectating = spectate;
         self.websocket = Some(ws);
-        self._on_message_closure = Some(on_message);
-        self._on_close_closure = Some(on_close);
-        self._on_error_closure = Some(on_error);
+        self.handlers = Some(SocketHandlers {
+            _on_open: on_open,
+            _on_message: on_message,
+            _on_close: on_close,
+            _on_error: on_error,
+        });
 
         Ok(())
     }
@@ -217,104 +662,803 @@ impl GameClient {
         }
         Ok(())
     }
-}
 
-fn update_ui() {
-    unsafe {
-        if let Some(client) = &GAME_CLIENT {
-            if let Ok(players) = client.players.lock() {
-                let mut html = String::new();
-                for player in players.values() {
-                    html.push_str(&format!(
-                        r#"<div class="player" style="position: absolute; left: {}px; top: {}px; 
-                            width: 20px; height: 20px; background: {}; border-radius: 50%; 
-                            border: 2px solid #fff; box-shadow: 0 2px 4px rgba(0,0,0,0.3);" 
-                            title="{}"></div>"#,
-                        player.x, player.y, player.color, player.nickname
-                    ));
-                }
-                
-                if let Some(window) = web_sys::window() {
-                    if let Some(document) = window.document() {
-                        if let Some(container) = document.get_element_by_id("players-container") {
-                            container.set_inner_html(&html);
-                        }
+    // Sends the latest move once per move interval, or per
+    // HIDDEN_MOVE_INTERVAL_MS while the tab is hidden; moves in between are
+    // held back, and the last of them goes out when the interval is up.
+    // Moves are kept inside the world and moves into an obstacle dropped,
+    // the same way the server would.
+    fn send_move(&self, x: f32, y: f32) -> Result<(), JsValue> {
+        let target = self.world.lock().ok().and_then(|world| {
+            let target = Position { x, y }.clamped(world.camera.bounds());
+            world.fits(target).then_some(target)
+        });
+        let Some(Position { x, y }) = target else {
+            self.pending_move.set(None);
+            return Ok(());
+        };
+        if self
+            .last_sent_move
+            .get()
+            .is_some_and(|(sent_x, sent_y)| (x - sent_x).hypot(y - sent_y) < MOVE_EPSILON)
+        {
+            self.pending_move.set(None);
+            return Ok(());
+        }
+        let mut interval = self.move_interval_ms.get();
+        if visibility::hidden() {
+            interval = interval.max(HIDDEN_MOVE_INTERVAL_MS);
+        }
+        let now = js_sys::Date::now();
+        let wait = self.last_move_ms.get() + interval - now;
+        if wait > 0.0 {
+            self.pending_move.set(Some((x, y)));
+            self.schedule_move_flush(wait);
+            return Ok(());
+        }
+        self.pending_move.set(None);
+        self.last_move_ms.set(now);
+        self.last_sent_move.set(Some((x, y)));
+        self.send_message(ClientMessage::Move { x, y })
+    }
+
+    fn schedule_move_flush(&self, wait_ms: f64) {
+        if self.move_flush_scheduled.replace(true) {
+            return;
+        }
+        let flush = Closure::once_into_js(|| {
+            GAME_CLIENT.with(|client| {
+                if let Some(client) = client.borrow().as_ref() {
+                    client.move_flush_scheduled.set(false);
+                    if let Some((x, y)) = client.pending_move.take() {
+                        let _ = client.send_move(x, y);
                     }
                 }
+            })
+        });
+        let scheduled = web_sys::window().and_then(|window| {
+            window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(flush.unchecked_ref(), wait_ms.ceil() as i32)
+                .ok()
+        });
+        if scheduled.is_none() {
+            self.move_flush_scheduled.set(false);
+        }
+    }
+
+    // Shows the player as idle while the tab is hidden. Coming back sends the
+    // move that was held back and asks for a fresh Welcome, as updates were
+    // not drawn in the meantime.
+    fn visibility_changed(&self, hidden: bool) {
+        let Some(ws) = self.websocket.as_ref().filter(|ws| ws.ready_state() == WebSocket::OPEN) else {
+            return;
+        };
+        send_client_message(ws, &ClientMessage::SetIdle { idle: hidden });
+        if !hidden {
+            if let Some((x, y)) = self.pending_move.take() {
+                let _ = self.send_move(x, y);
             }
+            send_client_message(ws, &ClientMessage::Resync);
         }
     }
 }
 
-fn add_chat_message(nickname: &str, message: &str, timestamp: u64) {
+// So a client that goes away never leaves a socket calling into freed closures
+impl Drop for GameClient {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+type FrameCallback = Closure<dyn FnMut()>;
+
+fn request_animation_frame(callback: &FrameCallback) {
     if let Some(window) = web_sys::window() {
-        if let Some(document) = window.document() {
-            if let Some(chat_messages) = document.get_element_by_id("chat-messages") {
-                let time = js_sys::Date::new(&JsValue::from_f64(timestamp as f64 * 1000.0));
-                let time_str = time.to_locale_time_string("en-US");
-                
-                let current_html = chat_messages.inner_html();
-                let new_message = format!(
-                    r#"<div><strong>[{}] {}:</strong> {}</div>"#,
-                    time_str.as_string().unwrap_or_default(),
-                    nickname,
-                    message
-                );
-                
-                chat_messages.set_inner_html(&(current_html + &new_message));
-                chat_messages.set_scroll_top(chat_messages.scroll_height());
-            }
+        let _ = window.request_animation_frame(callback.as_ref().unchecked_ref());
+    }
+}
+
+// Runs the systems every frame. Message handlers only change the world, and
+// what they changed shows up on the next frame. The closure schedules itself
+// again, so it lives as long as the page.
+fn start_render_loop(world: Arc<Mutex<World>>) {
+    let frame: Rc<RefCell<Option<FrameCallback>>> = Rc::new(RefCell::new(None));
+    let next_frame = Rc::clone(&frame);
+    *frame.borrow_mut() = Some(Closure::new(move || {
+        if visibility::hidden() {
+            // Nothing to draw for; the frames resume with the tab
+        } else if let Ok(mut world) = world.lock() {
+            systems::run(&mut world, js_sys::Date::now());
+        }
+        if let Some(callback) = next_frame.borrow().as_ref() {
+            request_animation_frame(callback);
         }
+    }));
+    let first_frame = frame.borrow();
+    if let Some(callback) = first_frame.as_ref() {
+        request_animation_frame(callback);
     }
 }
 
-// Export functions for JavaScript to call
-#[wasm_bindgen]
-pub fn connect_to_game(nickname: Option<String>) -> Result<(), JsValue> {
-    unsafe {
-        if GAME_CLIENT.is_none() {
-            GAME_CLIENT = Some(GameClient::new());
+// Applies the saved color, if the player may choose one and it differs, and
+// the saved avatar, and goes to the link's `?room=` or else the saved room,
+// unless the page was opened with an invite. Returns the room joined.
+fn restore_prefs(ws: &WebSocket, me: Option<&Profile>, capabilities: &Capabilities) -> Option<String> {
+    if let Some(saved) = prefs::load(Pref::Color) {
+        if capabilities.choose_color && me.is_none_or(|me| me.color != saved) {
+            send_client_message(ws, &ClientMessage::ChangeColor { color: saved });
         }
-        if let Some(client) = &mut GAME_CLIENT {
-            client.connect(nickname)?;
+    }
+    if let Some(saved) = prefs::load(Pref::Sprite).and_then(|sprite| sprite.parse().ok()) {
+        if me.is_none_or(|me| me.sprite != saved) {
+            send_client_message(ws, &ClientMessage::ChangeSprite { sprite: saved });
         }
     }
+    if links::param("invite").is_some() {
+        return None;
+    }
+    let room = links::param("room").or_else(|| prefs::load(Pref::Room)).filter(|room| room != DEFAULT_ROOM)?;
+    send_client_message(ws, &ClientMessage::JoinRoom { room: room.clone(), password: None });
+    Some(room)
+}
+
+fn send_client_message(ws: &WebSocket, message: &ClientMessage) {
+    let json = serde_json::to_string(message).unwrap();
+    if let Err(e) = ws.send_with_str(&json) {
+        console_error!("Failed to send {:?}: {:?}", message, e);
+        errors::report(ErrorKind::Send, "Failed to send a message to the server", true);
+    }
+}
+
+fn send_time_sync_request(ws: &WebSocket) {
+    send_client_message(ws, &ClientMessage::TimeSyncRequest { client_time: js_sys::Date::now() });
+}
+
+// Acks periodically and requests a resync when messages were skipped. A
+// Welcome restarts tracking since it carries the complete state.
+fn track_sequence(ws: &WebSocket, last_seq: &mut u64, seq: u64, is_welcome: bool) {
+    if seq == 0 {
+        return;
+    }
+    if !is_welcome && *last_seq != 0 && seq > *last_seq + 1 {
+        console_error!("Missed server messages {}..{}, requesting resync", *last_seq + 1, seq - 1);
+        send_client_message(ws, &ClientMessage::Resync);
+    }
+    *last_seq = seq.max(*last_seq);
+    if is_welcome || seq.is_multiple_of(ACK_INTERVAL) {
+        send_client_message(ws, &ClientMessage::Ack { seq });
+    }
+}
+
+// Applies a binary state frame, returning its sequence number. The frame is
+// the seq as 8 little-endian bytes, then, on flatbuffers-zstd connections, a
+// flags byte, then the snapshot.
+fn read_state_frame(world: &mut World, frame: &[u8], zstd: bool, now: f64) -> Result<u64, String> {
+    let Some((seq, snapshot)) = frame.split_first_chunk::<8>() else {
+        return Err("binary frame too short".to_string());
+    };
+    if zstd {
+        apply_state_snapshot(world, &unwrap_zstd_frame(snapshot)?, now)?;
+    } else {
+        apply_state_snapshot(world, snapshot, now)?;
+    }
+    Ok(u64::from_le_bytes(*seq))
+}
+
+// The FlatBuffers buffer in a flatbuffers-zstd snapshot, decompressed if its
+// flags say so
+fn unwrap_zstd_frame(frame: &[u8]) -> Result<Vec<u8>, String> {
+    let Some((&flags, payload)) = frame.split_first() else {
+        return Err("empty snapshot".to_string());
+    };
+    if flags & FLAG_ZSTD == 0 {
+        return Ok(payload.to_vec());
+    }
+    decompress(payload)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn decompress(payload: &[u8]) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let mut decoder = ruzstd::decoding::StreamingDecoder::new(payload).map_err(|e| e.to_string())?;
+    let mut buffer = Vec::new();
+    decoder.read_to_end(&mut buffer).map_err(|e| e.to_string())?;
+    Ok(buffer)
+}
+
+// ruzstd is only a dependency of the browser build. Other targets just
+// compile the client alongside the server and never receive a frame.
+#[cfg(not(target_arch = "wasm32"))]
+fn decompress(_payload: &[u8]) -> Result<Vec<u8>, String> {
+    Err("zstd snapshots are only read in the browser".to_string())
+}
+
+// Reads positions straight out of the FlatBuffers buffer without
+// deserializing it. Quantized positions are scaled back up to the current
+// map's bounds.
+fn apply_state_snapshot(world: &mut World, bytes: &[u8], now: f64) -> Result<(), String> {
+    if !state_snapshot_buffer_has_identifier(bytes) {
+        return Err("unknown binary frame".to_string());
+    }
+    read_state_snapshot(world, bytes, now).map_err(|e| e.to_string())
+}
+
+fn read_state_snapshot(world: &mut World, bytes: &[u8], now: f64) -> planus::Result<()> {
+    let snapshot = root_as_state_snapshot(bytes)?;
+    let Some(states) = snapshot.players()? else {
+        return Ok(());
+    };
+    let quantized = snapshot.quantized()?;
+    let bounds = world.map.bounds;
+    for state in states {
+        let state = state?;
+        let position = if quantized {
+            Position { x: dequantize(state.qx()?, bounds.width), y: dequantize(state.qy()?, bounds.height) }
+        } else {
+            Position { x: state.x()?, y: state.y()? }
+        };
+        world.move_player(state.id()?, position, (state.vx()?, state.vy()?), now);
+    }
     Ok(())
 }
 
+fn render_leaderboard(list_id: &str, entries: &[LeaderboardEntry]) {
+    let Some(document) = web_sys::window().and_then(|w| w.document()) else {
+        return;
+    };
+    let Some(list) = document.get_element_by_id(list_id) else {
+        return;
+    };
+    list.set_inner_html("");
+    for entry in entries {
+        if let Ok(item) = document.create_element("li") {
+            let score = entry.score.to_string();
+            let args = [("nickname", entry.nickname.as_str()), ("score", score.as_str())];
+            item.set_text_content(Some(&i18n::text("leaderboard.entry", &args)));
+            let _ = list.append_child(&item);
+        }
+    }
+}
+
+// One entry per room, carrying the room name and whether it's locked for the
+// page's click handler
+fn render_room_list(rooms: &[RoomInfo]) {
+    let Some(document) = web_sys::window().and_then(|w| w.document()) else {
+        return;
+    };
+    let Some(list) = document.get_element_by_id("room-list") else {
+        return;
+    };
+    list.set_inner_html("");
+    for room in rooms {
+        let Ok(item) = document.create_element("li") else {
+            continue;
+        };
+        let players = match room.capacity {
+            Some(capacity) => format!("{}/{}", room.players, capacity),
+            None => room.players.to_string(),
+        };
+        let lock = if room.locked { " 🔒" } else { "" };
+        let args = [("name", room.name.as_str()), ("lock", lock), ("players", players.as_str()), ("mode", room.mode.as_str())];
+        item.set_text_content(Some(&i18n::text("room.entry", &args)));
+        let _ = item.set_attribute("data-room", &room.name);
+        let _ = item.set_attribute("data-locked", if room.locked { "true" } else { "false" });
+        let _ = list.append_child(&item);
+    }
+}
+
+// Unix seconds, for chat lines the client adds itself
+fn now_secs() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+// Shows a notification in the corner of the page for a few seconds
+fn show_toast(text: &str) {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let Some(document) = window.document() else {
+        return;
+    };
+    let (Some(container), Ok(toast)) = (document.get_element_by_id("toasts"), document.create_element("div")) else {
+        return;
+    };
+    toast.set_class_name("toast");
+    toast.set_text_content(Some(text));
+    if container.append_child(&toast).is_err() {
+        return;
+    }
+    let remove = Closure::once_into_js(move || toast.remove());
+    let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(remove.unchecked_ref(), 4000);
+}
+
+// Where the WebSocket connects. Without `server_url` that's /ws on the page's
+// own host, except that pages served from localhost go to the server's
+// default port. `server_url` may be ws(s)://, http(s):// (mapped to ws(s)) or
+// relative to the page.
+fn websocket_url(server_url: Option<&str>) -> Result<String, JsValue> {
+    let window = web_sys::window().ok_or("no window")?;
+    let location = window.location();
+    let Some(server_url) = server_url else {
+        let hostname = location.hostname()?;
+        if hostname == "localhost" || hostname == "127.0.0.1" {
+            return Ok("ws://127.0.0.1:8080/ws".to_string());
+        }
+        let ws_protocol = if location.protocol()? == "https:" { "wss" } else { "ws" };
+        return Ok(format!("{}://{}/ws", ws_protocol, location.host()?));
+    };
+    let url = web_sys::Url::new_with_base(server_url, &location.href()?)?;
+    match url.protocol().as_str() {
+        "ws:" | "wss:" => {}
+        "http:" => url.set_protocol("ws"),
+        "https:" => url.set_protocol("wss"),
+        other => return Err(JsValue::from_str(&format!("unsupported server URL scheme {}", other))),
+    }
+    Ok(url.href())
+}
+
+// Everything connect_with_options takes; all optional. From JS:
+// `const options = new ConnectOptions(); options.server_url = "wss://...";`
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Default)]
+pub struct ConnectOptions {
+    // See websocket_url
+    pub server_url: Option<String>,
+    pub nickname: Option<String>,
+    // A session token from /api/login or /api/register
+    pub token: Option<String>,
+    // When another tab of this browser is already playing: "spectate" (the
+    // default) watches the lobby without joining, "refuse" doesn't connect
+    pub other_tab: Option<String>,
+}
+
+#[wasm_bindgen]
+impl ConnectOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// Export functions for JavaScript to call
+// `token` is an optional session token from /api/login or /api/register, and
+// `server_url` points the client at a server other than the page's own.
+// Without a nickname, the link's `?nick=` is used, or else the one from last
+// time in localStorage. The promise works like connect_with_options'.
+#[wasm_bindgen]
+pub fn connect_to_game(nickname: Option<String>, token: Option<String>, server_url: Option<String>) -> js_sys::Promise {
+    connect_with_options(ConnectOptions { server_url, nickname, token, other_tab: None })
+}
+
+// Resolves with the player's id once the server has welcomed them, or with
+// null for a spectator once connected; rejects if the join is refused, the
+// connection closes or there's no Welcome within 10 seconds
+#[wasm_bindgen]
+pub fn connect_with_options(mut options: ConnectOptions) -> js_sys::Promise {
+    let spectate = tabs::playing_elsewhere();
+    if spectate && options.other_tab.as_deref() == Some("refuse") {
+        let message = "Already playing in another tab";
+        errors::report_with_code(ErrorKind::Connection, message, Some("other_tab"), false);
+        return js_sys::Promise::reject(&js_sys::Error::new(message));
+    }
+    match &options.nickname {
+        Some(nickname) => prefs::save(Pref::Nickname, nickname),
+        None => options.nickname = default_nickname(),
+    }
+    let pending = PendingConnect::default();
+    let promise = pending.promise();
+    let connected = GAME_CLIENT.with(|client| {
+        let mut client = client.borrow_mut();
+        client.get_or_insert_with(GameClient::new).connect(options, pending.clone(), spectate)
+    });
+    match connected {
+        Ok(()) => promise,
+        Err(e) => js_sys::Promise::reject(&e),
+    }
+}
+
+// Closes the connection; the pending connect promise, if any, rejects
+#[wasm_bindgen]
+pub fn disconnect() {
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow_mut().as_mut() {
+            client.disconnect();
+        }
+    })
+}
+
+// Whether the connection is watching rather than playing, because another
+// tab was already playing when it connected
+#[wasm_bindgen]
+pub fn is_spectating() -> bool {
+    GAME_CLIENT.with(|client| client.borrow().as_ref().is_some_and(|client| client.spectating))
+}
+
 #[wasm_bindgen]
 pub fn move_player(x: f32, y: f32) -> Result<(), JsValue> {
-    unsafe {
-        if let Some(client) = &GAME_CLIENT {
-            let message = ClientMessage::Move { x, y };
-            client.send_message(message)?;
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
+            client.send_move(x, y)?;
         }
-    }
-    Ok(())
+        Ok(())
+    })
+}
+
+// How many Moves a second may go out at most (default 30); move_player can be
+// called more often, e.g. on every mousemove, and only the latest move of
+// each interval is sent. 0 or less sends every move.
+#[wasm_bindgen]
+pub fn set_move_rate_hz(hz: f64) {
+    let interval = if hz > 0.0 { 1000.0 / hz } else { 0.0 };
+    GAME_CLIENT.with(|client| {
+        let mut client = client.borrow_mut();
+        client.get_or_insert_with(GameClient::new).move_interval_ms.set(interval);
+    })
 }
 
 #[wasm_bindgen]
 pub fn send_chat_message(message: String) -> Result<(), JsValue> {
-    unsafe {
-        if let Some(client) = &GAME_CLIENT {
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
             let chat_msg = ClientMessage::Chat { message };
             client.send_message(chat_msg)?;
         }
-    }
-    Ok(())
+        Ok(())
+    })
+}
+
+// Sends an app-defined message on `channel` to everyone in the room, or just
+// to the player `to`; `payload` is any JSON-serializable value
+#[wasm_bindgen]
+pub fn send_custom(channel: String, payload: JsValue, to: Option<String>) -> Result<(), JsValue> {
+    let json = String::from(js_sys::JSON::stringify(&payload)?);
+    let payload = serde_json::from_str(&json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
+            client.send_message(ClientMessage::Custom { channel, payload, to })?;
+        }
+        Ok(())
+    })
+}
+
+// Called with `(channel, payload, from)` for each Custom message; pass null
+// to stop
+#[wasm_bindgen]
+pub fn on_custom(callback: Option<js_sys::Function>) {
+    channels::set_callback(callback);
 }
 
 #[wasm_bindgen]
 pub fn change_nickname(nickname: String) -> Result<(), JsValue> {
-    unsafe {
-        if let Some(client) = &GAME_CLIENT {
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
+            prefs::save(Pref::Nickname, &nickname);
             let msg = ClientMessage::ChangeNick { nickname };
             client.send_message(msg)?;
         }
-    }
+        Ok(())
+    })
+}
+
+// `#RRGGBB`; the server rejects it for guests. Remembered and applied again
+// on the next join that may choose a color.
+#[wasm_bindgen]
+pub fn change_color(color: String) -> Result<(), JsValue> {
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
+            prefs::save(Pref::Color, &color);
+            client.send_message(ClientMessage::ChangeColor { color })?;
+        }
+        Ok(())
+    })
+}
+
+// Picks the avatar drawn from the sprite sheet's row `sprite`. Remembered
+// and applied again on the next join.
+#[wasm_bindgen]
+pub fn change_sprite(sprite: u32) -> Result<(), JsValue> {
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
+            prefs::save(Pref::Sprite, &sprite.to_string());
+            client.send_message(ClientMessage::ChangeSprite { sprite })?;
+        }
+        Ok(())
+    })
+}
+
+// Draws avatars from the sprite sheet at `url` instead of as circles: one
+// avatar per row, frames of `frame_width` by `frame_height` pixels, an idle
+// frame first and then `walk_frames` walking ones. Resolves once the image
+// has loaded and is in use.
+#[wasm_bindgen]
+pub fn load_sprite_sheet(url: String, frame_width: f64, frame_height: f64, walk_frames: u32) -> js_sys::Promise {
+    wasm_bindgen_futures::future_to_promise(async move {
+        let sheet = sprites::SpriteSheet::load(url, frame_width, frame_height, walk_frames).await?;
+        update_world(|world| {
+            world.sprite_sheet = Some(sheet);
+            world.dirty = true;
+        });
+        Ok(JsValue::UNDEFINED)
+    })
+}
+
+// Logs a player's stats to the console; defaults to your own
+#[wasm_bindgen]
+pub fn request_stats(player_id: Option<String>) -> Result<(), JsValue> {
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
+            client.send_message(ClientMessage::RequestStats { player_id })?;
+        }
+        Ok(())
+    })
+}
+
+// Reports a player to the moderators; the server replies in chat
+#[wasm_bindgen]
+pub fn report_player(target_id: String, reason: String) -> Result<(), JsValue> {
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
+            client.send_message(ClientMessage::Report { target_id, reason })?;
+        }
+        Ok(())
+    })
+}
+
+// Switches rooms; the server answers with a Welcome for the new room, or an
+// Error such as a wrong password
+#[wasm_bindgen]
+pub fn join_room(room: String, password: Option<String>) -> Result<(), JsValue> {
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
+            if let Ok(mut joining) = client.joining_room.lock() {
+                *joining = Some(room.clone());
+            }
+            client.send_message(ClientMessage::JoinRoom { room, password })?;
+        }
+        Ok(())
+    })
+}
+
+// Copies a link into the current room to the clipboard: a signed invite when
+// the server gives one out, or else a `?room=` link. The Promise resolves to
+// the link, or rejects when the clipboard can't be written.
+#[wasm_bindgen]
+pub fn copy_invite_link() -> js_sys::Promise {
+    let (room, server_url) = GAME_CLIENT
+        .with(|client| {
+            let client = client.borrow();
+            let client = client.as_ref()?;
+            let room = client.room.lock().ok()?.clone();
+            Some((room, client.server_url.clone()))
+        })
+        .unwrap_or_else(|| (DEFAULT_ROOM.to_string(), String::new()));
+    wasm_bindgen_futures::future_to_promise(async move {
+        let link = links::invite_link(&room, &server_url).await?;
+        links::copy(&link).await?;
+        Ok(JsValue::from_str(&link))
+    })
+}
+
+// Asks for the public rooms, which are rendered into #room-list
+#[wasm_bindgen]
+pub fn list_rooms() -> Result<(), JsValue> {
+    GAME_CLIENT.with(|client| {
+        if let Some(client) = client.borrow().as_ref() {
+            client.send_message(ClientMessage::ListRooms)?;
+        }
+        Ok(())
+    })
+}
+
+fn update_world(update: impl FnOnce(&mut World)) {
+    GAME_CLIENT.with(|client| {
+        let mut client = client.borrow_mut();
+        let world = &client.get_or_insert_with(GameClient::new).world;
+        if let Ok(mut world) = world.lock() {
+            update(&mut world);
+        };
+    })
+}
+
+// How long chat bubbles stay above the speaker, in seconds (default 5)
+#[wasm_bindgen]
+pub fn set_chat_bubble_duration(secs: f64) {
+    update_world(|world| world.bubble_duration_ms = (secs * 1000.0).max(0.0));
+}
+
+// How far in the past other players are drawn, in ms (default 100). More
+// delay rides out jitter; less shows them closer to where they really are.
+#[wasm_bindgen]
+pub fn set_interpolation_delay_ms(ms: f64) {
+    update_world(|world| world.smoothing.interpolation_delay_ms = ms.max(0.0));
+}
+
+// How long a player keeps moving at their last velocity when an update is
+// late, in ms (default 250); 0 turns extrapolation off
+#[wasm_bindgen]
+pub fn set_max_extrapolation_ms(ms: f64) {
+    update_world(|world| world.smoothing.max_extrapolation_ms = ms.max(0.0));
+}
+
+// Corrections of at least this many world units snap instead of blending in
+// (default 100); 0 always snaps
+#[wasm_bindgen]
+pub fn set_snap_threshold(distance: f32) {
+    update_world(|world| world.smoothing.snap_threshold = distance.max(0.0));
+}
+
+// Formats chat timestamps for `locale` (a BCP 47 tag like "de-DE"), or the
+// browser's language when null. Returns false for an invalid tag.
+#[wasm_bindgen]
+pub fn set_locale(locale: Option<String>) -> bool {
+    i18n::set_locale(locale.as_deref())
+}
+
+// Rewords client-generated text: takes an object of string keys (see
+// src/i18n.rs) to templates with `{name}` placeholders
+#[wasm_bindgen]
+pub fn set_strings(strings: JsValue) -> Result<(), JsValue> {
+    let json = String::from(js_sys::JSON::stringify(&strings)?);
+    let strings = serde_json::from_str(&json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    i18n::set_strings(strings);
     Ok(())
 }
 
+// Zooms the game area in on the local player, from 1 (the whole world) to 4
+#[wasm_bindgen]
+pub fn set_zoom(level: f64) {
+    update_world(|world| world.camera.set_zoom(level));
+}
+
+#[wasm_bindgen]
+pub fn zoom_level() -> f64 {
+    GAME_CLIENT.with(|client| {
+        let client = client.borrow();
+        client
+            .as_ref()
+            .and_then(|client| client.world.lock().ok().map(|world| world.camera.zoom()))
+            .unwrap_or(camera::MIN_ZOOM)
+    })
+}
+
+// Opt in to desktop notifications for @mentions while the tab is hidden. The
+// browser asks for permission the first time.
+#[wasm_bindgen]
+pub fn enable_notifications(enabled: bool) {
+    prefs::save(Pref::Notifications, if enabled { "true" } else { "false" });
+    notify::set_enabled(enabled);
+}
+
+// The nickname connect_to_game falls back to, for pre-filling a form
+#[wasm_bindgen]
+pub fn default_nickname() -> Option<String> {
+    links::param("nick").or_else(|| prefs::load(Pref::Nickname))
+}
+
+// Whether notifications were enabled, now or on an earlier visit
+#[wasm_bindgen]
+pub fn notifications_enabled() -> bool {
+    prefs::load(Pref::Notifications).as_deref() == Some("true")
+}
+
+// Tell the client whether the chat panel is collapsed; messages arriving
+// while it is count as unread, as they do while the tab is hidden
+#[wasm_bindgen]
+pub fn set_chat_collapsed(collapsed: bool) {
+    unread::set_collapsed(collapsed);
+    if !collapsed {
+        chatlog::scroll_to_bottom();
+    }
+}
+
+// Binds the key with KeyboardEvent.code `key` to `action`: one of move_up,
+// move_down, move_left, move_right, focus_chat or emote. The key stops doing
+// whatever it did before. Remembered for the next visit.
+#[wasm_bindgen]
+pub fn set_key_binding(action: String, key: String) -> Result<(), JsValue> {
+    keys::set_binding(&action, &key).map_err(|e| JsValue::from_str(&e))
+}
+
+// Every action and the keys bound to it, e.g. `{ "move_up": ["KeyW", "ArrowUp"], ... }`
+#[wasm_bindgen]
+pub fn key_bindings() -> Result<JsValue, JsValue> {
+    let json = serde_json::to_string(&keys::bindings()).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    js_sys::JSON::parse(&json)
+}
+
+// The action a KeyboardEvent.code is bound to, for the page's key handler
+#[wasm_bindgen]
+pub fn key_action(key: String) -> Option<String> {
+    keys::action(&key)
+}
+
+#[wasm_bindgen]
+pub fn reset_key_bindings() {
+    keys::reset();
+}
+
+// Called with `{kind, message, recoverable}` for problems worth showing the
+// player: "parse" and "send" failures, "server" Error messages (with the
+// server's `code`, e.g. "nickname_reserved", when it sent one), and
+// "connection" failures, which aren't recoverable. Pass null to stop.
+#[wasm_bindgen]
+pub fn on_error(callback: Option<js_sys::Function>) {
+    errors::set_callback(callback);
+}
+
+// Called with the new number of unread chat messages whenever it changes;
+// pass null to stop
+#[wasm_bindgen]
+pub fn on_unread_change(callback: Option<js_sys::Function>) {
+    unread::set_on_change(callback);
+}
+
+#[wasm_bindgen]
+pub fn unread_count() -> u32 {
+    unread::count()
+}
+
+// Sends minimap frames to `worker`, which was given the #minimap canvas with
+// transferControlToOffscreen() (see minimap-worker.js)
+#[wasm_bindgen]
+pub fn use_minimap_worker(worker: Worker) {
+    minimap::use_worker(worker);
+}
+
+// Called inside that worker with the canvas; draws every frame it's sent
+#[wasm_bindgen]
+pub fn start_minimap_worker(canvas: OffscreenCanvas) -> Result<(), JsValue> {
+    minimap::draw_in_worker(canvas)
+}
+
+// The server-assigned ID, available once Welcome has been received
+#[wasm_bindgen]
+pub fn get_player_id() -> Option<String> {
+    GAME_CLIENT.with(|client| {
+        client
+            .borrow()
+            .as_ref()
+            .and_then(|client| Some(client.world.lock().ok()?.my_profile()?.id.clone()))
+    })
+}
+
+// Takes another clock sync sample; call periodically to track drift
+#[wasm_bindgen]
+pub fn sync_clock() {
+    GAME_CLIENT.with(|client| {
+        if let Some(ws) = client.borrow().as_ref().and_then(|c| c.websocket.as_ref()) {
+            send_time_sync_request(ws);
+        }
+    })
+}
+
+// Estimated server clock minus local clock, in milliseconds
+#[wasm_bindgen]
+pub fn server_time_offset_ms() -> f64 {
+    GAME_CLIENT.with(|client| {
+        client
+            .borrow()
+            .as_ref()
+            .and_then(|c| Some(c.clock.lock().ok()?.offset_ms()))
+            .unwrap_or(0.0)
+    })
+}
+
+// Current server time estimate as Unix epoch milliseconds
+#[wasm_bindgen]
+pub fn server_now_ms() -> f64 {
+    js_sys::Date::now() + server_time_offset_ms()
+}
+
+// Round trip of the best clock sync sample, if any sync completed yet
+#[wasm_bindgen]
+pub fn server_round_trip_ms() -> Option<f64> {
+    GAME_CLIENT.with(|client| {
+        client.borrow().as_ref().and_then(|c| c.clock.lock().ok()?.round_trip_ms())
+    })
+}
+
 // Legacy functions (keep for compatibility)
 #[wasm_bindgen]
 pub fn greet(name: &str) {
@@ -334,4 +1478,16 @@ pub fn get_message() -> String {
 #[wasm_bindgen(start)]
 pub fn main() {
     console_log!("Rust WASM WebSocket Game Client loaded successfully!");
+    unread::watch();
+    camera::on_gesture(|factor| update_world(|world| world.camera.set_zoom(world.camera.zoom() * factor)));
+    if notifications_enabled() {
+        notify::set_enabled(true);
+    }
+    visibility::on_change(|hidden| {
+        GAME_CLIENT.with(|client| {
+            if let Some(client) = client.borrow().as_ref() {
+                client.visibility_changed(hidden);
+            }
+        })
+    });
 } 
\ No newline at end of file
@@ -0,0 +1,151 @@
+// Fitting into a Kubernetes rolling update, where a pod is taken out of its
+// Service and then stopped:
+// - GET /healthz (liveness) answers 200 for as long as the process serves
+// - GET /readyz (readiness) answers 503 once draining starts, so no new
+//   players are routed here
+// - `server --prestop`, run as the preStop hook, starts a drain through
+//   POST /lifecycle/prestop and returns once it's drained or
+//   SHUTDOWN_GRACE_SECS is up. That endpoint takes no credentials, so it's
+//   only served on its own plain HTTP listener on 127.0.0.1:LIFECYCLE_PORT,
+//   out of reach of anything proxying to the main port.
+// - SIGTERM or Ctrl-C drains the same way, closes whoever is left after
+//   SHUTDOWN_GRACE_SECS, flushes storage and returns so main can exit
+use crate::config::Config;
+use crate::http::{error_response, json_response};
+use crate::{listen, GameServer};
+use anyhow::{bail, Result};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn};
+
+// Close code for connections still open when the grace period runs out
+pub const SHUTDOWN_CLOSE_CODE: u16 = 1001;
+// How often a wait for the drain to finish checks on it
+const DRAIN_POLL: Duration = Duration::from_millis(200);
+// How long closed connections get to finish their close handshake
+const CLOSE_WAIT: Duration = Duration::from_secs(2);
+
+impl GameServer {
+    // Waits for the last connection to close, for at most `timeout`. True if
+    // it did.
+    pub async fn wait_drained(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.open_connections() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(DRAIN_POLL).await;
+        }
+        true
+    }
+
+    // Drains, then closes whoever is still connected after the grace period
+    // and waits for storage to catch up
+    pub async fn shut_down(&self) {
+        self.start_drain();
+        let grace = Duration::from_secs(self.config().shutdown_grace_secs);
+        if !self.wait_drained(grace).await {
+            warn!(connections = self.open_connections(), "Grace period over, closing the remaining connections");
+            for connection in self.connections.iter() {
+                connection.queue.close(SHUTDOWN_CLOSE_CODE, "Server shutting down");
+            }
+            self.wait_drained(CLOSE_WAIT).await;
+        }
+        self.storage.flush().await;
+        info!("Shut down");
+    }
+}
+
+// The probes, on the main port
+pub fn handle_probe(req: &Request<Incoming>, server: &GameServer) -> Response<Full<Bytes>> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/healthz") => json_response(StatusCode::OK, serde_json::json!({ "ok": true }).to_string()),
+        (&Method::GET, "/readyz") if server.is_draining() => error_response(StatusCode::SERVICE_UNAVAILABLE, "draining"),
+        (&Method::GET, "/readyz") => json_response(StatusCode::OK, serde_json::json!({ "ready": true }).to_string()),
+        _ => error_response(StatusCode::NOT_FOUND, "unknown probe"),
+    }
+}
+
+// The loopback listener for `--prestop`
+pub fn bind(config: &Config) -> Result<TcpListener> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, config.lifecycle_port));
+    Ok(listen::bind(&[addr])?.remove(0))
+}
+
+// Serves the loopback listener until it stops working, returning why
+pub async fn serve(listener: TcpListener, server: GameServer) -> anyhow::Error {
+    loop {
+        let tcp = match listen::accept(&listener).await {
+            Ok((tcp, _)) => tcp,
+            Err(e) => return e,
+        };
+        let server = server.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let server = server.clone();
+                async move { Ok::<_, Infallible>(handle_lifecycle(req, server).await) }
+            });
+            if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(tcp), service).await {
+                debug!("Error serving a lifecycle connection: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_lifecycle(req: Request<Incoming>, server: GameServer) -> Response<Full<Bytes>> {
+    match (req.method(), req.uri().path()) {
+        (&Method::POST, "/lifecycle/prestop") => {
+            server.start_drain();
+            server.wait_drained(Duration::from_secs(server.config().shutdown_grace_secs)).await;
+            json_response(StatusCode::OK, server.drain_status().to_string())
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "unknown lifecycle endpoint"),
+    }
+}
+
+// Resolves on SIGTERM or Ctrl-C
+pub async fn terminated() {
+    #[cfg(unix)]
+    {
+        let mut terms = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(terms) => terms,
+            Err(e) => {
+                warn!("Can't listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = terms.recv() => info!("Received SIGTERM"),
+            _ = tokio::signal::ctrl_c() => info!("Received Ctrl-C"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Received Ctrl-C");
+    }
+}
+
+// `server --prestop`: asks the server in this pod to drain and waits for it
+pub async fn prestop(config: &Config) -> Result<()> {
+    let url = format!("http://127.0.0.1:{}/lifecycle/prestop", config.lifecycle_port);
+    let response = reqwest::Client::new()
+        .post(&url)
+        .timeout(Duration::from_secs(config.shutdown_grace_secs) + CLOSE_WAIT)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("{url} answered {}", response.status());
+    }
+    info!("{}", response.text().await?);
+    Ok(())
+}
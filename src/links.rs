@@ -0,0 +1,94 @@
+// Shareable links: the page URL's `?room=`, `?nick=` and `?invite=`, keeping
+// `?room=` pointed at the current room so the address bar can be shared as
+// is, and invite links for the clipboard
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Headers, RequestInit, Response, UrlSearchParams};
+
+fn params() -> Option<UrlSearchParams> {
+    let search = web_sys::window()?.location().search().ok()?;
+    UrlSearchParams::new_with_str(&search).ok()
+}
+
+// A non-empty query parameter of the page URL
+pub fn param(name: &str) -> Option<String> {
+    params()?.get(name).filter(|value| !value.is_empty())
+}
+
+// Replaces `?room=` without adding a history entry or reloading. An invite
+// is only good for getting in once, so it's dropped from the URL.
+pub fn show_room(room: &str) {
+    let (Some(window), Some(params)) = (web_sys::window(), params()) else {
+        return;
+    };
+    params.delete("invite");
+    if room == crate::DEFAULT_ROOM {
+        params.delete("room");
+    } else {
+        params.set("room", room);
+    }
+    let Ok(path) = window.location().pathname() else {
+        return;
+    };
+    let query = String::from(params.to_string());
+    let url = if query.is_empty() { path } else { format!("{}?{}", path, query) };
+    if let Ok(history) = window.history() {
+        let _ = history.replace_state_with_url(&JsValue::NULL, "", Some(&url));
+    }
+}
+
+// A link that brings someone into `room`. Prefers a signed invite from
+// POST /api/invites on the game server at `server_url`, which private rooms
+// need; the server hands those out to holders of an invite to the room and
+// to staff. Anyone else gets a plain `?room=` link, which works for public
+// rooms.
+pub async fn invite_link(room: &str, server_url: &str) -> Result<String, JsValue> {
+    let window = web_sys::window().ok_or("no window")?;
+    let location = window.location();
+    let page = format!("{}{}", location.origin()?, location.pathname()?);
+    if room == crate::DEFAULT_ROOM {
+        return Ok(page);
+    }
+    match signed_invite(room, server_url).await {
+        Some(url) => Ok(url),
+        None => Ok(format!("{}?room={}", page, String::from(js_sys::encode_uri_component(room)))),
+    }
+}
+
+// The HTTP address of `path` on the server whose WebSocket is at `server_url`,
+// or `path` on the page's own host before any connection
+fn api_url(server_url: &str, path: &str) -> String {
+    let Ok(url) = web_sys::Url::new(server_url) else {
+        return path.to_string();
+    };
+    url.set_protocol(if url.protocol() == "wss:" { "https" } else { "http" });
+    url.set_pathname(path);
+    url.set_search("");
+    url.href()
+}
+
+async fn signed_invite(room: &str, server_url: &str) -> Option<String> {
+    let body = serde_json::json!({ "room": room, "invite": param("invite") });
+    let headers = Headers::new().ok()?;
+    headers.set("content-type", "application/json").ok()?;
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_headers(&headers);
+    init.set_body(&JsValue::from_str(&body.to_string()));
+    let response = JsFuture::from(web_sys::window()?.fetch_with_str_and_init(&api_url(server_url, "/api/invites"), &init))
+        .await
+        .ok()?
+        .dyn_into::<Response>()
+        .ok()?;
+    if !response.ok() {
+        return None;
+    }
+    let json = JsFuture::from(response.json().ok()?).await.ok()?;
+    js_sys::Reflect::get(&json, &JsValue::from_str("url")).ok()?.as_string()
+}
+
+pub async fn copy(text: &str) -> Result<(), JsValue> {
+    let clipboard = web_sys::window().ok_or("no window")?.navigator().clipboard();
+    JsFuture::from(clipboard.write_text(text)).await?;
+    Ok(())
+}
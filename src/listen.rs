@@ -0,0 +1,102 @@
+// The listening sockets: every address in LISTEN (or whatever systemd passed
+// in), plus the admin listener on the same addresses at ADMIN_PORT. Each gets
+// its own accept loop, all serving the same GameServer.
+use crate::{serve_connection, tls, GameServer};
+use anyhow::{bail, Context, Result};
+use hyper_util::rt::TokioIo;
+use socket2::{Domain, Socket, Type};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+
+const BACKLOG: i32 = 1024;
+// How long to wait after an accept error like running out of file
+// descriptors, which clears up as connections close
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_millis(100);
+
+// Binds each address. An IPv6 wildcard would normally take IPv4 too, which
+// clashes with binding 0.0.0.0 on the same port, so IPv6 sockets only take
+// IPv6 when there is one.
+pub fn bind(addrs: &[SocketAddr]) -> Result<Vec<TcpListener>> {
+    addrs
+        .iter()
+        .map(|&addr| {
+            let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+            socket.set_reuse_address(true)?;
+            if addr.is_ipv6() {
+                socket.set_only_v6(addrs.iter().any(|a| a.is_ipv4() && a.port() == addr.port()))?;
+            }
+            socket.bind(&addr.into()).with_context(|| format!("failed to bind {addr}"))?;
+            socket.listen(BACKLOG)?;
+            socket.set_nonblocking(true)?;
+            Ok(TcpListener::from_std(socket.into())?)
+        })
+        .collect()
+}
+
+// The listen addresses with the admin port instead, leaving out those a
+// wildcard address already covers, since those no longer differ by port
+pub fn admin_addrs(addrs: &[SocketAddr], admin_port: u16) -> Vec<SocketAddr> {
+    let covered = |addr: &SocketAddr| {
+        !addr.ip().is_unspecified() && addrs.iter().any(|a| a.ip().is_unspecified() && a.is_ipv4() == addr.is_ipv4())
+    };
+    let mut admin = Vec::new();
+    for addr in addrs.iter().filter(|addr| !covered(addr)) {
+        let addr = SocketAddr::new(addr.ip(), admin_port);
+        if !admin.contains(&addr) {
+            admin.push(addr);
+        }
+    }
+    admin
+}
+
+// The next connection. Errors only once the listening socket stops working;
+// anything else going wrong with an accept is waited out.
+pub async fn accept(listener: &TcpListener) -> Result<(TcpStream, SocketAddr)> {
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => return Ok(accepted),
+            // The socket isn't listening any more
+            Err(e) if e.kind() == ErrorKind::InvalidInput => {
+                let addr = listener.local_addr().map_or_else(|_| "?".to_string(), |addr| addr.to_string());
+                bail!("listener on {addr} closed: {e}");
+            }
+            // Only the one connection is gone
+            Err(e) if matches!(e.kind(), ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset) => {
+                debug!("Connection closed before it was accepted: {}", e);
+            }
+            Err(e) => {
+                warn!("Failed to accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_PAUSE).await;
+            }
+        }
+    }
+}
+
+// Serves connections until the listening socket stops working, returning why
+pub async fn accept_loop(listener: TcpListener, tls: Option<TlsAcceptor>, server: GameServer) -> anyhow::Error {
+    loop {
+        let (tcp, peer) = match accept(&listener).await {
+            Ok(accepted) => accepted,
+            Err(e) => return e,
+        };
+        let server = server.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            match tls {
+                Some(tls) => match tls.accept(tcp).await {
+                    Ok(stream) => {
+                        // Only the admin listener asks for one
+                        let cert = tls::client_certificate(stream.get_ref().1.peer_certificates());
+                        serve_connection(TokioIo::new(stream), server, peer, cert).await
+                    }
+                    Err(e) => debug!(%peer, "TLS handshake failed: {}", e),
+                },
+                None => serve_connection(TokioIo::new(tcp), server, peer, None).await,
+            }
+        });
+    }
+}
@@ -0,0 +1,157 @@
+use crate::config::{Config, LogFormat, LogRotation};
+use anyhow::Result;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, Layer, Registry};
+
+// Chat lines are logged under this target and kept out of the server log
+pub const CHAT_TARGET: &str = "chat";
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+// Installs the global tracing subscriber. With LOG_FORMAT=json each event is
+// written as one JSON object with its own fields (msg_type, ...) at the top
+// level and the enclosing connection span's conn_id/player_id under `spans`. With LOG_DIR set, the same events are also written
+// to a rotating `server.log` there, and chat goes to its own `chat.log`.
+// With the `console` feature and TOKIO_CONSOLE set, a console-subscriber
+// layer is added so `tokio-console` can attach (127.0.0.1:6669 by default,
+// see TOKIO_CONSOLE_BIND) and show every per-connection task and tokio
+// channel live.
+//
+// The returned guards flush the file writers and must be held until exit.
+pub fn init(config: &Config) -> Result<Vec<WorkerGuard>> {
+    // Filters are per layer because the console layer needs tokio's
+    // trace-level events
+    let server_events = || {
+        Targets::new()
+            .with_default(LevelFilter::INFO)
+            .with_target(CHAT_TARGET, LevelFilter::OFF)
+    };
+
+    let mut layers = vec![fmt_layer(config.log_format, io::stdout, true)
+        .with_filter(server_events())
+        .boxed()];
+    let mut guards = Vec::new();
+
+    if let Some(dir) = &config.log_dir {
+        fs::create_dir_all(dir)?;
+        let files = [
+            ("server.log", server_events()),
+            ("chat.log", Targets::new().with_target(CHAT_TARGET, LevelFilter::INFO)),
+        ];
+        for (name, filter) in files {
+            let (writer, guard) = match config.log_rotation {
+                LogRotation::Size(max_bytes) => tracing_appender::non_blocking(SizeRotatingFile::open(
+                    dir.join(name),
+                    max_bytes,
+                    config.log_max_files,
+                )?),
+                rotation => tracing_appender::non_blocking(rolling_appender(dir, name, rotation, config.log_max_files)?),
+            };
+            layers.push(fmt_layer(config.log_format, writer, false).with_filter(filter).boxed());
+            guards.push(guard);
+        }
+    }
+
+    #[cfg(feature = "console")]
+    if std::env::var_os("TOKIO_CONSOLE").is_some() {
+        layers.push(console_subscriber::spawn().boxed());
+    }
+
+    tracing_subscriber::registry().with(layers).init();
+    #[cfg(feature = "console")]
+    if std::env::var_os("TOKIO_CONSOLE").is_some() {
+        tracing::info!("🔬 tokio-console instrumentation enabled");
+    }
+    if let Some(dir) = &config.log_dir {
+        tracing::info!("📝 Logging to {} ({:?} rotation)", dir.display(), config.log_rotation);
+    }
+    Ok(guards)
+}
+
+fn fmt_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
+    }
+}
+
+fn rolling_appender(dir: &Path, name: &str, rotation: LogRotation, max_files: usize) -> Result<RollingFileAppender> {
+    let rotation = match rotation {
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never | LogRotation::Size(_) => Rotation::NEVER,
+    };
+    Ok(RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(name)
+        .max_log_files(max_files)
+        .build(dir)?)
+}
+
+// Appends to `path` and, once it would grow past `max_bytes`, shifts it to
+// `path.1` (and older files to `path.2` and so on, keeping `max_files` of them)
+// before starting a fresh file
+struct SizeRotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for index in (1..self.max_files).rev() {
+            let _ = fs::rename(self.rotated(index), self.rotated(index + 1));
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
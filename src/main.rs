@@ -1,52 +1,178 @@
+mod accounts;
+mod achievements;
+mod ack;
+mod admin;
+mod admin_ws;
+mod api;
+mod assets;
+mod audit;
+mod bans;
+mod codec;
+mod commands;
+mod config;
+mod cors;
+mod custom;
+mod drain;
+#[cfg(feature = "embedded")]
+mod embedded;
+mod emoji;
+mod frame;
+mod handoff;
+mod http;
+mod integrations;
+mod interest;
+mod invites;
+mod join_limit;
+mod leaderboard;
+mod lifecycle;
+mod listen;
+mod logging;
+mod maps;
+mod metrics;
+mod moderation;
+mod nav;
+mod nicknames;
+mod npcs;
+mod oauth;
+mod overload;
+mod origin;
+mod plugins;
+mod presence;
+#[cfg(feature = "persistence")]
+mod postgres;
+#[cfg(feature = "pprof")]
+mod profiling;
+mod protobuf;
+mod queue;
+mod remote;
+mod reports;
+mod rooms;
+mod scripting;
+mod session;
+mod slowmode;
+mod snapshot;
+mod stats;
+mod state_fb;
+mod state_writer;
+mod storage;
+mod sweeper;
+mod systemd;
+mod takeover;
+mod tick;
+mod tier;
+mod tls;
+mod wordfilter;
+
 use anyhow::Result;
+use accounts::AccountStore;
+use admin_ws::{AdminEvent, AdminEvents};
+use audit::AuditLog;
+use bans::BanStore;
+use ack::AckTracker;
+use codec::Codec;
+use commands::Command;
+use config::Config;
+use maps::{Obstacle, WorldMap};
+use frame::OutboundFrame;
+use interest::InterestFilter;
+use leaderboard::{LeaderboardEntry, Period};
+use tier::{Capabilities, ChatLimiter, Role, Tier};
+use metrics::Metrics;
+use moderation::{Connection, Moderator};
+use oauth::OAuthLogins;
+use plugins::PluginEvent;
+use presence::Presence;
+use queue::{Outgoing, SendQueue};
+use remote::RemoteAddr;
+use reports::ReportStore;
+use rooms::{RoomId, RoomInfo, Rooms};
+use stats::{PlayerStats, StatsTracker};
+use storage::{ChatRecord, StorageHandle};
+use arc_swap::ArcSwap;
 use dashmap::DashMap;
 use futures_util::{SinkExt, StreamExt};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::broadcast;
-use tokio_tungstenite::{accept_async, tungstenite::protocol::Message};
-use tracing::{error, info, warn};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message};
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
 use uuid::Uuid;
 use hyper::{Request, Response, StatusCode, Method};
 use hyper::body::Incoming;
-use hyper::server::conn::http1;
 use hyper::service::service_fn;
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
 use http_body_util::Full;
 use hyper::body::Bytes;
 use std::convert::Infallible;
 use sha1::{Sha1, Digest};
 use base64::{Engine as _, engine::general_purpose};
 
+// Player IDs are cloned into every message and map operation, so they're
+// shared rather than reallocated
+pub type PlayerId = Arc<str>;
+
+pub const PLAYER_COLORS: [&str; 6] = ["#FF6B6B", "#4ECDC4", "#45B7D1", "#96CEB4", "#FECA57", "#FF9FF3"];
+
 // Player state
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Player {
-    pub id: String,
+    pub id: PlayerId,
     pub nickname: String,
     pub x: f32,
     pub y: f32,
     pub color: String,
     pub last_seen: u64,
+    #[serde(default)]
+    pub tier: Tier,
+    #[serde(default = "rooms::default_room")]
+    pub room: RoomId,
+    // Set while the player's tab is in the background
+    #[serde(default)]
+    pub idle: bool,
+    // Row of the avatar sprite sheet the client draws this player with
+    #[serde(default)]
+    pub sprite: u32,
+}
+
+// What other players see of a player besides their nickname
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Appearance {
+    pub color: String,
+    // Row of the avatar sprite sheet
+    pub avatar: u32,
 }
 
 impl Player {
-    pub fn new(nickname: Option<String>) -> Self {
-        let id = Uuid::new_v4().to_string();
+    pub fn appearance(&self) -> Appearance {
+        Appearance { color: self.color.clone(), avatar: self.sprite }
+    }
+
+    // Spawns somewhere free in `world`
+    pub fn new(nickname: Option<String>, world: &WorldMap) -> Self {
+        let id: PlayerId = Uuid::new_v4().to_string().into();
         let nickname = nickname.unwrap_or_else(|| format!("Player{}", &id[..6]));
         let mut rng = thread_rng();
-        let colors = ["#FF6B6B", "#4ECDC4", "#45B7D1", "#96CEB4", "#FECA57", "#FF9FF3"];
-        let color = colors[rng.gen_range(0..colors.len())].to_string();
+        let color = PLAYER_COLORS[rng.gen_range(0..PLAYER_COLORS.len())].to_string();
+        let (x, y) = world.spawn_point();
         
         Self {
             id,
             nickname,
-            x: rng.gen_range(50.0..750.0),
-            y: rng.gen_range(50.0..350.0),
+            x,
+            y,
             color,
             last_seen: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            tier: Tier::Guest,
+            room: rooms::default_room(),
+            idle: false,
+            sprite: 0,
         }
     }
 }
@@ -55,10 +181,66 @@ impl Player {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum ClientMessage {
-    Join { nickname: Option<String> },
+    // `token` is a session token from /api/login to play as a registered account
+    Join {
+        nickname: Option<String>,
+        #[serde(default)]
+        token: Option<String>,
+        // Signed invite from POST /api/invites, to join its room instead of the lobby
+        #[serde(default)]
+        invite: Option<String>,
+    },
     Move { x: f32, y: f32 },
     Chat { message: String },
     ChangeNick { nickname: String },
+    // `#RRGGBB`; only for tiers that may choose their color
+    ChangeColor { color: String },
+    // Acknowledges every server message up to and including `seq`
+    Ack { seq: u64 },
+    // Sent by clients that detected a sequence gap; answered with a fresh Welcome
+    Resync,
+    // Clock sync probe; `client_time` is echoed back (Unix epoch ms)
+    TimeSyncRequest { client_time: f64 },
+    // Asks for a StatsSnapshot of a connected player, by default yourself
+    RequestStats {
+        #[serde(default)]
+        player_id: Option<PlayerId>,
+    },
+    // Flags a player for moderators, with the reporter's explanation
+    Report { target_id: PlayerId, reason: String },
+    // Moves to another room, creating it (locked with `password`, if given)
+    // when it doesn't exist yet. Answered with a fresh Welcome.
+    JoinRoom {
+        room: String,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    // Asks for a RoomList
+    ListRooms,
+    // Sent when the player's tab is hidden (idle) or shown again
+    SetIdle { idle: bool },
+    // Picks an avatar from the sprite sheet, below MAX_SPRITES
+    ChangeSprite { sprite: u32 },
+    // App-defined message passed on to the room, or just to `to`, see custom.rs
+    Custom {
+        channel: String,
+        payload: serde_json::Value,
+        #[serde(default)]
+        to: Option<PlayerId>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerPosition {
+    pub player_id: PlayerId,
+    pub x: f32,
+    pub y: f32,
+    // Estimated by the server from the previous snapshot, in world units per
+    // second
+    #[serde(default)]
+    pub vx: f32,
+    #[serde(default)]
+    pub vy: f32,
 }
 
 // Server -> Client messages
@@ -66,182 +248,959 @@ pub enum ClientMessage {
 #[serde(tag = "type")]
 pub enum ServerMessage {
     Welcome { 
-        your_id: String, 
-        players: Vec<Player> 
+        your_id: PlayerId, 
+        players: Vec<Player>,
+        // What this player's tier allows
+        capabilities: Capabilities,
+        // The room the player is in
+        room: RoomId,
+        // How the joining player looks, also in their entry in `players`
+        appearance: Appearance,
+        // The room's world, which positions are kept inside
+        world: WorldMap,
+        // The room's slow mode interval, 0 when it's off
+        #[serde(default)]
+        slow_mode_secs: u64,
     },
     PlayerJoined { player: Player },
-    PlayerLeft { player_id: String },
-    PlayerMoved { 
-        player_id: String, 
-        x: f32, 
-        y: f32 
-    },
+    PlayerLeft { player_id: PlayerId },
+    // Positions of every player that moved since the previous tick
+    Snapshot { players: Vec<PlayerPosition> },
     ChatMessage { 
-        player_id: String, 
+        player_id: PlayerId, 
         nickname: String, 
         message: String, 
         timestamp: u64 
     },
-    Error { message: String },
+    Error {
+        message: String,
+        // Set for failures clients may want to handle rather than just show
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<ErrorCode>,
+    },
+    // Reply to TimeSyncRequest, all times in Unix epoch milliseconds
+    TimeSyncResponse {
+        client_time: f64,
+        server_receive_time: f64,
+        server_send_time: f64,
+    },
+    // Broadcast when a player unlocks an achievement
+    AchievementUnlocked {
+        player_id: PlayerId,
+        nickname: String,
+        achievement: String,
+        name: String,
+        description: String,
+    },
+    // Top registered players, sent after Welcome and whenever it changes
+    Leaderboard {
+        daily: Vec<LeaderboardEntry>,
+        alltime: Vec<LeaderboardEntry>,
+    },
+    // Reply to RequestStats. `lifetime` is only set for registered players.
+    StatsSnapshot {
+        player_id: PlayerId,
+        session: PlayerStats,
+        lifetime: Option<PlayerStats>,
+    },
+    // Shown in chat without a sender: announcements and replies to commands
+    SystemMessage { message: String, timestamp: u64 },
+    // Reply to ListRooms: every public room
+    RoomList { rooms: Vec<RoomInfo> },
+    // Broadcast to the room when a player goes idle or comes back
+    PlayerIdle { player_id: PlayerId, idle: bool },
+    // Broadcast to the room when a player's color or avatar changes; `avatar`
+    // is the sprite sheet row
+    AppearanceChanged { player_id: PlayerId, color: String, avatar: u32 },
+    // Sent before closing a connection whose account joined again elsewhere
+    // (DUPLICATE_SESSIONS=takeover)
+    SessionTakenOver,
+    // Broadcast to a room switched to another world; players it didn't fit
+    // were respawned and show up in the next Snapshot
+    WorldChanged { world: WorldMap },
+    // The obstacles of a chunk of a chunked world that came into range of
+    // the player, see maps.rs
+    ChunkLoaded { column: i32, row: i32, obstacles: Vec<Obstacle> },
+    // A chunk that went out of range; the client forgets its obstacles
+    ChunkUnloaded { column: i32, row: i32 },
+    // Broadcast to the room when a player's ChangeNick goes through
+    NickChanged { player_id: PlayerId, nickname: String },
+    // Broadcast to the room when a moderator changes its slow mode; 0 is off
+    SlowModeChanged { interval_secs: u64, by: String },
+    // An app-defined message; `from` is the player who sent it, if any
+    Custom { channel: String, payload: serde_json::Value, from: Option<PlayerId> },
+    // Broadcast when the server starts draining for a deploy: reconnect,
+    // which lands on another instance, after `retry_after_secs`
+    Draining { retry_after_secs: u64 },
+}
+
+impl ClientMessage {
+    // Variant name, used as the `msg_type` log field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientMessage::Join { .. } => "Join",
+            ClientMessage::Move { .. } => "Move",
+            ClientMessage::Chat { .. } => "Chat",
+            ClientMessage::ChangeNick { .. } => "ChangeNick",
+            ClientMessage::ChangeColor { .. } => "ChangeColor",
+            ClientMessage::Ack { .. } => "Ack",
+            ClientMessage::Resync => "Resync",
+            ClientMessage::TimeSyncRequest { .. } => "TimeSyncRequest",
+            ClientMessage::RequestStats { .. } => "RequestStats",
+            ClientMessage::Report { .. } => "Report",
+            ClientMessage::JoinRoom { .. } => "JoinRoom",
+            ClientMessage::ListRooms => "ListRooms",
+            ClientMessage::SetIdle { .. } => "SetIdle",
+            ClientMessage::ChangeSprite { .. } => "ChangeSprite",
+            ClientMessage::Custom { .. } => "Custom",
+        }
+    }
+}
+
+impl ServerMessage {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerMessage::Welcome { .. } => "Welcome",
+            ServerMessage::PlayerJoined { .. } => "PlayerJoined",
+            ServerMessage::PlayerLeft { .. } => "PlayerLeft",
+            ServerMessage::Snapshot { .. } => "Snapshot",
+            ServerMessage::ChatMessage { .. } => "ChatMessage",
+            ServerMessage::Error { .. } => "Error",
+            ServerMessage::TimeSyncResponse { .. } => "TimeSyncResponse",
+            ServerMessage::StatsSnapshot { .. } => "StatsSnapshot",
+            ServerMessage::AchievementUnlocked { .. } => "AchievementUnlocked",
+            ServerMessage::Leaderboard { .. } => "Leaderboard",
+            ServerMessage::SystemMessage { .. } => "SystemMessage",
+            ServerMessage::RoomList { .. } => "RoomList",
+            ServerMessage::PlayerIdle { .. } => "PlayerIdle",
+            ServerMessage::AppearanceChanged { .. } => "AppearanceChanged",
+            ServerMessage::NickChanged { .. } => "NickChanged",
+            ServerMessage::SessionTakenOver => "SessionTakenOver",
+            ServerMessage::WorldChanged { .. } => "WorldChanged",
+            ServerMessage::ChunkLoaded { .. } => "ChunkLoaded",
+            ServerMessage::ChunkUnloaded { .. } => "ChunkUnloaded",
+            ServerMessage::SlowModeChanged { .. } => "SlowModeChanged",
+            ServerMessage::Custom { .. } => "Custom",
+            ServerMessage::Draining { .. } => "Draining",
+        }
+    }
+}
+
+// Machine-readable reasons for an Error
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // Join or ChangeNick with a nickname reserved for someone else
+    NicknameReserved,
+    // Join as an account that's already playing (DUPLICATE_SESSIONS=reject)
+    AlreadyConnected,
+    // Chat sent before the room's slow mode allows another message
+    SlowMode,
+    // Join from an address, or while the server, is over its join rate limit
+    JoinRateLimited,
+    // Join while the server is overloaded, see overload.rs
+    ServerBusy,
+}
+
+impl ErrorCode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "nickname_reserved" => Some(ErrorCode::NicknameReserved),
+            "already_connected" => Some(ErrorCode::AlreadyConnected),
+            "slow_mode" => Some(ErrorCode::SlowMode),
+            "join_rate_limited" => Some(ErrorCode::JoinRateLimited),
+            "server_busy" => Some(ErrorCode::ServerBusy),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::NicknameReserved => "nickname_reserved",
+            ErrorCode::AlreadyConnected => "already_connected",
+            ErrorCode::SlowMode => "slow_mode",
+            ErrorCode::JoinRateLimited => "join_rate_limited",
+            ErrorCode::ServerBusy => "server_busy",
+        }
+    }
+}
+
+fn error_frame(message: &str) -> Arc<OutboundFrame> {
+    OutboundFrame::new(ServerMessage::Error { message: message.to_string(), code: None })
+}
+
+fn coded_error_frame(code: ErrorCode, message: &str) -> Arc<OutboundFrame> {
+    OutboundFrame::new(ServerMessage::Error { message: message.to_string(), code: Some(code) })
+}
+
+// Sprite sheets have at most this many avatar rows
+pub const MAX_SPRITES: u32 = 64;
+
+pub fn unix_millis() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64() * 1000.0
 }
 
 // Game server state
 #[derive(Clone)]
 pub struct GameServer {
-    players: Arc<DashMap<String, Player>>,
-    broadcast_tx: broadcast::Sender<ServerMessage>,
+    players: Arc<DashMap<PlayerId, Player>>,
+    // Registered players from a restored snapshot by username, out of sight
+    // until they log in again, see handoff.rs
+    restored: Arc<DashMap<String, Player>>,
+    rooms: Arc<Rooms>,
+    // Server-wide messages; everything about players goes to their room
+    broadcast_tx: broadcast::Sender<Arc<OutboundFrame>>,
+    // Players whose position changed since the last tick
+    moved: Arc<Mutex<HashSet<PlayerId>>>,
+    // Where each player was in the last snapshot, see GameServer::tick
+    tracks: Arc<Mutex<HashMap<PlayerId, tick::Track>>>,
+    // Swapped out whole on reload, see GameServer::reload_config
+    config: Arc<ArcSwap<Config>>,
+    metrics: Arc<Metrics>,
+    accounts: Arc<AccountStore>,
+    oauth: Arc<OAuthLogins>,
+    storage: StorageHandle,
+    presence: Option<Arc<Presence>>,
+    stats: Arc<StatsTracker>,
+    bans: Arc<BanStore>,
+    reports: Arc<ReportStore>,
+    audit: Arc<AuditLog>,
+    connections: Arc<DashMap<PlayerId, Connection>>,
+    // Mute expiry by account or address, see Connection::mute_key
+    mutes: Arc<DashMap<String, std::time::Instant>>,
+    // Streamed to /ws/admin
+    events: Arc<AdminEvents>,
+    drain: Arc<drain::Drain>,
+    join_limits: Arc<join_limit::JoinLimits>,
+    overload: Arc<overload::Overload>,
+    npcs: Arc<npcs::Npcs>,
+    #[cfg(feature = "scripting")]
+    scripts: Arc<scripting::Scripts>,
+    #[cfg(feature = "plugins")]
+    plugins: Arc<plugins::Plugins>,
 }
 
 impl GameServer {
-    pub fn new() -> Self {
-        let (broadcast_tx, _) = broadcast::channel(1000);
-        Self {
-            players: Arc::new(DashMap::new()),
+    pub async fn new(
+        config: Config,
+        accounts: AccountStore,
+        bans: BanStore,
+        reports: ReportStore,
+        audit: AuditLog,
+        storage: StorageHandle,
+    ) -> Result<Self> {
+        let (broadcast_tx, _) = broadcast::channel(config.broadcast_capacity);
+        let players = Arc::new(DashMap::new());
+        let metrics = Arc::new(Metrics::default());
+        let events = Arc::new(AdminEvents::default());
+        let presence = match &config.redis_url {
+            Some(url) => Some(Presence::connect(url, Arc::clone(&players)).await?),
+            None => None,
+        };
+        Ok(Self {
+            players,
+            restored: Arc::new(DashMap::new()),
+            rooms: Arc::new(Rooms::new(config.room_capacity, config.broadcast_capacity, maps::load(&config)?, Arc::clone(&metrics), Arc::clone(&events))),
             broadcast_tx,
-        }
+            moved: Arc::new(Mutex::new(HashSet::new())),
+            tracks: Arc::new(Mutex::new(HashMap::new())),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            metrics,
+            accounts: Arc::new(accounts),
+            oauth: Arc::new(OAuthLogins::default()),
+            storage,
+            presence,
+            stats: Arc::new(StatsTracker::default()),
+            bans: Arc::new(bans),
+            reports: Arc::new(reports),
+            audit: Arc::new(audit),
+            connections: Arc::new(DashMap::new()),
+            mutes: Arc::new(DashMap::new()),
+            events,
+            drain: Arc::new(drain::Drain::default()),
+            join_limits: Arc::new(join_limit::JoinLimits::default()),
+            overload: Arc::new(overload::Overload::default()),
+            npcs: Arc::new(npcs::Npcs::default()),
+            #[cfg(feature = "scripting")]
+            scripts: Arc::new(scripting::Scripts::default()),
+            #[cfg(feature = "plugins")]
+            plugins: Arc::new(plugins::Plugins::default()),
+        })
     }
 
-    pub fn add_player(&self, player: Player) -> Result<String> {
+    pub fn add_player(&self, mut player: Player) -> Result<PlayerId> {
+        self.fit_to_room(&mut player);
         let player_id = player.id.clone();
         let join_msg = ServerMessage::PlayerJoined { player: player.clone() };
-        
+        if let Some(presence) = &self.presence {
+            presence.online(&player_id, &player.nickname, &player.room);
+        }
+
+        let room = player.room.clone();
+        self.events.emit(AdminEvent::PlayerJoined {
+            player_id: player_id.clone(),
+            nickname: player.nickname.clone(),
+            room: room.to_string(),
+        });
+        let nickname = player.nickname.clone();
         self.players.insert(player_id.clone(), player);
-        self.broadcast_message(join_msg)?;
+        self.broadcast_to_room(&room, join_msg)?;
+        self.on_player_join(&player_id, &nickname, &room);
+        self.notify_plugins(PluginEvent::PlayerJoin { player_id: &player_id, nickname: &nickname, room: &room });
         
         Ok(player_id)
     }
 
-    pub fn remove_player(&self, player_id: &str) -> Result<()> {
-        if self.players.remove(player_id).is_some() {
-            let leave_msg = ServerMessage::PlayerLeft { 
-                player_id: player_id.to_string() 
-            };
-            self.broadcast_message(leave_msg)?;
+    pub fn remove_player(&self, player_id: &str) -> Result<Option<Player>> {
+        let Some((player_id, player)) = self.players.remove(player_id) else {
+            return Ok(None);
+        };
+        if let Some(presence) = &self.presence {
+            presence.offline(&player_id);
         }
-        Ok(())
+        self.tracks.lock().unwrap().remove(&player_id);
+        self.events.emit(AdminEvent::PlayerLeft {
+            player_id: player_id.clone(),
+            nickname: player.nickname.clone(),
+            room: player.room.to_string(),
+        });
+        let leave_msg = ServerMessage::PlayerLeft { player_id };
+        self.broadcast_to_room(&player.room, leave_msg)?;
+        Ok(Some(player))
     }
 
     pub fn move_player(&self, player_id: &str, x: f32, y: f32) -> Result<()> {
-        let x = x.clamp(0.0, 800.0);
-        let y = y.clamp(0.0, 400.0);
-
         if let Some(mut player) = self.players.get_mut(player_id) {
+            let world = self.rooms.world_of(&player.room);
+            let x = x.clamp(0.0, world.width);
+            let y = y.clamp(0.0, world.height);
+            // Obstacles block the move outright, wherever they are along it
+            if !world.reachable((player.x, player.y), (x, y)) {
+                return Ok(());
+            }
+            self.stats.moved(player_id, f64::from((x - player.x).hypot(y - player.y)));
             player.x = x;
             player.y = y;
             player.last_seen = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-
-            let move_msg = ServerMessage::PlayerMoved {
-                player_id: player_id.to_string(),
-                x,
-                y,
-            };
-            self.broadcast_message(move_msg)?;
+            self.moved.lock().unwrap().insert(player.id.clone());
         }
         Ok(())
     }
 
     pub fn send_chat(&self, player_id: &str, message: String) -> Result<()> {
+        let Some(message) = self.on_chat(player_id, message) else {
+            return Ok(());
+        };
         if let Some(player) = self.players.get(player_id) {
+            let config = self.config();
+            let mut message = wordfilter::censor(&message, &config.word_filter);
+            if config.expand_emoji {
+                message = emoji::expand(&message);
+            }
+            info!(target: logging::CHAT_TARGET, player_id = %player.id, nickname = %player.nickname, "{}", message);
+            self.stats.chatted(player_id);
+            self.metrics.chat_messages.fetch_add(1, Ordering::Relaxed);
             let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            let record = ChatRecord {
+                player_id: player.id.to_string(),
+                nickname: player.nickname.clone(),
+                message: message.clone(),
+                sent_at: timestamp,
+                room: player.room.to_string(),
+            };
+            self.reports.record_chat(&player.id, record.clone());
+            self.storage.append_chat(record);
+            self.events.emit(AdminEvent::Chat {
+                player_id: player.id.clone(),
+                nickname: player.nickname.clone(),
+                room: player.room.to_string(),
+                message: message.clone(),
+            });
 
             let chat_msg = ServerMessage::ChatMessage {
-                player_id: player_id.to_string(),
+                player_id: player.id.clone(),
                 nickname: player.nickname.clone(),
-                message,
+                message: message.clone(),
                 timestamp,
             };
 
-            self.broadcast_message(chat_msg)?;
+            let room = player.room.clone();
+            drop(player);
+            self.broadcast_to_room(&room, chat_msg)?;
+            self.notify_plugins(PluginEvent::Chat { player_id, message: &message });
         }
         Ok(())
     }
 
-    pub fn get_welcome_message(&self, player_id: &str) -> ServerMessage {
-        let players: Vec<Player> = self.players.iter().map(|p| p.value().clone()).collect();
+    // Stats of a connected player; lifetime totals include what hasn't been
+    // flushed to the account yet
+    pub fn stats_snapshot(&self, player_id: &PlayerId) -> Option<ServerMessage> {
+        let (session, pending) = self.stats.current(player_id)?;
+        let lifetime = pending.and_then(|(username, unflushed)| {
+            let mut lifetime = self.accounts.get(&username)?.stats;
+            lifetime.add(&unflushed);
+            Some(lifetime)
+        });
+        Some(ServerMessage::StatsSnapshot {
+            player_id: player_id.clone(),
+            session,
+            lifetime,
+        })
+    }
+
+    pub fn leaderboard_message(&self) -> ServerMessage {
+        ServerMessage::Leaderboard {
+            daily: self.accounts.leaderboard(Period::Daily),
+            alltime: self.accounts.leaderboard(Period::AllTime),
+        }
+    }
+
+    pub fn get_welcome_message(&self, player_id: &PlayerId) -> ServerMessage {
+        let (tier, room, appearance) = self
+            .players
+            .get(player_id)
+            .map(|p| (p.tier, p.room.clone(), p.appearance()))
+            .unwrap_or_else(|| (Tier::default(), rooms::default_room(), Appearance::default()));
+        let players: Vec<Player> = self
+            .players
+            .iter()
+            .filter(|p| p.room == room)
+            .map(|p| p.value().clone())
+            .collect();
+        let world = self.rooms.world_of(&room).announced();
+        let slow_mode_secs = self.rooms.get(&room).and_then(|room| room.slow_mode()).map_or(0, |interval| interval.as_secs());
         ServerMessage::Welcome {
-            your_id: player_id.to_string(),
+            your_id: player_id.clone(),
             players,
+            capabilities: tier.capabilities(&self.config()),
+            room,
+            appearance,
+            world,
+            slow_mode_secs,
         }
     }
 
+    pub fn set_idle(&self, player_id: &str, idle: bool) -> Result<()> {
+        let Some(mut player) = self.players.get_mut(player_id) else {
+            return Ok(());
+        };
+        if player.idle == idle {
+            return Ok(());
+        }
+        player.idle = idle;
+        let (player_id, room) = (player.id.clone(), player.room.clone());
+        drop(player);
+        debug!(%player_id, idle, "Player idle changed");
+        self.broadcast_to_room(&room, ServerMessage::PlayerIdle { player_id, idle })
+    }
+
+    pub fn set_nickname(&self, player_id: &str, nickname: &str) -> Result<()> {
+        let Some(mut player) = self.players.get_mut(player_id) else {
+            return Ok(());
+        };
+        if player.nickname == nickname {
+            return Ok(());
+        }
+        player.nickname = nickname.to_string();
+        let (player_id, room) = (player.id.clone(), player.room.clone());
+        drop(player);
+        info!(%player_id, nickname, "Player changed nickname");
+        self.broadcast_to_room(&room, ServerMessage::NickChanged { player_id, nickname: nickname.to_string() })
+    }
+
+    pub fn set_sprite(&self, player_id: &str, sprite: u32) -> Result<()> {
+        self.change_appearance(player_id, |appearance| appearance.avatar = sprite)
+    }
+
+    pub fn set_color(&self, player_id: &str, color: &str) -> Result<()> {
+        self.change_appearance(player_id, |appearance| appearance.color = color.to_string())
+    }
+
+    // Applies `change` and tells the room, if it changed anything
+    fn change_appearance(&self, player_id: &str, change: impl FnOnce(&mut Appearance)) -> Result<()> {
+        let Some(mut player) = self.players.get_mut(player_id) else {
+            return Ok(());
+        };
+        let mut appearance = player.appearance();
+        change(&mut appearance);
+        if appearance == player.appearance() {
+            return Ok(());
+        }
+        player.color = appearance.color.clone();
+        player.sprite = appearance.avatar;
+        let (player_id, room) = (player.id.clone(), player.room.clone());
+        drop(player);
+        info!(%player_id, color = %appearance.color, avatar = appearance.avatar, "Player changed appearance");
+        let Appearance { color, avatar } = appearance;
+        self.broadcast_to_room(&room, ServerMessage::AppearanceChanged { player_id, color, avatar })
+    }
+
     pub fn broadcast_message(&self, message: ServerMessage) -> Result<()> {
-        let _ = self.broadcast_tx.send(message);
+        let receivers = self.broadcast_tx.send(OutboundFrame::new(message)).unwrap_or(0);
+        self.metrics.broadcast_fanout.observe(receivers as u64);
+        Ok(())
+    }
+
+    pub fn broadcast_to_room(&self, room: &str, message: ServerMessage) -> Result<()> {
+        if let Some(room) = self.rooms.get(room) {
+            room.broadcast(OutboundFrame::new(message));
+        }
         Ok(())
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<ServerMessage> {
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<OutboundFrame>> {
         self.broadcast_tx.subscribe()
     }
+
+    // The current config. Don't hold on to it: a reload can replace it.
+    pub fn config(&self) -> arc_swap::Guard<Arc<Config>> {
+        self.config.load()
+    }
+
+    // Applies changed runtime settings (see Config::reload) without touching
+    // connections
+    pub fn reload_config(&self) -> Result<()> {
+        let config = self.config().reload()?;
+        info!(
+            guest_chat_per_minute = config.guest_chat_per_minute,
+            registered_chat_per_minute = config.registered_chat_per_minute,
+            joins_per_minute_per_ip = config.joins_per_minute_per_ip,
+            joins_per_minute = config.joins_per_minute,
+            motd = config.motd.is_some(),
+            filtered_words = config.word_filter.len(),
+            expand_emoji = config.expand_emoji,
+            npc_count = config.npc_count,
+            "Config reloaded"
+        );
+        let npc_count = config.npc_count;
+        self.config.store(Arc::new(config));
+        self.set_npc_count(npc_count);
+        Ok(())
+    }
 }
 
+// From this many lags on, a connection falling behind the broadcast channels
+// is logged as a warning, naming the client
+const REPEATED_LAGS: u64 = 3;
+
+// Tags every log line belonging to one WebSocket connection
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
 async fn handle_websocket_upgrade(
     stream: hyper::upgrade::Upgraded,
-    addr: SocketAddr,
     server: GameServer,
+    codec: Codec,
+    // Session token from the signed session cookie sent with the upgrade
+    cookie_token: Option<String>,
+    addr: RemoteAddr,
 ) -> Result<()> {
-    info!("WebSocket connection from: {}", addr);
+    info!(codec = codec.subprotocol(), "WebSocket connection opened");
+    // Filled in on the connection span once the client joins
+    let connection_span = Span::current();
     
     let ws_stream = tokio_tungstenite::WebSocketStream::from_raw_socket(
         TokioIo::new(stream),
         tokio_tungstenite::tungstenite::protocol::Role::Server,
         None,
     ).await;
-    let (ws_sender, mut ws_receiver) = ws_stream.split();
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+
+    // The account isn't known until Join, but a staff session cookie already
+    // exempts the connection from IP bans
+    let cookie_role = cookie_token
+        .as_deref()
+        .and_then(|token| server.accounts.session_account(token))
+        .map_or(Role::Player, |account| Role::of(&server.config(), &account));
+    if let Some(ban) = server.bans.find(moderation::banned_ip(cookie_role, addr.ip()), None) {
+        info!(ban_id = ban.id, "Rejected banned address");
+        send_frame(&mut ws_sender, codec, 0, &error_frame(&ban.message())).await?;
+        send_close(&mut ws_sender, bans::BANNED_CLOSE_CODE, "Banned".to_string()).await?;
+        // Give the client a moment to complete the close handshake
+        let _ = tokio::time::timeout(Duration::from_secs(1), ws_receiver.next()).await;
+        return Ok(());
+    }
     
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    let queue = Arc::new(SendQueue::new(
+        server.config().send_queue_capacity,
+        server.config().send_queue_drop_limit,
+        Arc::clone(&server.metrics),
+    ));
     let mut broadcast_rx = server.subscribe();
-    let mut player_id: Option<String> = None;
+    // Spectators watch the lobby; joining hands the broadcast task a
+    // subscription to the player's room. Subscribing happens before the player
+    // is added, so nothing sent to the room in between is missed.
+    let mut room_rx = server.rooms.lobby().subscribe();
+    let (room_change_tx, mut room_change_rx) = tokio::sync::mpsc::unbounded_channel::<broadcast::Receiver<Arc<OutboundFrame>>>();
+    let mut player_id: Option<PlayerId> = None;
+    // Username of the registered account this connection plays as
+    let mut account: Option<String> = None;
+    let mut tier = Tier::Guest;
+    let mut role = Role::Player;
+    let mut chat_limiter = ChatLimiter::default();
+    let acks = Arc::new(AckTracker::default());
+    // The joined player's ID, needed by the outgoing task for interest filtering
+    let viewer_id: Arc<Mutex<Option<PlayerId>>> = Arc::new(Mutex::new(None));
     
     // Handle incoming messages
     let server_clone = server.clone();
-    let tx_clone = tx.clone();
+    let queue_clone = Arc::clone(&queue);
+    let acks_clone = Arc::clone(&acks);
+    let viewer_id_clone = Arc::clone(&viewer_id);
     let incoming_task = tokio::spawn(async move {
         while let Some(msg) = ws_receiver.next().await {
+            // Kicked or banned; the close frame is on its way
+            if queue_clone.is_closing() {
+                break;
+            }
             match msg {
-                Ok(Message::Text(text)) => {
-                    if let Ok(client_msg) = serde_json::from_str::<ClientMessage>(&text) {
-                        match client_msg {
-                            ClientMessage::Join { nickname } => {
-                                let player = Player::new(nickname);
-                                match server_clone.add_player(player.clone()) {
-                                    Ok(pid) => {
-                                        player_id = Some(pid.clone());
-                                        let welcome = server_clone.get_welcome_message(&pid);
-                                        let welcome_json = serde_json::to_string(&welcome).unwrap();
-                                        if let Err(e) = tx_clone.send(Message::Text(welcome_json)) {
-                                            error!("Failed to send welcome: {}", e);
+                Ok(frame @ (Message::Text(_) | Message::Binary(_))) => {
+                    let received_at = unix_millis();
+                    match codec.decode(&frame) {
+                        Ok(client_msg) => {
+                            server_clone.metrics.messages_received.fetch_add(1, Ordering::Relaxed);
+                            let msg_type = client_msg.kind();
+                            debug!(msg_type, "Received message");
+                            match client_msg {
+                                ClientMessage::Join { nickname, token, invite } => {
+                                    if let Err(wait) = server_clone.try_join(addr.ip()) {
+                                        let wait = Duration::from_secs(wait.as_secs().max(1));
+                                        let message = format!("Too many attempts to join, try again in {}", commands::format_duration(wait));
+                                        let _ = queue_clone.push(coded_error_frame(ErrorCode::JoinRateLimited, &message));
+                                        queue_clone.close(join_limit::JOIN_LIMITED_CLOSE_CODE, "Too many joins");
+                                        continue;
+                                    }
+                                    if server_clone.is_overloaded() {
+                                        server_clone.metrics.joins_busy.fetch_add(1, Ordering::Relaxed);
+                                        let _ = queue_clone.push(coded_error_frame(ErrorCode::ServerBusy, "The server is too busy right now, try again shortly"));
+                                        queue_clone.close(overload::BUSY_CLOSE_CODE, "Server busy");
+                                        continue;
+                                    }
+                                    let mut player = Player::new(nickname, &server_clone.rooms.lobby().world());
+                                    // An explicit token must be valid; a stale session
+                                    // cookie just means joining as a guest
+                                    let explicit = token.is_some();
+                                    let registered = token
+                                        .or_else(|| cookie_token.clone())
+                                        .and_then(|token| server_clone.accounts.session_account(&token));
+                                    if let Some(registered) = registered {
+                                        role = Role::of(&server_clone.config(), &registered);
+                                        player.tier = role.tier();
+                                        if let Some((x, y)) = registered.resume_position(server_clone.config().resume_window_secs) {
+                                            player.x = x;
+                                            player.y = y;
+                                        }
+                                        let banned_ip = moderation::banned_ip(role, addr.ip());
+                                        if let Some(ban) = server_clone.bans.find(banned_ip, Some(&registered.username)) {
+                                            info!(ban_id = ban.id, account = %registered.username, "Rejected banned account");
+                                            let _ = queue_clone.push(error_frame(&ban.message()));
+                                            queue_clone.close(bans::BANNED_CLOSE_CODE, "Banned");
+                                            // The client's reply to the close ends the loop
+                                            continue;
+                                        }
+                                        if let takeover::Duplicate::Reject = server_clone.check_duplicate(&registered.username) {
+                                            let frame = coded_error_frame(ErrorCode::AlreadyConnected, "You're already playing in another window");
+                                            if queue_clone.push(frame).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        player.nickname = registered.nickname;
+                                        player.color = registered.color;
+                                        account = Some(registered.username);
+                                    } else if explicit {
+                                        if queue_clone.push(error_frame("Invalid or expired session token")).is_err() {
                                             break;
                                         }
-                                        info!("Player {} joined as {}", pid, player.nickname);
+                                        continue;
+                                    }
+                                    // Back after a restart, where they were, unless
+                                    // an invite says where to go
+                                    if let (Some(username), None) = (&account, &invite) {
+                                        server_clone.reclaim_restored(username, &mut player);
+                                    }
+                                    if let Some(invite) = invite {
+                                        let invite = match invites::Invite::verify(&server_clone.config(), &invite) {
+                                            Ok(invite) => invite,
+                                            Err(e) => {
+                                                if queue_clone.push(error_frame(&e.to_string())).is_err() {
+                                                    break;
+                                                }
+                                                continue;
+                                            }
+                                        };
+                                        let room = server_clone.rooms.get_or_create_private(&invite.room);
+                                        if let Err(e) = server_clone.check_capacity(&room) {
+                                            if queue_clone.push(error_frame(&e.to_string())).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        player.room = room.id.clone();
+                                        // Registered players keep their account's nickname
+                                        if let (Some(nickname), None) = (invite.nickname, &account) {
+                                            player.nickname = nickname;
+                                        }
+                                    }
+                                    // Guests' nicknames get the ChangeNick checks;
+                                    // registered players play under their account's,
+                                    // which was checked when it was set
+                                    if account.is_none() {
+                                        player.nickname = player.nickname.trim().to_string();
+                                        if !nicknames::is_valid(&player.nickname) {
+                                            let message = format!("Nicknames must be 1 to {} characters", nicknames::MAX_LEN);
+                                            if queue_clone.push(error_frame(&message)).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        if let Some(reason) = server_clone.nickname_reserved(&player.nickname, None, role) {
+                                            if queue_clone.push(coded_error_frame(ErrorCode::NicknameReserved, &reason)).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                    }
+                                    if &*player.room != rooms::DEFAULT_ROOM {
+                                        if let Some(room) = server_clone.rooms.get(&player.room) {
+                                            let _ = room_change_tx.send(room.subscribe());
+                                        }
+                                    }
+                                    tier = player.tier;
+                                    match server_clone.add_player(player.clone()) {
+                                        Ok(pid) => {
+                                            if let Some(username) = &account {
+                                                server_clone.forget_restored(username);
+                                            }
+                                            let joined_account = account.as_deref().and_then(|u| server_clone.accounts.get(u));
+                                            server_clone.stats.joined(&pid, joined_account.as_ref());
+                                            server_clone.connections.insert(pid.clone(), Connection {
+                                                queue: Arc::clone(&queue_clone),
+                                                account: account.clone(),
+                                                addr,
+                                                role,
+                                            });
+                                            player_id = Some(pid.clone());
+                                            connection_span.record("player_id", &*pid);
+                                            let welcome = server_clone.get_welcome_message(&pid);
+                                            if let Err(e) = queue_clone.push(OutboundFrame::new(welcome)) {
+                                                error!("Failed to send welcome: {}", e);
+                                                break;
+                                            }
+                                            // Only now, so no chunk of the world goes out before the Welcome
+                                            *viewer_id_clone.lock().unwrap() = Some(pid.clone());
+                                            let leaderboard = server_clone.leaderboard_message();
+                                            if queue_clone.push(OutboundFrame::new(leaderboard)).is_err() {
+                                                break;
+                                            }
+                                            if let Some(motd) = server_clone.config().motd.clone() {
+                                                if queue_clone.push(OutboundFrame::new(moderation::system_message(motd))).is_err() {
+                                                    break;
+                                                }
+                                            }
+                                            info!(nickname = %player.nickname, account = account.as_deref(), room = %player.room, "Player joined");
+                                        }
+                                        Err(e) => error!("Failed to add player: {}", e),
                                     }
-                                    Err(e) => error!("Failed to add player: {}", e),
                                 }
-                            }
-                            ClientMessage::Move { x, y } => {
-                                if let Some(ref pid) = player_id {
-                                    if let Err(e) = server_clone.move_player(pid, x, y) {
-                                        error!("Failed to move player: {}", e);
+                                ClientMessage::Move { x, y } => {
+                                    if let Some(ref pid) = player_id {
+                                        if let Err(e) = server_clone.move_player(pid, x, y) {
+                                            error!(msg_type, "Failed to move player: {}", e);
+                                        }
                                     }
                                 }
-                            }
-                            ClientMessage::Chat { message } => {
-                                if let Some(ref pid) = player_id {
-                                    if let Err(e) = server_clone.send_chat(pid, message) {
-                                        error!("Failed to send chat: {}", e);
+                                ClientMessage::Chat { message } => {
+                                    if let Some(ref pid) = player_id {
+                                        if !chat_limiter.try_send(tier.capabilities(&server_clone.config()).chat_per_minute) {
+                                            if queue_clone.push(error_frame("You're sending messages too fast")).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        if let Some(command) = Command::parse(&message) {
+                                            let Some((nickname, room)) = server_clone.players.get(pid).map(|p| (p.nickname.clone(), p.room.clone())) else {
+                                                continue;
+                                            };
+                                            let by = Moderator { role, name: &nickname };
+                                            if let Err(e) = command.and_then(|c| c.in_room(&room).run(&server_clone, &by)) {
+                                                if queue_clone.push(error_frame(&e.to_string())).is_err() {
+                                                    break;
+                                                }
+                                            }
+                                            continue;
+                                        }
+                                        if let Some(remaining) = server_clone.muted_for(pid) {
+                                            let message = format!("You are muted for {} more minute(s)", remaining.as_secs().div_ceil(60));
+                                            if queue_clone.push(error_frame(&message)).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        if role < Role::Moderator {
+                                            if let Some(wait) = server_clone.slow_mode_wait(pid) {
+                                                let message = format!("Slow mode is on, wait {}s before sending another message", wait.as_millis().div_ceil(1000));
+                                                if queue_clone.push(coded_error_frame(ErrorCode::SlowMode, &message)).is_err() {
+                                                    break;
+                                                }
+                                                continue;
+                                            }
+                                        }
+                                        if let Err(e) = server_clone.send_chat(pid, message) {
+                                            error!(msg_type, "Failed to send chat: {}", e);
+                                        }
                                     }
                                 }
-                            }
-                            ClientMessage::ChangeNick { nickname } => {
-                                if let Some(ref pid) = player_id {
-                                    if let Some(mut player) = server_clone.players.get_mut(pid) {
-                                        player.nickname = nickname;
-                                        info!("Player {} changed nickname to {}", pid, player.nickname);
+                                ClientMessage::ChangeNick { nickname } => {
+                                    if let Some(ref pid) = player_id {
+                                        if !tier.capabilities(&server_clone.config()).change_nickname {
+                                            if queue_clone.push(error_frame("Register an account to change your nickname")).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        let nickname = nickname.trim().to_string();
+                                        if !nicknames::is_valid(&nickname) {
+                                            let message = format!("Nicknames must be 1 to {} characters", nicknames::MAX_LEN);
+                                            if queue_clone.push(error_frame(&message)).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        if let Some(reason) = server_clone.nickname_reserved(&nickname, account.as_deref(), role) {
+                                            if queue_clone.push(coded_error_frame(ErrorCode::NicknameReserved, &reason)).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        let _ = server_clone.set_nickname(pid, &nickname);
+                                        if let (Some(presence), Some(player)) = (&server_clone.presence, server_clone.players.get(pid)) {
+                                            presence.online(pid, &nickname, &player.room);
+                                        }
+                                        if let Some(ref username) = account {
+                                            server_clone.accounts.set_nickname(username, &nickname);
+                                        }
+                                    }
+                                }
+                                ClientMessage::ChangeColor { color } => {
+                                    if let Some(ref pid) = player_id {
+                                        let rejection = if !tier.capabilities(&server_clone.config()).choose_color {
+                                            Some("Register an account to choose your color")
+                                        } else if !tier::is_valid_color(&color) {
+                                            Some("Colors must be in #RRGGBB form")
+                                        } else {
+                                            None
+                                        };
+                                        if let Some(rejection) = rejection {
+                                            if queue_clone.push(error_frame(rejection)).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        let _ = server_clone.set_color(pid, &color);
+                                        if let Some(ref username) = account {
+                                            server_clone.accounts.set_color(username, &color);
+                                        }
+                                    }
+                                }
+                                ClientMessage::Ack { seq } => acks_clone.ack(seq),
+                                ClientMessage::TimeSyncRequest { client_time } => {
+                                    let response = ServerMessage::TimeSyncResponse {
+                                        client_time,
+                                        server_receive_time: received_at,
+                                        server_send_time: unix_millis(),
+                                    };
+                                    if queue_clone.push(OutboundFrame::new(response)).is_err() {
+                                        break;
+                                    }
+                                }
+                                ClientMessage::Resync => {
+                                    if let Some(ref pid) = player_id {
+                                        let welcome = server_clone.get_welcome_message(pid);
+                                        if queue_clone.push(OutboundFrame::new(welcome)).is_err() {
+                                            break;
+                                        }
+                                    }
+                                }
+                                ClientMessage::Report { target_id, reason } => {
+                                    if let Some(ref pid) = player_id {
+                                        let frame = match server_clone.report(pid, &target_id, reason) {
+                                            Ok(()) => OutboundFrame::new(moderation::system_message(
+                                                "Thanks, your report was sent to the moderators".to_string(),
+                                            )),
+                                            Err(e) => error_frame(&e.to_string()),
+                                        };
+                                        if queue_clone.push(frame).is_err() {
+                                            break;
+                                        }
+                                    }
+                                }
+                                ClientMessage::JoinRoom { room, password } => {
+                                    if let Some(ref pid) = player_id {
+                                        // Chunks wait for the new room's Welcome, as after joining
+                                        *viewer_id_clone.lock().unwrap() = None;
+                                        match server_clone.change_room(pid, &room, password, addr.ip()).await {
+                                            Ok(subscription) => {
+                                                let _ = room_change_tx.send(subscription);
+                                                let welcome = server_clone.get_welcome_message(pid);
+                                                if queue_clone.push(OutboundFrame::new(welcome)).is_err() {
+                                                    break;
+                                                }
+                                            }
+                                            Err(e) => {
+                                                if queue_clone.push(error_frame(&e.to_string())).is_err() {
+                                                    break;
+                                                }
+                                            }
+                                        }
+                                        *viewer_id_clone.lock().unwrap() = Some(pid.clone());
+                                    }
+                                }
+                                ClientMessage::SetIdle { idle } => {
+                                    if let Some(ref pid) = player_id {
+                                        let _ = server_clone.set_idle(pid, idle);
+                                    }
+                                }
+                                ClientMessage::ChangeSprite { sprite } => {
+                                    if let Some(ref pid) = player_id {
+                                        if sprite >= MAX_SPRITES {
+                                            if queue_clone.push(error_frame("Unknown sprite")).is_err() {
+                                                break;
+                                            }
+                                            continue;
+                                        }
+                                        let _ = server_clone.set_sprite(pid, sprite);
+                                    }
+                                }
+                                ClientMessage::Custom { channel, payload, to } => {
+                                    if let Some(ref pid) = player_id {
+                                        if let Err(e) = server_clone.send_custom(pid, channel, payload, to.as_deref()) {
+                                            if queue_clone.push(error_frame(&e.to_string())).is_err() {
+                                                break;
+                                            }
+                                        }
+                                    }
+                                }
+                                ClientMessage::ListRooms => {
+                                    let rooms = ServerMessage::RoomList { rooms: server_clone.room_list(false) };
+                                    if queue_clone.push(OutboundFrame::new(rooms)).is_err() {
+                                        break;
+                                    }
+                                }
+                                ClientMessage::RequestStats { player_id: target } => {
+                                    let Some(target) = target.or_else(|| player_id.clone()) else {
+                                        continue;
+                                    };
+                                    let frame = match server_clone.stats_snapshot(&target) {
+                                        Some(snapshot) => OutboundFrame::new(snapshot),
+                                        None => error_frame("No such player"),
+                                    };
+                                    if queue_clone.push(frame).is_err() {
+                                        break;
                                     }
                                 }
                             }
                         }
-                    } else {
-                        warn!("Invalid message format: {}", text);
+                        Err(e) => warn!("Invalid message: {}", e),
                     }
                 }
                 Ok(Message::Close(_)) => {
@@ -258,66 +1217,145 @@ async fn handle_websocket_upgrade(
 
         // Clean up player when connection closes
         if let Some(pid) = player_id {
-            if let Err(e) = server_clone.remove_player(&pid) {
-                error!("Failed to remove player: {}", e);
-            } else {
-                info!("Player {} disconnected", pid);
+            server_clone.connections.remove(&pid);
+            server_clone.reports.forget(&pid);
+            match server_clone.remove_player(&pid) {
+                Ok(player) => {
+                    // Remember where registered players left so they can resume there
+                    if let (Some(player), Some(username)) = (player, &account) {
+                        server_clone.accounts.save_position(username, player.x, player.y);
+                    }
+                    if let Some((username, stats)) = server_clone.stats.left(&pid) {
+                        server_clone.accounts.add_stats(&username, &stats);
+                    }
+                    info!("Player disconnected");
+                }
+                Err(e) => error!("Failed to remove player: {}", e),
             }
         }
-    });
+    }.instrument(info_span!("incoming")));
 
-    // Handle outgoing messages
-    let outgoing_task = tokio::spawn(async move {
-        let mut ws_sender = ws_sender;
+    // Queue broadcasts for this connection, filtered by interest
+    let queue_clone = Arc::clone(&queue);
+    let server_clone = server.clone();
+    let broadcast_task = tokio::spawn(async move {
+        let new_filter = || {
+            let config = server_clone.config();
+            InterestFilter::new(config.interest_near_radius, config.far_update_hz, config.chunk_radius)
+        };
+        let mut interest = new_filter();
+        let mut far_flush = tokio::time::interval(interest.far_interval());
         loop {
-            tokio::select! {
-                // Send broadcast messages
-                server_msg = broadcast_rx.recv() => {
-                    match server_msg {
-                        Ok(msg) => {
-                            let json = serde_json::to_string(&msg).unwrap();
-                            if let Err(e) = ws_sender.send(Message::Text(json)).await {
-                                error!("Failed to send broadcast message: {}", e);
-                                break;
-                            }
-                        }
-                        Err(_) => break,
-                    }
+            let received = tokio::select! {
+                // A room change comes first, so nothing sent to the old room
+                // after the player left it gets through
+                biased;
+                Some(rx) = room_change_rx.recv() => {
+                    room_rx = rx;
+                    // Nothing held back from the previous room applies any more
+                    interest = new_filter();
+                    continue;
                 }
-                // Send direct messages
-                direct_msg = rx.recv() => {
-                    match direct_msg {
-                        Some(msg) => {
-                            if let Err(e) = ws_sender.send(msg).await {
-                                error!("Failed to send direct message: {}", e);
-                                break;
-                            }
-                        }
-                        None => break,
+                server_msg = broadcast_rx.recv() => server_msg.map(Some),
+                room_msg = room_rx.recv() => room_msg.map(Some),
+                // Send far-away position updates that were held back
+                _ = far_flush.tick() => Ok(None),
+            };
+            let viewer = viewer_id.lock().unwrap().clone();
+            let viewer = viewer.and_then(|id| {
+                let player = server_clone.players.get(&id)?;
+                Some((id.clone(), player.x, player.y, player.room.clone()))
+            });
+            let frame = match received {
+                Ok(Some(frame)) => interest.apply(viewer.as_ref().map(|(id, x, y, _)| (&**id, *x, *y)), frame),
+                Ok(None) => interest.flush(),
+                // What was missed is gone; carry on from the oldest message
+                // still in the channel
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    let lags = queue_clone.lagged(missed);
+                    if lags >= REPEATED_LAGS {
+                        warn!(missed, lags, "Client keeps falling behind broadcasts");
+                    } else {
+                        debug!(missed, lags, "Client fell behind broadcasts");
                     }
+                    continue;
                 }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            // Chunks come into and go out of range after the frame, which
+            // may be the WorldChanged they belong to
+            let chunks = viewer.map_or_else(Vec::new, |(_, x, y, room)| {
+                interest.stream_chunks(&server_clone.rooms.world_of(&room), x, y)
+            });
+            if let Err(e) = frame.into_iter().chain(chunks).try_for_each(|frame| queue_clone.push(frame)) {
+                warn!("Disconnecting slow client: {}", e);
+                break;
             }
         }
-    });
+    }.instrument(info_span!("broadcast")));
 
-    // Wait for either task to complete
+    // Write queued messages to the socket
+    let outgoing_task = tokio::spawn(async move {
+        let mut ws_sender = ws_sender;
+        loop {
+            let (frame, queued) = match queue.pop().await {
+                Outgoing::Frame(frame, queued) => (frame, queued),
+                Outgoing::Close(code, reason) => {
+                    let _ = send_close(&mut ws_sender, code, reason).await;
+                    break;
+                }
+            };
+            if acks.is_stalled() {
+                warn!(unacked = acks.unacked(), "Client stalled, disconnecting");
+                let _ = ws_sender.close().await;
+                break;
+            }
+            if let Err(e) = send_frame(&mut ws_sender, codec, acks.next_seq(), &frame).await {
+                error!(msg_type = frame.message.kind(), "Failed to send message: {}", e);
+                break;
+            }
+            queue.sent(&frame, queued);
+        }
+    }.instrument(info_span!("outgoing")));
+
+    // Wait for any task to complete
     tokio::select! {
         _ = incoming_task => {},
+        _ = broadcast_task => {},
         _ = outgoing_task => {},
     }
 
-    info!("WebSocket connection {} closed", addr);
+    info!("WebSocket connection closed");
     Ok(())
 }
 
+// Writes every WebSocket frame of an outbound message before flushing
+async fn send_frame<S>(ws_sender: &mut S, codec: Codec, seq: u64, frame: &OutboundFrame) -> Result<(), S::Error>
+where
+    S: futures_util::Sink<Message> + Unpin,
+{
+    for message in codec.encode(seq, frame) {
+        ws_sender.feed(message).await?;
+    }
+    ws_sender.flush().await
+}
+
+async fn send_close<S>(ws_sender: &mut S, code: u16, reason: String) -> Result<(), S::Error>
+where
+    S: futures_util::Sink<Message> + Unpin,
+{
+    let frame = CloseFrame { code: CloseCode::from(code), reason: reason.into() };
+    ws_sender.send(Message::Close(Some(frame))).await
+}
+
 // WebSocket magic string as defined in RFC 6455
 const WEBSOCKET_MAGIC_STRING: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
 // Check if this is a WebSocket upgrade request
 fn is_websocket_upgrade(req: &Request<Incoming>) -> bool {
     req.method() == Method::GET &&
-    req.headers().get("upgrade").map_or(false, |h| h == "websocket") &&
-    req.headers().get("connection").map_or(false, |h| h.to_str().unwrap_or("").to_lowercase().contains("upgrade")) &&
+    req.headers().get("upgrade").is_some_and(|h| h == "websocket") &&
+    req.headers().get("connection").is_some_and(|h| h.to_str().unwrap_or("").to_lowercase().contains("upgrade")) &&
     req.headers().get("sec-websocket-key").is_some()
 }
 
@@ -327,17 +1365,58 @@ fn calculate_websocket_accept(key: &str) -> String {
     hasher.update(key.as_bytes());
     hasher.update(WEBSOCKET_MAGIC_STRING.as_bytes());
     let hash = hasher.finalize();
-    general_purpose::STANDARD.encode(&hash)
+    general_purpose::STANDARD.encode(hash)
 }
 
 async fn handle_request(
+    req: Request<Incoming>,
+    server: GameServer,
+    peer: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let cors = server.config().cors.clone();
+    if cors::is_preflight(&req) {
+        return Ok(cors::preflight(&cors, &req));
+    }
+    let origin = req.headers().get(hyper::header::ORIGIN).cloned();
+    let mut response = route(req, server, peer).await?;
+    cors::apply(&cors, origin.as_ref(), response.headers_mut());
+    Ok(response)
+}
+
+async fn route(
     mut req: Request<Incoming>,
     server: GameServer,
+    peer: SocketAddr,
 ) -> Result<Response<Full<Bytes>>, Infallible> {
-    
+
+    // With an admin listener, the admin routes are only served there, and
+    // it serves nothing else
+    let path = req.uri().path();
+    let admin_route = path == "/admin" || path.starts_with("/admin/") || path.starts_with("/debug/pprof/") || path == "/ws/admin";
+    let on_admin_listener = req.extensions().get::<tls::ClientCertificate>().is_some();
+    let misrouted = if on_admin_listener {
+        !admin_route
+    } else {
+        admin_route && server.config().admin_port.is_some()
+    };
+    if misrouted {
+        return Ok(http::error_response(StatusCode::NOT_FOUND, "not found"));
+    }
+
+    let is_ws = matches!(req.uri().path(), "/ws" | "/ws/admin") && is_websocket_upgrade(&req);
+    if is_ws && !origin::allowed(&server.config(), &req) {
+        warn!(origin = ?req.headers().get(hyper::header::ORIGIN), "Rejected WebSocket upgrade from another site");
+        return Ok(http::error_response(StatusCode::FORBIDDEN, "origin not allowed"));
+    }
+
     // Handle WebSocket upgrade
     if req.uri().path() == "/ws" && is_websocket_upgrade(&req) {
         info!("WebSocket upgrade request received");
+        if server.is_draining() {
+            let mut response = http::error_response(StatusCode::SERVICE_UNAVAILABLE, "server is draining, reconnect elsewhere");
+            response.headers_mut().insert("retry-after", server.config().drain_retry_after_secs.into());
+            return Ok(response);
+        }
         
         // Get the WebSocket key for handshake
         let ws_key = req.headers()
@@ -346,112 +1425,259 @@ async fn handle_request(
             .unwrap_or("");
         
         let accept_key = calculate_websocket_accept(ws_key);
-        
-        match hyper::upgrade::on(&mut req).await {
-            Ok(upgraded) => {
-                let addr = "0.0.0.0:80".parse().unwrap(); // Placeholder
-                
-                tokio::spawn(async move {
-                    if let Err(e) = handle_websocket_upgrade(upgraded, addr, server).await {
+
+        let requested = req.headers()
+            .get("sec-websocket-protocol")
+            .and_then(|h| h.to_str().ok());
+        let negotiated = requested.and_then(Codec::negotiate);
+        let codec = negotiated.unwrap_or(Codec::Json);
+
+        // The upgrade only completes once the 101 response below has been sent,
+        // so it must be awaited in the background
+        let cookie_token = session::session_token(&server.config(), &req);
+        let addr = RemoteAddr::from_request(&server.config(), &req, peer);
+        let on_upgrade = hyper::upgrade::on(&mut req);
+        let conn_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+        // Every log line from this connection's tasks carries these fields
+        let span = info_span!("connection", conn_id, %addr, player_id = field::Empty);
+        let open = server.open_connection();
+        tokio::spawn(async move {
+            let _open = open;
+            match on_upgrade.await {
+                Ok(upgraded) => {
+                    if let Err(e) = handle_websocket_upgrade(upgraded, server, codec, cookie_token, addr).await {
                         error!("WebSocket handler error: {}", e);
                     }
-                });
-
-                return Ok(Response::builder()
-                    .status(StatusCode::SWITCHING_PROTOCOLS)
-                    .header("upgrade", "websocket")
-                    .header("connection", "upgrade")
-                    .header("sec-websocket-accept", accept_key)
-                    .body(Full::new(Bytes::new()))
-                    .unwrap());
-            }
-            Err(e) => {
-                error!("WebSocket upgrade failed: {}", e);
+                }
+                Err(e) => error!("WebSocket upgrade failed: {}", e),
             }
+        }.instrument(span));
+
+        let mut response = Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header("upgrade", "websocket")
+            .header("connection", "upgrade")
+            .header("sec-websocket-accept", accept_key);
+        if let Some(codec) = negotiated {
+            response = response.header("sec-websocket-protocol", codec.subprotocol());
         }
+        return Ok(response.body(Full::new(Bytes::new())).unwrap());
+    }
+
+    if req.uri().path() == "/ws/admin" && is_websocket_upgrade(&req) {
+        let addr = RemoteAddr::from_request(&server.config(), &req, peer);
+        return Ok(admin_ws::upgrade(req, server, addr));
+    }
+
+    if req.uri().path().starts_with("/api/") {
+        return Ok(api::handle_api(req, server).await);
+    }
+
+    if matches!(req.uri().path(), "/healthz" | "/readyz") {
+        return Ok(lifecycle::handle_probe(&req, &server));
+    }
+
+    if req.uri().path().starts_with("/auth/") {
+        return Ok(oauth::handle_auth(req, server).await);
+    }
+
+    if req.uri().path() == "/admin" || req.uri().path().starts_with("/admin/") || req.uri().path().starts_with("/debug/pprof/") {
+        return Ok(admin::handle_admin(req, server).await);
     }
 
     // Handle regular HTTP requests
-    let static_path = std::env::var("STATIC_PATH").unwrap_or_else(|_| "dist".to_string());
-    
+    let config = server.config();
     let path = req.uri().path();
-    let file_path = if path == "/" {
-        format!("{}/index.html", static_path)
-    } else {
-        format!("{}{}", static_path, path)
-    };
+    let file_path = if path == "/" { "/index.html" } else { path };
 
-    match tokio::fs::read(&file_path).await {
-        Ok(contents) => {
-            let content_type = match std::path::Path::new(&file_path).extension() {
-                Some(ext) => match ext.to_str() {
-                    Some("html") => "text/html",
-                    Some("css") => "text/css", 
-                    Some("js") => "application/javascript",
-                    Some("wasm") => "application/wasm",
-                    Some("json") => "application/json",
-                    _ => "application/octet-stream",
-                },
-                None => "text/html",
-            };
+    match assets::read(&config, file_path).await {
+        Some(contents) => {
+            let content_type = assets::content_type(file_path);
 
-            Ok(Response::builder()
+            let response = Response::builder()
                 .status(StatusCode::OK)
-                .header("content-type", content_type)
-                .header("access-control-allow-origin", "*")
-                .body(Full::new(Bytes::from(contents)))
+                .header("content-type", content_type);
+            Ok(http::with_security_headers(response, &config.security_headers)
+                .body(Full::new(contents))
                 .unwrap())
         }
-        Err(_) => {
-            let index_path = format!("{}/index.html", static_path);
-            let index_content = tokio::fs::read(index_path).await
-                .unwrap_or_else(|_| b"<h1>Error: Frontend not built. Run 'npm run build' first.</h1>".to_vec());
+        None => {
+            let index_content = assets::read(&config, "/index.html").await
+                .unwrap_or_else(|| Bytes::from_static(b"<h1>Error: Frontend not built. Run 'npm run build' first.</h1>"));
             
-            Ok(Response::builder()
+            let response = Response::builder()
                 .status(StatusCode::OK)
-                .header("content-type", "text/html")
-                .header("access-control-allow-origin", "*")
-                .body(Full::new(Bytes::from(index_content)))
+                .header("content-type", "text/html; charset=utf-8");
+            Ok(http::with_security_headers(response, &config.security_headers)
+                .body(Full::new(index_content))
                 .unwrap())
         }
     }
 }
 
+// Adds registered players' stats to their accounts every FLUSH_INTERVAL
+async fn flush_stats(server: GameServer) {
+    let mut interval = tokio::time::interval(stats::FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        for (username, stats) in server.stats.take_unflushed() {
+            server.accounts.add_stats(&username, &stats);
+        }
+    }
+}
+
+// Destroys rooms that have been empty for ROOM_IDLE_SECS
+async fn collect_idle_rooms(server: GameServer) {
+    let period = server.config().room_idle.clamp(Duration::from_secs(1), rooms::GC_INTERVAL);
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        server.collect_idle_rooms();
+    }
+}
+
+// Reloads the config on SIGHUP, e.g. `systemctl reload`
+#[cfg(unix)]
+async fn reload_on_sighup(server: GameServer) {
+    let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!("Can't listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(e) = server.reload_config() {
+            error!("Config reload failed: {:#}", e);
+        }
+    }
+}
+
+// Broadcasts the leaderboard whenever it changed since the last broadcast
+async fn broadcast_leaderboard(server: GameServer) {
+    let mut interval = tokio::time::interval(leaderboard::BROADCAST_INTERVAL);
+    let mut last = None;
+    loop {
+        interval.tick().await;
+        let current = (server.accounts.leaderboard(Period::Daily), server.accounts.leaderboard(Period::AllTime));
+        if last.as_ref() == Some(&current) {
+            continue;
+        }
+        let (daily, alltime) = current.clone();
+        let _ = server.broadcast_message(ServerMessage::Leaderboard { daily, alltime });
+        last = Some(current);
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    let config = Config::load()?;
+    let _log_guards = logging::init(&config)?;
+    if config.prestop {
+        return lifecycle::prestop(&config).await;
+    }
+
+    let restore_path = config.restore_path.clone();
+    let handoff_path = config.handoff_path.clone();
 
-    let server = GameServer::new();
+    let backend = storage::open(&config).await?;
+    let loaded = backend.load_accounts().await?;
+    let loaded_bans = backend.load_bans().await?;
+    let loaded_reports = backend.load_reports().await?;
+    let loaded_audit = backend.load_audit().await?;
+    let storage = StorageHandle::spawn(backend);
+    let accounts = AccountStore::new(loaded, storage.clone());
+    info!("👤 Loaded {} accounts", accounts.count());
+    let bans = BanStore::new(loaded_bans, storage.clone());
+    let reports = ReportStore::new(loaded_reports, storage.clone());
+    let audit = AuditLog::new(loaded_audit, storage.clone());
+    let server = GameServer::new(config, accounts, bans, reports, audit, storage).await?;
     info!("🎮 Rust Monolith Server starting...");
 
-    let port = std::env::var("PORT")
-        .unwrap_or_else(|_| "8080".to_string())
-        .parse::<u16>()
-        .unwrap_or(8080);
-    
-    let addr: SocketAddr = ([0, 0, 0, 0], port).into();
-    let listener = tokio::net::TcpListener::bind(addr).await?;
+    if let Some(path) = restore_path {
+        let snapshot = snapshot::load_snapshot(&path)?;
+        info!("♻️ Restoring {} players from {}", snapshot.players.len(), path.display());
+        server.restore(snapshot);
+    } else if let Some(snapshot) = handoff_path.as_deref().map(handoff::take).transpose()?.flatten() {
+        info!("♻️ Restoring {} players and {} rooms handed off by the previous process", snapshot.players.len(), snapshot.rooms.len());
+        server.restore(snapshot);
+    }
+    server.load_scripts(&server.config())?;
+    server.load_plugins(&server.config())?;
+    server.set_npc_count(server.config().npc_count);
     
-    info!("🚀 Server listening on http://0.0.0.0:{}", port);
+    tokio::spawn(tick::run_tick_loop(server.clone()));
+    tokio::spawn(flush_stats(server.clone()));
+    tokio::spawn(broadcast_leaderboard(server.clone()));
+    tokio::spawn(collect_idle_rooms(server.clone()));
+    tokio::spawn(sweeper::run(server.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(server.clone()));
+
+    let activated = systemd::activated_listeners()?;
+    let listeners = if activated.is_empty() {
+        listen::bind(&server.config().listen)?
+    } else {
+        info!(sockets = activated.len(), "🔌 Using the sockets passed by systemd");
+        activated.into_iter().map(tokio::net::TcpListener::from_std).collect::<std::io::Result<_>>()?
+    };
+
+    let tls = tls::acceptor(&server.config())?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    let mut accept_loops = tokio::task::JoinSet::new();
+    for listener in listeners {
+        info!("🚀 Server listening on {}://{}", scheme, listener.local_addr()?);
+        accept_loops.spawn(listen::accept_loop(listener, tls.clone(), server.clone()));
+    }
+    let lifecycle_listener = lifecycle::bind(&server.config())?;
+    info!("♻️ Lifecycle endpoint on http://{}/lifecycle/prestop", lifecycle_listener.local_addr()?);
+    accept_loops.spawn(lifecycle::serve(lifecycle_listener, server.clone()));
     info!("🌐 HTTP static files served from /");
     info!("🔌 WebSocket endpoint: /ws (same port)");
+    info!("🛡️ Admin WebSocket endpoint: /ws/admin");
 
-    while let Ok((tcp, _)) = listener.accept().await {
-        let io = TokioIo::new(tcp);
-        let server_clone = server.clone();
-        
-        tokio::task::spawn(async move {
-            let service = service_fn(move |req| handle_request(req, server_clone.clone()));
-            
-            if let Err(err) = http1::Builder::new()
-                .serve_connection(io, service)
-                .with_upgrades()
-                .await
-            {
-                error!("Error serving connection: {}", err);
+    if let Some(admin_tls) = tls::admin_acceptor(&server.config())? {
+        let admin_port = server.config().admin_port.unwrap_or_default();
+        for listener in listen::bind(&listen::admin_addrs(&server.config().listen, admin_port))? {
+            info!("🔐 Admin listener on https://{}, client certificates required", listener.local_addr()?);
+            accept_loops.spawn(listen::accept_loop(listener, Some(admin_tls.clone()), server.clone()));
+        }
+    }
+
+    // Connections are still accepted while shutting down, so probes and late
+    // upgrades get an answer. The server stops once that's done, and shuts
+    // down the same way if a listener stops working.
+    tokio::select! {
+        _ = lifecycle::terminated() => server.shut_down().await,
+        Some(ended) = accept_loops.join_next() => {
+            match ended {
+                Ok(e) => error!("{:#}", e),
+                Err(e) => error!("Accept loop failed: {}", e),
             }
-        });
+            server.shut_down().await;
+        }
     }
 
     Ok(())
+}
+
+// Speaks HTTP/1.1, with upgrades for WebSockets, or HTTP/2 to clients that
+// open with its preface. Requests on the admin listener carry the client's
+// certificate.
+async fn serve_connection<I>(io: I, server: GameServer, peer: SocketAddr, cert: Option<tls::ClientCertificate>)
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let service = service_fn(move |mut req: Request<Incoming>| {
+        if let Some(cert) = &cert {
+            req.extensions_mut().insert(cert.clone());
+        }
+        handle_request(req, server.clone(), peer)
+    });
+    if let Err(err) = auto::Builder::new(TokioExecutor::new())
+        .serve_connection_with_upgrades(io, service)
+        .await
+    {
+        error!("Error serving connection: {}", err);
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,223 @@
+// The worlds rooms can be played on, each with its own size and obstacles.
+// They're read from WORLDS_FILE at startup; without one there's a single
+// world named "default" of WORLD_WIDTH by WORLD_HEIGHT. Rooms start on the
+// first, and POST /admin/world switches a room to another.
+//
+// A world with a `chunk_size` is split into square chunks, and its obstacles
+// are left out of Welcome: each client is sent the chunks around its player
+// as it moves instead, see interest.rs.
+use crate::config::Config;
+use crate::{GameServer, Player, ServerMessage};
+use anyhow::{bail, Context, Result};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::info;
+
+pub const DEFAULT_WORLD: &str = "default";
+// New players spawn at least this far from the world's edges
+const SPAWN_MARGIN: f32 = 50.0;
+// Random spots tried before giving up on avoiding obstacles
+const SPAWN_ATTEMPTS: usize = 32;
+
+// A rectangle players can't stand in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Obstacle {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Obstacle {
+    fn contains(&self, x: f32, y: f32) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    // Whether the straight line between two points passes through it. Running
+    // along an edge or leaving from one doesn't count.
+    fn crosses(&self, (x0, y0): (f32, f32), (x1, y1): (f32, f32)) -> bool {
+        let (mut enter, mut exit) = (0.0f32, 1.0f32);
+        let axes = [(x0, x1 - x0, self.x, self.x + self.width), (y0, y1 - y0, self.y, self.y + self.height)];
+        for (start, delta, low, high) in axes {
+            if delta == 0.0 {
+                if start <= low || start >= high {
+                    return false;
+                }
+                continue;
+            }
+            let (a, b) = ((low - start) / delta, (high - start) / delta);
+            enter = enter.max(a.min(b));
+            exit = exit.min(a.max(b));
+            if enter >= exit {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Chunk coordinates: column and row
+pub type ChunkId = (i32, i32);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WorldMap {
+    pub name: String,
+    pub width: f32,
+    pub height: f32,
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<f32>,
+    // Obstacles by the chunk their centre is in, for chunked worlds
+    #[serde(skip)]
+    chunks: HashMap<ChunkId, Vec<Obstacle>>,
+}
+
+impl WorldMap {
+    pub fn new(name: String, width: f32, height: f32, obstacles: Vec<Obstacle>, chunk_size: Option<f32>) -> Self {
+        let mut world = Self { name, width, height, obstacles, chunk_size, chunks: HashMap::new() };
+        if let Some(size) = chunk_size {
+            for obstacle in &world.obstacles {
+                let chunk = chunk_of(size, obstacle.x + obstacle.width / 2.0, obstacle.y + obstacle.height / 2.0);
+                world.chunks.entry(chunk).or_default().push(obstacle.clone());
+            }
+        }
+        world
+    }
+
+    // The world as Welcome and WorldChanged describe it: without the
+    // obstacles of a chunked world, which are streamed
+    pub fn announced(&self) -> WorldMap {
+        let mut world = self.clone();
+        if world.chunk_size.is_some() {
+            world.obstacles.clear();
+            world.chunks.clear();
+        }
+        world
+    }
+
+    // The chunks within `radius` chunks of (x, y), for a chunked world
+    pub fn chunks_around(&self, x: f32, y: f32, radius: i32) -> HashSet<ChunkId> {
+        let Some(size) = self.chunk_size else {
+            return HashSet::new();
+        };
+        let (column, row) = chunk_of(size, x, y);
+        let (columns, rows) = ((self.width / size).ceil() as i32, (self.height / size).ceil() as i32);
+        let mut chunks = HashSet::new();
+        for cx in (column - radius).max(0)..=(column + radius).min(columns - 1) {
+            for cy in (row - radius).max(0)..=(row + radius).min(rows - 1) {
+                chunks.insert((cx, cy));
+            }
+        }
+        chunks
+    }
+
+    pub fn chunk(&self, chunk: ChunkId) -> Vec<Obstacle> {
+        self.chunks.get(&chunk).cloned().unwrap_or_default()
+    }
+
+    // Whether a player may stand at (x, y)
+    pub fn fits(&self, x: f32, y: f32) -> bool {
+        (0.0..=self.width).contains(&x)
+            && (0.0..=self.height).contains(&y)
+            && !self.obstacles.iter().any(|obstacle| obstacle.contains(x, y))
+    }
+
+    // Whether a player may go straight from one spot to another: `to` has to
+    // fit and no obstacle may be in the way, so a single move can't jump
+    // through a wall
+    pub fn reachable(&self, from: (f32, f32), to: (f32, f32)) -> bool {
+        self.fits(to.0, to.1) && !self.obstacles.iter().any(|obstacle| obstacle.crosses(from, to))
+    }
+
+    // A random free spot away from the edges
+    pub fn spawn_point(&self) -> (f32, f32) {
+        let mut rng = thread_rng();
+        let margin = |size: f32| SPAWN_MARGIN.min(size / 4.0);
+        let mut random = || {
+            (
+                rng.gen_range(margin(self.width)..self.width - margin(self.width)),
+                rng.gen_range(margin(self.height)..self.height - margin(self.height)),
+            )
+        };
+        (0..SPAWN_ATTEMPTS)
+            .map(|_| random())
+            .find(|&(x, y)| self.fits(x, y))
+            .unwrap_or_else(random)
+    }
+}
+
+// The worlds from WORLDS_FILE, a JSON array of WorldMaps, or the default one
+pub fn load(config: &Config) -> Result<Vec<Arc<WorldMap>>> {
+    let Some(path) = &config.worlds_file else {
+        return Ok(vec![Arc::new(WorldMap::new(
+            DEFAULT_WORLD.to_string(),
+            config.world.width,
+            config.world.height,
+            Vec::new(),
+            None,
+        ))]);
+    };
+    let contents = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let worlds: Vec<WorldMap> =
+        serde_json::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))?;
+    if worlds.is_empty() {
+        bail!("{} has no worlds", path.display());
+    }
+    let mut names = HashSet::new();
+    for world in &worlds {
+        if !names.insert(world.name.as_str()) {
+            bail!("{} has two worlds named {}", path.display(), world.name);
+        }
+        if !(world.width > 0.0 && world.height > 0.0 && world.width.is_finite() && world.height.is_finite()) {
+            bail!("world {} needs a positive width and height", world.name);
+        }
+        if world.chunk_size.is_some_and(|size| !(size > 0.0 && size.is_finite())) {
+            bail!("world {} needs a positive chunk_size", world.name);
+        }
+    }
+    info!("🗺️ Loaded {} worlds from {}", worlds.len(), path.display());
+    Ok(worlds
+        .into_iter()
+        .map(|world| Arc::new(WorldMap::new(world.name, world.width, world.height, world.obstacles, world.chunk_size)))
+        .collect())
+}
+
+fn chunk_of(size: f32, x: f32, y: f32) -> ChunkId {
+    ((x / size).floor() as i32, (y / size).floor() as i32)
+}
+
+impl GameServer {
+    // Moves `player` somewhere free if where it stands doesn't fit its
+    // room's world
+    pub fn fit_to_room(&self, player: &mut Player) {
+        let world = self.rooms.world_of(&player.room);
+        if !world.fits(player.x, player.y) {
+            (player.x, player.y) = world.spawn_point();
+        }
+    }
+
+    // Puts the room on another world. Players it doesn't fit are respawned,
+    // and everyone in the room gets WorldChanged.
+    pub fn switch_world(&self, room_id: &str, world_name: &str) -> Result<()> {
+        let Some(room) = self.rooms.get(room_id) else {
+            bail!("No room named {room_id}");
+        };
+        let Some(world) = self.rooms.world(world_name) else {
+            bail!("No world named {world_name}");
+        };
+        room.set_world(Arc::clone(&world));
+        let mut respawned = Vec::new();
+        for mut player in self.players.iter_mut().filter(|player| player.room == room.id) {
+            if !world.fits(player.x, player.y) {
+                (player.x, player.y) = world.spawn_point();
+                respawned.push(player.id.clone());
+            }
+        }
+        self.moved.lock().unwrap().extend(respawned);
+        info!(room = %room.id, world = %world.name, "Room switched world");
+        self.broadcast_to_room(&room.id, ServerMessage::WorldChanged { world: world.announced() })
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// Buckets of a Histogram: bucket i counts values up to 2^i, the last one
+// everything above
+const BUCKETS: usize = 24;
+
+// A Prometheus histogram of whole numbers in power-of-two buckets, so
+// observing is a couple of atomic adds with no locking
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, value: u64) {
+        let bucket = if value <= 1 { 0 } else { 64 - (value - 1).leading_zeros() as usize };
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Latencies are kept in microseconds
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+}
+
+// Process-wide counters, rendered in the Prometheus text format by
+// GET /admin/metrics
+#[derive(Default)]
+pub struct Metrics {
+    // Movement snapshots discarded from full per-connection send queues
+    pub dropped_updates: AtomicU64,
+    // Connections closed because their send queue could not keep up
+    pub slow_client_disconnects: AtomicU64,
+    // Every decoded message from a client
+    pub messages_received: AtomicU64,
+    // Times a connection fell behind a broadcast channel, and the messages
+    // it missed because of it
+    pub broadcast_lags: AtomicU64,
+    pub broadcast_missed: AtomicU64,
+    // Join attempts turned away by the join rate limits
+    pub joins_limited: AtomicU64,
+    // Join attempts turned away while overloaded
+    pub joins_busy: AtomicU64,
+    // 1 while shedding load, see overload.rs
+    pub overloaded: AtomicU64,
+    // Chat messages sent to a room, not counting commands
+    pub chat_messages: AtomicU64,
+    // Rooms that currently exist, the lobby included
+    pub rooms: AtomicU64,
+    pub rooms_created: AtomicU64,
+    // Rooms removed by the GC after sitting empty
+    pub rooms_destroyed: AtomicU64,
+    // How many connections each broadcast, server-wide or to a room, went to
+    pub broadcast_fanout: Histogram,
+    // From a message being created to it being written to a connection, in
+    // microseconds, once per connection it goes to
+    pub delivery_latency: Histogram,
+    // From a message being queued for a connection to it being written out,
+    // in microseconds
+    pub send_latency: Histogram,
+}
+
+impl Metrics {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "game_dropped_updates_total",
+            "Movement snapshots dropped from full send queues",
+            &self.dropped_updates,
+        );
+        counter(
+            &mut out,
+            "game_slow_client_disconnects_total",
+            "Connections closed for falling too far behind",
+            &self.slow_client_disconnects,
+        );
+        counter(
+            &mut out,
+            "game_messages_received_total",
+            "Messages received from clients",
+            &self.messages_received,
+        );
+        counter(
+            &mut out,
+            "game_broadcast_lags_total",
+            "Times a connection fell behind a broadcast channel (raise BROADCAST_CAPACITY if frequent)",
+            &self.broadcast_lags,
+        );
+        counter(
+            &mut out,
+            "game_broadcast_missed_total",
+            "Broadcast messages connections missed by falling behind",
+            &self.broadcast_missed,
+        );
+        counter(&mut out, "game_joins_limited_total", "Join attempts over the join rate limits", &self.joins_limited);
+        counter(&mut out, "game_joins_busy_total", "Join attempts turned away while overloaded", &self.joins_busy);
+        gauge(&mut out, "game_overloaded", "1 while the server is shedding load", &self.overloaded);
+        counter(&mut out, "game_chat_messages_total", "Chat messages sent", &self.chat_messages);
+        gauge(&mut out, "game_rooms", "Rooms that currently exist", &self.rooms);
+        counter(&mut out, "game_rooms_created_total", "Rooms created", &self.rooms_created);
+        counter(
+            &mut out,
+            "game_rooms_destroyed_total",
+            "Empty rooms destroyed after their idle period",
+            &self.rooms_destroyed,
+        );
+        histogram(
+            &mut out,
+            "game_broadcast_fanout",
+            "Connections each broadcast was sent to",
+            &self.broadcast_fanout,
+            1.0,
+        );
+        histogram(
+            &mut out,
+            "game_delivery_latency_seconds",
+            "Time from a message being created to it being written to a connection",
+            &self.delivery_latency,
+            1e6,
+        );
+        histogram(
+            &mut out,
+            "game_send_latency_seconds",
+            "Time messages spend in a connection's send queue before being written",
+            &self.send_latency,
+            1e6,
+        );
+        out
+    }
+
+    // Current values by field name, for the admin WebSocket
+    pub fn counters(&self) -> BTreeMap<&'static str, u64> {
+        [
+            ("dropped_updates", &self.dropped_updates),
+            ("slow_client_disconnects", &self.slow_client_disconnects),
+            ("messages_received", &self.messages_received),
+            ("broadcast_lags", &self.broadcast_lags),
+            ("broadcast_missed", &self.broadcast_missed),
+            ("joins_limited", &self.joins_limited),
+            ("joins_busy", &self.joins_busy),
+            ("overloaded", &self.overloaded),
+            ("chat_messages", &self.chat_messages),
+            ("rooms", &self.rooms),
+            ("rooms_created", &self.rooms_created),
+            ("rooms_destroyed", &self.rooms_destroyed),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name, value.load(Ordering::Relaxed)))
+        .collect()
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+}
+
+// Values are divided by `scale` on the way out, e.g. 1e6 to render
+// microseconds as seconds
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram, scale: f64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    let mut cumulative = 0;
+    for (i, bucket) in histogram.buckets[..BUCKETS - 1].iter().enumerate() {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{le=\"{}\"}} {cumulative}", (1u64 << i) as f64 / scale);
+    }
+    let count = histogram.count.load(Ordering::Relaxed);
+    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+    let _ = writeln!(out, "{name}_sum {}", histogram.sum.load(Ordering::Relaxed) as f64 / scale);
+    let _ = writeln!(out, "{name}_count {count}");
+}
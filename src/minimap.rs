@@ -0,0 +1,217 @@
+// Scaled-down overview of the whole world in the #minimap canvas: every
+// player as a dot, plus a rectangle around the part of the game area that's
+// scrolled into view.
+//
+// Where the browser supports OffscreenCanvas the page hands the canvas to a
+// worker (see minimap-worker.js). Each frame is then posted to the worker as
+// JSON and drawn there; otherwise it's drawn right here.
+use crate::world::World;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{
+    CanvasRenderingContext2d, DedicatedWorkerGlobalScope, HtmlCanvasElement, MessageEvent, OffscreenCanvas,
+    OffscreenCanvasRenderingContext2d, Worker,
+};
+
+const DOT_RADIUS: f64 = 2.0;
+
+thread_local! {
+    // Set on the page once the canvas went to a worker
+    static WORKER: RefCell<Option<Worker>> = const { RefCell::new(None) };
+}
+
+// Everything needed to draw one frame, in world coordinates
+#[derive(Serialize, Deserialize)]
+struct Frame {
+    // Width and height of the world
+    world: (f64, f64),
+    dots: Vec<Dot>,
+    view: Option<(f64, f64, f64, f64)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Dot {
+    x: f64,
+    y: f64,
+    color: String,
+    mine: bool,
+}
+
+// The calls drawing needs, shared by the on-page and offscreen 2D contexts
+trait Canvas2d {
+    fn clear_rect(&self, x: f64, y: f64, w: f64, h: f64);
+    fn fill_rect(&self, x: f64, y: f64, w: f64, h: f64);
+    fn stroke_rect(&self, x: f64, y: f64, w: f64, h: f64);
+    fn set_fill_style_str(&self, style: &str);
+    fn set_stroke_style_str(&self, style: &str);
+    fn begin_path(&self);
+    fn arc(&self, x: f64, y: f64, radius: f64, start: f64, end: f64) -> Result<(), JsValue>;
+    fn fill(&self);
+    fn stroke(&self);
+}
+
+macro_rules! impl_canvas2d {
+    ($context:ty) => {
+        impl Canvas2d for $context {
+            fn clear_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+                <$context>::clear_rect(self, x, y, w, h)
+            }
+            fn fill_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+                <$context>::fill_rect(self, x, y, w, h)
+            }
+            fn stroke_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+                <$context>::stroke_rect(self, x, y, w, h)
+            }
+            fn set_fill_style_str(&self, style: &str) {
+                <$context>::set_fill_style_str(self, style)
+            }
+            fn set_stroke_style_str(&self, style: &str) {
+                <$context>::set_stroke_style_str(self, style)
+            }
+            fn begin_path(&self) {
+                <$context>::begin_path(self)
+            }
+            fn arc(&self, x: f64, y: f64, radius: f64, start: f64, end: f64) -> Result<(), JsValue> {
+                <$context>::arc(self, x, y, radius, start, end)
+            }
+            fn fill(&self) {
+                <$context>::fill(self)
+            }
+            fn stroke(&self) {
+                <$context>::stroke(self)
+            }
+        }
+    };
+}
+
+impl_canvas2d!(CanvasRenderingContext2d);
+impl_canvas2d!(OffscreenCanvasRenderingContext2d);
+
+pub fn render(world: &World) {
+    let bounds = world.camera.bounds();
+    let frame = Frame {
+        world: (f64::from(bounds.width), f64::from(bounds.height)),
+        dots: world
+            .profiles
+            .iter()
+            .filter_map(|(entity, profile)| {
+                let position = world.positions.get(entity)?;
+                Some(Dot {
+                    x: f64::from(position.x),
+                    y: f64::from(position.y),
+                    color: profile.color.clone(),
+                    mine: world.me == Some(*entity),
+                })
+            })
+            .collect(),
+        view: visible_area(world.camera.view()),
+    };
+    let posted = WORKER.with(|worker| {
+        let worker = worker.borrow();
+        let worker = worker.as_ref()?;
+        let json = serde_json::to_string(&frame).ok()?;
+        worker.post_message(&JsValue::from_str(&json)).ok()
+    });
+    if posted.is_none() {
+        draw_on_page(&frame);
+    }
+}
+
+// From now on frames go to `worker`, which has the canvas
+pub fn use_worker(worker: Worker) {
+    WORKER.with(|w| *w.borrow_mut() = Some(worker));
+}
+
+fn draw_on_page(frame: &Frame) {
+    let Some(document) = web_sys::window().and_then(|w| w.document()) else {
+        return;
+    };
+    let Some(canvas) = document
+        .get_element_by_id("minimap")
+        .and_then(|e| e.dyn_into::<HtmlCanvasElement>().ok())
+    else {
+        return;
+    };
+    let Some(context) = canvas
+        .get_context("2d")
+        .ok()
+        .flatten()
+        .and_then(|c| c.dyn_into::<CanvasRenderingContext2d>().ok())
+    else {
+        return;
+    };
+    draw(&context, f64::from(canvas.width()), f64::from(canvas.height()), frame);
+}
+
+// Runs in the worker: draws every frame posted by the page onto `canvas`
+pub fn draw_in_worker(canvas: OffscreenCanvas) -> Result<(), JsValue> {
+    let scope = js_sys::global().dyn_into::<DedicatedWorkerGlobalScope>()?;
+    let context = canvas
+        .get_context("2d")?
+        .ok_or("no 2d context")?
+        .dyn_into::<OffscreenCanvasRenderingContext2d>()?;
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |e: MessageEvent| {
+        let Some(json) = e.data().as_string() else {
+            return;
+        };
+        if let Ok(frame) = serde_json::from_str::<Frame>(&json) {
+            draw(&context, f64::from(canvas.width()), f64::from(canvas.height()), &frame);
+        }
+    });
+    scope.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    on_message.forget();
+    Ok(())
+}
+
+fn draw(context: &impl Canvas2d, width: f64, height: f64, frame: &Frame) {
+    let (world_width, world_height) = frame.world;
+    let (scale_x, scale_y) = (width / world_width, height / world_height);
+
+    context.clear_rect(0.0, 0.0, width, height);
+    context.set_fill_style_str("rgba(0, 0, 0, 0.5)");
+    context.fill_rect(0.0, 0.0, width, height);
+
+    for dot in &frame.dots {
+        context.begin_path();
+        let radius = if dot.mine { DOT_RADIUS * 1.5 } else { DOT_RADIUS };
+        let _ = context.arc(dot.x * scale_x, dot.y * scale_y, radius, 0.0, std::f64::consts::TAU);
+        context.set_fill_style_str(&dot.color);
+        context.fill();
+        if dot.mine {
+            context.set_stroke_style_str("#fff");
+            context.stroke();
+        }
+    }
+
+    if let Some((x, y, w, h)) = frame.view {
+        context.set_stroke_style_str("rgba(255, 255, 255, 0.8)");
+        context.stroke_rect(x * scale_x, y * scale_y, w * scale_x, h * scale_y);
+    }
+}
+
+// The part of the world that's on screen, in world coordinates: the camera's
+// view, cut down further when the page is scrolled or the window is smaller
+// than the game area
+fn visible_area(camera: (f64, f64, f64, f64)) -> Option<(f64, f64, f64, f64)> {
+    let window = web_sys::window()?;
+    let area = window.document()?.get_element_by_id("game-area")?.get_bounding_client_rect();
+    if area.width() <= 0.0 || area.height() <= 0.0 {
+        return None;
+    }
+    let view_width = window.inner_width().ok()?.as_f64()?;
+    let view_height = window.inner_height().ok()?.as_f64()?;
+    let left = (-area.left()).clamp(0.0, area.width());
+    let top = (-area.top()).clamp(0.0, area.height());
+    let right = (view_width - area.left()).clamp(0.0, area.width());
+    let bottom = (view_height - area.top()).clamp(0.0, area.height());
+    let (view_x, view_y, view_w, view_h) = camera;
+    let (scale_x, scale_y) = (view_w / area.width(), view_h / area.height());
+    Some((
+        view_x + left * scale_x,
+        view_y + top * scale_y,
+        (right - left) * scale_x,
+        (bottom - top) * scale_y,
+    ))
+}
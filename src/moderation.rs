@@ -0,0 +1,232 @@
+use crate::admin_ws::AdminEvent;
+use crate::audit::AuditAction;
+use crate::bans::{Ban, BANNED_CLOSE_CODE};
+use crate::commands::{format_duration, MAX_DURATION};
+use crate::frame::OutboundFrame;
+use crate::queue::SendQueue;
+use crate::remote::RemoteAddr;
+use crate::reports::{ReportedPlayer, MAX_REASON_LEN};
+use crate::tier::Role;
+use crate::{error_frame, GameServer, PlayerId, ServerMessage};
+use anyhow::{bail, Result};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+// WebSocket close code sent to kicked players
+pub const KICKED_CLOSE_CODE: u16 = 4001;
+
+// A joined player's connection, for messages and moderation aimed at one
+// player rather than everyone
+pub struct Connection {
+    pub queue: Arc<SendQueue>,
+    // Username of the registered account, if any
+    pub account: Option<String>,
+    pub addr: RemoteAddr,
+    pub role: Role,
+}
+
+impl Connection {
+    // Mutes follow the account, or the address for guests, so reconnecting
+    // doesn't lift them
+    fn mute_key(&self) -> String {
+        match &self.account {
+            Some(username) => format!("account:{}", username.to_lowercase()),
+            None => format!("ip:{}", self.addr.ip()),
+        }
+    }
+}
+
+// Who's taking a moderation action: their role decides who they may act on,
+// and their name goes in the notification everyone sees
+pub struct Moderator<'a> {
+    pub role: Role,
+    pub name: &'a str,
+}
+
+// IP bans don't apply to staff, so a moderator sharing an address with a
+// banned guest isn't locked out
+pub fn banned_ip(role: Role, ip: IpAddr) -> Option<IpAddr> {
+    (role == Role::Player).then_some(ip)
+}
+
+pub fn system_message(message: String) -> ServerMessage {
+    ServerMessage::SystemMessage {
+        message,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+    }
+}
+
+impl GameServer {
+    // The connected player with this nickname, ignoring case
+    pub fn find_player(&self, nickname: &str) -> Result<PlayerId> {
+        let mut matches = self
+            .players
+            .iter()
+            .filter(|p| p.nickname.eq_ignore_ascii_case(nickname))
+            .map(|p| p.id.clone());
+        match (matches.next(), matches.next()) {
+            (Some(player_id), None) => Ok(player_id),
+            (Some(_), Some(_)) => bail!("More than one player is called {nickname}"),
+            (None, _) => bail!("No player called {nickname}"),
+        }
+    }
+
+    // Staff can only act on players below their own role. Returns the
+    // target's nickname.
+    fn check_target(
+        &self,
+        player_id: &str,
+        by: &Moderator,
+    ) -> Result<(String, dashmap::mapref::one::Ref<'_, PlayerId, Connection>)> {
+        let (Some(connection), Some(player)) = (self.connections.get(player_id), self.players.get(player_id)) else {
+            bail!("No such player");
+        };
+        if connection.role >= by.role {
+            bail!("You can't moderate a {}", connection.role.name());
+        }
+        Ok((player.nickname.clone(), connection))
+    }
+
+    // Tells everyone about a moderation action
+    fn notify(&self, message: String) -> Result<()> {
+        self.events.emit(AdminEvent::Moderation { message: message.clone() });
+        self.broadcast_message(system_message(format!("🔨 {message}")))
+    }
+
+    // Disconnects the player after telling them why
+    pub fn kick(&self, player_id: &str, by: &Moderator, reason: Option<&str>) -> Result<()> {
+        let (nickname, connection) = self.check_target(player_id, by)?;
+        let message = match reason {
+            Some(reason) => format!("You were kicked: {reason}"),
+            None => "You were kicked".to_string(),
+        };
+        let _ = connection.queue.push(error_frame(&message));
+        connection.queue.close(KICKED_CLOSE_CODE, "Kicked");
+        drop(connection);
+        info!(%player_id, reason, by = by.name, "Player kicked");
+        self.audit.record(AuditAction::Kick, by, nickname.clone(), reason.map(str::to_string), None);
+        match reason {
+            Some(reason) => self.notify(format!("{nickname} was kicked by {}: {reason}", by.name)),
+            None => self.notify(format!("{nickname} was kicked by {}", by.name)),
+        }
+    }
+
+    pub fn mute(&self, player_id: &str, by: &Moderator, duration: Duration) -> Result<()> {
+        let until = Instant::now().checked_add(duration).filter(|_| duration <= MAX_DURATION);
+        let Some(until) = until else {
+            bail!("A mute can last at most {}", format_duration(MAX_DURATION));
+        };
+        let (nickname, connection) = self.check_target(player_id, by)?;
+        self.mutes.insert(connection.mute_key(), until);
+        drop(connection);
+        info!(%player_id, secs = duration.as_secs(), by = by.name, "Player muted");
+        self.audit.record(AuditAction::Mute, by, nickname.clone(), None, Some(format_duration(duration)));
+        self.notify(format!("{nickname} was muted by {} for {}", by.name, format_duration(duration)))
+    }
+
+    // Bans the player's account, or their address if they're a guest, and
+    // disconnects every connection the ban covers. `duration` None is permanent.
+    pub fn ban(&self, player_id: &str, by: &Moderator, duration: Option<Duration>, reason: String) -> Result<()> {
+        if duration.is_some_and(|duration| duration > MAX_DURATION) {
+            bail!("A ban can last at most {}; leave the duration out for a permanent one", format_duration(MAX_DURATION));
+        }
+        let (nickname, connection) = self.check_target(player_id, by)?;
+        let (account, ip) = match &connection.account {
+            Some(username) => (Some(username.clone()), None),
+            None => (None, Some(connection.addr.ip())),
+        };
+        drop(connection);
+        let ban = self.bans.add(account, ip, reason, duration);
+        info!(%player_id, ban_id = ban.id, by = by.name, "Player banned");
+        self.enforce_ban(&ban);
+        let length = match duration {
+            Some(duration) => format!("for {}", format_duration(duration)),
+            None => "permanently".to_string(),
+        };
+        self.audit.record(
+            AuditAction::Ban,
+            by,
+            nickname.clone(),
+            Some(ban.reason.clone()),
+            Some(format!("ban #{} {length}", ban.id)),
+        );
+        self.notify(format!("{nickname} was banned by {} {length}: {}", by.name, ban.reason))
+    }
+
+    // Disconnects everyone already connected that a new ban covers
+    pub fn enforce_ban(&self, ban: &Ban) {
+        for connection in self.connections.iter() {
+            if ban.matches(banned_ip(connection.role, connection.addr.ip()), connection.account.as_deref()) {
+                let _ = connection.queue.push(error_frame(&ban.message()));
+                connection.queue.close(BANNED_CLOSE_CODE, "Banned");
+            }
+        }
+    }
+
+    // How much longer the player is muted for, if they are
+    pub fn muted_for(&self, player_id: &str) -> Option<Duration> {
+        let key = self.connections.get(player_id)?.mute_key();
+        let until = *self.mutes.get(&key)?;
+        let remaining = until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            self.mutes.remove(&key);
+            return None;
+        }
+        Some(remaining)
+    }
+
+    fn reported_player(&self, player_id: &str) -> Option<ReportedPlayer> {
+        let player = self.players.get(player_id)?;
+        Some(ReportedPlayer {
+            player_id: player_id.to_string(),
+            nickname: player.nickname.clone(),
+            account: self.connections.get(player_id).and_then(|c| c.account.clone()),
+        })
+    }
+
+    // Files a report with the target's recent chat and tells staff who are
+    // online about it
+    pub fn report(&self, reporter_id: &str, target_id: &str, reason: String) -> Result<()> {
+        let reason = reason.trim();
+        if reason.is_empty() {
+            bail!("Say why you're reporting them");
+        }
+        if reason.chars().count() > MAX_REASON_LEN {
+            bail!("Reports are limited to {MAX_REASON_LEN} characters");
+        }
+        if reporter_id == target_id {
+            bail!("You can't report yourself");
+        }
+        if self.reports.has_reported(reporter_id, target_id) {
+            bail!("You already reported this player");
+        }
+        let (Some(reporter), Some(target)) = (self.reported_player(reporter_id), self.reported_player(target_id)) else {
+            bail!("No such player");
+        };
+        let report = self.reports.add(reporter, target, reason.to_string());
+        info!(report_id = report.id, %target_id, "Player reported");
+        self.events.emit(AdminEvent::Report {
+            report_id: report.id,
+            reporter: report.reporter.nickname.clone(),
+            target: report.target.nickname.clone(),
+            reason: report.reason.clone(),
+        });
+        let notice = system_message(format!(
+            "🚩 Report #{}: {} reported {}: {}",
+            report.id, report.reporter.nickname, report.target.nickname, report.reason
+        ));
+        let frame = OutboundFrame::new(notice);
+        for connection in self.connections.iter().filter(|c| c.role >= Role::Moderator) {
+            let _ = connection.queue.push(Arc::clone(&frame));
+        }
+        Ok(())
+    }
+
+    pub fn announce(&self, message: String) -> Result<()> {
+        info!(%message, "Announcement");
+        self.events.emit(AdminEvent::Announcement { message: message.clone() });
+        self.broadcast_message(system_message(format!("📢 {message}")))
+    }
+}
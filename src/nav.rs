@@ -0,0 +1,102 @@
+// Navigation for NPCs: the world's free space as a grid of NAV_CELL squares,
+// and A* over it. A cell is walkable when its centre is somewhere a player
+// may stand, so paths keep to the same rules as moves.
+use crate::maps::WorldMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+pub const NAV_CELL: f32 = 16.0;
+// Step costs, straight and diagonal, scaled to keep the arithmetic integral
+const STRAIGHT: u32 = 10;
+const DIAGONAL: u32 = 14;
+
+type Cell = (i32, i32);
+
+pub struct NavGrid {
+    columns: i32,
+    rows: i32,
+    walkable: Vec<bool>,
+}
+
+impl NavGrid {
+    pub fn new(world: &WorldMap) -> Self {
+        let columns = (world.width / NAV_CELL).ceil().max(1.0) as i32;
+        let rows = (world.height / NAV_CELL).ceil().max(1.0) as i32;
+        let mut walkable = Vec::with_capacity((columns * rows) as usize);
+        for row in 0..rows {
+            for column in 0..columns {
+                let (x, y) = centre((column, row));
+                walkable.push(world.fits(x.min(world.width), y.min(world.height)));
+            }
+        }
+        Self { columns, rows, walkable }
+    }
+
+    fn walkable(&self, (column, row): Cell) -> bool {
+        (0..self.columns).contains(&column)
+            && (0..self.rows).contains(&row)
+            && self.walkable[(row * self.columns + column) as usize]
+    }
+
+    fn cell_of(&self, x: f32, y: f32) -> Cell {
+        (
+            ((x / NAV_CELL) as i32).clamp(0, self.columns - 1),
+            ((y / NAV_CELL) as i32).clamp(0, self.rows - 1),
+        )
+    }
+
+    // Waypoints from `from` to `to` around obstacles, ending at `to` itself,
+    // or None when `to` can't be reached. Diagonal steps don't cut corners.
+    pub fn find_path(&self, from: (f32, f32), to: (f32, f32)) -> Option<Vec<(f32, f32)>> {
+        let (start, goal) = (self.cell_of(from.0, from.1), self.cell_of(to.0, to.1));
+        if !self.walkable(goal) {
+            return None;
+        }
+        let mut open = BinaryHeap::from([Reverse((heuristic(start, goal), start))]);
+        let mut cost: HashMap<Cell, u32> = HashMap::from([(start, 0)]);
+        let mut came_from: HashMap<Cell, Cell> = HashMap::new();
+        while let Some(Reverse((_, cell))) = open.pop() {
+            if cell == goal {
+                let mut cells = vec![goal];
+                while let Some(&previous) = came_from.get(cells.last().unwrap()) {
+                    cells.push(previous);
+                }
+                cells.pop();
+                cells.reverse();
+                cells.pop();
+                let mut path: Vec<(f32, f32)> = cells.into_iter().map(centre).collect();
+                path.push(to);
+                return Some(path);
+            }
+            let here = cost[&cell];
+            for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                let next = (cell.0 + dx, cell.1 + dy);
+                if !self.walkable(next) {
+                    continue;
+                }
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal && !(self.walkable((cell.0 + dx, cell.1)) && self.walkable((cell.0, cell.1 + dy))) {
+                    continue;
+                }
+                let step = if diagonal { DIAGONAL } else { STRAIGHT };
+                let through = here + step;
+                if cost.get(&next).is_none_or(|&known| through < known) {
+                    cost.insert(next, through);
+                    came_from.insert(next, cell);
+                    open.push(Reverse((through + heuristic(next, goal), next)));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn centre((column, row): Cell) -> (f32, f32) {
+    ((column as f32 + 0.5) * NAV_CELL, (row as f32 + 0.5) * NAV_CELL)
+}
+
+// Octile distance, which never overestimates on an 8-connected grid
+fn heuristic(a: Cell, b: Cell) -> u32 {
+    let (dx, dy) = (a.0.abs_diff(b.0), a.1.abs_diff(b.1));
+    STRAIGHT * dx.max(dy) + (DIAGONAL - STRAIGHT) * dx.min(dy)
+}
@@ -0,0 +1,59 @@
+use crate::tier::Role;
+use crate::GameServer;
+
+// Longest nickname ChangeNick accepts, in characters
+pub const MAX_LEN: usize = 24;
+
+// Not blank, not too long and without control characters; callers trim first
+pub fn is_valid(nickname: &str) -> bool {
+    let len = nickname.chars().count();
+    (1..=MAX_LEN).contains(&len) && !nickname.chars().any(char::is_control)
+}
+
+// Nicknames only their owners may play under: each registered account's
+// current nickname belongs to that account, and RESERVED_NICKNAMES sets names
+// aside for a given account or for staff. Compared ignoring case.
+impl GameServer {
+    // Why `account` (None for guests), with `role`, can't use `nickname`, if
+    // it can't
+    pub fn nickname_reserved(&self, nickname: &str, account: Option<&str>, role: Role) -> Option<String> {
+        let is_account = |username: &str| account.is_some_and(|account| account.eq_ignore_ascii_case(username));
+        let key = nickname.to_lowercase();
+        match self.config().reserved_nicknames.get(&key) {
+            Some(Some(owner)) if !is_account(owner) => {
+                return Some(format!("The nickname {nickname} is reserved"));
+            }
+            Some(None) if role < Role::Moderator => {
+                return Some(format!("The nickname {nickname} is reserved for staff"));
+            }
+            _ => {}
+        }
+        match self.accounts.nickname_owner(nickname) {
+            Some(owner) if !is_account(&owner) => {
+                Some(format!("The nickname {nickname} belongs to a registered player"))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_nicknames_up_to_max_len_characters() {
+        assert!(is_valid("a"));
+        assert!(is_valid(&"a".repeat(MAX_LEN)));
+        // Counted in characters, not bytes
+        assert!(is_valid(&"é".repeat(MAX_LEN)));
+        assert!(!is_valid(&"a".repeat(MAX_LEN + 1)));
+    }
+
+    #[test]
+    fn rejects_blank_nicknames_and_control_characters() {
+        assert!(!is_valid(""));
+        assert!(!is_valid("alice\n"));
+        assert!(!is_valid("al\u{7}ice"));
+    }
+}
@@ -0,0 +1,55 @@
+// Desktop notifications for chat messages that mention you, shown only while
+// the tab is hidden. Off until the page opts in with enable_notifications(),
+// which asks the browser for permission the first time.
+use crate::{i18n, visibility};
+use std::cell::Cell;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{Notification, NotificationOptions, NotificationPermission};
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.with(|e| e.set(enabled));
+    // Asking again after a refusal does nothing, so only ask while undecided
+    if enabled && Notification::permission() == NotificationPermission::Default {
+        let _ = Notification::request_permission();
+    }
+}
+
+// "@nickname" anywhere in the message, ignoring case, and not followed by more
+// of a longer nickname
+pub fn mentions(message: &str, nickname: &str) -> bool {
+    let message = message.to_lowercase();
+    let mention = format!("@{}", nickname.to_lowercase());
+    message.match_indices(&mention).any(|(start, _)| {
+        message[start + mention.len()..]
+            .chars()
+            .next()
+            .is_none_or(|next| !next.is_alphanumeric() && next != '_' && next != '-')
+    })
+}
+
+pub fn mention(nickname: &str, message: &str) {
+    if !ENABLED.with(Cell::get) || Notification::permission() != NotificationPermission::Granted || !visibility::hidden() {
+        return;
+    }
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let options = NotificationOptions::new();
+    options.set_body(message);
+    // One notification per sender; a newer message replaces the older one
+    options.set_tag(&format!("mention-{}", nickname));
+    let Ok(notification) = Notification::new_with_options(&i18n::text("notification.mention", &[("nickname", nickname)]), &options) else {
+        return;
+    };
+    let clicked = notification.clone();
+    let on_click = Closure::once_into_js(move || {
+        let _ = window.focus();
+        clicked.close();
+    });
+    notification.set_onclick(Some(on_click.unchecked_ref()));
+}
@@ -0,0 +1,131 @@
+// Server-controlled players. NPC_COUNT of them join the lobby at startup,
+// and a config reload spawns or removes some to match a new count. They
+// wander the lobby: each picks a free spot, walks there along an A* path (see
+// nav.rs) and rests a moment before the next. They move in the tick loop and
+// reach clients in the same Snapshots as players.
+use crate::maps::WorldMap;
+use crate::nav::NavGrid;
+use crate::{rooms, GameServer, Player, PlayerId};
+use rand::{thread_rng, Rng};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+// World units per second, about a brisk walk
+const NPC_SPEED: f32 = 60.0;
+// How long an NPC rests between walks, at random
+const REST_MS: std::ops::Range<u64> = 1_000..4_000;
+
+struct Npc {
+    // The world the path was found in; a room switched to another one
+    // makes for a new path
+    world: Arc<WorldMap>,
+    // Waypoints still ahead, the next one last
+    path: Vec<(f32, f32)>,
+    rest_until: Instant,
+}
+
+#[derive(Default)]
+pub struct Npcs {
+    npcs: Mutex<HashMap<PlayerId, Npc>>,
+    // Nav grids by world name, built when an NPC first walks there
+    grids: Mutex<HashMap<String, Arc<NavGrid>>>,
+}
+
+impl Npcs {
+    pub fn contains(&self, player_id: &str) -> bool {
+        self.npcs.lock().unwrap().contains_key(player_id)
+    }
+
+    fn grid(&self, world: &WorldMap) -> Arc<NavGrid> {
+        let mut grids = self.grids.lock().unwrap();
+        Arc::clone(grids.entry(world.name.clone()).or_insert_with(|| Arc::new(NavGrid::new(world))))
+    }
+}
+
+impl GameServer {
+    // Spawns or removes NPCs until there are `count`. New ones are named
+    // after the lowest "Bot N" not taken.
+    pub fn set_npc_count(&self, count: usize) {
+        let current: Vec<PlayerId> = self.npcs.npcs.lock().unwrap().keys().cloned().collect();
+        if current.len() > count {
+            for player_id in &current[count..] {
+                self.npcs.npcs.lock().unwrap().remove(player_id);
+                if let Err(e) = self.remove_player(player_id) {
+                    error!("Failed to remove NPC: {}", e);
+                }
+            }
+            info!("🤖 Removed {} NPCs", current.len() - count);
+            return;
+        }
+        let taken: HashSet<String> =
+            current.iter().filter_map(|id| self.players.get(id).map(|p| p.nickname.clone())).collect();
+        let nicknames: Vec<String> = (1..)
+            .map(|number| format!("Bot {number}"))
+            .filter(|nickname| !taken.contains(nickname))
+            .take(count - current.len())
+            .collect();
+        let world = self.rooms.lobby().world();
+        for nickname in &nicknames {
+            let player = Player::new(Some(nickname.clone()), &world);
+            let player_id = player.id.clone();
+            if let Err(e) = self.add_player(player) {
+                error!("Failed to spawn NPC: {}", e);
+                continue;
+            }
+            let npc = Npc { world: Arc::clone(&world), path: Vec::new(), rest_until: Instant::now() };
+            self.npcs.npcs.lock().unwrap().insert(player_id, npc);
+        }
+        if !nicknames.is_empty() {
+            info!("🤖 Spawned {} NPCs in {}", nicknames.len(), rooms::DEFAULT_ROOM);
+        }
+    }
+
+    // Walks every NPC `elapsed` seconds further along its path
+    pub fn move_npcs(&self, elapsed: f32) {
+        let now = Instant::now();
+        let mut npcs = self.npcs.npcs.lock().unwrap();
+        for (player_id, npc) in npcs.iter_mut() {
+            let Some(mut player) = self.players.get_mut(player_id) else {
+                continue;
+            };
+            let world = self.rooms.world_of(&player.room);
+            if !Arc::ptr_eq(&world, &npc.world) {
+                npc.world = Arc::clone(&world);
+                npc.path.clear();
+            }
+            if npc.path.is_empty() {
+                if now < npc.rest_until {
+                    continue;
+                }
+                let destination = world.spawn_point();
+                match self.npcs.grid(&world).find_path((player.x, player.y), destination) {
+                    Some(mut path) => {
+                        path.reverse();
+                        npc.path = path;
+                    }
+                    // Somewhere walled off; another spot next tick
+                    None => continue,
+                }
+            }
+            let mut step = NPC_SPEED * elapsed;
+            while let Some(&(x, y)) = npc.path.last() {
+                let distance = (x - player.x).hypot(y - player.y);
+                if distance > step {
+                    player.x += (x - player.x) / distance * step;
+                    player.y += (y - player.y) / distance * step;
+                    break;
+                }
+                (player.x, player.y) = (x, y);
+                step -= distance;
+                npc.path.pop();
+            }
+            if npc.path.is_empty() {
+                npc.rest_until = now + Duration::from_millis(thread_rng().gen_range(REST_MS));
+            }
+            player.last_seen = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            self.moved.lock().unwrap().insert(player_id.clone());
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use crate::config::{Config, OAuthClient};
+use crate::http::{error_response, json_response};
+use crate::nicknames;
+use crate::session;
+use crate::tier::Role;
+use crate::{GameServer, PLAYER_COLORS};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use dashmap::DashMap;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use rand::prelude::*;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use url::Url;
+
+// How long a user has to finish signing in at the provider
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// Holds the `state` of the login this browser started, so a callback URL
+// from someone else's login can't sign it in as them
+const STATE_COOKIE: &str = "oauth_state";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    GitHub,
+    Google,
+}
+
+impl Provider {
+    const ALL: [Provider; 2] = [Provider::GitHub, Provider::Google];
+
+    fn name(self) -> &'static str {
+        match self {
+            Provider::GitHub => "github",
+            Provider::Google => "google",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    fn client(self, config: &Config) -> Option<&OAuthClient> {
+        match self {
+            Provider::GitHub => config.github_oauth.as_ref(),
+            Provider::Google => config.google_oauth.as_ref(),
+        }
+    }
+
+    fn authorize_url(self) -> &'static str {
+        match self {
+            Provider::GitHub => "https://github.com/login/oauth/authorize",
+            Provider::Google => "https://accounts.google.com/o/oauth2/v2/auth",
+        }
+    }
+
+    fn token_url(self) -> &'static str {
+        match self {
+            Provider::GitHub => "https://github.com/login/oauth/access_token",
+            Provider::Google => "https://oauth2.googleapis.com/token",
+        }
+    }
+
+    fn userinfo_url(self) -> &'static str {
+        match self {
+            Provider::GitHub => "https://api.github.com/user",
+            Provider::Google => "https://openidconnect.googleapis.com/v1/userinfo",
+        }
+    }
+
+    fn scope(self) -> &'static str {
+        match self {
+            Provider::GitHub => "read:user",
+            Provider::Google => "openid profile",
+        }
+    }
+}
+
+struct PendingLogin {
+    provider: Provider,
+    // PKCE code verifier, sent with the code exchange
+    verifier: String,
+    started: Instant,
+}
+
+// In-flight authorization-code logins, keyed by the `state` parameter
+pub struct OAuthLogins {
+    pending: DashMap<String, PendingLogin>,
+    http: reqwest::Client,
+}
+
+impl Default for OAuthLogins {
+    fn default() -> Self {
+        Self {
+            pending: DashMap::new(),
+            http: reqwest::Client::builder()
+                .user_agent("rust-wasm-hello")
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("failed to build HTTP client"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct GitHubUser {
+    id: u64,
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct GoogleUser {
+    sub: String,
+    name: Option<String>,
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    thread_rng().fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn redirect_uri(config: &Config, provider: Provider) -> String {
+    format!("{}/auth/{}/callback", config.public_url, provider.name())
+}
+
+fn redirect(location: &str) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header("location", location)
+        .body(Full::new(Bytes::new()))
+        .unwrap()
+}
+
+// GET /auth/providers               - configured providers, for the login buttons
+// GET /auth/{provider}/login        - redirects to the provider's consent page
+// GET /auth/{provider}/callback     - finishes the login and redirects to
+//                                     `/` with the signed session cookie set
+pub async fn handle_auth(req: Request<Incoming>, server: GameServer) -> Response<Full<Bytes>> {
+    if req.method() != Method::GET {
+        return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    let path = req.uri().path();
+    if path == "/auth/providers" {
+        let names: Vec<&str> = Provider::ALL
+            .into_iter()
+            .filter(|p| p.client(&server.config()).is_some())
+            .map(Provider::name)
+            .collect();
+        return json_response(StatusCode::OK, serde_json::to_string(&names).unwrap());
+    }
+
+    let Some((provider, action)) = path
+        .strip_prefix("/auth/")
+        .and_then(|rest| rest.split_once('/'))
+        .and_then(|(name, action)| Some((Provider::from_name(name)?, action)))
+    else {
+        return error_response(StatusCode::NOT_FOUND, "unknown auth endpoint");
+    };
+    let Some(client) = provider.client(&server.config()).cloned() else {
+        return error_response(StatusCode::NOT_FOUND, "login provider not configured");
+    };
+
+    match action {
+        "login" => {
+            let state = random_token();
+            let verifier = random_token();
+            let challenge = general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+            server.oauth.pending.retain(|_, login| login.started.elapsed() < LOGIN_TIMEOUT);
+            server.oauth.pending.insert(
+                state.clone(),
+                PendingLogin {
+                    provider,
+                    verifier,
+                    started: Instant::now(),
+                },
+            );
+            let url = Url::parse_with_params(
+                provider.authorize_url(),
+                [
+                    ("response_type", "code"),
+                    ("client_id", client.client_id.as_str()),
+                    ("redirect_uri", redirect_uri(&server.config(), provider).as_str()),
+                    ("scope", provider.scope()),
+                    ("state", state.as_str()),
+                    ("code_challenge", challenge.as_str()),
+                    ("code_challenge_method", "S256"),
+                ],
+            )
+            .unwrap();
+            let mut response = redirect(url.as_str());
+            let cookie = session::signed_cookie(&server.config(), STATE_COOKIE, &state, LOGIN_TIMEOUT.as_secs());
+            response.headers_mut().insert("set-cookie", cookie.parse().unwrap());
+            response
+        }
+        "callback" => {
+            let params: HashMap<String, String> = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect();
+            let (Some(code), Some(state)) = (params.get("code"), params.get("state")) else {
+                return error_response(StatusCode::BAD_REQUEST, "missing code or state");
+            };
+            if session::signed_cookie_value(&server.config(), &req, STATE_COOKIE).as_ref() != Some(state) {
+                return error_response(StatusCode::BAD_REQUEST, "login wasn't started in this browser");
+            }
+            let Some((_, login)) = server.oauth.pending.remove(state) else {
+                return error_response(StatusCode::BAD_REQUEST, "unknown or expired login");
+            };
+            if login.provider != provider || login.started.elapsed() >= LOGIN_TIMEOUT {
+                return error_response(StatusCode::BAD_REQUEST, "unknown or expired login");
+            }
+
+            match finish_login(&server, provider, &client, code, &login.verifier).await {
+                Ok(token) => {
+                    let mut response = redirect("/");
+                    let cookie = session::session_cookie(&server.config(), &token);
+                    response.headers_mut().append("set-cookie", cookie.parse().unwrap());
+                    let cookie = session::clear_cookie(&server.config(), STATE_COOKIE);
+                    response.headers_mut().append("set-cookie", cookie.parse().unwrap());
+                    response
+                }
+                Err(e) => {
+                    tracing::warn!(provider = provider.name(), "OAuth login failed: {:#}", e);
+                    error_response(StatusCode::BAD_GATEWAY, "login with provider failed")
+                }
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "unknown auth endpoint"),
+    }
+}
+
+// Exchanges the code, looks up the external identity and returns a session
+// token for the account tied to it, creating the account on first login
+async fn finish_login(server: &GameServer, provider: Provider, client: &OAuthClient, code: &str, verifier: &str) -> Result<String> {
+    let redirect_uri = redirect_uri(&server.config(), provider);
+    let response = server
+        .oauth
+        .http
+        .post(provider.token_url())
+        .header("accept", "application/json")
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("client_id", client.client_id.as_str()),
+            ("client_secret", client.client_secret.as_str()),
+            ("code_verifier", verifier),
+        ])
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("token exchange returned {}", response.status());
+    }
+    let token: TokenResponse = response.json().await.context("invalid token response")?;
+
+    let userinfo = server
+        .oauth
+        .http
+        .get(provider.userinfo_url())
+        .bearer_auth(&token.access_token)
+        .send()
+        .await?
+        .error_for_status()?;
+    let (external_id, display_name) = match provider {
+        Provider::GitHub => {
+            let user: GitHubUser = userinfo.json().await?;
+            (user.id.to_string(), user.login)
+        }
+        Provider::Google => {
+            let user: GoogleUser = userinfo.json().await?;
+            let name = user.name.unwrap_or_else(|| format!("Player{}", &user.sub[..6.min(user.sub.len())]));
+            (user.sub, name)
+        }
+    };
+
+    // Provider-qualified usernames can't collide with password accounts,
+    // which may not contain ':'
+    let username = format!("{}:{}", provider.name(), external_id);
+    // A new account's nickname follows the same rules as ChangeNick; one the
+    // provider's name breaks gets a generated one instead
+    let display_name = display_name.trim().to_string();
+    let nickname = if nicknames::is_valid(&display_name) && server.nickname_reserved(&display_name, Some(&username), Role::Player).is_none() {
+        display_name
+    } else {
+        format!("Player{}", &external_id[..6.min(external_id.len())])
+    };
+    let color = PLAYER_COLORS[thread_rng().gen_range(0..PLAYER_COLORS.len())].to_string();
+    let account = server.accounts.find_or_create_external(&username, nickname, color);
+    Ok(server.accounts.create_session(&account.username))
+}
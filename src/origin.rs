@@ -0,0 +1,42 @@
+// Origin checks on WebSocket upgrades, against cross-site WebSocket hijacking:
+// browsers send a session cookie along with any page's `new WebSocket`, so a
+// page elsewhere could otherwise play as whoever visits it. An upgrade is let
+// through when it has no Origin (not a browser), comes from a page on the
+// same host or PUBLIC_URL, from a page on this machine to a server on this
+// machine (development), or from an origin in ALLOWED_ORIGINS.
+use crate::config::Config;
+use hyper::header;
+use hyper::Request;
+use std::net::IpAddr;
+
+pub fn allowed<B>(config: &Config, req: &Request<B>) -> bool {
+    let Some(origin) = req.headers().get(header::ORIGIN) else {
+        return true;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    let origin = origin.trim_end_matches('/');
+    let Some(origins) = &config.allowed_origins else {
+        return true;
+    };
+    if origins.contains(origin) || origin.eq_ignore_ascii_case(&config.public_url) {
+        return true;
+    }
+    let Some((_, origin_authority)) = origin.split_once("://") else {
+        return false;
+    };
+    let Some(host) = req.headers().get(header::HOST).and_then(|h| h.to_str().ok()) else {
+        return false;
+    };
+    origin_authority.eq_ignore_ascii_case(host) || (is_loopback(origin_authority) && is_loopback(host))
+}
+
+// Whether a `host[:port]` names this machine
+fn is_loopback(authority: &str) -> bool {
+    let hostname = match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(rest),
+        None => authority.split(':').next().unwrap_or(authority),
+    };
+    hostname.eq_ignore_ascii_case("localhost") || hostname.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
@@ -0,0 +1,59 @@
+// Load shedding. After each tick the server counts as overloaded if the tick
+// took more than TICK_BUDGET of its interval, or a broadcast channel is more
+// than half full (connections aren't keeping up with what's sent to them).
+// While it is, snapshots go out at a fraction of SNAPSHOT_RATE_HZ and new
+// Joins are turned away with a server_busy Error, so the players already on
+// keep playing; once neither sign has shown for RECOVERY it's back to normal.
+// OVERLOAD_PROTECTION=false turns it off.
+use crate::admin_ws::AdminEvent;
+use crate::GameServer;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// WebSocket close code for Joins turned away ("Try Again Later")
+pub const BUSY_CLOSE_CODE: u16 = 1013;
+// Only every this many ticks sends a snapshot while overloaded
+pub const SNAPSHOT_DIVISOR: u32 = 2;
+const TICK_BUDGET: f64 = 0.8;
+const RECOVERY: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+pub struct Overload {
+    overloaded: AtomicBool,
+    // When a sign of overload was last seen
+    last_sign: Mutex<Option<Instant>>,
+}
+
+impl GameServer {
+    pub fn is_overloaded(&self) -> bool {
+        self.overload.overloaded.load(Ordering::Relaxed)
+    }
+
+    // Checks for overload after a tick that took `took` out of `interval`
+    pub fn check_overload(&self, took: Duration, interval: Duration) {
+        if !self.config().overload_protection {
+            return;
+        }
+        let backlog = self.broadcast_tx.len().max(self.rooms.broadcast_backlog());
+        let saturated = backlog > self.config().broadcast_capacity / 2;
+        let slow = took.as_secs_f64() > interval.as_secs_f64() * TICK_BUDGET;
+        let now = Instant::now();
+        let mut last_sign = self.overload.last_sign.lock().unwrap();
+        if slow || saturated {
+            *last_sign = Some(now);
+            if !self.overload.overloaded.swap(true, Ordering::Relaxed) {
+                let tick_ms = took.as_millis() as u64;
+                warn!(tick_ms, backlog, "Overloaded: slowing snapshots and turning away new players");
+                self.metrics.overloaded.store(1, Ordering::Relaxed);
+                self.events.emit(AdminEvent::Overloaded { tick_ms, backlog });
+            }
+        } else if self.is_overloaded() && last_sign.is_some_and(|t| now.duration_since(t) >= RECOVERY) {
+            self.overload.overloaded.store(false, Ordering::Relaxed);
+            info!("Recovered from overload");
+            self.metrics.overloaded.store(0, Ordering::Relaxed);
+            self.events.emit(AdminEvent::Recovered);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+// WebAssembly plugins: a sandboxed alternative to the Lua hooks. Every
+// `.wasm` module in PLUGINS_DIR is instantiated at startup, with no access
+// to anything but the host functions below, and hears about server events
+// as JSON:
+//
+//   {"event":"player_join","player_id":"...","nickname":"...","room":"..."}
+//   {"event":"chat","player_id":"...","message":"..."}
+//   {"event":"tick","seconds":0.05}
+//
+// A module exports `memory`, `alloc(len) -> ptr`, which the host writes each
+// event into, and `on_event(ptr, len)`. It may import from "game", strings
+// being (ptr, len) in its memory:
+//
+//   broadcast(ptr, len)                          system message to everyone
+//   kick_player(id_ptr, id_len, reason_ptr, reason_len) -> 1 kicked, 0 not
+//   log(ptr, len)
+//
+// Each call gets PLUGIN_FUEL units of fuel; a plugin that runs out, traps or
+// fails to load is logged and skipped. Needs the `plugins` feature.
+use crate::config::Config;
+use crate::GameServer;
+use anyhow::Result;
+use serde::Serialize;
+
+// One event, as sent to on_event
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PluginEvent<'a> {
+    PlayerJoin { player_id: &'a str, nickname: &'a str, room: &'a str },
+    Chat { player_id: &'a str, message: &'a str },
+    Tick { seconds: f32 },
+}
+
+#[cfg(feature = "plugins")]
+#[derive(Default)]
+pub struct Plugins {
+    loaded: std::sync::OnceLock<Vec<std::sync::Mutex<host::Plugin>>>,
+}
+
+impl GameServer {
+    // Loads every module in PLUGINS_DIR, if set
+    pub fn load_plugins(&self, config: &Config) -> Result<()> {
+        let Some(dir) = &config.plugins_dir else {
+            return Ok(());
+        };
+        #[cfg(feature = "plugins")]
+        {
+            let plugins = host::load_dir(self, dir, config.plugin_fuel)?;
+            tracing::info!("🧩 Loaded {} plugins from {}", plugins.len(), dir.display());
+            let _ = self.plugins.loaded.set(plugins.into_iter().map(std::sync::Mutex::new).collect());
+        }
+        #[cfg(not(feature = "plugins"))]
+        tracing::warn!("PLUGINS_DIR is set to {} but this build has no plugins feature", dir.display());
+        Ok(())
+    }
+
+    pub fn notify_plugins(&self, event: PluginEvent) {
+        #[cfg(feature = "plugins")]
+        {
+            let Some(plugins) = self.plugins.loaded.get().filter(|plugins| !plugins.is_empty()) else {
+                return;
+            };
+            let json = serde_json::to_vec(&event).unwrap();
+            for plugin in plugins {
+                plugin.lock().unwrap().on_event(&json);
+            }
+        }
+        #[cfg(not(feature = "plugins"))]
+        let _ = event;
+    }
+}
+
+#[cfg(feature = "plugins")]
+mod host {
+    use crate::moderation::{self, Moderator};
+    use crate::tier::Role;
+    use crate::GameServer;
+    use anyhow::{anyhow, Context, Result};
+    use std::path::Path;
+    use tracing::{info, warn};
+    use wasmtime::{Caller, Config, Engine, Instance, Linker, Memory, Module, Store, TypedFunc};
+
+    // What host functions can reach
+    pub struct Host {
+        server: GameServer,
+        plugin: String,
+    }
+
+    pub struct Plugin {
+        name: String,
+        fuel: u64,
+        store: Store<Host>,
+        memory: Memory,
+        alloc: TypedFunc<i32, i32>,
+        on_event: TypedFunc<(i32, i32), ()>,
+    }
+
+    impl Plugin {
+        pub fn on_event(&mut self, json: &[u8]) {
+            if let Err(e) = self.call(json) {
+                warn!(plugin = %self.name, "Plugin failed: {:#}", e);
+            }
+        }
+
+        fn call(&mut self, json: &[u8]) -> Result<()> {
+            self.store.set_fuel(self.fuel)?;
+            let len = i32::try_from(json.len())?;
+            let ptr = self.alloc.call(&mut self.store, len)?;
+            self.memory.write(&mut self.store, ptr as u32 as usize, json)?;
+            self.on_event.call(&mut self.store, (ptr, len))?;
+            Ok(())
+        }
+    }
+
+    pub fn load_dir(server: &GameServer, dir: &Path, fuel: u64) -> Result<Vec<Plugin>> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let linker = linker(&engine)?;
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .with_context(|| format!("failed to read {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+            .collect();
+        paths.sort();
+        let mut plugins = Vec::new();
+        for path in paths {
+            match load(server, &engine, &linker, &path, fuel) {
+                Ok(plugin) => {
+                    info!(plugin = %plugin.name, "Loaded plugin");
+                    plugins.push(plugin);
+                }
+                Err(e) => warn!(path = %path.display(), "Skipping plugin: {:#}", e),
+            }
+        }
+        Ok(plugins)
+    }
+
+    fn load(server: &GameServer, engine: &Engine, linker: &Linker<Host>, path: &Path, fuel: u64) -> Result<Plugin> {
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let module = Module::from_file(engine, path)?;
+        let mut store = Store::new(engine, Host { server: server.clone(), plugin: name.clone() });
+        store.set_fuel(fuel)?;
+        let instance: Instance = linker.instantiate(&mut store, &module)?;
+        let memory = instance.get_memory(&mut store, "memory").ok_or_else(|| anyhow!("no exported memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let on_event = instance.get_typed_func::<(i32, i32), ()>(&mut store, "on_event")?;
+        Ok(Plugin { name, fuel, store, memory, alloc, on_event })
+    }
+
+    fn linker(engine: &Engine) -> Result<Linker<Host>> {
+        let mut linker = Linker::new(engine);
+        linker.func_wrap("game", "broadcast", |mut caller: Caller<'_, Host>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let message = read_string(&mut caller, ptr, len)?;
+            caller.data().server.broadcast_message(moderation::system_message(message)).map_err(wasmtime::Error::from_anyhow)?;
+            Ok(())
+        })?;
+        linker.func_wrap(
+            "game",
+            "kick_player",
+            |mut caller: Caller<'_, Host>, id_ptr: i32, id_len: i32, reason_ptr: i32, reason_len: i32| -> wasmtime::Result<i32> {
+                let player_id = read_string(&mut caller, id_ptr, id_len)?;
+                let reason = read_string(&mut caller, reason_ptr, reason_len)?;
+                let host = caller.data();
+                let by = Moderator { role: Role::Admin, name: &host.plugin };
+                let reason = (!reason.is_empty()).then_some(reason.as_str());
+                Ok(i32::from(host.server.kick(&player_id, &by, reason).is_ok()))
+            },
+        )?;
+        linker.func_wrap("game", "log", |mut caller: Caller<'_, Host>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let message = read_string(&mut caller, ptr, len)?;
+            info!(target: "plugin", plugin = %caller.data().plugin, "{}", message);
+            Ok(())
+        })?;
+        Ok(linker)
+    }
+
+    fn read_string(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> wasmtime::Result<String> {
+        let memory = caller
+            .get_export("memory")
+            .and_then(|export| export.into_memory())
+            .ok_or_else(|| wasmtime::Error::msg("no exported memory"))?;
+        let mut bytes = vec![0; usize::try_from(len)?];
+        memory.read(&*caller, ptr as u32 as usize, &mut bytes)?;
+        Ok(String::from_utf8(bytes)?)
+    }
+}
@@ -0,0 +1,344 @@
+use crate::accounts::{Account, SavedPosition};
+use crate::achievements::UnlockedAchievement;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::bans::Ban;
+use crate::leaderboard::DailyScore;
+use crate::reports::{Report, ReportedPlayer};
+use crate::stats::PlayerStats;
+use crate::storage::{ArchivedChat, ChatQuery, ChatRecord, Storage};
+use crate::tier::Role;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Accounts, scores, chat history, bans, reports and the audit log in Postgres. Migrations in ./migrations
+// are embedded at build time and applied on connect.
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(url)
+            .await
+            .context("failed to connect to DATABASE_URL")?;
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await
+            .context("failed to run database migrations")?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn load_accounts(&self) -> Result<Vec<Account>> {
+        let rows = sqlx::query(
+            "SELECT p.username, p.password_hash, p.nickname, p.color, p.role, p.created_at, p.last_x, p.last_y,
+                    p.last_position_at, COALESCE(s.score, 0) AS score,
+                    COALESCE(s.daily_day, 0) AS daily_day, COALESCE(s.daily_score, 0) AS daily_score, COALESCE(st.distance, 0) AS distance,
+                    COALESCE(st.messages_sent, 0) AS messages_sent, COALESCE(st.session_secs, 0) AS session_secs,
+                    COALESCE(st.joins, 0) AS joins
+             FROM players p
+             LEFT JOIN scores s ON s.username = p.username
+             LEFT JOIN player_stats st ON st.username = p.username",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut achievements: HashMap<String, Vec<UnlockedAchievement>> = HashMap::new();
+        for row in sqlx::query("SELECT username, achievement, unlocked_at FROM achievements ORDER BY unlocked_at")
+            .fetch_all(&self.pool)
+            .await?
+        {
+            achievements.entry(row.try_get("username")?).or_default().push(UnlockedAchievement {
+                id: row.try_get("achievement")?,
+                unlocked_at: row.try_get::<i64, _>("unlocked_at")? as u64,
+            });
+        }
+        rows.iter()
+            .map(|row| {
+                let last_x: Option<f32> = row.try_get("last_x")?;
+                let last_y: Option<f32> = row.try_get("last_y")?;
+                let last_position_at: Option<i64> = row.try_get("last_position_at")?;
+                let last_position = match (last_x, last_y, last_position_at) {
+                    (Some(x), Some(y), Some(at)) => Some(SavedPosition { x, y, saved_at: at as u64 }),
+                    _ => None,
+                };
+                let username: String = row.try_get("username")?;
+                let role: String = row.try_get("role")?;
+                Ok(Account {
+                    role: Role::from_name(&role).with_context(|| format!("unknown role {role:?} for {username}"))?,
+                    achievements: achievements.remove(&username).unwrap_or_default(),
+                    daily_score: DailyScore {
+                        day: row.try_get::<i64, _>("daily_day")? as u64,
+                        score: row.try_get::<i64, _>("daily_score")? as u64,
+                    },
+                    username,
+                    password_hash: row.try_get("password_hash")?,
+                    nickname: row.try_get("nickname")?,
+                    color: row.try_get("color")?,
+                    score: row.try_get::<i64, _>("score")? as u64,
+                    created_at: row.try_get::<i64, _>("created_at")? as u64,
+                    last_position,
+                    stats: PlayerStats {
+                        distance: row.try_get("distance")?,
+                        messages_sent: row.try_get::<i64, _>("messages_sent")? as u64,
+                        session_secs: row.try_get::<i64, _>("session_secs")? as u64,
+                        joins: row.try_get::<i64, _>("joins")? as u64,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    async fn save_account(&self, account: &Account) -> Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO players (username, password_hash, nickname, color, created_at, last_x, last_y, last_position_at, role)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             ON CONFLICT (username) DO UPDATE
+             SET password_hash = EXCLUDED.password_hash, nickname = EXCLUDED.nickname, color = EXCLUDED.color,
+                 role = EXCLUDED.role, last_x = EXCLUDED.last_x, last_y = EXCLUDED.last_y, last_position_at = EXCLUDED.last_position_at",
+        )
+        .bind(&account.username)
+        .bind(&account.password_hash)
+        .bind(&account.nickname)
+        .bind(&account.color)
+        .bind(account.created_at as i64)
+        .bind(account.last_position.map(|p| p.x))
+        .bind(account.last_position.map(|p| p.y))
+        .bind(account.last_position.map(|p| p.saved_at as i64))
+        .bind(account.role.name())
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO scores (username, score, updated_at, daily_day, daily_score) VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (username) DO UPDATE
+             SET score = EXCLUDED.score, updated_at = EXCLUDED.updated_at,
+                 daily_day = EXCLUDED.daily_day, daily_score = EXCLUDED.daily_score",
+        )
+        .bind(&account.username)
+        .bind(account.score as i64)
+        .bind(now)
+        .bind(account.daily_score.day as i64)
+        .bind(account.daily_score.score as i64)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO player_stats (username, distance, messages_sent, session_secs, joins)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (username) DO UPDATE
+             SET distance = EXCLUDED.distance, messages_sent = EXCLUDED.messages_sent,
+                 session_secs = EXCLUDED.session_secs, joins = EXCLUDED.joins",
+        )
+        .bind(&account.username)
+        .bind(account.stats.distance)
+        .bind(account.stats.messages_sent as i64)
+        .bind(account.stats.session_secs as i64)
+        .bind(account.stats.joins as i64)
+        .execute(&mut *tx)
+        .await?;
+        // Unlocks are never revoked, so only new ones need inserting
+        for unlocked in &account.achievements {
+            sqlx::query(
+                "INSERT INTO achievements (username, achievement, unlocked_at) VALUES ($1, $2, $3)
+                 ON CONFLICT DO NOTHING",
+            )
+            .bind(&account.username)
+            .bind(&unlocked.id)
+            .bind(unlocked.unlocked_at as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn append_chat(&self, chat: &ChatRecord) -> Result<()> {
+        sqlx::query("INSERT INTO chat_messages (player_id, nickname, message, sent_at, room) VALUES ($1, $2, $3, $4, $5)")
+            .bind(&chat.player_id)
+            .bind(&chat.nickname)
+            .bind(&chat.message)
+            .bind(chat.sent_at as i64)
+            .bind(&chat.room)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn query_chat(&self, query: &ChatQuery) -> Result<Option<Vec<ArchivedChat>>> {
+        let rows = sqlx::query(
+            "SELECT id, player_id, nickname, message, sent_at, room FROM chat_messages
+             WHERE ($1::BIGINT IS NULL OR id < $1) AND ($2::TEXT IS NULL OR room = $2) AND ($3::TEXT IS NULL OR player_id = $3)
+             ORDER BY id DESC LIMIT $4",
+        )
+        .bind(query.before.map(|before| before as i64))
+        .bind(&query.room)
+        .bind(&query.player_id)
+        .bind(query.limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let chat = rows
+            .into_iter()
+            .map(|row| {
+                Ok(ArchivedChat {
+                    id: row.try_get::<i64, _>("id")? as u64,
+                    record: ChatRecord {
+                        player_id: row.try_get("player_id")?,
+                        nickname: row.try_get("nickname")?,
+                        message: row.try_get("message")?,
+                        sent_at: row.try_get::<i64, _>("sent_at")? as u64,
+                        room: row.try_get("room")?,
+                    },
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(chat))
+    }
+
+    async fn load_bans(&self) -> Result<Vec<Ban>> {
+        let rows = sqlx::query("SELECT id, username, ip, reason, expires_at, created_at FROM bans")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                let ip: Option<String> = row.try_get("ip")?;
+                Ok(Ban {
+                    id: row.try_get::<i64, _>("id")? as u64,
+                    username: row.try_get("username")?,
+                    ip: ip.map(|ip| ip.parse()).transpose().context("invalid IP address in bans")?,
+                    reason: row.try_get("reason")?,
+                    expires_at: row.try_get::<Option<i64>, _>("expires_at")?.map(|at| at as u64),
+                    created_at: row.try_get::<i64, _>("created_at")? as u64,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_ban(&self, ban: &Ban) -> Result<()> {
+        // Ids are allocated by the ban store so they match what admins see
+        sqlx::query(
+            "INSERT INTO bans (id, username, ip, reason, expires_at, created_at) VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (id) DO UPDATE
+             SET username = EXCLUDED.username, ip = EXCLUDED.ip, reason = EXCLUDED.reason,
+                 expires_at = EXCLUDED.expires_at",
+        )
+        .bind(ban.id as i64)
+        .bind(&ban.username)
+        .bind(ban.ip.map(|ip| ip.to_string()))
+        .bind(&ban.reason)
+        .bind(ban.expires_at.map(|at| at as i64))
+        .bind(ban.created_at as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_ban(&self, id: u64) -> Result<()> {
+        sqlx::query("DELETE FROM bans WHERE id = $1")
+            .bind(id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn load_reports(&self) -> Result<Vec<Report>> {
+        let rows = sqlx::query(
+            "SELECT id, reporter_id, reporter_nickname, reporter_account, target_id, target_nickname, target_account,
+                    reason, recent_chat, created_at
+             FROM reports",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                let recent_chat: String = row.try_get("recent_chat")?;
+                Ok(Report {
+                    id: row.try_get::<i64, _>("id")? as u64,
+                    reporter: ReportedPlayer {
+                        player_id: row.try_get("reporter_id")?,
+                        nickname: row.try_get("reporter_nickname")?,
+                        account: row.try_get("reporter_account")?,
+                    },
+                    target: ReportedPlayer {
+                        player_id: row.try_get("target_id")?,
+                        nickname: row.try_get("target_nickname")?,
+                        account: row.try_get("target_account")?,
+                    },
+                    reason: row.try_get("reason")?,
+                    recent_chat: serde_json::from_str(&recent_chat).context("invalid recent_chat in reports")?,
+                    created_at: row.try_get::<i64, _>("created_at")? as u64,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_report(&self, report: &Report) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO reports (id, reporter_id, reporter_nickname, reporter_account, target_id, target_nickname,
+                                  target_account, reason, recent_chat, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+             ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(report.id as i64)
+        .bind(&report.reporter.player_id)
+        .bind(&report.reporter.nickname)
+        .bind(&report.reporter.account)
+        .bind(&report.target.player_id)
+        .bind(&report.target.nickname)
+        .bind(&report.target.account)
+        .bind(&report.reason)
+        .bind(serde_json::to_string(&report.recent_chat)?)
+        .bind(report.created_at as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_audit(&self) -> Result<Vec<AuditEntry>> {
+        let rows = sqlx::query("SELECT id, action, actor, actor_role, target, reason, detail, created_at FROM audit_log")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                let action: String = row.try_get("action")?;
+                let role: String = row.try_get("actor_role")?;
+                Ok(AuditEntry {
+                    id: row.try_get::<i64, _>("id")? as u64,
+                    action: AuditAction::from_name(&action).with_context(|| format!("unknown audit action {action:?}"))?,
+                    actor: row.try_get("actor")?,
+                    actor_role: Role::from_name(&role).with_context(|| format!("unknown role {role:?} in audit log"))?,
+                    target: row.try_get("target")?,
+                    reason: row.try_get("reason")?,
+                    detail: row.try_get("detail")?,
+                    created_at: row.try_get::<i64, _>("created_at")? as u64,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_audit(&self, entry: &AuditEntry) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO audit_log (id, action, actor, actor_role, target, reason, detail, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(entry.id as i64)
+        .bind(entry.action.name())
+        .bind(&entry.actor)
+        .bind(entry.actor_role.name())
+        .bind(&entry.target)
+        .bind(&entry.reason)
+        .bind(&entry.detail)
+        .bind(entry.created_at as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
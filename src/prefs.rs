@@ -0,0 +1,54 @@
+// Settings remembered in localStorage between visits. Storage can be missing
+// or full (private browsing), in which case nothing is remembered.
+use web_sys::Storage;
+
+#[derive(Clone, Copy)]
+pub enum Pref {
+    Nickname,
+    // `#RRGGBB`, applied after joining when the tier may choose its color
+    Color,
+    // Row of the avatar sprite sheet, applied after joining
+    Sprite,
+    // The room to go back to after joining the lobby
+    Room,
+    // "true" once notifications were enabled
+    Notifications,
+    // JSON of the remapped keys, see keys.rs
+    KeyBindings,
+    // Which tab is playing, see tabs.rs
+    Tab,
+}
+
+impl Pref {
+    fn key(self) -> &'static str {
+        match self {
+            Pref::Nickname => "game.nickname",
+            Pref::Color => "game.color",
+            Pref::Sprite => "game.sprite",
+            Pref::Room => "game.room",
+            Pref::Notifications => "game.notifications",
+            Pref::KeyBindings => "game.keys",
+            Pref::Tab => "game.tab",
+        }
+    }
+}
+
+fn storage() -> Option<Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+pub fn load(pref: Pref) -> Option<String> {
+    storage()?.get_item(pref.key()).ok()?.filter(|value| !value.is_empty())
+}
+
+pub fn save(pref: Pref, value: &str) {
+    if let Some(storage) = storage() {
+        let _ = storage.set_item(pref.key(), value);
+    }
+}
+
+pub fn clear(pref: Pref) {
+    if let Some(storage) = storage() {
+        let _ = storage.remove_item(pref.key());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::rooms::RoomId;
+use crate::{Player, PlayerId};
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use redis::aio::ConnectionManager;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+// Online entries expire unless refreshed, so players on an instance that dies
+// drop out on their own
+const ONLINE_TTL_SECS: u64 = 90;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+// How long "last seen" is kept after a player goes offline
+const OFFLINE_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+// Sorted set of online player ids, scored by their last heartbeat
+const ONLINE_KEY: &str = "presence:online";
+
+fn player_key(player_id: &str) -> String {
+    format!("presence:player:{player_id}")
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[derive(Serialize, Debug)]
+pub struct PresenceRecord {
+    pub player_id: String,
+    pub status: String,
+    pub nickname: String,
+    pub room: String,
+    // The server instance the player is connected to
+    pub instance: String,
+    pub last_seen: u64,
+}
+
+enum PresenceEvent {
+    Online { player_id: PlayerId, nickname: String, room: RoomId },
+    Offline { player_id: PlayerId },
+}
+
+// Per-player presence in Redis, shared by every server instance pointed at the
+// same REDIS_URL. Updates are queued for a background task, like storage
+// writes, so joins and leaves never wait on Redis.
+pub struct Presence {
+    conn: ConnectionManager,
+    instance: String,
+    tx: mpsc::UnboundedSender<PresenceEvent>,
+}
+
+impl Presence {
+    pub async fn connect(url: &str, players: Arc<DashMap<PlayerId, Player>>) -> Result<Arc<Self>> {
+        let client = redis::Client::open(url).context("invalid REDIS_URL")?;
+        let conn = client.get_connection_manager().await.context("failed to connect to Redis")?;
+        let instance = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+        info!(%instance, "📡 Publishing presence to Redis");
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let presence = Arc::new(Self { conn, instance, tx });
+        tokio::spawn(presence.clone().run(rx, players));
+        Ok(presence)
+    }
+
+    pub fn online(&self, player_id: &PlayerId, nickname: &str, room: &RoomId) {
+        let _ = self.tx.send(PresenceEvent::Online {
+            player_id: player_id.clone(),
+            nickname: nickname.to_string(),
+            room: room.clone(),
+        });
+    }
+
+    pub fn offline(&self, player_id: &PlayerId) {
+        let _ = self.tx.send(PresenceEvent::Offline {
+            player_id: player_id.clone(),
+        });
+    }
+
+    async fn run(self: Arc<Self>, mut rx: mpsc::UnboundedReceiver<PresenceEvent>, players: Arc<DashMap<PlayerId, Player>>) {
+        let mut conn = self.conn.clone();
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            let result = tokio::select! {
+                event = rx.recv() => match event {
+                    Some(PresenceEvent::Online { player_id, nickname, room }) => {
+                        self.mark_online(&mut conn, &player_id, Some(&nickname), &room).await
+                    }
+                    Some(PresenceEvent::Offline { player_id }) => self.mark_offline(&mut conn, &player_id).await,
+                    None => break,
+                },
+                _ = heartbeat.tick() => self.heartbeat(&mut conn, &players).await,
+            };
+            if let Err(e) = result {
+                warn!("Presence update failed: {:#}", e);
+            }
+        }
+    }
+
+    async fn mark_online(&self, conn: &mut ConnectionManager, player_id: &str, nickname: Option<&str>, room: &str) -> Result<()> {
+        let key = player_key(player_id);
+        let now = now_secs();
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .hset(&key, "status", "online")
+            .hset(&key, "room", room)
+            .hset(&key, "instance", &self.instance)
+            .hset(&key, "last_seen", now)
+            .expire(&key, ONLINE_TTL_SECS as i64)
+            .zadd(ONLINE_KEY, player_id, now);
+        if let Some(nickname) = nickname {
+            pipe.hset(&key, "nickname", nickname);
+        }
+        pipe.query_async::<()>(conn).await?;
+        Ok(())
+    }
+
+    async fn mark_offline(&self, conn: &mut ConnectionManager, player_id: &str) -> Result<()> {
+        let key = player_key(player_id);
+        redis::pipe()
+            .atomic()
+            .hset(&key, "status", "offline")
+            .hset(&key, "last_seen", now_secs())
+            .expire(&key, OFFLINE_TTL_SECS as i64)
+            .zrem(ONLINE_KEY, player_id)
+            .query_async::<()>(conn)
+            .await?;
+        Ok(())
+    }
+
+    // Refreshes this instance's players and drops entries whose instance
+    // stopped refreshing them
+    async fn heartbeat(&self, conn: &mut ConnectionManager, players: &DashMap<PlayerId, Player>) -> Result<()> {
+        let ids: Vec<(PlayerId, RoomId)> = players.iter().map(|p| (p.key().clone(), p.room.clone())).collect();
+        for (id, room) in ids {
+            self.mark_online(conn, &id, None, &room).await?;
+        }
+        redis::cmd("ZREMRANGEBYSCORE")
+            .arg(ONLINE_KEY)
+            .arg("-inf")
+            .arg(now_secs().saturating_sub(ONLINE_TTL_SECS))
+            .query_async::<()>(conn)
+            .await?;
+        Ok(())
+    }
+
+    // Everyone online across all instances
+    pub async fn list_online(&self) -> Result<Vec<PresenceRecord>> {
+        let mut conn = self.conn.clone();
+        let min = now_secs().saturating_sub(ONLINE_TTL_SECS);
+        let ids: Vec<String> = redis::cmd("ZRANGEBYSCORE")
+            .arg(ONLINE_KEY)
+            .arg(min)
+            .arg("+inf")
+            .query_async(&mut conn)
+            .await?;
+        let mut records = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(record) = self.get(&id).await? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    // A player's presence, or None if they've never been seen or the record expired
+    pub async fn get(&self, player_id: &str) -> Result<Option<PresenceRecord>> {
+        let mut conn = self.conn.clone();
+        let mut fields: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(player_key(player_id))
+            .query_async(&mut conn)
+            .await?;
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        let mut take = |name: &str| fields.remove(name).unwrap_or_default();
+        Ok(Some(PresenceRecord {
+            player_id: player_id.to_string(),
+            status: take("status"),
+            nickname: take("nickname"),
+            room: take("room"),
+            instance: take("instance"),
+            last_seen: take("last_seen").parse().unwrap_or(0),
+        }))
+    }
+}
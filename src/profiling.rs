@@ -0,0 +1,67 @@
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Request, Response, StatusCode};
+use pprof::protos::Message as _;
+use std::time::Duration;
+
+const DEFAULT_SECONDS: u64 = 10;
+const MAX_SECONDS: u64 = 60;
+const SAMPLE_HZ: i32 = 99;
+
+// GET /debug/pprof/profile?seconds=N&format=flamegraph|protobuf
+//
+// Samples the whole process for N seconds and returns either an SVG flamegraph
+// (the default) or a raw pprof protobuf for `go tool pprof`. Only one
+// profile can run at a time.
+pub async fn profile(req: &Request<Incoming>) -> Response<Full<Bytes>> {
+    let mut seconds = DEFAULT_SECONDS;
+    let mut flamegraph = true;
+    for (key, value) in req.uri().query().unwrap_or("").split('&').filter_map(|kv| kv.split_once('=')) {
+        match key {
+            "seconds" => seconds = value.parse().unwrap_or(DEFAULT_SECONDS).clamp(1, MAX_SECONDS),
+            "format" => flamegraph = value != "protobuf",
+            _ => {}
+        }
+    }
+
+    let guard = match pprof::ProfilerGuardBuilder::default()
+        .frequency(SAMPLE_HZ)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+    {
+        Ok(guard) => guard,
+        Err(e) => return text_response(StatusCode::CONFLICT, format!("profiler unavailable: {e}")),
+    };
+    tokio::time::sleep(Duration::from_secs(seconds)).await;
+
+    let report = match guard.report().build() {
+        Ok(report) => report,
+        Err(e) => return text_response(StatusCode::INTERNAL_SERVER_ERROR, format!("failed to build report: {e}")),
+    };
+    let (content_type, body) = if flamegraph {
+        let mut svg = Vec::new();
+        if let Err(e) = report.flamegraph(&mut svg) {
+            return text_response(StatusCode::INTERNAL_SERVER_ERROR, format!("failed to render flamegraph: {e}"));
+        }
+        ("image/svg+xml", svg)
+    } else {
+        match report.pprof() {
+            Ok(profile) => ("application/octet-stream", profile.encode_to_vec()),
+            Err(e) => return text_response(StatusCode::INTERNAL_SERVER_ERROR, format!("failed to encode profile: {e}")),
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", content_type)
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
+
+fn text_response(status: StatusCode, body: String) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "text/plain")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
@@ -0,0 +1,515 @@
+// Protobuf types generated from proto/game.proto by build.rs, plus
+// conversions to and from the serde message enums
+use crate::maps::{Obstacle, WorldMap};
+use crate::leaderboard::LeaderboardEntry;
+use crate::rooms::{RoomInfo, RoomMode};
+use crate::stats::PlayerStats;
+use crate::tier::{Capabilities, Tier};
+use crate::{Appearance, ClientMessage, ErrorCode, Player, PlayerPosition, ServerMessage};
+
+pub mod game {
+    include!(concat!(env!("OUT_DIR"), "/game.rs"));
+
+    // Field number of ServerMessage.seq, written separately from the shared body
+    pub const SEQ_TAG: u32 = 15;
+}
+
+impl From<&WorldMap> for game::WorldMap {
+    fn from(world: &WorldMap) -> Self {
+        Self {
+            width: world.width,
+            height: world.height,
+            name: world.name.clone(),
+            obstacles: world.obstacles.iter().map(Into::into).collect(),
+            chunk_size: world.chunk_size,
+        }
+    }
+}
+
+impl From<game::WorldMap> for WorldMap {
+    fn from(world: game::WorldMap) -> Self {
+        WorldMap::new(
+            world.name,
+            world.width,
+            world.height,
+            world.obstacles.into_iter().map(Into::into).collect(),
+            world.chunk_size,
+        )
+    }
+}
+
+impl From<&Obstacle> for game::Obstacle {
+    fn from(obstacle: &Obstacle) -> Self {
+        Self { x: obstacle.x, y: obstacle.y, width: obstacle.width, height: obstacle.height }
+    }
+}
+
+impl From<game::Obstacle> for Obstacle {
+    fn from(obstacle: game::Obstacle) -> Self {
+        Self { x: obstacle.x, y: obstacle.y, width: obstacle.width, height: obstacle.height }
+    }
+}
+
+impl From<&Player> for game::Player {
+    fn from(player: &Player) -> Self {
+        Self {
+            id: player.id.to_string(),
+            nickname: player.nickname.clone(),
+            x: player.x,
+            y: player.y,
+            color: player.color.clone(),
+            last_seen: player.last_seen,
+            tier: game::Tier::from(player.tier) as i32,
+            idle: player.idle,
+            sprite: player.sprite,
+        }
+    }
+}
+
+impl From<game::Player> for Player {
+    fn from(player: game::Player) -> Self {
+        Self {
+            id: player.id.into(),
+            nickname: player.nickname,
+            x: player.x,
+            y: player.y,
+            color: player.color,
+            last_seen: player.last_seen,
+            tier: game::Tier::try_from(player.tier).map(Into::into).unwrap_or_default(),
+            // Clients only ever hear about players in their own room
+            room: crate::rooms::default_room(),
+            idle: player.idle,
+            sprite: player.sprite,
+        }
+    }
+}
+
+impl From<Tier> for game::Tier {
+    fn from(tier: Tier) -> Self {
+        match tier {
+            Tier::Guest => game::Tier::Guest,
+            Tier::Registered => game::Tier::Registered,
+            Tier::Moderator => game::Tier::Moderator,
+        }
+    }
+}
+
+impl From<game::Tier> for Tier {
+    fn from(tier: game::Tier) -> Self {
+        match tier {
+            game::Tier::Guest => Tier::Guest,
+            game::Tier::Registered => Tier::Registered,
+            game::Tier::Moderator => Tier::Moderator,
+        }
+    }
+}
+
+impl From<&Capabilities> for game::Capabilities {
+    fn from(capabilities: &Capabilities) -> Self {
+        Self {
+            change_nickname: capabilities.change_nickname,
+            choose_color: capabilities.choose_color,
+            chat_per_minute: capabilities.chat_per_minute,
+        }
+    }
+}
+
+impl From<game::Capabilities> for Capabilities {
+    fn from(capabilities: game::Capabilities) -> Self {
+        Self {
+            change_nickname: capabilities.change_nickname,
+            choose_color: capabilities.choose_color,
+            chat_per_minute: capabilities.chat_per_minute,
+        }
+    }
+}
+
+impl From<&PlayerStats> for game::PlayerStats {
+    fn from(stats: &PlayerStats) -> Self {
+        Self {
+            distance: stats.distance,
+            messages_sent: stats.messages_sent,
+            session_secs: stats.session_secs,
+            joins: stats.joins,
+        }
+    }
+}
+
+impl From<game::PlayerStats> for PlayerStats {
+    fn from(stats: game::PlayerStats) -> Self {
+        Self {
+            distance: stats.distance,
+            messages_sent: stats.messages_sent,
+            session_secs: stats.session_secs,
+            joins: stats.joins,
+        }
+    }
+}
+
+impl From<&RoomInfo> for game::RoomInfo {
+    fn from(room: &RoomInfo) -> Self {
+        Self {
+            name: room.name.clone(),
+            players: room.players as u32,
+            capacity: room.capacity.map(|c| c as u32),
+            mode: room.mode.name().to_string(),
+            locked: room.locked,
+        }
+    }
+}
+
+impl From<game::RoomInfo> for RoomInfo {
+    fn from(room: game::RoomInfo) -> Self {
+        Self {
+            name: room.name,
+            players: room.players as usize,
+            capacity: room.capacity.map(|c| c as usize),
+            mode: RoomMode::from_name(&room.mode).unwrap_or_default(),
+            locked: room.locked,
+        }
+    }
+}
+
+impl From<&LeaderboardEntry> for game::LeaderboardEntry {
+    fn from(entry: &LeaderboardEntry) -> Self {
+        Self {
+            rank: entry.rank,
+            nickname: entry.nickname.clone(),
+            score: entry.score,
+        }
+    }
+}
+
+impl From<game::LeaderboardEntry> for LeaderboardEntry {
+    fn from(entry: game::LeaderboardEntry) -> Self {
+        Self {
+            rank: entry.rank,
+            nickname: entry.nickname,
+            score: entry.score,
+        }
+    }
+}
+
+impl From<&PlayerPosition> for game::PlayerPosition {
+    fn from(position: &PlayerPosition) -> Self {
+        Self {
+            player_id: position.player_id.to_string(),
+            x: position.x,
+            y: position.y,
+            vx: position.vx,
+            vy: position.vy,
+        }
+    }
+}
+
+impl From<game::PlayerPosition> for PlayerPosition {
+    fn from(position: game::PlayerPosition) -> Self {
+        Self {
+            player_id: position.player_id.into(),
+            x: position.x,
+            y: position.y,
+            vx: position.vx,
+            vy: position.vy,
+        }
+    }
+}
+
+impl From<&ClientMessage> for game::ClientMessage {
+    fn from(message: &ClientMessage) -> Self {
+        use game::client_message::Message;
+        let message = match message {
+            ClientMessage::Join { nickname, token, invite } => Message::Join(game::Join {
+                nickname: nickname.clone(),
+                token: token.clone(),
+                invite: invite.clone(),
+            }),
+            ClientMessage::Move { x, y } => Message::Move(game::Move { x: *x, y: *y }),
+            ClientMessage::Chat { message } => Message::Chat(game::Chat { message: message.clone() }),
+            ClientMessage::ChangeNick { nickname } => {
+                Message::ChangeNick(game::ChangeNick { nickname: nickname.clone() })
+            }
+            ClientMessage::ChangeColor { color } => Message::ChangeColor(game::ChangeColor { color: color.clone() }),
+            ClientMessage::Ack { seq } => Message::Ack(game::Ack { seq: *seq }),
+            ClientMessage::Resync => Message::Resync(game::Resync {}),
+            ClientMessage::TimeSyncRequest { client_time } => {
+                Message::TimeSyncRequest(game::TimeSyncRequest { client_time: *client_time })
+            }
+            ClientMessage::RequestStats { player_id } => Message::RequestStats(game::RequestStats {
+                player_id: player_id.as_ref().map(|id| id.to_string()),
+            }),
+            ClientMessage::Report { target_id, reason } => Message::Report(game::Report {
+                target_id: target_id.to_string(),
+                reason: reason.clone(),
+            }),
+            ClientMessage::JoinRoom { room, password } => Message::JoinRoom(game::JoinRoom {
+                room: room.clone(),
+                password: password.clone(),
+            }),
+            ClientMessage::ListRooms => Message::ListRooms(game::ListRooms {}),
+            ClientMessage::SetIdle { idle } => Message::SetIdle(game::SetIdle { idle: *idle }),
+            ClientMessage::ChangeSprite { sprite } => Message::ChangeSprite(game::ChangeSprite { sprite: *sprite }),
+            ClientMessage::Custom { channel, payload, to } => Message::Custom(game::SendCustom {
+                channel: channel.clone(),
+                payload_json: payload.to_string(),
+                to: to.as_ref().map(|to| to.to_string()),
+            }),
+        };
+        Self { message: Some(message) }
+    }
+}
+
+impl TryFrom<game::ClientMessage> for ClientMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(message: game::ClientMessage) -> anyhow::Result<Self> {
+        use game::client_message::Message;
+        Ok(match message.message {
+            Some(Message::Join(join)) => ClientMessage::Join {
+                nickname: join.nickname,
+                token: join.token,
+                invite: join.invite,
+            },
+            Some(Message::Move(m)) => ClientMessage::Move { x: m.x, y: m.y },
+            Some(Message::Chat(chat)) => ClientMessage::Chat { message: chat.message },
+            Some(Message::ChangeNick(change)) => ClientMessage::ChangeNick { nickname: change.nickname },
+            Some(Message::ChangeColor(change)) => ClientMessage::ChangeColor { color: change.color },
+            Some(Message::Ack(ack)) => ClientMessage::Ack { seq: ack.seq },
+            Some(Message::Resync(_)) => ClientMessage::Resync,
+            Some(Message::TimeSyncRequest(request)) => ClientMessage::TimeSyncRequest {
+                client_time: request.client_time,
+            },
+            Some(Message::RequestStats(request)) => ClientMessage::RequestStats {
+                player_id: request.player_id.map(Into::into),
+            },
+            Some(Message::Report(report)) => ClientMessage::Report {
+                target_id: report.target_id.into(),
+                reason: report.reason,
+            },
+            Some(Message::JoinRoom(join)) => ClientMessage::JoinRoom {
+                room: join.room,
+                password: join.password,
+            },
+            Some(Message::ListRooms(_)) => ClientMessage::ListRooms,
+            Some(Message::SetIdle(set)) => ClientMessage::SetIdle { idle: set.idle },
+            Some(Message::ChangeSprite(change)) => ClientMessage::ChangeSprite { sprite: change.sprite },
+            Some(Message::Custom(custom)) => ClientMessage::Custom {
+                channel: custom.channel,
+                payload: serde_json::from_str(&custom.payload_json)?,
+                to: custom.to.map(Into::into),
+            },
+            None => anyhow::bail!("empty client message"),
+        })
+    }
+}
+
+impl From<&ServerMessage> for game::ServerMessage {
+    fn from(message: &ServerMessage) -> Self {
+        use game::server_message::Message;
+        let message = match message {
+            ServerMessage::Welcome { your_id, players, capabilities, room, appearance, world, slow_mode_secs } => Message::Welcome(game::Welcome {
+                your_id: your_id.to_string(),
+                players: players.iter().map(Into::into).collect(),
+                capabilities: Some(capabilities.into()),
+                room: room.to_string(),
+                appearance: Some(game::Appearance { color: appearance.color.clone(), avatar: appearance.avatar }),
+                world: Some(world.into()),
+                slow_mode_secs: *slow_mode_secs,
+            }),
+            ServerMessage::PlayerJoined { player } => Message::PlayerJoined(game::PlayerJoined {
+                player: Some(player.into()),
+            }),
+            ServerMessage::PlayerLeft { player_id } => Message::PlayerLeft(game::PlayerLeft {
+                player_id: player_id.to_string(),
+            }),
+            ServerMessage::Snapshot { players } => Message::Snapshot(game::Snapshot {
+                players: players.iter().map(Into::into).collect(),
+            }),
+            ServerMessage::ChatMessage { player_id, nickname, message, timestamp } => {
+                Message::ChatMessage(game::ChatMessage {
+                    player_id: player_id.to_string(),
+                    nickname: nickname.clone(),
+                    message: message.clone(),
+                    timestamp: *timestamp,
+                })
+            }
+            ServerMessage::Error { message, code } => Message::Error(game::Error {
+                message: message.clone(),
+                code: code.map(|code| code.name().to_string()),
+            }),
+            ServerMessage::TimeSyncResponse { client_time, server_receive_time, server_send_time } => {
+                Message::TimeSyncResponse(game::TimeSyncResponse {
+                    client_time: *client_time,
+                    server_receive_time: *server_receive_time,
+                    server_send_time: *server_send_time,
+                })
+            }
+            ServerMessage::StatsSnapshot { player_id, session, lifetime } => {
+                Message::StatsSnapshot(game::StatsSnapshot {
+                    player_id: player_id.to_string(),
+                    session: Some(session.into()),
+                    lifetime: lifetime.as_ref().map(Into::into),
+                })
+            }
+            ServerMessage::AchievementUnlocked { player_id, nickname, achievement, name, description } => {
+                Message::AchievementUnlocked(game::AchievementUnlocked {
+                    player_id: player_id.to_string(),
+                    nickname: nickname.clone(),
+                    achievement: achievement.clone(),
+                    name: name.clone(),
+                    description: description.clone(),
+                })
+            }
+            ServerMessage::Leaderboard { daily, alltime } => Message::Leaderboard(game::Leaderboard {
+                daily: daily.iter().map(Into::into).collect(),
+                alltime: alltime.iter().map(Into::into).collect(),
+            }),
+            ServerMessage::SystemMessage { message, timestamp } => Message::SystemMessage(game::SystemMessage {
+                message: message.clone(),
+                timestamp: *timestamp,
+            }),
+            ServerMessage::RoomList { rooms } => Message::RoomList(game::RoomList {
+                rooms: rooms.iter().map(Into::into).collect(),
+            }),
+            ServerMessage::PlayerIdle { player_id, idle } => Message::PlayerIdle(game::PlayerIdle {
+                player_id: player_id.to_string(),
+                idle: *idle,
+            }),
+            ServerMessage::AppearanceChanged { player_id, color, avatar } => Message::AppearanceChanged(game::AppearanceChanged {
+                player_id: player_id.to_string(),
+                color: color.clone(),
+                avatar: *avatar,
+            }),
+            ServerMessage::NickChanged { player_id, nickname } => Message::NickChanged(game::NickChanged {
+                player_id: player_id.to_string(),
+                nickname: nickname.clone(),
+            }),
+            ServerMessage::SessionTakenOver => Message::SessionTakenOver(game::SessionTakenOver {}),
+            ServerMessage::WorldChanged { world } => Message::WorldChanged(game::WorldChanged { world: Some(world.into()) }),
+            ServerMessage::ChunkLoaded { column, row, obstacles } => Message::ChunkLoaded(game::ChunkLoaded {
+                column: *column,
+                row: *row,
+                obstacles: obstacles.iter().map(Into::into).collect(),
+            }),
+            ServerMessage::ChunkUnloaded { column, row } => {
+                Message::ChunkUnloaded(game::ChunkUnloaded { column: *column, row: *row })
+            }
+            ServerMessage::SlowModeChanged { interval_secs, by } => Message::SlowModeChanged(game::SlowModeChanged {
+                interval_secs: *interval_secs,
+                by: by.clone(),
+            }),
+            ServerMessage::Custom { channel, payload, from } => Message::Custom(game::Custom {
+                channel: channel.clone(),
+                payload_json: payload.to_string(),
+                from: from.as_ref().map(|from| from.to_string()),
+            }),
+            ServerMessage::Draining { retry_after_secs } => {
+                Message::Draining(game::Draining { retry_after_secs: *retry_after_secs })
+            }
+        };
+        Self { message: Some(message), seq: 0 }
+    }
+}
+
+impl TryFrom<game::ServerMessage> for ServerMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(message: game::ServerMessage) -> anyhow::Result<Self> {
+        use game::server_message::Message;
+        Ok(match message.message {
+            Some(Message::Welcome(welcome)) => ServerMessage::Welcome {
+                your_id: welcome.your_id.into(),
+                players: welcome.players.into_iter().map(Into::into).collect(),
+                capabilities: welcome
+                    .capabilities
+                    .map(Into::into)
+                    .ok_or_else(|| anyhow::anyhow!("Welcome without capabilities"))?,
+                room: welcome.room.into(),
+                appearance: welcome
+                    .appearance
+                    .map(|appearance| Appearance { color: appearance.color, avatar: appearance.avatar })
+                    .unwrap_or_default(),
+                world: welcome.world.map(Into::into).ok_or_else(|| anyhow::anyhow!("Welcome without world"))?,
+                slow_mode_secs: welcome.slow_mode_secs,
+            },
+            Some(Message::PlayerJoined(joined)) => ServerMessage::PlayerJoined {
+                player: joined.player.map(Into::into).ok_or_else(|| anyhow::anyhow!("PlayerJoined without player"))?,
+            },
+            Some(Message::PlayerLeft(left)) => ServerMessage::PlayerLeft { player_id: left.player_id.into() },
+            Some(Message::Snapshot(snapshot)) => ServerMessage::Snapshot {
+                players: snapshot.players.into_iter().map(Into::into).collect(),
+            },
+            Some(Message::ChatMessage(chat)) => ServerMessage::ChatMessage {
+                player_id: chat.player_id.into(),
+                nickname: chat.nickname,
+                message: chat.message,
+                timestamp: chat.timestamp,
+            },
+            Some(Message::Error(error)) => ServerMessage::Error {
+                message: error.message,
+                code: error.code.as_deref().and_then(ErrorCode::from_name),
+            },
+            Some(Message::TimeSyncResponse(response)) => ServerMessage::TimeSyncResponse {
+                client_time: response.client_time,
+                server_receive_time: response.server_receive_time,
+                server_send_time: response.server_send_time,
+            },
+            Some(Message::StatsSnapshot(snapshot)) => ServerMessage::StatsSnapshot {
+                player_id: snapshot.player_id.into(),
+                session: snapshot.session.map(Into::into).unwrap_or_default(),
+                lifetime: snapshot.lifetime.map(Into::into),
+            },
+            Some(Message::AchievementUnlocked(unlocked)) => ServerMessage::AchievementUnlocked {
+                player_id: unlocked.player_id.into(),
+                nickname: unlocked.nickname,
+                achievement: unlocked.achievement,
+                name: unlocked.name,
+                description: unlocked.description,
+            },
+            Some(Message::Leaderboard(leaderboard)) => ServerMessage::Leaderboard {
+                daily: leaderboard.daily.into_iter().map(Into::into).collect(),
+                alltime: leaderboard.alltime.into_iter().map(Into::into).collect(),
+            },
+            Some(Message::SystemMessage(system)) => ServerMessage::SystemMessage {
+                message: system.message,
+                timestamp: system.timestamp,
+            },
+            Some(Message::RoomList(list)) => ServerMessage::RoomList {
+                rooms: list.rooms.into_iter().map(Into::into).collect(),
+            },
+            Some(Message::PlayerIdle(idle)) => ServerMessage::PlayerIdle {
+                player_id: idle.player_id.into(),
+                idle: idle.idle,
+            },
+            Some(Message::AppearanceChanged(change)) => ServerMessage::AppearanceChanged {
+                player_id: change.player_id.into(),
+                color: change.color,
+                avatar: change.avatar,
+            },
+            Some(Message::NickChanged(change)) => ServerMessage::NickChanged {
+                player_id: change.player_id.into(),
+                nickname: change.nickname,
+            },
+            Some(Message::SessionTakenOver(_)) => ServerMessage::SessionTakenOver,
+            Some(Message::WorldChanged(changed)) => ServerMessage::WorldChanged {
+                world: changed.world.map(Into::into).ok_or_else(|| anyhow::anyhow!("WorldChanged without world"))?,
+            },
+            Some(Message::ChunkLoaded(chunk)) => ServerMessage::ChunkLoaded {
+                column: chunk.column,
+                row: chunk.row,
+                obstacles: chunk.obstacles.into_iter().map(Into::into).collect(),
+            },
+            Some(Message::ChunkUnloaded(chunk)) => ServerMessage::ChunkUnloaded { column: chunk.column, row: chunk.row },
+            Some(Message::SlowModeChanged(changed)) => ServerMessage::SlowModeChanged {
+                interval_secs: changed.interval_secs,
+                by: changed.by,
+            },
+            Some(Message::Custom(custom)) => ServerMessage::Custom {
+                channel: custom.channel,
+                payload: serde_json::from_str(&custom.payload_json)?,
+                from: custom.from.map(Into::into),
+            },
+            Some(Message::Draining(draining)) => ServerMessage::Draining { retry_after_secs: draining.retry_after_secs },
+            None => anyhow::bail!("empty server message"),
+        })
+    }
+}
@@ -0,0 +1,144 @@
+use crate::frame::OutboundFrame;
+use crate::metrics::Metrics;
+use crate::ServerMessage;
+use anyhow::{bail, Result};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+// Bounded per-connection queue between the broadcast/direct message sources
+// and the task writing to the socket. When a slow client lets it fill up,
+// the oldest movement snapshot is dropped to make room; chat and every other
+// message are never dropped. A connection is given up on once the queue is full
+// of undroppable messages or it has dropped `drop_limit` snapshots without
+// catching up. Closing the queue sends a close frame once everything queued
+// before it has been written; anything pushed afterwards is discarded.
+pub struct SendQueue {
+    frames: Mutex<Pending>,
+    notify: Notify,
+    capacity: usize,
+    drop_limit: u64,
+    // Times this connection fell so far behind a broadcast channel that
+    // messages were lost before it could queue them
+    lags: AtomicU64,
+    metrics: Arc<Metrics>,
+}
+
+struct Pending {
+    // With when each was queued
+    frames: VecDeque<(Arc<OutboundFrame>, Instant)>,
+    // Snapshots dropped since the queue was last empty
+    drops: u64,
+    close: Option<(u16, String)>,
+}
+
+pub enum Outgoing {
+    // A frame and when it was queued
+    Frame(Arc<OutboundFrame>, Instant),
+    // Close the connection with this code and reason
+    Close(u16, String),
+}
+
+fn is_droppable(frame: &OutboundFrame) -> bool {
+    matches!(frame.message, ServerMessage::Snapshot { .. })
+}
+
+impl SendQueue {
+    pub fn new(capacity: usize, drop_limit: u64, metrics: Arc<Metrics>) -> Self {
+        Self {
+            frames: Mutex::new(Pending {
+                frames: VecDeque::with_capacity(capacity),
+                drops: 0,
+                close: None,
+            }),
+            notify: Notify::new(),
+            capacity,
+            drop_limit,
+            lags: AtomicU64::new(0),
+            metrics,
+        }
+    }
+
+    // Errors mean the client can't keep up and should be disconnected
+    pub fn push(&self, frame: Arc<OutboundFrame>) -> Result<()> {
+        let mut pending = self.frames.lock().unwrap();
+        if pending.close.is_some() {
+            return Ok(());
+        }
+        if pending.frames.len() >= self.capacity {
+            let oldest_update = pending.frames.iter().position(|(f, _)| is_droppable(f));
+            match oldest_update {
+                Some(index) => {
+                    pending.frames.remove(index);
+                    pending.frames.push_back((frame, Instant::now()));
+                }
+                // Dropping the incoming snapshot is as good as dropping an older one
+                None if is_droppable(&frame) => {}
+                None => {
+                    self.metrics.slow_client_disconnects.fetch_add(1, Ordering::Relaxed);
+                    bail!("send queue full ({} messages)", pending.frames.len());
+                }
+            }
+            pending.drops += 1;
+            self.metrics.dropped_updates.fetch_add(1, Ordering::Relaxed);
+            if pending.drops > self.drop_limit {
+                self.metrics.slow_client_disconnects.fetch_add(1, Ordering::Relaxed);
+                bail!("dropped {} position updates without catching up", pending.drops);
+            }
+        } else {
+            pending.frames.push_back((frame, Instant::now()));
+        }
+        drop(pending);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    pub fn close(&self, code: u16, reason: impl Into<String>) {
+        self.frames.lock().unwrap().close.get_or_insert((code, reason.into()));
+        self.notify.notify_one();
+    }
+
+    // Records how long a popped frame took to get out, once it's written
+    pub fn sent(&self, frame: &OutboundFrame, queued: Instant) {
+        self.metrics.send_latency.observe_duration(queued.elapsed());
+        self.metrics.delivery_latency.observe_duration(frame.created.elapsed());
+    }
+
+    // Records `missed` broadcasts lost to lag; returns how many times this
+    // connection has lagged
+    pub fn lagged(&self, missed: u64) -> u64 {
+        self.metrics.broadcast_lags.fetch_add(1, Ordering::Relaxed);
+        self.metrics.broadcast_missed.fetch_add(missed, Ordering::Relaxed);
+        self.lags.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn lags(&self) -> u64 {
+        self.lags.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.frames.lock().unwrap().close.is_some()
+    }
+
+    // Waits for the next frame, or the close once no frames are left; only
+    // one task may pop
+    pub async fn pop(&self) -> Outgoing {
+        loop {
+            {
+                let mut pending = self.frames.lock().unwrap();
+                if let Some((frame, queued)) = pending.frames.pop_front() {
+                    if pending.frames.is_empty() {
+                        pending.drops = 0;
+                    }
+                    return Outgoing::Frame(frame, queued);
+                }
+                if let Some((code, reason)) = pending.close.clone() {
+                    return Outgoing::Close(code, reason);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::config::{Config, TrustedProxies};
+use hyper::body::Incoming;
+use hyper::Request;
+use serde::Serialize;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+// Where a connection comes from: the TCP peer it was accepted from and, when
+// that peer is a trusted reverse proxy (TRUSTED_PROXIES), the client address
+// the proxy reported in `Forwarded` or `X-Forwarded-For`. Bans, room
+// password lockouts, mutes and the admin API all go by `ip()`.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct RemoteAddr {
+    pub peer: SocketAddr,
+    pub forwarded: Option<IpAddr>,
+}
+
+impl RemoteAddr {
+    pub fn from_request(config: &Config, req: &Request<Incoming>, peer: SocketAddr) -> Self {
+        let forwarded = if config.trusted_proxies.trusts(peer.ip()) {
+            forwarded_for(req, &config.trusted_proxies)
+        } else {
+            None
+        };
+        Self { peer, forwarded }
+    }
+
+    // The client's address as best known
+    pub fn ip(&self) -> IpAddr {
+        self.forwarded.unwrap_or(self.peer.ip())
+    }
+}
+
+impl fmt::Display for RemoteAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.forwarded {
+            Some(ip) => write!(f, "{} via {}", ip, self.peer),
+            None => write!(f, "{}", self.peer),
+        }
+    }
+}
+
+// Each proxy appends the address it got the request from, so the client is
+// the last address that isn't itself a trusted proxy. `Forwarded` wins over
+// `X-Forwarded-For` when both are present.
+fn forwarded_for(req: &Request<Incoming>, trusted: &TrustedProxies) -> Option<IpAddr> {
+    let header_values = |name| {
+        req.headers()
+            .get_all(name)
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+    let forwarded: Vec<IpAddr> = header_values("forwarded")
+        .into_iter()
+        .filter_map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find_map(|(key, value)| key.eq_ignore_ascii_case("for").then_some(value))
+                .and_then(parse_node)
+        })
+        .collect();
+    let chain = if forwarded.is_empty() {
+        header_values("x-forwarded-for").into_iter().filter_map(parse_node).collect()
+    } else {
+        forwarded
+    };
+    // With every peer trusted there's no telling proxies apart, so the nearest
+    // proxy's word is taken
+    let is_proxy = |ip: &IpAddr| matches!(trusted, TrustedProxies::Only(proxies) if proxies.contains(ip));
+    chain.into_iter().rev().find(|ip| !is_proxy(ip))
+}
+
+// An address as proxies write it: `1.2.3.4`, `1.2.3.4:5678`, `2001:db8::1`,
+// `[2001:db8::1]:5678`, optionally quoted
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?.split_once(']')?.0.parse().ok()
+}
@@ -0,0 +1,102 @@
+use crate::storage::{ChatRecord, StorageHandle};
+use crate::PlayerId;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How many of the target's latest chat messages are attached to a report
+pub const CHAT_CONTEXT: usize = 20;
+pub const MAX_REASON_LEN: usize = 500;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReportedPlayer {
+    pub player_id: String,
+    pub nickname: String,
+    // Username of the registered account, if any
+    pub account: Option<String>,
+}
+
+// A player's complaint about another, kept for moderators to review
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Report {
+    pub id: u64,
+    pub reporter: ReportedPlayer,
+    pub target: ReportedPlayer,
+    pub reason: String,
+    // What the target had said recently when the report was made
+    pub recent_chat: Vec<ChatRecord>,
+    pub created_at: u64,
+}
+
+// Every report, written behind to storage, plus the recent chat of each
+// connected player so reports can show what was said
+pub struct ReportStore {
+    reports: DashMap<u64, Report>,
+    next_id: AtomicU64,
+    recent_chat: DashMap<PlayerId, VecDeque<ChatRecord>>,
+    storage: StorageHandle,
+}
+
+impl ReportStore {
+    pub fn new(reports: Vec<Report>, storage: StorageHandle) -> Self {
+        let next_id = reports.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+        Self {
+            reports: reports.into_iter().map(|r| (r.id, r)).collect(),
+            next_id: AtomicU64::new(next_id),
+            recent_chat: DashMap::new(),
+            storage,
+        }
+    }
+
+    pub fn record_chat(&self, player_id: &PlayerId, chat: ChatRecord) {
+        let mut recent = self.recent_chat.entry(player_id.clone()).or_default();
+        if recent.len() == CHAT_CONTEXT {
+            recent.pop_front();
+        }
+        recent.push_back(chat);
+    }
+
+    // Drops a disconnected player's chat history
+    pub fn forget(&self, player_id: &str) {
+        self.recent_chat.remove(player_id);
+    }
+
+    // Whether this player already reported the target during this session
+    pub fn has_reported(&self, reporter_id: &str, target_id: &str) -> bool {
+        self.reports
+            .iter()
+            .any(|r| r.reporter.player_id == reporter_id && r.target.player_id == target_id)
+    }
+
+    pub fn add(&self, reporter: ReportedPlayer, target: ReportedPlayer, reason: String) -> Report {
+        let recent_chat = self
+            .recent_chat
+            .get(target.player_id.as_str())
+            .map(|recent| recent.iter().cloned().collect())
+            .unwrap_or_default();
+        let report = Report {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            reporter,
+            target,
+            reason,
+            recent_chat,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        };
+        self.reports.insert(report.id, report.clone());
+        self.storage.save_report(report.clone());
+        report
+    }
+
+    // Newest first
+    pub fn list(&self) -> Vec<Report> {
+        let mut reports: Vec<Report> = self.reports.iter().map(|r| r.value().clone()).collect();
+        reports.sort_by_key(|r| std::cmp::Reverse(r.id));
+        reports
+    }
+
+    pub fn get(&self, id: u64) -> Option<Report> {
+        self.reports.get(&id).map(|r| r.clone())
+    }
+}
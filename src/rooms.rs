@@ -0,0 +1,550 @@
+use crate::accounts::{hash_password, verify_password};
+use crate::admin_ws::{AdminEvent, AdminEvents};
+use crate::frame::OutboundFrame;
+use crate::maps::WorldMap;
+use crate::metrics::Metrics;
+use crate::{GameServer, PlayerId, ServerMessage};
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::info;
+
+pub type RoomId = Arc<str>;
+
+// Where players go unless an invite sends them somewhere else
+pub const DEFAULT_ROOM: &str = "lobby";
+pub const MAX_PASSWORD_LEN: usize = 128;
+// Wrong room passwords an address may try before it has to wait
+const MAX_PASSWORD_FAILURES: u32 = 5;
+const PASSWORD_LOCKOUT: Duration = Duration::from_secs(60);
+// How often the room GC looks for empty rooms, at most
+pub const GC_INTERVAL: Duration = Duration::from_secs(10);
+
+pub fn default_room() -> RoomId {
+    DEFAULT_ROOM.into()
+}
+
+// Room ids appear in invite links, so keep them URL-safe
+pub fn valid_room_id(id: &str) -> bool {
+    (1..=32).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// What's played in a room. Free roaming is all there is so far; games built
+// on the starter add their own modes here.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomMode {
+    #[default]
+    Freeplay,
+}
+
+impl RoomMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            RoomMode::Freeplay => "freeplay",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "freeplay" => Some(RoomMode::Freeplay),
+            _ => None,
+        }
+    }
+}
+
+// A public room, as listed by GET /api/rooms and ListRooms
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoomInfo {
+    pub name: String,
+    pub players: usize,
+    // None for the lobby, which has no limit
+    pub capacity: Option<usize>,
+    pub mode: RoomMode,
+    // Whether JoinRoom needs a password
+    pub locked: bool,
+}
+
+// A room as kept in a snapshot, so --restore and handoffs bring it back
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoomState {
+    pub id: String,
+    pub private: bool,
+    pub capacity: Option<usize>,
+    pub password_hash: Option<String>,
+    pub world: String,
+    #[serde(default)]
+    pub slow_mode_secs: u64,
+}
+
+// A separate game world: players only see, and chat with, others in the
+// same room. Server-wide messages like announcements go through
+// GameServer::broadcast_message instead.
+pub struct Room {
+    pub id: RoomId,
+    // Only reachable through an invite link, see invites.rs
+    pub private: bool,
+    pub mode: RoomMode,
+    pub capacity: Option<usize>,
+    // Argon2 hash of the password JoinRoom has to supply
+    password_hash: Option<String>,
+    // What's being played on, see maps.rs
+    world: Mutex<Arc<WorldMap>>,
+    broadcast_tx: broadcast::Sender<Arc<OutboundFrame>>,
+    // When the room GC first found it empty; None while it's in use
+    empty_since: Mutex<Option<Instant>>,
+    // Least time between one player's chat messages, see slowmode.rs
+    slow_mode: Mutex<Option<Duration>>,
+    // When each player last chatted, kept while slow mode is on
+    last_chat: Mutex<HashMap<PlayerId, Instant>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Room {
+    fn new(
+        id: RoomId,
+        private: bool,
+        capacity: Option<usize>,
+        password_hash: Option<String>,
+        world: Arc<WorldMap>,
+        broadcast_capacity: usize,
+        metrics: Arc<Metrics>,
+    ) -> Arc<Self> {
+        let (broadcast_tx, _) = broadcast::channel(broadcast_capacity);
+        Arc::new(Self {
+            id,
+            private,
+            mode: RoomMode::default(),
+            capacity,
+            password_hash,
+            world: Mutex::new(world),
+            broadcast_tx,
+            empty_since: Mutex::new(None),
+            slow_mode: Mutex::new(None),
+            last_chat: Mutex::new(HashMap::new()),
+            metrics,
+        })
+    }
+
+    // Marks the room as in use, so the GC starts its idle timer over
+    fn claim(&self) {
+        *self.empty_since.lock().unwrap() = None;
+    }
+
+    pub fn world(&self) -> Arc<WorldMap> {
+        Arc::clone(&self.world.lock().unwrap())
+    }
+
+    pub fn set_world(&self, world: Arc<WorldMap>) {
+        *self.world.lock().unwrap() = world;
+    }
+
+    pub fn slow_mode(&self) -> Option<Duration> {
+        *self.slow_mode.lock().unwrap()
+    }
+
+    pub fn set_slow_mode(&self, interval: Option<Duration>) {
+        *self.slow_mode.lock().unwrap() = interval;
+        self.last_chat.lock().unwrap().clear();
+    }
+
+    // Counts a chat message from `player_id` against slow mode: how much
+    // longer they have to wait, or None if the message may go out
+    pub fn slow_mode_wait(&self, player_id: &PlayerId) -> Option<Duration> {
+        let interval = self.slow_mode()?;
+        let mut last_chat = self.last_chat.lock().unwrap();
+        let now = Instant::now();
+        last_chat.retain(|_, at| now.duration_since(*at) < interval);
+        if let Some(at) = last_chat.get(player_id) {
+            return Some(interval - now.duration_since(*at));
+        }
+        last_chat.insert(player_id.clone(), now);
+        None
+    }
+
+    pub fn locked(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    pub fn broadcast(&self, frame: Arc<OutboundFrame>) {
+        let receivers = self.broadcast_tx.send(frame).unwrap_or(0);
+        self.metrics.broadcast_fanout.observe(receivers as u64);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<OutboundFrame>> {
+        self.broadcast_tx.subscribe()
+    }
+}
+
+pub struct Rooms {
+    rooms: DashMap<RoomId, Arc<Room>>,
+    // Recent wrong passwords by address, plus any still being checked: how
+    // many, and when the first was
+    password_failures: DashMap<IpAddr, (u32, Instant)>,
+    // Player limit of every room but the lobby
+    capacity: usize,
+    // Messages each room's broadcast channel holds (BROADCAST_CAPACITY)
+    broadcast_capacity: usize,
+    // Every world loaded at startup; new rooms start on the first
+    worlds: Vec<Arc<WorldMap>>,
+    metrics: Arc<Metrics>,
+    events: Arc<AdminEvents>,
+}
+
+impl Rooms {
+    pub fn new(
+        capacity: usize,
+        broadcast_capacity: usize,
+        worlds: Vec<Arc<WorldMap>>,
+        metrics: Arc<Metrics>,
+        events: Arc<AdminEvents>,
+    ) -> Self {
+        let lobby = Room::new(default_room(), false, None, None, Arc::clone(&worlds[0]), broadcast_capacity, Arc::clone(&metrics));
+        let rooms = DashMap::new();
+        rooms.insert(default_room(), lobby);
+        metrics.rooms.store(1, Ordering::Relaxed);
+        Self {
+            rooms,
+            password_failures: DashMap::new(),
+            capacity,
+            broadcast_capacity,
+            worlds,
+            metrics,
+            events,
+        }
+    }
+
+    pub fn world(&self, name: &str) -> Option<Arc<WorldMap>> {
+        self.worlds.iter().find(|world| world.name == name).cloned()
+    }
+
+    pub fn world_names(&self) -> Vec<String> {
+        self.worlds.iter().map(|world| world.name.clone()).collect()
+    }
+
+    fn default_world(&self) -> Arc<WorldMap> {
+        Arc::clone(&self.worlds[0])
+    }
+
+    // The world of room `id`, or the default one for a room that's gone
+    pub fn world_of(&self, id: &str) -> Arc<WorldMap> {
+        self.get(id).map_or_else(|| self.default_world(), |room| room.world())
+    }
+
+    pub fn states(&self) -> Vec<RoomState> {
+        self.rooms
+            .iter()
+            .map(|room| RoomState {
+                id: room.id.to_string(),
+                private: room.private,
+                capacity: room.capacity,
+                password_hash: room.password_hash.clone(),
+                world: room.world().name.clone(),
+                slow_mode_secs: room.slow_mode().map_or(0, |interval| interval.as_secs()),
+            })
+            .collect()
+    }
+
+    // Brings back rooms from a snapshot at startup. A room whose world is no
+    // longer loaded gets the default one.
+    pub fn restore(&self, states: Vec<RoomState>) {
+        for state in states {
+            let world = self.world(&state.world).unwrap_or_else(|| self.default_world());
+            let slow_mode = (state.slow_mode_secs > 0).then(|| Duration::from_secs(state.slow_mode_secs));
+            if state.id == DEFAULT_ROOM {
+                let lobby = self.lobby();
+                lobby.set_world(world);
+                lobby.set_slow_mode(slow_mode);
+                continue;
+            }
+            let room = Room::new(state.id.into(), state.private, state.capacity, state.password_hash, world, self.broadcast_capacity, Arc::clone(&self.metrics));
+            room.set_slow_mode(slow_mode);
+            if self.rooms.insert(room.id.clone(), Arc::clone(&room)).is_none() {
+                self.created(&room);
+            }
+        }
+    }
+
+    // The most messages waiting in any room's broadcast channel
+    pub fn broadcast_backlog(&self) -> usize {
+        self.rooms.iter().map(|room| room.broadcast_tx.len()).max().unwrap_or(0)
+    }
+
+    pub fn lobby(&self) -> Arc<Room> {
+        self.get(DEFAULT_ROOM).expect("the lobby always exists")
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Room>> {
+        self.rooms.get(id).map(|room| Arc::clone(&room))
+    }
+
+    // A room a player is about to enter. It's claimed while the map entry is
+    // locked, so the GC can't remove it in between.
+    pub fn get_claimed(&self, id: &str) -> Option<Arc<Room>> {
+        self.rooms.get(id).map(|room| {
+            room.claim();
+            Arc::clone(&room)
+        })
+    }
+
+    fn created(&self, room: &Room) {
+        self.metrics.rooms_created.fetch_add(1, Ordering::Relaxed);
+        self.metrics.rooms.fetch_add(1, Ordering::Relaxed);
+        info!(room = %room.id, private = room.private, locked = room.locked(), "Room created");
+        self.events.emit(AdminEvent::RoomCreated {
+            room: room.id.to_string(),
+            private: room.private,
+            locked: room.locked(),
+        });
+    }
+
+    // Invite links create their room when the first invited player arrives.
+    // An invite to a room that already exists gets in without its password.
+    pub fn get_or_create_private(&self, id: &str) -> Arc<Room> {
+        let room = self.rooms.entry(id.into()).or_insert_with(|| {
+            let room = Room::new(id.into(), true, Some(self.capacity), None, self.default_world(), self.broadcast_capacity, Arc::clone(&self.metrics));
+            self.created(&room);
+            room
+        });
+        room.claim();
+        Arc::clone(&room)
+    }
+
+    // The room JoinRoom asked for, checking its password. Rooms that don't
+    // exist yet are created, locked with `password` if one is given.
+    pub async fn enter(&self, id: &str, password: Option<String>, ip: IpAddr) -> Result<Arc<Room>> {
+        if !valid_room_id(id) {
+            bail!("Room names are 1-32 letters, digits, '_' or '-'");
+        }
+        let password = password.filter(|p| !p.is_empty());
+        if password.as_ref().is_some_and(|p| p.len() > MAX_PASSWORD_LEN) {
+            bail!("Room passwords are limited to {MAX_PASSWORD_LEN} characters");
+        }
+        let room = match self.get_claimed(id) {
+            Some(room) => room,
+            None => {
+                let password_hash = match password.clone() {
+                    Some(password) => Some(tokio::task::spawn_blocking(move || hash_password(&password)).await??),
+                    None => None,
+                };
+                let created = Room::new(id.into(), false, Some(self.capacity), password_hash, self.default_world(), self.broadcast_capacity, Arc::clone(&self.metrics));
+                let room = {
+                    let entry = self.rooms.entry(id.into()).or_insert_with(|| Arc::clone(&created));
+                    entry.claim();
+                    Arc::clone(&entry)
+                };
+                // Someone else may have created it while the password was hashing
+                if Arc::ptr_eq(&room, &created) {
+                    self.created(&room);
+                    return Ok(room);
+                }
+                room
+            }
+        };
+        if room.private {
+            bail!("That room is invite-only");
+        }
+        let Some(hash) = room.password_hash.clone() else {
+            return Ok(room);
+        };
+        let Some(password) = password else {
+            bail!("That room needs a password");
+        };
+        self.start_attempt(ip)?;
+        if !tokio::task::spawn_blocking(move || verify_password(&password, &hash)).await? {
+            bail!("Wrong password for {id}");
+        }
+        self.give_back_attempt(ip);
+        Ok(room)
+    }
+
+    // Destroys rooms that have had nobody in them for `idle`, dropping their
+    // broadcast channel. `occupied` is every room with a player in it.
+    pub fn collect_idle(&self, occupied: &HashSet<RoomId>, idle: Duration) {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        for room in self.rooms.iter().filter(|r| &*r.id != DEFAULT_ROOM) {
+            let mut empty_since = room.empty_since.lock().unwrap();
+            if occupied.contains(&room.id) {
+                *empty_since = None;
+            } else if empty_since.is_none_or(|since| now.duration_since(since) < idle) {
+                empty_since.get_or_insert(now);
+            } else {
+                expired.push(room.id.clone());
+            }
+        }
+        for id in expired {
+            // Someone may have claimed it since
+            let removed = self.rooms.remove_if(&id, |_, room| {
+                room.empty_since
+                    .lock()
+                    .unwrap()
+                    .is_some_and(|since| now.duration_since(since) >= idle)
+            });
+            if removed.is_some() {
+                self.metrics.rooms_destroyed.fetch_add(1, Ordering::Relaxed);
+                self.metrics.rooms.fetch_sub(1, Ordering::Relaxed);
+                info!(room = %id, idle_secs = idle.as_secs(), "Room destroyed");
+                self.events.emit(AdminEvent::RoomDestroyed { room: id.to_string() });
+            }
+        }
+        self.password_failures.retain(|_, (_, since)| since.elapsed() < PASSWORD_LOCKOUT);
+    }
+
+    // Counts a password attempt against `ip` before it's checked, under the
+    // same entry as the lockout check, so a burst of parallel attempts can't
+    // all get past it before the first failure is in
+    fn start_attempt(&self, ip: IpAddr) -> Result<()> {
+        let mut entry = self.password_failures.entry(ip).or_insert((0, Instant::now()));
+        let (attempts, since) = &mut *entry;
+        if since.elapsed() >= PASSWORD_LOCKOUT {
+            (*attempts, *since) = (0, Instant::now());
+        }
+        if *attempts >= MAX_PASSWORD_FAILURES {
+            let remaining = PASSWORD_LOCKOUT.saturating_sub(since.elapsed());
+            bail!("Too many wrong passwords, try again in {} seconds", remaining.as_secs().max(1));
+        }
+        *attempts += 1;
+        Ok(())
+    }
+
+    // A right password doesn't count against `ip`
+    fn give_back_attempt(&self, ip: IpAddr) {
+        if let Some(mut entry) = self.password_failures.get_mut(&ip) {
+            entry.0 = entry.0.saturating_sub(1);
+        }
+    }
+}
+
+impl GameServer {
+    // Public rooms, or every room for staff, the lobby first and then the busiest
+    pub fn room_list(&self, include_private: bool) -> Vec<RoomInfo> {
+        let mut population: HashMap<RoomId, usize> = HashMap::new();
+        for player in self.players.iter() {
+            *population.entry(player.room.clone()).or_default() += 1;
+        }
+        let mut rooms: Vec<RoomInfo> = self
+            .rooms
+            .rooms
+            .iter()
+            .filter(|room| include_private || !room.private)
+            .map(|room| RoomInfo {
+                name: room.id.to_string(),
+                players: population.get(&room.id).copied().unwrap_or(0),
+                capacity: room.capacity,
+                mode: room.mode,
+                locked: room.locked(),
+            })
+            .collect();
+        rooms.sort_by(|a, b| {
+            (b.name == DEFAULT_ROOM, b.players, &a.name).cmp(&(a.name == DEFAULT_ROOM, a.players, &b.name))
+        });
+        rooms
+    }
+
+    // Refuses another player in a room that's at capacity
+    pub fn check_capacity(&self, room: &Room) -> Result<()> {
+        let Some(capacity) = room.capacity else {
+            return Ok(());
+        };
+        if self.players.iter().filter(|p| p.room == room.id).count() >= capacity {
+            bail!("{} is full", room.id);
+        }
+        Ok(())
+    }
+
+    pub fn collect_idle_rooms(&self) {
+        let occupied: HashSet<RoomId> = self.players.iter().map(|p| p.room.clone()).collect();
+        self.rooms.collect_idle(&occupied, self.config().room_idle);
+    }
+
+    // Moves a joined player into another room. Returns a subscription to the
+    // new room, taken before anyone there hears about the player.
+    pub async fn change_room(
+        &self,
+        player_id: &str,
+        room_id: &str,
+        password: Option<String>,
+        ip: IpAddr,
+    ) -> Result<broadcast::Receiver<Arc<OutboundFrame>>> {
+        if self.players.get(player_id).is_some_and(|p| &*p.room == room_id) {
+            bail!("You're already in {room_id}");
+        }
+        let room = self.rooms.enter(room_id, password, ip).await?;
+        self.check_capacity(&room)?;
+        let subscription = room.subscribe();
+        let Some(mut player) = self.players.get_mut(player_id) else {
+            bail!("No such player");
+        };
+        let previous = std::mem::replace(&mut player.room, room.id.clone());
+        self.fit_to_room(&mut player);
+        let joined = ServerMessage::PlayerJoined { player: player.clone() };
+        let nickname = player.nickname.clone();
+        let player_id = player.id.clone();
+        drop(player);
+        if let Some(presence) = &self.presence {
+            presence.online(&player_id, &nickname, &room.id);
+        }
+        self.broadcast_to_room(&previous, ServerMessage::PlayerLeft { player_id: player_id.clone() })?;
+        self.broadcast_to_room(&room.id, joined)?;
+        info!(from = %previous, to = %room.id, "Player changed room");
+        self.events.emit(AdminEvent::RoomChanged {
+            player_id,
+            from: previous.to_string(),
+            to: room.id.to_string(),
+        });
+        Ok(subscription)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rooms() -> Rooms {
+        let world = Arc::new(WorldMap::new("test".into(), 800.0, 600.0, Vec::new(), None));
+        Rooms::new(8, 16, vec![world], Arc::default(), Arc::default())
+    }
+
+    #[test]
+    fn locks_out_after_max_password_failures() {
+        let rooms = rooms();
+        let ip: IpAddr = [192, 0, 2, 1].into();
+        for _ in 0..MAX_PASSWORD_FAILURES {
+            rooms.start_attempt(ip).unwrap();
+        }
+        let err = rooms.start_attempt(ip).unwrap_err();
+        assert!(err.to_string().starts_with("Too many wrong passwords"), "{err}");
+        // Other addresses aren't affected
+        rooms.start_attempt([192, 0, 2, 2].into()).unwrap();
+    }
+
+    #[test]
+    fn right_passwords_dont_count() {
+        let rooms = rooms();
+        let ip: IpAddr = [192, 0, 2, 1].into();
+        for _ in 0..MAX_PASSWORD_FAILURES * 2 {
+            rooms.start_attempt(ip).unwrap();
+            rooms.give_back_attempt(ip);
+        }
+        rooms.start_attempt(ip).unwrap();
+    }
+
+    #[test]
+    fn lockout_expires() {
+        let rooms = rooms();
+        let ip: IpAddr = [192, 0, 2, 1].into();
+        for _ in 0..MAX_PASSWORD_FAILURES {
+            rooms.start_attempt(ip).unwrap();
+        }
+        rooms.password_failures.get_mut(&ip).unwrap().1 = Instant::now() - PASSWORD_LOCKOUT;
+        rooms.start_attempt(ip).unwrap();
+        assert_eq!(rooms.password_failures.get(&ip).unwrap().0, 1);
+    }
+}
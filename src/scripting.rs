@@ -0,0 +1,144 @@
+// Lua hooks for server events, so gameplay can change without a rebuild.
+// The script at SCRIPT_PATH is run once at startup and may define any of:
+//
+//   on_player_join(player_id, nickname, room)
+//   on_chat(player_id, message)  -- return a string to replace the message,
+//                                -- or false to drop it
+//   on_tick(seconds)             -- every tick, with the time since the last
+//
+// and call into the server through the `game` table: `game.announce(message)`,
+// `game.tell(player_id, message)`, `game.move_player(player_id, x, y)`,
+// `game.player(player_id)` and `game.log(message)`. A hook that errors is
+// logged and otherwise ignored. Needs the `scripting` feature; without it
+// the hooks do nothing.
+use crate::config::Config;
+use crate::GameServer;
+use anyhow::Result;
+#[cfg(feature = "scripting")]
+use std::sync::OnceLock;
+#[cfg(not(feature = "scripting"))]
+use tracing::warn;
+
+#[cfg(feature = "scripting")]
+#[derive(Default)]
+pub struct Scripts {
+    lua: OnceLock<mlua::Lua>,
+}
+
+impl GameServer {
+    // Runs SCRIPT_PATH, if set, to define the hooks
+    pub fn load_scripts(&self, config: &Config) -> Result<()> {
+        let Some(path) = &config.script_path else {
+            return Ok(());
+        };
+        #[cfg(feature = "scripting")]
+        {
+            let lua = lua::open(self)?;
+            let source = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+            lua.load(source)
+                .set_name(path.display().to_string())
+                .exec()
+                .map_err(|e| anyhow::anyhow!("failed to run {}: {}", path.display(), e))?;
+            let _ = self.scripts.lua.set(lua);
+            tracing::info!("📜 Loaded script {}", path.display());
+        }
+        #[cfg(not(feature = "scripting"))]
+        warn!("SCRIPT_PATH is set to {} but this build has no scripting feature", path.display());
+        Ok(())
+    }
+
+    pub fn on_player_join(&self, player_id: &str, nickname: &str, room: &str) {
+        #[cfg(feature = "scripting")]
+        self.call_hook::<()>("on_player_join", (player_id, nickname, room));
+        #[cfg(not(feature = "scripting"))]
+        let _ = (player_id, nickname, room);
+    }
+
+    // The message to send in place of `message`, or None to drop it
+    pub fn on_chat(&self, player_id: &str, message: String) -> Option<String> {
+        #[cfg(feature = "scripting")]
+        match self.call_hook::<mlua::Value>("on_chat", (player_id, message.as_str())) {
+            Some(mlua::Value::Boolean(false)) => return None,
+            Some(mlua::Value::String(replaced)) => return Some(replaced.to_string_lossy()),
+            _ => {}
+        }
+        #[cfg(not(feature = "scripting"))]
+        let _ = player_id;
+        Some(message)
+    }
+
+    pub fn on_tick(&self, seconds: f32) {
+        #[cfg(feature = "scripting")]
+        self.call_hook::<()>("on_tick", seconds);
+        #[cfg(not(feature = "scripting"))]
+        let _ = seconds;
+    }
+
+    // Calls the script's global function `name`, if it defined one
+    #[cfg(feature = "scripting")]
+    fn call_hook<R: mlua::FromLuaMulti>(&self, name: &str, args: impl mlua::IntoLuaMulti) -> Option<R> {
+        let lua = self.scripts.lua.get()?;
+        let hook = lua.globals().get::<Option<mlua::Function>>(name).ok()??;
+        match hook.call::<R>(args) {
+            Ok(result) => Some(result),
+            Err(e) => {
+                tracing::warn!(hook = name, "Script hook failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "scripting")]
+mod lua {
+    use crate::frame::OutboundFrame;
+    use crate::{moderation, GameServer};
+    use mlua::{Lua, Table};
+
+    // A Lua state with the `game` table calling into `server`
+    pub fn open(server: &GameServer) -> mlua::Result<Lua> {
+        let lua = Lua::new();
+        let game = lua.create_table()?;
+
+        let s = server.clone();
+        game.set("announce", lua.create_function(move |_, message: String| {
+            s.announce(message).map_err(mlua::Error::external)
+        })?)?;
+
+        let s = server.clone();
+        game.set("tell", lua.create_function(move |_, (player_id, message): (String, String)| {
+            if let Some(connection) = s.connections.get(player_id.as_str()) {
+                let _ = connection.queue.push(OutboundFrame::new(moderation::system_message(message)));
+            }
+            Ok(())
+        })?)?;
+
+        let s = server.clone();
+        game.set("move_player", lua.create_function(move |_, (player_id, x, y): (String, f32, f32)| {
+            s.move_player(&player_id, x, y).map_err(mlua::Error::external)
+        })?)?;
+
+        let s = server.clone();
+        game.set("player", lua.create_function(move |lua, player_id: String| {
+            let Some(player) = s.players.get(player_id.as_str()).map(|p| p.clone()) else {
+                return Ok(None);
+            };
+            let table: Table = lua.create_table()?;
+            table.set("id", player.id.to_string())?;
+            table.set("nickname", player.nickname)?;
+            table.set("x", player.x)?;
+            table.set("y", player.y)?;
+            table.set("room", player.room.to_string())?;
+            Ok(Some(table))
+        })?)?;
+
+        game.set("log", lua.create_function(|_, message: String| {
+            tracing::info!(target: "script", "{}", message);
+            Ok(())
+        })?)?;
+
+        lua.globals().set("game", game)?;
+        Ok(lua)
+    }
+}
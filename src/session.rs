@@ -0,0 +1,112 @@
+use crate::config::Config;
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use hyper::body::Incoming;
+use hyper::Request;
+use sha2::Sha256;
+
+// Browser sessions: the session token from AccountStore, carried in an
+// HttpOnly cookie signed with SESSION_SECRET so it can't be forged or
+// tampered with client-side. The WebSocket upgrade reads the same cookie, so
+// a signed-in browser joins as its account without passing a token around.
+pub const COOKIE_NAME: &str = "session";
+
+// Matches the AccountStore session lifetime
+const COOKIE_MAX_AGE_SECS: u64 = 30 * 24 * 60 * 60;
+
+pub fn signature(secret: &[u8], token: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(token.as_bytes());
+    mac
+}
+
+// `Set-Cookie` value carrying `token`
+pub fn session_cookie(config: &Config, token: &str) -> String {
+    signed_cookie(config, COOKIE_NAME, token, COOKIE_MAX_AGE_SECS)
+}
+
+// `Set-Cookie` value that removes the session cookie
+pub fn clear_session_cookie(config: &Config) -> String {
+    clear_cookie(config, COOKIE_NAME)
+}
+
+// `Set-Cookie` value for an HttpOnly cookie `name` holding `value`, signed
+// the same way as the session cookie
+pub fn signed_cookie(config: &Config, name: &str, value: &str, max_age: u64) -> String {
+    let tag = general_purpose::URL_SAFE_NO_PAD.encode(signature(&config.session_secret, value).finalize().into_bytes());
+    cookie(config, name, &format!("{value}.{tag}"), max_age)
+}
+
+// `Set-Cookie` value that removes cookie `name`
+pub fn clear_cookie(config: &Config, name: &str) -> String {
+    cookie(config, name, "", 0)
+}
+
+fn cookie(config: &Config, name: &str, value: &str, max_age: u64) -> String {
+    let secure = if config.public_url.starts_with("https://") { "; Secure" } else { "" };
+    format!("{name}={value}; Path=/; HttpOnly; SameSite=Lax; Max-Age={max_age}{secure}")
+}
+
+// The session token from a validly signed cookie on the request, if any
+pub fn session_token(config: &Config, req: &Request<Incoming>) -> Option<String> {
+    signed_cookie_value(config, req, COOKIE_NAME)
+}
+
+// The value of cookie `name` on the request, if it's validly signed
+pub fn signed_cookie_value(config: &Config, req: &Request<Incoming>, name: &str) -> Option<String> {
+    let value = req
+        .headers()
+        .get_all("cookie")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find_map(|(cookie, value)| (cookie == name).then_some(value))?;
+    verify(config, value)
+}
+
+// What a signed cookie value holds, if its signature is right
+fn verify(config: &Config, value: &str) -> Option<String> {
+    let (token, tag) = value.rsplit_once('.')?;
+    let tag = general_purpose::URL_SAFE_NO_PAD.decode(tag).ok()?;
+    signature(&config.session_secret, token).verify_slice(&tag).ok()?;
+    Some(token.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The value a `Set-Cookie` header sets
+    fn value(set_cookie: &str) -> &str {
+        set_cookie.split_once('=').unwrap().1.split_once(';').unwrap().0
+    }
+
+    #[test]
+    fn verifies_a_signed_cookie() {
+        let config = Config::for_tests("secret");
+        let cookie = session_cookie(&config, "token.with.dots");
+        assert!(cookie.starts_with("session="));
+        assert_eq!(verify(&config, value(&cookie)).as_deref(), Some("token.with.dots"));
+    }
+
+    #[test]
+    fn rejects_a_tampered_cookie() {
+        let config = Config::for_tests("secret");
+        let signed = value(&signed_cookie(&config, "oauth_state", "abc", 60)).to_string();
+        let (_, tag) = signed.rsplit_once('.').unwrap();
+        assert_eq!(verify(&config, &format!("abd.{tag}")), None);
+        assert_eq!(verify(&config, &format!("abc.{}", &tag[1..])), None);
+        assert_eq!(verify(&config, "abc"), None);
+        assert_eq!(verify(&config, "abc."), None);
+        assert_eq!(verify(&Config::for_tests("other"), &signed), None);
+    }
+
+    #[test]
+    fn clears_a_cookie() {
+        let config = Config::for_tests("secret");
+        let cookie = clear_session_cookie(&config);
+        assert!(cookie.starts_with("session=;"));
+        assert!(cookie.contains("Max-Age=0"));
+    }
+}
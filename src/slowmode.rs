@@ -0,0 +1,43 @@
+// Slow mode: a room where each player may chat at most once per interval.
+// Moderators turn it on with `/slow <duration>` or POST /admin/slowmode, and
+// the room hears about it in a SlowModeChanged. Staff aren't held to it.
+use crate::admin_ws::AdminEvent;
+use crate::audit::AuditAction;
+use crate::commands::format_duration;
+use crate::frame::OutboundFrame;
+use crate::moderation::Moderator;
+use crate::{GameServer, PlayerId, ServerMessage};
+use anyhow::{bail, Result};
+use std::time::Duration;
+use tracing::info;
+
+impl GameServer {
+    // Sets the interval for `room`, or turns slow mode off with None
+    pub fn set_slow_mode(&self, room: &str, interval: Option<Duration>, by: &Moderator) -> Result<()> {
+        let Some(room) = self.rooms.get(room) else {
+            bail!("No room named {}", room);
+        };
+        room.set_slow_mode(interval);
+        let interval_secs = interval.map_or(0, |interval| interval.as_secs());
+        info!(room = %room.id, interval_secs, by = by.name, "Slow mode changed");
+        let detail = interval.map_or_else(|| "off".to_string(), format_duration);
+        self.audit.record(AuditAction::SlowMode, by, room.id.to_string(), None, Some(detail));
+        let message = match interval {
+            Some(interval) => format!("{} turned on slow mode in {}: one message every {}", by.name, room.id, format_duration(interval)),
+            None => format!("{} turned off slow mode in {}", by.name, room.id),
+        };
+        self.events.emit(AdminEvent::Moderation { message });
+        room.broadcast(OutboundFrame::new(ServerMessage::SlowModeChanged {
+            interval_secs,
+            by: by.name.to_string(),
+        }));
+        Ok(())
+    }
+
+    // How much longer `player_id` has to wait before chatting in their room;
+    // None lets the message through and starts their next wait
+    pub fn slow_mode_wait(&self, player_id: &PlayerId) -> Option<Duration> {
+        let room = self.players.get(player_id)?.room.clone();
+        self.rooms.get(&room)?.slow_mode_wait(player_id)
+    }
+}
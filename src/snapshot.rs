@@ -0,0 +1,82 @@
+use crate::accounts::ScoreState;
+use crate::rooms::RoomState;
+use crate::{GameServer, Player, PlayerId};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Full serialized game state, as returned by GET /admin/snapshot and
+// accepted by --restore
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GameSnapshot {
+    pub taken_at: u64,
+    pub players: Vec<Player>,
+    // Username of each registered player among `players`, by player id.
+    // Only they can take their place back after a restore.
+    #[serde(default)]
+    pub accounts: HashMap<PlayerId, String>,
+    // Missing from snapshots taken before rooms were kept
+    #[serde(default)]
+    pub rooms: Vec<RoomState>,
+    // Registered players' scores and stats, likewise
+    #[serde(default)]
+    pub scores: Vec<ScoreState>,
+}
+
+impl GameServer {
+    pub fn snapshot(&self) -> GameSnapshot {
+        // Stats not flushed yet would otherwise be missing from the scores
+        for (username, stats) in self.stats.take_unflushed() {
+            self.accounts.add_stats(&username, &stats);
+        }
+        let mut accounts: HashMap<PlayerId, String> = self
+            .connections
+            .iter()
+            .filter_map(|c| Some((c.key().clone(), c.account.clone()?)))
+            .collect();
+        accounts.extend(self.restored.iter().map(|p| (p.id.clone(), p.key().clone())));
+        GameSnapshot {
+            taken_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            players: self
+                .players
+                .iter()
+                .filter(|p| !self.npcs.contains(p.key()))
+                .map(|p| p.value().clone())
+                .chain(self.restored.iter().map(|p| p.value().clone()))
+                .collect(),
+            accounts,
+            rooms: self.rooms.states(),
+            scores: self.accounts.scores(),
+        }
+    }
+
+    // Rehydrate state from a snapshot. Only meant to run at startup, before any
+    // connections exist, so nothing is broadcast. Nobody is connected as the
+    // registered players yet, so they're kept out of sight until they log in
+    // again, see GameServer::reclaim_restored; guests aren't kept. They count
+    // as seen now, so they get the full STALE_PLAYER_SECS to come back however
+    // old the snapshot is.
+    pub fn restore(&self, snapshot: GameSnapshot) {
+        self.rooms.restore(snapshot.rooms);
+        self.accounts.restore_scores(snapshot.scores);
+        self.restored.clear();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        for mut player in snapshot.players {
+            let Some(username) = snapshot.accounts.get(&player.id) else {
+                continue;
+            };
+            player.last_seen = now;
+            self.restored.insert(username.to_lowercase(), player);
+        }
+    }
+}
+
+pub fn load_snapshot(path: &Path) -> Result<GameSnapshot> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read snapshot {}", path.display()))?;
+    let snapshot = serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse snapshot {}", path.display()))?;
+    Ok(snapshot)
+}
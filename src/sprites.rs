@@ -0,0 +1,66 @@
+// Avatars drawn from a sprite sheet image instead of colored circles. Each row
+// of the sheet is one avatar, picked by the player's `sprite`; the first
+// column is the idle frame and the columns after it the walk cycle.
+use crate::world::Position;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::HtmlImageElement;
+
+// How long each walk frame shows
+const WALK_FRAME_MS: f64 = 120.0;
+// A player still counts as walking this long after they last moved, so the
+// gaps between snapshots don't flicker back to the idle frame
+const WALK_HOLD_MS: f64 = 150.0;
+
+pub struct SpriteSheet {
+    pub url: String,
+    pub frame_width: f64,
+    pub frame_height: f64,
+    pub walk_frames: u32,
+}
+
+impl SpriteSheet {
+    // Waits for the image so avatars only switch over once it can be drawn
+    pub async fn load(url: String, frame_width: f64, frame_height: f64, walk_frames: u32) -> Result<Self, JsValue> {
+        let image = HtmlImageElement::new()?;
+        image.set_src(&url);
+        JsFuture::from(image.decode()).await?;
+        Ok(Self { url, frame_width, frame_height, walk_frames })
+    }
+
+    // CSS for the avatar's frame out of `sprite`'s row
+    pub fn style(&self, sprite: u32, frame: u32) -> String {
+        format!(
+            "background: url('{}') {}px {}px; width: {}px; height: {}px;",
+            self.url.replace('\'', "%27"),
+            -f64::from(frame) * self.frame_width,
+            -f64::from(sprite) * self.frame_height,
+            self.frame_width,
+            self.frame_height
+        )
+    }
+}
+
+// Which frame of its row an entity shows, from how it has been moving
+#[derive(Default)]
+pub struct Animation {
+    last_position: Option<Position>,
+    moved_at: f64,
+    pub frame: u32,
+}
+
+impl Animation {
+    // Advances to the frame for `now`; returns whether it changed
+    pub fn update(&mut self, position: Position, walk_frames: u32, now: f64) -> bool {
+        if self.last_position.is_some_and(|last| last != position) {
+            self.moved_at = now;
+        }
+        self.last_position = Some(position);
+        let frame = if walk_frames > 0 && now - self.moved_at < WALK_HOLD_MS {
+            1 + (now / WALK_FRAME_MS) as u32 % walk_frames
+        } else {
+            0
+        };
+        std::mem::replace(&mut self.frame, frame) != frame
+    }
+}
@@ -0,0 +1,10 @@
+// FlatBuffers code generated from proto/state.fbs by build.rs, for the
+// server's writer (state_writer.rs) and the client's reader
+// (state_reader.rs). Each side only uses its half of it.
+#[allow(dead_code, clippy::all)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/state.rs"));
+}
+
+pub use generated::game::fb::*;
+pub use generated::STATE_SNAPSHOT_IDENTIFIER;
@@ -0,0 +1,38 @@
+// Reads proto/state.fbs snapshots in place with the code generated from it.
+// planus checks every offset as a field is read, so a malformed buffer is an
+// error rather than undefined behaviour.
+use crate::state_fb::{StateSnapshotRef, STATE_SNAPSHOT_IDENTIFIER};
+use planus::ReadAsRoot;
+
+pub fn root_as_state_snapshot(buf: &[u8]) -> planus::Result<StateSnapshotRef<'_>> {
+    StateSnapshotRef::read_as_root(buf)
+}
+
+// Whether the buffer is a snapshot, going by the identifier after its root offset
+pub fn state_snapshot_buffer_has_identifier(buf: &[u8]) -> bool {
+    buf.get(4..8) == Some(&STATE_SNAPSHOT_IDENTIFIER[..])
+}
+
+// Inverse of the server's quantize
+pub fn dequantize(value: u16, extent: f32) -> f32 {
+    f32::from(value) / f32::from(u16::MAX) * extent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dequantizes_across_the_extent() {
+        assert_eq!(dequantize(0, 800.0), 0.0);
+        assert_eq!(dequantize(u16::MAX, 800.0), 800.0);
+        assert!((dequantize(32768, 800.0) - 400.0).abs() < 800.0 / f32::from(u16::MAX));
+    }
+
+    #[test]
+    fn checks_the_identifier_after_the_root_offset() {
+        assert!(state_snapshot_buffer_has_identifier(b"\x0c\0\0\0GSNP\0\0\0\0"));
+        assert!(!state_snapshot_buffer_has_identifier(b"GSNP\x0c\0\0\0\0\0\0\0"));
+        assert!(!state_snapshot_buffer_has_identifier(b"\x0c\0\0\0GS"));
+    }
+}
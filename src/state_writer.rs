@@ -0,0 +1,106 @@
+// Builds proto/state.fbs snapshots with the code generated from it
+use crate::state_fb::{PlayerState, StateSnapshot, STATE_SNAPSHOT_IDENTIFIER};
+use planus::Builder;
+
+// A coordinate in 0..=extent as a u16 spanning it, for quantized snapshots.
+// Anything outside is clamped to the edge.
+pub fn quantize(value: f32, extent: f32) -> u16 {
+    if extent <= 0.0 {
+        return 0;
+    }
+    ((value / extent).clamp(0.0, 1.0) * f32::from(u16::MAX)).round() as u16
+}
+
+// A finished snapshot buffer from (id, x, y, vx, vy) entries. Given the
+// world's (width, height), positions are quantized against it.
+pub fn encode_state_snapshot<'i>(
+    players: impl IntoIterator<Item = (&'i str, f32, f32, f32, f32)>,
+    bounds: Option<(f32, f32)>,
+) -> Vec<u8> {
+    let mut builder = Builder::with_capacity(256);
+    let states: Vec<_> = players
+        .into_iter()
+        .map(|(id, x, y, vx, vy)| {
+            // Fields left at their default aren't written, so a quantized
+            // snapshot carries no `x`/`y`
+            let (x, y, qx, qy) = match bounds {
+                Some((width, height)) => (0.0, 0.0, quantize(x, width), quantize(y, height)),
+                None => (x, y, 0, 0),
+            };
+            PlayerState::create(&mut builder, id, x, y, vx, vy, qx, qy)
+        })
+        .collect();
+    let snapshot = StateSnapshot::create(&mut builder, states, bounds.is_some());
+    with_identifier(builder.finish(snapshot, None))
+}
+
+// planus 1.3 writes a file identifier ahead of the root offset instead of
+// after it, so it's put in here the standard way, moving the root offset
+// past it. Nothing in the schema is aligned to more than 4 bytes, so the
+// extra 4 bytes don't misalign anything.
+fn with_identifier(buffer: &[u8]) -> Vec<u8> {
+    let (root, rest) = buffer.split_first_chunk::<4>().expect("a finished buffer starts with its root offset");
+    let root = u32::from_le_bytes(*root) + 4;
+    [&root.to_le_bytes()[..], &STATE_SNAPSHOT_IDENTIFIER, rest].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_fb::StateSnapshotRef;
+    use planus::ReadAsRoot;
+
+    // The client's dequantize
+    fn dequantize(value: u16, extent: f32) -> f32 {
+        f32::from(value) / f32::from(u16::MAX) * extent
+    }
+
+    #[test]
+    fn quantizes_across_the_extent() {
+        assert_eq!(quantize(0.0, 800.0), 0);
+        assert_eq!(quantize(800.0, 800.0), u16::MAX);
+        assert_eq!(quantize(400.0, 800.0), 32768);
+        // Clamped to the edges
+        assert_eq!(quantize(-5.0, 800.0), 0);
+        assert_eq!(quantize(900.0, 800.0), u16::MAX);
+        assert_eq!(quantize(10.0, 0.0), 0);
+    }
+
+    #[test]
+    fn quantized_positions_round_trip() {
+        let (width, height) = (2000.0, 600.0);
+        let players = [("a", 0.0, 0.0), ("b", 1234.567, 321.5), ("c", 1999.99, 599.99), ("d", width, height)];
+        let buffer = encode_state_snapshot(players.iter().map(|&(id, x, y)| (id, x, y, 0.0, 0.0)), Some((width, height)));
+        let snapshot = StateSnapshotRef::read_as_root(&buffer).unwrap();
+        assert!(snapshot.quantized().unwrap());
+        let states = snapshot.players().unwrap().unwrap();
+        assert_eq!(states.len(), players.len());
+        for (state, (id, x, y)) in states.iter().zip(players) {
+            let state = state.unwrap();
+            assert_eq!(state.id().unwrap(), id);
+            // Within half a step of the original
+            assert!((dequantize(state.qx().unwrap(), width) - x).abs() <= width / f32::from(u16::MAX) / 2.0);
+            assert!((dequantize(state.qy().unwrap(), height) - y).abs() <= height / f32::from(u16::MAX) / 2.0);
+            assert_eq!((state.x().unwrap(), state.y().unwrap()), (0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn float_positions_round_trip() {
+        let buffer = encode_state_snapshot([("a", 12.25, -3.5, 4.0, -8.0)], None);
+        let snapshot = StateSnapshotRef::read_as_root(&buffer).unwrap();
+        assert!(!snapshot.quantized().unwrap());
+        let state = snapshot.players().unwrap().unwrap().get(0).unwrap().unwrap();
+        assert_eq!((state.x().unwrap(), state.y().unwrap()), (12.25, -3.5));
+        assert_eq!((state.vx().unwrap(), state.vy().unwrap()), (4.0, -8.0));
+        assert_eq!((state.qx().unwrap(), state.qy().unwrap()), (0, 0));
+    }
+
+    #[test]
+    fn carries_the_file_identifier() {
+        for buffer in [encode_state_snapshot([], None), encode_state_snapshot([("a", 1.0, 2.0, 0.0, 0.0)], Some((10.0, 10.0)))] {
+            assert_eq!(&buffer[4..8], &STATE_SNAPSHOT_IDENTIFIER);
+            assert!(StateSnapshotRef::read_as_root(&buffer).is_ok());
+        }
+    }
+}
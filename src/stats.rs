@@ -0,0 +1,150 @@
+use crate::accounts::Account;
+use crate::achievements::{Achievement, ACHIEVEMENTS};
+use crate::PlayerId;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+// How often registered players' stats are added to their account
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayerStats {
+    // Pixels travelled
+    pub distance: f64,
+    pub messages_sent: u64,
+    // Time spent connected
+    pub session_secs: u64,
+    pub joins: u64,
+}
+
+impl PlayerStats {
+    pub fn add(&mut self, other: &PlayerStats) {
+        self.distance += other.distance;
+        self.messages_sent += other.messages_sent;
+        self.session_secs += other.session_secs;
+        self.joins += other.joins;
+    }
+}
+
+struct LiveStats {
+    account: Option<String>,
+    // Totals for the current connection
+    session: PlayerStats,
+    // What hasn't been added to the account yet
+    unflushed: PlayerStats,
+    // Session time is counted up to here
+    counted_until: Instant,
+    // Account's lifetime stats when the session started
+    baseline: Option<PlayerStats>,
+    unlocked: HashSet<&'static str>,
+}
+
+impl LiveStats {
+    // Moves whole seconds of elapsed session time into the totals
+    fn count_time(&mut self) {
+        let secs = self.counted_until.elapsed().as_secs();
+        self.session.session_secs += secs;
+        self.unflushed.session_secs += secs;
+        self.counted_until += Duration::from_secs(secs);
+    }
+
+    // What achievements are measured against
+    fn progress(&self) -> PlayerStats {
+        let mut progress = self.baseline.unwrap_or_default();
+        progress.add(&self.session);
+        progress
+    }
+}
+
+// Stats of everyone connected. Guests' stats only last for their session;
+// registered players' are flushed into their account periodically and on
+// disconnect.
+#[derive(Default)]
+pub struct StatsTracker {
+    live: DashMap<PlayerId, LiveStats>,
+}
+
+impl StatsTracker {
+    pub fn joined(&self, player_id: &PlayerId, account: Option<&Account>) {
+        let joined = PlayerStats { joins: 1, ..Default::default() };
+        let unlocked = account
+            .into_iter()
+            .flat_map(|a| &a.achievements)
+            .filter_map(|unlocked| ACHIEVEMENTS.iter().find(|a| a.id == unlocked.id))
+            .map(|a| a.id)
+            .collect();
+        self.live.insert(
+            player_id.clone(),
+            LiveStats {
+                account: account.map(|a| a.username.clone()),
+                session: joined,
+                unflushed: joined,
+                counted_until: Instant::now(),
+                baseline: account.map(|a| a.stats),
+                unlocked,
+            },
+        );
+    }
+
+    pub fn moved(&self, player_id: &str, distance: f64) {
+        if let Some(mut stats) = self.live.get_mut(player_id) {
+            stats.session.distance += distance;
+            stats.unflushed.distance += distance;
+        }
+    }
+
+    pub fn chatted(&self, player_id: &str) {
+        if let Some(mut stats) = self.live.get_mut(player_id) {
+            stats.session.messages_sent += 1;
+            stats.unflushed.messages_sent += 1;
+        }
+    }
+
+    // Stops tracking the player, returning the account and the stats still
+    // to be added to it
+    pub fn left(&self, player_id: &str) -> Option<(String, PlayerStats)> {
+        let (_, mut stats) = self.live.remove(player_id)?;
+        stats.count_time();
+        Some((stats.account?, stats.unflushed))
+    }
+
+    // Session totals, plus the account and its unflushed stats for
+    // registered players
+    pub fn current(&self, player_id: &str) -> Option<(PlayerStats, Option<(String, PlayerStats)>)> {
+        let mut stats = self.live.get_mut(player_id)?;
+        stats.count_time();
+        let pending = stats.account.clone().map(|account| (account, stats.unflushed));
+        Some((stats.session, pending))
+    }
+
+    // Achievements players reached since the last check, with the account to
+    // record them on for registered players
+    pub fn check_achievements(&self) -> Vec<(PlayerId, Option<String>, &'static Achievement)> {
+        let mut reached = Vec::new();
+        for mut stats in self.live.iter_mut() {
+            stats.count_time();
+            let progress = stats.progress();
+            for achievement in ACHIEVEMENTS {
+                if !stats.unlocked.contains(achievement.id) && achievement.rule.is_met(&progress) {
+                    stats.unlocked.insert(achievement.id);
+                    reached.push((stats.key().clone(), stats.account.clone(), achievement));
+                }
+            }
+        }
+        reached
+    }
+
+    // Takes everything not yet added to accounts
+    pub fn take_unflushed(&self) -> Vec<(String, PlayerStats)> {
+        let mut flushed = Vec::new();
+        for mut stats in self.live.iter_mut() {
+            stats.count_time();
+            if let Some(account) = stats.account.clone() {
+                flushed.push((account, std::mem::take(&mut stats.unflushed)));
+            }
+        }
+        flushed
+    }
+}
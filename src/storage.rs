@@ -0,0 +1,365 @@
+use crate::accounts::Account;
+use crate::audit::AuditEntry;
+use crate::bans::Ban;
+use crate::reports::Report;
+use crate::config::{Config, StorageBackend};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::{info, warn};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatRecord {
+    pub player_id: String,
+    pub nickname: String,
+    pub message: String,
+    pub sent_at: u64,
+    // Records from before rooms were kept were all sent in the lobby
+    #[serde(default = "default_chat_room")]
+    pub room: String,
+}
+
+fn default_chat_room() -> String {
+    crate::rooms::DEFAULT_ROOM.to_string()
+}
+
+// A stored chat message with its id, which orders messages and pages
+// through them
+#[derive(Serialize, Clone, Debug)]
+pub struct ArchivedChat {
+    pub id: u64,
+    #[serde(flatten)]
+    pub record: ChatRecord,
+}
+
+// Which stored messages to read: the newest `limit` with an id below
+// `before`, narrowed to a room and a player when given
+#[derive(Clone, Debug, Default)]
+pub struct ChatQuery {
+    pub room: Option<String>,
+    pub player_id: Option<String>,
+    pub before: Option<u64>,
+    pub limit: usize,
+}
+
+impl ChatQuery {
+    pub fn matches(&self, id: u64, chat: &ChatRecord) -> bool {
+        self.before.is_none_or(|before| id < before)
+            && self.room.as_ref().is_none_or(|room| *room == chat.room)
+            && self.player_id.as_ref().is_none_or(|player_id| *player_id == chat.player_id)
+    }
+}
+
+// A persistence backend. Accounts, bans and reports are read once at startup;
+// everything else is written through a StorageHandle so game code never
+// waits on storage.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn load_accounts(&self) -> Result<Vec<Account>>;
+    // Insert or update the account and its score
+    async fn save_account(&self, account: &Account) -> Result<()>;
+    async fn append_chat(&self, chat: &ChatRecord) -> Result<()>;
+    // Stored chat, newest first; None from backends that don't keep chat
+    async fn query_chat(&self, _query: &ChatQuery) -> Result<Option<Vec<ArchivedChat>>> {
+        Ok(None)
+    }
+    async fn load_bans(&self) -> Result<Vec<Ban>>;
+    async fn save_ban(&self, ban: &Ban) -> Result<()>;
+    async fn remove_ban(&self, id: u64) -> Result<()>;
+    async fn load_reports(&self) -> Result<Vec<Report>>;
+    async fn save_report(&self, report: &Report) -> Result<()>;
+    async fn load_audit(&self) -> Result<Vec<AuditEntry>>;
+    async fn save_audit(&self, entry: &AuditEntry) -> Result<()>;
+}
+
+// Opens the backend selected by STORAGE_BACKEND. Backends behind a feature
+// that wasn't built in fail at startup rather than silently using another.
+pub async fn open(config: &Config) -> Result<Arc<dyn Storage>> {
+    match config.storage_backend {
+        StorageBackend::Json => match &config.accounts_path {
+            Some(path) => {
+                info!("🗄️ Using JSON file storage at {}", path.display());
+                Ok(Arc::new(JsonFileStorage::open(path.clone())?))
+            }
+            None => Ok(Arc::new(MemoryStorage)),
+        },
+        StorageBackend::Postgres => {
+            let Some(url) = &config.database_url else {
+                bail!("STORAGE_BACKEND=postgres requires DATABASE_URL");
+            };
+            #[cfg(feature = "persistence")]
+            {
+                info!("🗄️ Using PostgreSQL storage");
+                Ok(Arc::new(crate::postgres::PostgresStorage::connect(url).await?))
+            }
+            #[cfg(not(feature = "persistence"))]
+            {
+                let _ = url;
+                bail!("PostgreSQL storage requires building with --features persistence")
+            }
+        }
+        StorageBackend::Redb => {
+            #[cfg(feature = "embedded")]
+            {
+                info!("🗄️ Using embedded storage at {}", config.storage_path.display());
+                Ok(Arc::new(crate::embedded::RedbStorage::open(&config.storage_path)?))
+            }
+            #[cfg(not(feature = "embedded"))]
+            bail!("embedded storage requires building with --features embedded")
+        }
+    }
+}
+
+enum StorageEvent {
+    SaveAccount(Account),
+    AppendChat(ChatRecord),
+    SaveBan(Ban),
+    RemoveBan(u64),
+    SaveReport(Report),
+    SaveAudit(AuditEntry),
+    // Answered once every write queued before it is done
+    Flush(oneshot::Sender<()>),
+}
+
+// Queues writes for a background task that applies them to the backend in
+// order. Failed writes are logged and dropped. Reads go to the backend
+// directly.
+#[derive(Clone)]
+pub struct StorageHandle {
+    tx: mpsc::UnboundedSender<StorageEvent>,
+    storage: Arc<dyn Storage>,
+}
+
+impl StorageHandle {
+    pub fn spawn(storage: Arc<dyn Storage>) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let reader = Arc::clone(&storage);
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let result = match event {
+                    StorageEvent::SaveAccount(account) => storage.save_account(&account).await,
+                    StorageEvent::AppendChat(chat) => storage.append_chat(&chat).await,
+                    StorageEvent::SaveBan(ban) => storage.save_ban(&ban).await,
+                    StorageEvent::RemoveBan(id) => storage.remove_ban(id).await,
+                    StorageEvent::SaveReport(report) => storage.save_report(&report).await,
+                    StorageEvent::SaveAudit(entry) => storage.save_audit(&entry).await,
+                    StorageEvent::Flush(done) => {
+                        let _ = done.send(());
+                        Ok(())
+                    }
+                };
+                if let Err(e) = result {
+                    warn!("Storage write failed: {:#}", e);
+                }
+            }
+        });
+        Self { tx, storage: reader }
+    }
+
+    // Misses messages still waiting in the write queue
+    pub async fn query_chat(&self, query: &ChatQuery) -> Result<Option<Vec<ArchivedChat>>> {
+        self.storage.query_chat(query).await
+    }
+
+    pub fn save_account(&self, account: Account) {
+        let _ = self.tx.send(StorageEvent::SaveAccount(account));
+    }
+
+    pub fn append_chat(&self, chat: ChatRecord) {
+        let _ = self.tx.send(StorageEvent::AppendChat(chat));
+    }
+
+    pub fn save_ban(&self, ban: Ban) {
+        let _ = self.tx.send(StorageEvent::SaveBan(ban));
+    }
+
+    pub fn remove_ban(&self, id: u64) {
+        let _ = self.tx.send(StorageEvent::RemoveBan(id));
+    }
+
+    pub fn save_report(&self, report: Report) {
+        let _ = self.tx.send(StorageEvent::SaveReport(report));
+    }
+
+    pub fn save_audit(&self, entry: AuditEntry) {
+        let _ = self.tx.send(StorageEvent::SaveAudit(entry));
+    }
+
+    // Waits for every write queued so far, e.g. before exiting
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.tx.send(StorageEvent::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+// Used when ACCOUNTS_PATH is empty: accounts live only as long as the process
+pub struct MemoryStorage;
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn load_accounts(&self) -> Result<Vec<Account>> {
+        Ok(Vec::new())
+    }
+
+    async fn save_account(&self, _account: &Account) -> Result<()> {
+        Ok(())
+    }
+
+    async fn append_chat(&self, _chat: &ChatRecord) -> Result<()> {
+        Ok(())
+    }
+
+    async fn load_bans(&self) -> Result<Vec<Ban>> {
+        Ok(Vec::new())
+    }
+
+    async fn save_ban(&self, _ban: &Ban) -> Result<()> {
+        Ok(())
+    }
+
+    async fn remove_ban(&self, _id: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn load_reports(&self) -> Result<Vec<Report>> {
+        Ok(Vec::new())
+    }
+
+    async fn save_report(&self, _report: &Report) -> Result<()> {
+        Ok(())
+    }
+
+    async fn load_audit(&self) -> Result<Vec<AuditEntry>> {
+        Ok(Vec::new())
+    }
+
+    async fn save_audit(&self, _entry: &AuditEntry) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Accounts in a single JSON file, rewritten on every change, with bans,
+// reports and the audit log in bans.json, reports.json and audit.json next to
+// it. Chat isn't stored; it already goes to chat.log when file logging is on.
+pub struct JsonFileStorage {
+    path: PathBuf,
+    accounts: Mutex<HashMap<String, Account>>,
+    bans_path: PathBuf,
+    bans: Mutex<HashMap<u64, Ban>>,
+    reports_path: PathBuf,
+    reports: Mutex<Vec<Report>>,
+    audit_path: PathBuf,
+    audit: Mutex<Vec<AuditEntry>>,
+}
+
+// The list stored at `path`, or nothing if the file doesn't exist yet
+fn read_list<T: DeserializeOwned>(path: &Path, what: &str) -> Result<Vec<T>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = std::fs::read_to_string(path).with_context(|| format!("failed to read {what} {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("failed to parse {what} {}", path.display()))
+}
+
+impl JsonFileStorage {
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let accounts = read_list::<Account>(&path, "accounts")?
+            .into_iter()
+            .map(|a| (a.username.clone(), a))
+            .collect();
+        let bans_path = path.with_file_name("bans.json");
+        let bans = read_list::<Ban>(&bans_path, "bans")?.into_iter().map(|b| (b.id, b)).collect();
+        let reports_path = path.with_file_name("reports.json");
+        let reports = read_list(&reports_path, "reports")?;
+        let audit_path = path.with_file_name("audit.json");
+        let audit = read_list(&audit_path, "audit log")?;
+        Ok(Self {
+            path,
+            accounts: Mutex::new(accounts),
+            bans_path,
+            bans: Mutex::new(bans),
+            reports_path,
+            reports: Mutex::new(reports),
+            audit_path,
+            audit: Mutex::new(audit),
+        })
+    }
+
+    async fn write_bans(&self, bans: &HashMap<u64, Ban>) -> Result<()> {
+        let mut stored: Vec<&Ban> = bans.values().collect();
+        stored.sort_by_key(|b| b.id);
+        write_atomic(&self.bans_path, serde_json::to_string_pretty(&stored)?).await
+    }
+}
+
+// Writes to a temporary file first so a crash never leaves a torn file
+async fn write_atomic(path: &Path, contents: String) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, contents).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for JsonFileStorage {
+    async fn load_accounts(&self) -> Result<Vec<Account>> {
+        Ok(self.accounts.lock().await.values().cloned().collect())
+    }
+
+    async fn save_account(&self, account: &Account) -> Result<()> {
+        // Held across the write so saves land in order
+        let mut accounts = self.accounts.lock().await;
+        accounts.insert(account.username.clone(), account.clone());
+        let mut stored: Vec<&Account> = accounts.values().collect();
+        stored.sort_by_key(|a| a.created_at);
+        write_atomic(&self.path, serde_json::to_string_pretty(&stored)?).await
+    }
+
+    async fn append_chat(&self, _chat: &ChatRecord) -> Result<()> {
+        Ok(())
+    }
+
+    async fn load_bans(&self) -> Result<Vec<Ban>> {
+        Ok(self.bans.lock().await.values().cloned().collect())
+    }
+
+    async fn save_ban(&self, ban: &Ban) -> Result<()> {
+        let mut bans = self.bans.lock().await;
+        bans.insert(ban.id, ban.clone());
+        self.write_bans(&bans).await
+    }
+
+    async fn remove_ban(&self, id: u64) -> Result<()> {
+        let mut bans = self.bans.lock().await;
+        bans.remove(&id);
+        self.write_bans(&bans).await
+    }
+
+    async fn load_reports(&self) -> Result<Vec<Report>> {
+        Ok(self.reports.lock().await.clone())
+    }
+
+    async fn save_report(&self, report: &Report) -> Result<()> {
+        // Reports are never edited, only added
+        let mut reports = self.reports.lock().await;
+        reports.push(report.clone());
+        write_atomic(&self.reports_path, serde_json::to_string_pretty(&*reports)?).await
+    }
+
+    async fn load_audit(&self) -> Result<Vec<AuditEntry>> {
+        Ok(self.audit.lock().await.clone())
+    }
+
+    async fn save_audit(&self, entry: &AuditEntry) -> Result<()> {
+        let mut audit = self.audit.lock().await;
+        audit.push(entry.clone());
+        write_atomic(&self.audit_path, serde_json::to_string_pretty(&*audit)?).await
+    }
+}
@@ -0,0 +1,48 @@
+use crate::GameServer;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+// How often the sweeper looks for stale players, at most
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+impl GameServer {
+    // Removes players nobody is connected as any more who haven't been seen
+    // for STALE_PLAYER_SECS, such as ones whose disconnect was missed. Their
+    // rooms get PlayerLeft as usual. Connected players and NPCs are never
+    // swept, however long they idle. Restored players who never came back are
+    // dropped too, silently since nobody saw them.
+    pub fn sweep_stale_players(&self) {
+        let threshold = self.config().stale_player_secs;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let stale: Vec<_> = self
+            .players
+            .iter()
+            .filter(|player| now.saturating_sub(player.last_seen) > threshold)
+            .filter(|player| !self.connections.contains_key(&player.id) && !self.npcs.contains(&player.id))
+            .map(|player| player.id.clone())
+            .collect();
+        for player_id in stale {
+            match self.remove_player(&player_id) {
+                Ok(_) => info!(%player_id, "Swept stale player"),
+                Err(e) => error!(%player_id, "Failed to sweep stale player: {}", e),
+            }
+        }
+        let restored = self.restored.len();
+        self.restored.retain(|_, player| now.saturating_sub(player.last_seen) <= threshold);
+        if self.restored.len() < restored {
+            info!(players = restored - self.restored.len(), "Swept restored players who didn't come back");
+        }
+    }
+}
+
+// Runs sweep_stale_players every SWEEP_INTERVAL, or more often for a short
+// STALE_PLAYER_SECS, and clears out old join attempts
+pub async fn run(server: GameServer) {
+    let period = Duration::from_secs(server.config().stale_player_secs).clamp(Duration::from_secs(1), SWEEP_INTERVAL);
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        server.sweep_stale_players();
+        server.join_limits.prune();
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+
+// First file descriptor passed by systemd, see sd_listen_fds(3)
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+// The listening sockets systemd passed in, when started by a socket unit
+// (one per ListenStream=). systemd keeps them open across restarts, so
+// connections made while the server restarts wait in their backlog instead of
+// being refused.
+#[cfg(unix)]
+pub fn activated_listeners() -> Result<Vec<std::net::TcpListener>> {
+    use std::os::fd::FromRawFd;
+
+    // LISTEN_PID guards against the variables leaking into another process
+    let for_us = std::env::var("LISTEN_PID").is_ok_and(|pid| pid == std::process::id().to_string());
+    let fds = std::env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<i32>().ok()).unwrap_or(0);
+    if !for_us {
+        return Ok(Vec::new());
+    }
+    (LISTEN_FDS_START..LISTEN_FDS_START + fds)
+        .map(|fd| {
+            // SAFETY: systemd hands over LISTEN_FDS descriptors starting at 3,
+            // and nothing else in the process owns them
+            let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            if listener.local_addr().is_err() {
+                anyhow::bail!("socket {fd} passed by systemd isn't a TCP/IP socket");
+            }
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn activated_listeners() -> Result<Vec<std::net::TcpListener>> {
+    Ok(Vec::new())
+}
@@ -0,0 +1,224 @@
+// The systems run on every animation frame, in order: interpolation moves
+// entities along their Motion, extrapolating past the newest sample,
+// animation picks sprite frames, effects expire chat bubbles, the camera follows the local player, and rendering
+// redraws the game area and player list when something changed plus the
+// minimap.
+use crate::{announce, minimap};
+use crate::world::{Position, Sample, World};
+
+// Defaults for Smoothing. Remote players are drawn this far in the past, so
+// there is usually a sample on either side to interpolate between.
+pub const INTERPOLATION_DELAY_MS: f64 = 100.0;
+// Past the newest sample a player keeps going at its velocity for at most
+// this long, covering a late snapshot without running off
+pub const MAX_EXTRAPOLATION_MS: f64 = 250.0;
+// Corrections farther than this are teleports and snap instead of blending
+pub const SNAP_THRESHOLD: f32 = 100.0;
+// How long a correction takes to blend out when a sample changes the path
+pub const CORRECTION_BLEND_MS: f64 = 150.0;
+
+// How remote players are smoothed, tunable from the page: a longer delay or
+// extrapolation hides more jitter at the cost of showing players later or
+// further off
+#[derive(Clone, Copy)]
+pub struct Smoothing {
+    pub interpolation_delay_ms: f64,
+    pub max_extrapolation_ms: f64,
+    pub snap_threshold: f32,
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Self {
+            interpolation_delay_ms: INTERPOLATION_DELAY_MS,
+            max_extrapolation_ms: MAX_EXTRAPOLATION_MS,
+            snap_threshold: SNAP_THRESHOLD,
+        }
+    }
+}
+
+// The circle avatar, border included
+const AVATAR_SIZE: f32 = 24.0;
+
+pub fn run(world: &mut World, now: f64) {
+    interpolate(world, now);
+    animate(world, now);
+    expire_bubbles(world, now);
+    follow_camera(world);
+    render(world);
+}
+
+fn interpolate(world: &mut World, now: f64) {
+    let World { motions, positions, dirty, smoothing, .. } = world;
+    let render_at = now - smoothing.interpolation_delay_ms;
+    for (entity, motion) in motions.iter_mut() {
+        let samples = &mut motion.samples;
+        // Keep one sample at or before render_at to interpolate from
+        while samples.len() > 1 && samples[1].at <= render_at {
+            samples.pop_front();
+        }
+        let target = match (samples.front().copied(), samples.get(1)) {
+            (Some(from), Some(to)) if from.at < render_at => {
+                let t = ((render_at - from.at) / (to.at - from.at)).clamp(0.0, 1.0) as f32;
+                Position {
+                    x: from.position.x + (to.position.x - from.position.x) * t,
+                    y: from.position.y + (to.position.y - from.position.y) * t,
+                }
+            }
+            (Some(from), None) if from.is_moving() && render_at - from.at <= smoothing.max_extrapolation_ms => {
+                from.extrapolate(render_at)
+            }
+            // A player standing still, or not heard from for too long, leaves
+            // an old sample behind; start the next move from here and now
+            (Some(from), None) => {
+                let stop = from.extrapolate(render_at.min(from.at + smoothing.max_extrapolation_ms));
+                samples[0] = Sample { at: from.at.max(render_at), position: stop, velocity: (0.0, 0.0) };
+                stop
+            }
+            (Some(from), _) => from.position,
+            (None, _) => continue,
+        };
+
+        let Some(position) = positions.get_mut(entity) else {
+            continue;
+        };
+        // Start fading out whatever gap a new sample opened up
+        if motion.fresh {
+            motion.fresh = false;
+            let gap = (position.x - target.x, position.y - target.y);
+            motion.correction = if gap.0.hypot(gap.1) < smoothing.snap_threshold { gap } else { (0.0, 0.0) };
+            motion.correction_at = now;
+        }
+        let fade = (1.0 - (now - motion.correction_at) / CORRECTION_BLEND_MS).max(0.0) as f32;
+        let drawn = Position {
+            x: target.x + motion.correction.0 * fade,
+            y: target.y + motion.correction.1 * fade,
+        };
+        if *position != drawn {
+            *position = drawn;
+            *dirty = true;
+        }
+    }
+}
+
+// Walking players cycle through their walk frames; frames only matter while a
+// sprite sheet is loaded
+fn animate(world: &mut World, now: f64) {
+    let World { animations, positions, sprite_sheet, dirty, .. } = world;
+    let walk_frames = sprite_sheet.as_ref().map_or(0, |sheet| sheet.walk_frames);
+    for (entity, animation) in animations.iter_mut() {
+        if let Some(position) = positions.get(entity) {
+            if animation.update(*position, walk_frames, now) {
+                *dirty = true;
+            }
+        }
+    }
+}
+
+fn expire_bubbles(world: &mut World, now: f64) {
+    let before = world.bubbles.len();
+    world.bubbles.retain(|_, bubble| bubble.expires_at > now);
+    if world.bubbles.len() != before {
+        world.dirty = true;
+    }
+}
+
+fn follow_camera(world: &mut World) {
+    // Centre of the avatar, roughly; sprites may be a different size
+    let target = world.me.and_then(|me| world.positions.get(&me)).map(|position| Position {
+        x: position.x + 12.0,
+        y: position.y + 12.0,
+    });
+    world.camera.follow(target);
+    world.camera.apply();
+}
+
+fn render(world: &mut World) {
+    minimap::render(world);
+    if world.dirty {
+        render_players(world);
+        announce::render_player_list(world);
+        world.dirty = false;
+    }
+}
+
+fn avatar_size(world: &World) -> (f32, f32) {
+    world
+        .sprite_sheet
+        .as_ref()
+        .map_or((AVATAR_SIZE, AVATAR_SIZE), |sheet| (sheet.frame_width as f32, sheet.frame_height as f32))
+}
+
+// Nicknames and chat are typed by players, so they're escaped before going
+// into the markup
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Obstacles go in first so avatars are drawn over them. Each avatar gets its
+// name tag below it and any chat bubble above.
+fn render_players(world: &World) {
+    let mut html = String::new();
+    for obstacle in world.obstacles() {
+        html.push_str(&format!(
+            r#"<div class="obstacle" style="left: {}px; top: {}px; width: {}px; height: {}px;"></div>"#,
+            obstacle.x, obstacle.y, obstacle.width, obstacle.height
+        ));
+    }
+    let (width, height) = avatar_size(world);
+    for (entity, profile) in &world.profiles {
+        let Some(position) = world.positions.get(entity) else {
+            continue;
+        };
+        let nickname = escape_html(&profile.nickname);
+        let (center_x, center_y) = (position.x + width / 2.0, position.y + height / 2.0);
+        let class = if profile.idle { "player idle" } else { "player" };
+        let look = match &world.sprite_sheet {
+            Some(sheet) => {
+                let frame = world.animations.get(entity).map_or(0, |animation| animation.frame);
+                escape_html(&sheet.style(profile.sprite, frame))
+            }
+            None => format!(
+                "width: 20px; height: 20px; background: {}; border-radius: 50%; \
+                 border: 2px solid #fff; box-shadow: 0 2px 4px rgba(0,0,0,0.3);",
+                profile.color
+            ),
+        };
+        html.push_str(&format!(
+            r#"<div class="{}" style="position: absolute; left: {}px; top: {}px; {}" title="{}"></div>"#,
+            class, position.x, position.y, look, nickname
+        ));
+        html.push_str(&format!(
+            r#"<div class="name-tag" style="left: {}px; top: {}px;">{}</div>"#,
+            center_x,
+            center_y + height / 2.0 + 2.0,
+            nickname
+        ));
+        if let Some(bubble) = world.bubbles.get(entity) {
+            html.push_str(&format!(
+                r#"<div class="chat-bubble" style="left: {}px; top: {}px;">{}</div>"#,
+                center_x,
+                center_y - height / 2.0 - 4.0,
+                escape_html(&bubble.message)
+            ));
+        }
+    }
+
+    if let Some(container) = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id("players-container"))
+    {
+        container.set_inner_html(&html);
+    }
+}
@@ -0,0 +1,78 @@
+// Keeps one tab per browser playing. The tab that joins holds a lock in
+// localStorage, "<tab id> <timestamp>", refreshed every HEARTBEAT_MS while it's
+// connected; one older than STALE_MS was left by a tab that closed or crashed.
+// Other tabs stay out or watch as spectators, see ConnectOptions::other_tab.
+use crate::prefs::{self, Pref};
+use std::cell::{Cell, RefCell};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+
+const HEARTBEAT_MS: i32 = 2_000;
+const STALE_MS: f64 = 5_000.0;
+
+// A setInterval handle and its callback
+type Interval = (i32, Closure<dyn FnMut()>);
+
+thread_local! {
+    static TAB_ID: String = uuid::Uuid::new_v4().to_string();
+    // Refreshes the lock while this tab plays
+    static HEARTBEAT: RefCell<Option<Interval>> = const { RefCell::new(None) };
+    static UNLOAD_HOOKED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn holder() -> Option<(String, f64)> {
+    let lock = prefs::load(Pref::Tab)?;
+    let (id, stamp) = lock.split_once(' ')?;
+    Some((id.to_string(), stamp.parse().ok()?))
+}
+
+fn refresh() {
+    let lock = TAB_ID.with(|id| format!("{} {}", id, js_sys::Date::now()));
+    prefs::save(Pref::Tab, &lock);
+}
+
+// Whether another tab is playing right now
+pub fn playing_elsewhere() -> bool {
+    let Some((id, stamp)) = holder() else {
+        return false;
+    };
+    TAB_ID.with(|own| id != *own) && js_sys::Date::now() - stamp < STALE_MS
+}
+
+// Takes the lock for this tab and keeps it until `release`, or until the page
+// goes away
+pub fn claim() {
+    refresh();
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    HEARTBEAT.with(|heartbeat| {
+        let mut heartbeat = heartbeat.borrow_mut();
+        if heartbeat.is_some() {
+            return;
+        }
+        let tick = Closure::<dyn FnMut()>::new(refresh);
+        if let Ok(handle) =
+            window.set_interval_with_callback_and_timeout_and_arguments_0(tick.as_ref().unchecked_ref(), HEARTBEAT_MS)
+        {
+            *heartbeat = Some((handle, tick));
+        }
+    });
+    if !UNLOAD_HOOKED.replace(true) {
+        let on_hide = Closure::<dyn FnMut()>::new(release);
+        let _ = window.add_event_listener_with_callback("pagehide", on_hide.as_ref().unchecked_ref());
+        on_hide.forget();
+    }
+}
+
+// Lets another tab play; a lock some other tab took since is left alone
+pub fn release() {
+    if let Some((handle, _)) = HEARTBEAT.with(|heartbeat| heartbeat.borrow_mut().take()) {
+        if let Some(window) = web_sys::window() {
+            window.clear_interval_with_handle(handle);
+        }
+    }
+    if holder().is_some_and(|(id, _)| TAB_ID.with(|own| id == *own)) {
+        prefs::clear(Pref::Tab);
+    }
+}
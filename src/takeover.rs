@@ -0,0 +1,50 @@
+use crate::config::DuplicateSessions;
+use crate::frame::OutboundFrame;
+use crate::{GameServer, PlayerId, ServerMessage};
+use tracing::info;
+
+// WebSocket close code sent to a connection whose account joined again
+// elsewhere under DUPLICATE_SESSIONS=takeover
+pub const TAKEN_OVER_CLOSE_CODE: u16 = 4004;
+
+// What to do when an account that's already playing joins again, say from a
+// second tab
+pub enum Duplicate {
+    // Go ahead; there's no other session, or the policy allows two
+    Join,
+    // Turn the new connection away
+    Reject,
+}
+
+impl GameServer {
+    // Applies DUPLICATE_SESSIONS to a join as `username`. Taking over closes
+    // the earlier connection, which leaves the game as usual.
+    pub fn check_duplicate(&self, username: &str) -> Duplicate {
+        let policy = self.config().duplicate_sessions;
+        if policy == DuplicateSessions::Allow {
+            return Duplicate::Join;
+        }
+        let existing: Vec<PlayerId> = self
+            .connections
+            .iter()
+            .filter(|c| c.account.as_deref().is_some_and(|account| account.eq_ignore_ascii_case(username)))
+            .map(|c| c.key().clone())
+            .collect();
+        if existing.is_empty() {
+            return Duplicate::Join;
+        }
+        if policy == DuplicateSessions::Reject {
+            info!(account = %username, "Rejected a second session");
+            return Duplicate::Reject;
+        }
+        let frame = OutboundFrame::new(ServerMessage::SessionTakenOver);
+        for player_id in existing {
+            if let Some(connection) = self.connections.get(&player_id) {
+                let _ = connection.queue.push(frame.clone());
+                connection.queue.close(TAKEN_OVER_CLOSE_CODE, "Session taken over");
+                info!(%player_id, account = %username, "Session taken over");
+            }
+        }
+        Duplicate::Join
+    }
+}
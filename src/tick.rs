@@ -0,0 +1,135 @@
+use crate::frame::OutboundFrame;
+use crate::overload::SNAPSHOT_DIVISOR;
+use crate::plugins::PluginEvent;
+use crate::rooms::RoomId;
+use crate::{GameServer, PlayerId, PlayerPosition, ServerMessage};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info};
+
+// Achievements don't need checking at the full tick rate
+const ACHIEVEMENT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// A player's position in the last snapshot and the velocity sent with it
+pub struct Track {
+    x: f32,
+    y: f32,
+    at: Instant,
+    vx: f32,
+    vy: f32,
+}
+
+impl GameServer {
+    // Broadcasts one consolidated Snapshot per room with every player that
+    // moved there since the previous tick, instead of a message per Move.
+    // Each position carries a velocity for clients to extrapolate with.
+    pub fn tick(&self) -> anyhow::Result<()> {
+        let moved = std::mem::take(&mut *self.moved.lock().unwrap());
+        let now = Instant::now();
+        let mut interval = (1.0 / self.config().snapshot_rate_hz) as f32;
+        if self.is_overloaded() {
+            interval *= SNAPSHOT_DIVISOR as f32;
+        }
+
+        let mut by_room: HashMap<RoomId, Vec<PlayerPosition>> = HashMap::new();
+        {
+            let mut tracks = self.tracks.lock().unwrap();
+            // Players that stopped get one more entry with zero velocity, so
+            // clients stop extrapolating them
+            let stopped: Vec<PlayerId> = tracks
+                .iter()
+                .filter(|(id, track)| (track.vx != 0.0 || track.vy != 0.0) && !moved.contains(*id))
+                .map(|(id, _)| id.clone())
+                .collect();
+            if moved.is_empty() && stopped.is_empty() {
+                return Ok(());
+            }
+
+            for id in moved.into_iter().chain(stopped) {
+                let Some(player) = self.players.get(&id) else {
+                    tracks.remove(&id);
+                    continue;
+                };
+                let (vx, vy) = match tracks.get(&id) {
+                    // A player that was standing still set off some time
+                    // during the last interval, not when they last moved
+                    Some(track) if player.x != track.x || player.y != track.y => {
+                        let elapsed = now.duration_since(track.at).as_secs_f32().min(interval);
+                        if elapsed > 0.0 {
+                            ((player.x - track.x) / elapsed, (player.y - track.y) / elapsed)
+                        } else {
+                            (0.0, 0.0)
+                        }
+                    }
+                    _ => (0.0, 0.0),
+                };
+                tracks.insert(id.clone(), Track { x: player.x, y: player.y, at: now, vx, vy });
+                let position = PlayerPosition { player_id: id.clone(), x: player.x, y: player.y, vx, vy };
+                by_room.entry(player.room.clone()).or_default().push(position);
+            }
+        }
+        for (room, players) in by_room {
+            if let Some(room) = self.rooms.get(&room) {
+                room.broadcast(OutboundFrame::world_snapshot(players, &room.world()));
+            }
+        }
+        Ok(())
+    }
+
+    // Records and announces achievements players have reached
+    pub fn check_achievements(&self) -> anyhow::Result<()> {
+        for (player_id, account, achievement) in self.stats.check_achievements() {
+            if let Some(username) = account {
+                self.accounts.unlock_achievement(&username, achievement.id);
+            }
+            let Some((nickname, room)) = self.players.get(&player_id).map(|p| (p.nickname.clone(), p.room.clone())) else {
+                continue;
+            };
+            info!(%player_id, achievement = achievement.id, "Achievement unlocked");
+            self.broadcast_to_room(&room, ServerMessage::AchievementUnlocked {
+                player_id,
+                nickname,
+                achievement: achievement.id.to_string(),
+                name: achievement.name.to_string(),
+                description: achievement.description.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn run_tick_loop(server: GameServer) {
+    let rate = server.config().snapshot_rate_hz;
+    info!("⏱️ Tick loop running at {} Hz", rate);
+
+    let period = Duration::from_secs_f64(1.0 / rate);
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut ticks: u32 = 0;
+    let mut achievements = tokio::time::interval(ACHIEVEMENT_CHECK_INTERVAL);
+    achievements.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let started = Instant::now();
+                server.move_npcs((1.0 / rate) as f32);
+                server.on_tick((1.0 / rate) as f32);
+                server.notify_plugins(PluginEvent::Tick { seconds: (1.0 / rate) as f32 });
+                // Overloaded, only some ticks send a snapshot
+                ticks = ticks.wrapping_add(1);
+                if !server.is_overloaded() || ticks.is_multiple_of(SNAPSHOT_DIVISOR) {
+                    if let Err(e) = server.tick() {
+                        error!("Tick failed: {}", e);
+                    }
+                }
+                server.check_overload(started.elapsed(), period);
+            }
+            _ = achievements.tick() => {
+                if let Err(e) = server.check_achievements() {
+                    error!("Achievement check failed: {}", e);
+                }
+            }
+        }
+    }
+}
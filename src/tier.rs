@@ -0,0 +1,129 @@
+use crate::accounts::Account;
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// What kind of player a connection is, decided at join: anonymous joins are
+// guests, a valid session token makes a registered player, and accounts with
+// the moderator or admin role are moderators.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tier {
+    #[default]
+    Guest,
+    Registered,
+    Moderator,
+}
+
+// What a tier is allowed to do, enforced server-side and sent in Welcome so
+// the client can hide what isn't available
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub change_nickname: bool,
+    pub choose_color: bool,
+    // None means unlimited
+    pub chat_per_minute: Option<u32>,
+}
+
+impl Tier {
+    // Chat limits come from the config, so they follow reloads
+    pub fn capabilities(self, config: &Config) -> Capabilities {
+        match self {
+            Tier::Guest => Capabilities {
+                change_nickname: false,
+                choose_color: false,
+                chat_per_minute: Some(config.guest_chat_per_minute),
+            },
+            Tier::Registered => Capabilities {
+                change_nickname: true,
+                choose_color: true,
+                chat_per_minute: Some(config.registered_chat_per_minute),
+            },
+            Tier::Moderator => Capabilities {
+                change_nickname: true,
+                choose_color: true,
+                chat_per_minute: None,
+            },
+        }
+    }
+}
+
+// Staff role of an account, which decides the chat commands and admin
+// endpoints it may use. Guests are always players.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Player,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "player" => Some(Role::Player),
+            "moderator" => Some(Role::Moderator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Player => "player",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    // The higher of the role stored on the account and the one given by
+    // ADMINS or MODERATORS
+    pub fn of(config: &Config, account: &Account) -> Self {
+        let configured = if config.admins.contains(&account.username) {
+            Role::Admin
+        } else if config.moderators.contains(&account.username) {
+            Role::Moderator
+        } else {
+            Role::Player
+        };
+        configured.max(account.role)
+    }
+
+    pub fn tier(self) -> Tier {
+        match self {
+            Role::Player => Tier::Registered,
+            Role::Moderator | Role::Admin => Tier::Moderator,
+        }
+    }
+}
+
+// Sliding one-minute window of a connection's chat messages
+#[derive(Default)]
+pub struct ChatLimiter {
+    sent: VecDeque<Instant>,
+}
+
+impl ChatLimiter {
+    // Records a message if the limit allows it
+    pub fn try_send(&mut self, per_minute: Option<u32>) -> bool {
+        let Some(limit) = per_minute else {
+            return true;
+        };
+        let now = Instant::now();
+        while self.sent.front().is_some_and(|t| now.duration_since(*t) >= Duration::from_secs(60)) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= limit as usize {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+// Colors are sent to every client and used directly as CSS, so only accept
+// plain `#RRGGBB`
+pub fn is_valid_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
@@ -0,0 +1,99 @@
+// TLS termination, for serving without a proxy in front. With TLS_CERT and
+// TLS_KEY set every connection is TLS, and ALPN offers h2 ahead of http/1.1
+// so browsers load the static files over HTTP/2. Browsers open WebSockets on
+// a separate HTTP/1.1 connection, which is where the upgrade happens.
+//
+// ADMIN_PORT and ADMIN_CLIENT_CA add a second listener for the admin API and
+// admin WebSocket that only completes a handshake with a client certificate
+// issued by that CA. Its holder is an admin, named by the certificate's
+// common name, without any token.
+use crate::config::Config;
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+// Who presented a client certificate on the admin listener; requests
+// carry it as an extension
+#[derive(Clone, Debug)]
+pub struct ClientCertificate {
+    pub name: String,
+}
+
+// None when TLS isn't configured
+pub fn acceptor(config: &Config) -> Result<Option<TlsAcceptor>> {
+    let Some((cert_path, key_path)) = cert_paths(config)? else {
+        return Ok(None);
+    };
+    let mut tls = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(read_certs(cert_path)?, read_key(key_path)?)
+        .context("invalid TLS certificate or key")?;
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Some(TlsAcceptor::from(Arc::new(tls))))
+}
+
+// For the admin listener; None when it isn't configured
+pub fn admin_acceptor(config: &Config) -> Result<Option<TlsAcceptor>> {
+    let (port, ca_path) = match (config.admin_port, &config.admin_client_ca) {
+        (Some(port), Some(ca)) => (port, ca),
+        (None, None) => return Ok(None),
+        _ => bail!("ADMIN_PORT and ADMIN_CLIENT_CA must be set together"),
+    };
+    let Some((cert_path, key_path)) = cert_paths(config)? else {
+        bail!("the admin listener on port {port} needs TLS_CERT and TLS_KEY");
+    };
+    let mut roots = RootCertStore::empty();
+    for ca in read_certs(ca_path)? {
+        roots.add(ca).with_context(|| format!("invalid CA certificate in {}", ca_path.display()))?;
+    }
+    let provider = Arc::new(ring::default_provider());
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .context("invalid ADMIN_CLIENT_CA")?;
+    let mut tls = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(read_certs(cert_path)?, read_key(key_path)?)
+        .context("invalid TLS certificate or key")?;
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Some(TlsAcceptor::from(Arc::new(tls))))
+}
+
+// The verified client certificate's holder, by its subject's common name, or
+// the whole subject if it has none
+pub fn client_certificate(certs: Option<&[CertificateDer<'_>]>) -> Option<ClientCertificate> {
+    let (_, cert) = x509_parser::parse_x509_certificate(certs?.first()?).ok()?;
+    let subject = cert.subject();
+    let name = subject
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| subject.to_string());
+    Some(ClientCertificate { name })
+}
+
+fn cert_paths(config: &Config) -> Result<Option<(&Path, &Path)>> {
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Ok(Some((cert, key))),
+        (None, None) => Ok(None),
+        _ => bail!("TLS_CERT and TLS_KEY must be set together"),
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to read certificates from {}", path.display()))
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).with_context(|| format!("failed to read a private key from {}", path.display()))
+}
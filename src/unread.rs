@@ -0,0 +1,101 @@
+// Chat messages that arrived while nobody could see them: the chat panel was
+// collapsed or the tab was hidden. The count is shown in front of the page
+// title, e.g. "(3) Game", and cleared once the panel gets focus or comes
+// back into view.
+use crate::visibility;
+use std::cell::RefCell;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+
+thread_local! {
+    static UNREAD: RefCell<Unread> = RefCell::new(Unread::default());
+}
+
+#[derive(Default)]
+struct Unread {
+    count: u32,
+    collapsed: bool,
+    // The title without the badge, taken the first time the badge is shown
+    title: Option<String>,
+    on_change: Option<js_sys::Function>,
+}
+
+// Called for every chat message from another player
+pub fn message_received() {
+    update(|unread| {
+        if unread.collapsed || visibility::hidden() {
+            unread.count += 1;
+            return true;
+        }
+        false
+    });
+}
+
+pub fn set_collapsed(collapsed: bool) {
+    UNREAD.with(|unread| unread.borrow_mut().collapsed = collapsed);
+    if !collapsed && !visibility::hidden() {
+        clear();
+    }
+}
+
+pub fn set_on_change(callback: Option<js_sys::Function>) {
+    UNREAD.with(|unread| unread.borrow_mut().on_change = callback);
+}
+
+pub fn count() -> u32 {
+    UNREAD.with(|unread| unread.borrow().count)
+}
+
+fn clear() {
+    update(|unread| std::mem::take(&mut unread.count) > 0);
+}
+
+// Applies `change`, and when it returns true refreshes the title and calls the
+// callback. The callback runs after the borrow ends, as it may call back in.
+fn update(change: impl FnOnce(&mut Unread) -> bool) {
+    let notify = UNREAD.with(|unread| {
+        let mut unread = unread.borrow_mut();
+        if !change(&mut unread) {
+            return None;
+        }
+        unread.show_badge();
+        Some((unread.on_change.clone()?, unread.count))
+    });
+    if let Some((callback, count)) = notify {
+        let _ = callback.call1(&JsValue::NULL, &JsValue::from(count));
+    }
+}
+
+// Clears the count when the chat panel is focused, or when the tab becomes
+// visible again with the panel open. Called once when the module loads.
+pub fn watch() {
+    visibility::on_change(|hidden| {
+        let collapsed = UNREAD.with(|unread| unread.borrow().collapsed);
+        if !collapsed && !hidden {
+            clear();
+        }
+    });
+
+    let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+        return;
+    };
+    if let Some(panel) = document.get_element_by_id("chat-panel") {
+        let on_focus = Closure::<dyn FnMut()>::new(clear);
+        let _ = panel.add_event_listener_with_callback("focusin", on_focus.as_ref().unchecked_ref());
+        on_focus.forget();
+    }
+}
+
+impl Unread {
+    fn show_badge(&mut self) {
+        let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+            return;
+        };
+        let title = self.title.get_or_insert_with(|| document.title());
+        if self.count == 0 {
+            document.set_title(title);
+        } else {
+            document.set_title(&format!("({}) {}", self.count, title));
+        }
+    }
+}
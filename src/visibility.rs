@@ -0,0 +1,21 @@
+// The Page Visibility API: whether the tab is in the background, and a hook
+// for when that changes
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+
+pub fn hidden() -> bool {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .is_some_and(|document| document.hidden())
+}
+
+// Calls `callback` with the new `hidden()` on every visibilitychange. The
+// listener lives as long as the page.
+pub fn on_change(mut callback: impl FnMut(bool) + 'static) {
+    let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+        return;
+    };
+    let listener = Closure::<dyn FnMut()>::new(move || callback(hidden()));
+    let _ = document.add_event_listener_with_callback("visibilitychange", listener.as_ref().unchecked_ref());
+    listener.forget();
+}
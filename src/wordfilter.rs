@@ -0,0 +1,31 @@
+use std::collections::HashSet;
+
+// Masks every word of `message` that's in `words` (lowercase) with asterisks,
+// ignoring case. Words are runs of letters and digits, so "darn!" and "DARN"
+// are caught but "darnation" isn't.
+pub fn censor(message: &str, words: &HashSet<String>) -> String {
+    if words.is_empty() {
+        return message.to_string();
+    }
+    let mut censored = String::with_capacity(message.len());
+    let mut word = String::new();
+    for c in message.chars() {
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else {
+            push_word(&mut censored, &mut word, words);
+            censored.push(c);
+        }
+    }
+    push_word(&mut censored, &mut word, words);
+    censored
+}
+
+fn push_word(censored: &mut String, word: &mut String, words: &HashSet<String>) {
+    if words.contains(&word.to_lowercase()) {
+        censored.extend(std::iter::repeat_n('*', word.chars().count()));
+    } else {
+        censored.push_str(word);
+    }
+    word.clear();
+}
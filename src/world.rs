@@ -0,0 +1,292 @@
+// Client game state as entities with components, kept in one map per
+// component type. Systems (see systems.rs) work over whichever components
+// they need, so new kinds of things are new components rather than more
+// fields on a player.
+use crate::bubbles::{self, Bubble};
+use crate::camera::Camera;
+use crate::sprites::{Animation, SpriteSheet};
+use crate::systems::Smoothing;
+use crate::Player;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+pub type Entity = u32;
+
+// The area the server clamps positions to, as its Welcome says
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub width: f32,
+    pub height: f32,
+}
+
+// Until the first Welcome
+impl Default for Bounds {
+    fn default() -> Self {
+        Self { width: 800.0, height: 400.0 }
+    }
+}
+
+// A rectangle players can't stand in
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Obstacle {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Obstacle {
+    fn contains(&self, position: Position) -> bool {
+        (self.x..self.x + self.width).contains(&position.x) && (self.y..self.y + self.height).contains(&position.y)
+    }
+}
+
+// The world the room is played on, from Welcome or WorldChanged. A chunked
+// world comes without its obstacles; they arrive chunk by chunk as the player
+// moves.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Map {
+    #[serde(default)]
+    pub name: String,
+    #[serde(flatten)]
+    pub bounds: Bounds,
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+    #[serde(default)]
+    pub chunk_size: Option<f32>,
+}
+
+// Who a player is, as the server describes them
+pub struct Profile {
+    pub id: String,
+    pub nickname: String,
+    pub color: String,
+    pub idle: bool,
+    pub sprite: u32,
+}
+
+// Where an entity is drawn
+#[derive(Clone, Copy, PartialEq)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Position {
+    // Where the server will put a move to this position
+    pub fn clamped(self, bounds: Bounds) -> Self {
+        Self {
+            x: self.x.clamp(0.0, bounds.width),
+            y: self.y.clamp(0.0, bounds.height),
+        }
+    }
+}
+
+// A position from the server with the velocity it came with, in world units
+// per second, stamped with the local time it arrived
+#[derive(Clone, Copy)]
+pub struct Sample {
+    pub at: f64,
+    pub position: Position,
+    pub velocity: (f32, f32),
+}
+
+impl Sample {
+    // Where the entity would be at `at` if it kept going at this velocity
+    pub fn extrapolate(&self, at: f64) -> Position {
+        let seconds = ((at - self.at).max(0.0) / 1000.0) as f32;
+        Position {
+            x: self.position.x + self.velocity.0 * seconds,
+            y: self.position.y + self.velocity.1 * seconds,
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.velocity != (0.0, 0.0)
+    }
+}
+
+// Samples received from the server, oldest first; the interpolation system
+// moves Position along them
+pub struct Motion {
+    pub samples: VecDeque<Sample>,
+    // How far off the drawn position was when a sample changed the path,
+    // faded out from `correction_at` instead of jumping
+    pub correction: (f32, f32),
+    pub correction_at: f64,
+    // A sample arrived since the last frame
+    pub fresh: bool,
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            correction: (0.0, 0.0),
+            correction_at: f64::NEG_INFINITY,
+            fresh: false,
+        }
+    }
+}
+
+pub struct World {
+    next_entity: Entity,
+    // Server player id to entity
+    players: HashMap<String, Entity>,
+    // The local player, set by Welcome
+    pub me: Option<Entity>,
+    pub profiles: HashMap<Entity, Profile>,
+    pub positions: HashMap<Entity, Position>,
+    pub motions: HashMap<Entity, Motion>,
+    pub bubbles: HashMap<Entity, Bubble>,
+    pub animations: HashMap<Entity, Animation>,
+    pub bubble_duration_ms: f64,
+    pub smoothing: Smoothing,
+    pub camera: Camera,
+    pub map: Map,
+    // Obstacles of the chunks of a chunked world in range, by column and row
+    pub chunks: HashMap<(i32, i32), Vec<Obstacle>>,
+    // Avatars are colored circles until a sheet is loaded
+    pub sprite_sheet: Option<SpriteSheet>,
+    // Something visible changed since the last render
+    pub dirty: bool,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self {
+            next_entity: 0,
+            players: HashMap::new(),
+            me: None,
+            profiles: HashMap::new(),
+            positions: HashMap::new(),
+            motions: HashMap::new(),
+            bubbles: HashMap::new(),
+            animations: HashMap::new(),
+            bubble_duration_ms: bubbles::DEFAULT_DURATION_MS,
+            smoothing: Smoothing::default(),
+            camera: Camera::default(),
+            map: Map::default(),
+            chunks: HashMap::new(),
+            sprite_sheet: None,
+            dirty: false,
+        }
+    }
+}
+
+impl World {
+    // Forgets every entity, for a Welcome that carries the whole room
+    pub fn clear(&mut self) {
+        self.players.clear();
+        self.me = None;
+        self.profiles.clear();
+        self.positions.clear();
+        self.motions.clear();
+        self.bubbles.clear();
+        self.animations.clear();
+        self.dirty = true;
+    }
+
+    // Puts the game area, camera and minimap on `map`
+    pub fn set_map(&mut self, map: Map) {
+        self.camera.set_bounds(map.bounds);
+        self.map = map;
+        self.chunks.clear();
+        self.dirty = true;
+    }
+
+    // Every obstacle known: the map's own and those of the loaded chunks
+    pub fn obstacles(&self) -> impl Iterator<Item = &Obstacle> {
+        self.map.obstacles.iter().chain(self.chunks.values().flatten())
+    }
+
+    // Whether a player may stand at `position`, as far as the obstacles known
+    // here go; the server's WorldMap::fits has the final say
+    pub fn fits(&self, position: Position) -> bool {
+        !self.obstacles().any(|obstacle| obstacle.contains(position))
+    }
+
+    pub fn entity(&self, player_id: &str) -> Option<Entity> {
+        self.players.get(player_id).copied()
+    }
+
+    // Adds the player, or replaces what's known about them
+    pub fn spawn_player(&mut self, player: Player) -> Entity {
+        let entity = match self.entity(&player.id) {
+            Some(entity) => entity,
+            None => {
+                let entity = self.next_entity;
+                self.next_entity += 1;
+                self.players.insert(player.id.clone(), entity);
+                entity
+            }
+        };
+        let position = Position { x: player.x, y: player.y };
+        self.positions.insert(entity, position);
+        self.motions.insert(entity, Motion::default());
+        self.animations.insert(entity, Animation::default());
+        self.profiles.insert(
+            entity,
+            Profile {
+                id: player.id,
+                nickname: player.nickname,
+                color: player.color,
+                idle: player.idle,
+                sprite: player.sprite,
+            },
+        );
+        self.dirty = true;
+        entity
+    }
+
+    pub fn despawn_player(&mut self, player_id: &str) {
+        if let Some(entity) = self.players.remove(player_id) {
+            self.profiles.remove(&entity);
+            self.positions.remove(&entity);
+            self.motions.remove(&entity);
+            self.bubbles.remove(&entity);
+            self.animations.remove(&entity);
+            if self.me == Some(entity) {
+                self.me = None;
+            }
+            self.dirty = true;
+        }
+    }
+
+    // A position and velocity from the server, received at `now`. The local
+    // player is placed right away; others are interpolated towards it.
+    pub fn move_player(&mut self, player_id: &str, position: Position, velocity: (f32, f32), now: f64) {
+        let Some(entity) = self.entity(player_id) else {
+            return;
+        };
+        if self.me == Some(entity) {
+            if self.positions.insert(entity, position) != Some(position) {
+                self.dirty = true;
+            }
+        } else if let Some(motion) = self.motions.get_mut(&entity) {
+            motion.samples.push_back(Sample { at: now, position, velocity });
+            motion.fresh = true;
+        }
+    }
+
+    pub fn profile(&self, player_id: &str) -> Option<&Profile> {
+        self.profiles.get(&self.entity(player_id)?)
+    }
+
+    pub fn profile_mut(&mut self, player_id: &str) -> Option<&mut Profile> {
+        let entity = self.entity(player_id)?;
+        self.dirty = true;
+        self.profiles.get_mut(&entity)
+    }
+
+    pub fn my_profile(&self) -> Option<&Profile> {
+        self.profiles.get(&self.me?)
+    }
+
+    pub fn say(&mut self, player_id: &str, message: &str, now: f64) {
+        if let Some(entity) = self.entity(player_id) {
+            self.bubbles.insert(entity, Bubble::new(message, now + self.bubble_duration_ms));
+            self.dirty = true;
+        }
+    }
+}